@@ -1,3 +1,5 @@
 fn main() {
+    // only the desktop app needs Tauri's build step
+    #[cfg(feature = "gui")]
     tauri_build::build()
 }
@@ -0,0 +1,377 @@
+//! An optional HTTP server that lets external tools, e.g. DAW scripts or a Stream Deck, search and
+//! tag items in the open repo. The server only listens on localhost, and every request must send
+//! the token from the app config as `Authorization: Bearer <token>`.
+//!
+//! Endpoints:
+//!
+//! - `GET /items?query=<query>&scope=<folder>`: ids of items matching the query, `scope` is
+//!   optional and limits the results to items inside a folder
+//! - `GET /items/<id>`: details of an item
+//! - `POST /tags`: add tags to items, the body is `{ "ids": [1, 2], "tags": ["kick"] }`
+//! - `DELETE /tags`: remove tags from items, the body is the same as `POST /tags`
+//! - `GET /events`: a WebSocket that receives the events sent to the app's windows, e.g.
+//!   `repo-changed` and `status-changed`, see `EventStream`. Messages from the client are ignored.
+//!
+//! The tag endpoints also accept `"last_seen": { "1": <updated_at> }`, the `updated_at` of each
+//! item when it was fetched. If an item has changed since, nothing is changed and `409 Conflict`
+//! is returned.
+//!
+//! Errors are returned as `{ "error": "<message>" }`.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tauri::Runtime;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+use crate::events::{event_message, EventStream};
+use crate::manager::{ItemDetails, RepoManager};
+use crate::repo::{InsertTagsError, QueryError, RemoveTagsError, SearchError};
+
+/// The open repo's manager, shared with the Tauri commands.
+pub(crate) type SharedManager<R> = Arc<RwLock<Option<RepoManager<R>>>>;
+
+#[derive(Error, Debug)]
+pub enum ApiServerError {
+    #[error("the API server can't be started without a token")]
+    MissingToken,
+    #[error("failed to start API server, {0}")]
+    IOError(#[from] std::io::Error),
+}
+
+/// Settings for the API server, stored in the app config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiServerConfig {
+    pub(crate) enabled: bool,
+    /// Port on localhost to listen on.
+    pub(crate) port: u16,
+    /// Secret that clients must send with every request. The server isn't started if this is
+    /// empty.
+    pub(crate) token: String,
+}
+
+impl Default for ApiServerConfig {
+    fn default() -> Self {
+        Self { enabled: false, port: 7311, token: String::new() }
+    }
+}
+
+/// A running API server, the server is stopped when this is dropped.
+#[derive(Debug)]
+pub(crate) struct ApiServer {
+    handle: JoinHandle<()>,
+    addr: SocketAddr,
+}
+
+impl ApiServer {
+    /// Start listening on localhost. The port is bound before this returns, so an error is
+    /// returned if it is already in use.
+    pub(crate) fn start<R: Runtime>(
+        config: &ApiServerConfig,
+        manager: SharedManager<R>,
+        events: EventStream,
+    ) -> Result<Self, ApiServerError> {
+        if config.token.is_empty() {
+            return Err(ApiServerError::MissingToken);
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, config.port))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        let state = ApiState {
+            manager,
+            events,
+            token: Arc::from(config.token.as_str()),
+        };
+        let router = router(state);
+        let handle = tokio::spawn(async move {
+            let server = match axum::Server::from_tcp(listener) {
+                Ok(server) => server,
+                Err(err) => {
+                    error!("failed to start API server: {:?}", err);
+                    return;
+                }
+            };
+            debug!("API server listening on {}", addr);
+            if let Err(err) = server.serve(router.into_make_service()).await {
+                error!("API server stopped: {:?}", err);
+            }
+        });
+        Ok(Self { handle, addr })
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop the server and wait until its port is released.
+    pub(crate) async fn stop(mut self) {
+        self.handle.abort();
+        // the task has been aborted, so this can't fail in a way that matters
+        let _ = (&mut self.handle).await;
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+struct ApiState<R: Runtime> {
+    manager: SharedManager<R>,
+    events: EventStream,
+    token: Arc<str>,
+}
+
+// derive(Clone) would require `R: Clone`
+impl<R: Runtime> Clone for ApiState<R> {
+    fn clone(&self) -> Self {
+        Self {
+            manager: self.manager.clone(),
+            events: self.events.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+fn router<R: Runtime>(state: ApiState<R>) -> Router {
+    Router::new()
+        .route("/items", get(query_items::<R>))
+        .route("/items/:id", get(get_item::<R>))
+        .route("/tags", post(insert_tags::<R>).delete(remove_tags::<R>))
+        .route("/events", get(subscribe_events::<R>))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            check_token::<R, _>,
+        ))
+        .with_state(state)
+}
+
+/// Whether an `Authorization` header value contains the token.
+fn is_authorized(header: Option<&str>, token: &str) -> bool {
+    let Some(given) = header.and_then(|x| x.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    // compare every byte, so the time taken doesn't depend on how much of the token is correct
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+async fn check_token<R: Runtime, B>(
+    State(state): State<ApiState<R>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok());
+    if !is_authorized(header, &state.token) {
+        return ApiError(StatusCode::UNAUTHORIZED, String::from("invalid token")).into_response();
+    }
+    next.run(request).await
+}
+
+/// An error returned to the client with a status code.
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn no_open_repo() -> Self {
+        Self(
+            StatusCode::SERVICE_UNAVAILABLE,
+            String::from("no active repo"),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: String,
+        }
+
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(err: QueryError) -> Self {
+        let status = match err {
+            QueryError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            QueryError::BackendError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, err.to_string())
+    }
+}
+
+impl From<SearchError> for ApiError {
+    fn from(err: SearchError) -> Self {
+        let status = match err {
+            SearchError::ItemNotFound => StatusCode::NOT_FOUND,
+            SearchError::BackendError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, err.to_string())
+    }
+}
+
+impl From<InsertTagsError> for ApiError {
+    fn from(err: InsertTagsError) -> Self {
+        let status = match err {
+            InsertTagsError::InvalidTag(_) => StatusCode::BAD_REQUEST,
+            InsertTagsError::ReadOnly(_) => StatusCode::FORBIDDEN,
+            InsertTagsError::Conflict(_) => StatusCode::CONFLICT,
+            InsertTagsError::BackendError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, err.to_string())
+    }
+}
+
+impl From<RemoveTagsError> for ApiError {
+    fn from(err: RemoveTagsError) -> Self {
+        let status = match err {
+            RemoveTagsError::ReadOnly(_) => StatusCode::FORBIDDEN,
+            RemoveTagsError::Conflict(_) => StatusCode::CONFLICT,
+            RemoveTagsError::BackendError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, err.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    #[serde(default)]
+    query: String,
+    scope: Option<String>,
+}
+
+async fn query_items<R: Runtime>(
+    State(state): State<ApiState<R>>,
+    Query(params): Query<QueryParams>,
+) -> Result<Json<Vec<i64>>, ApiError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ApiError::no_open_repo());
+    };
+    Ok(Json(manager.query(&params.query, params.scope).await?))
+}
+
+async fn get_item<R: Runtime>(
+    State(state): State<ApiState<R>>,
+    Path(id): Path<i64>,
+) -> Result<Json<ItemDetails>, ApiError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ApiError::no_open_repo());
+    };
+    Ok(Json(manager.get_item_details(id).await?))
+}
+
+#[derive(Deserialize)]
+struct TagsBody {
+    ids: Vec<i64>,
+    tags: Vec<String>,
+    /// The `updated_at` of each item when it was last fetched, to fail if it has changed since
+    #[serde(default)]
+    last_seen: Option<HashMap<i64, i64>>,
+}
+
+async fn insert_tags<R: Runtime>(
+    State(state): State<ApiState<R>>,
+    Json(body): Json<TagsBody>,
+) -> Result<StatusCode, ApiError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ApiError::no_open_repo());
+    };
+    manager
+        .insert_tags(body.ids, body.tags, body.last_seen)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_tags<R: Runtime>(
+    State(state): State<ApiState<R>>,
+    Json(body): Json<TagsBody>,
+) -> Result<StatusCode, ApiError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ApiError::no_open_repo());
+    };
+    manager
+        .remove_tags(body.ids, body.tags, body.last_seen)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn subscribe_events<R: Runtime>(
+    State(state): State<ApiState<R>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // subscribe before upgrading, so no events are missed while the connection is set up
+    let receiver = state.events.subscribe();
+    ws.on_upgrade(move |socket| forward_events(socket, receiver))
+}
+
+/// Send events to a WebSocket client until it disconnects.
+async fn forward_events(mut socket: WebSocket, mut receiver: broadcast::Receiver<Arc<str>>) {
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let message = match event {
+                    Ok(message) => message.to_string(),
+                    // the client is too slow, tell it that events were skipped so it can refetch
+                    // anything it shows
+                    Err(RecvError::Lagged(count)) => match event_message("events-skipped", &count) {
+                        Ok(message) => message,
+                        Err(_) => continue,
+                    },
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(message)).await.is_err() {
+                    break;
+                }
+            }
+            // reading from the socket notices when the client disconnects
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+    debug!("event subscriber disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_checked() {
+        assert!(is_authorized(Some("Bearer secret"), "secret"));
+        assert!(!is_authorized(Some("Bearer secreT"), "secret"));
+        assert!(!is_authorized(Some("Bearer secret2"), "secret"));
+        assert!(!is_authorized(Some("secret"), "secret"));
+        assert!(!is_authorized(None, "secret"));
+    }
+}
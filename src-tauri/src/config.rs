@@ -0,0 +1,662 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::api_server::ApiServerConfig;
+use crate::helpers::fs::write_atomic;
+
+/// Name of the config file, stored in the app's config folder.
+const CONFIG_FILE_NAME: &str = "config.json";
+
+/// Number of unpinned repos kept in the recent repos list.
+const MAX_RECENT_REPOS: usize = 10;
+
+/// Number of quick tag slots, slots are numbered from 0.
+pub(crate) const QUICK_TAG_SLOTS: u8 = 10;
+
+/// Functions to upgrade a config file to the next version, the function at index `i` upgrades
+/// a config from version `i` to version `i + 1`. Config files without a version are version 0.
+const MIGRATIONS: &[fn(&mut Value)] = &[normalize_extensions];
+
+/// The version of config files written by this version of the app.
+const CONFIG_VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Error, Debug)]
+pub enum AppConfigError {
+    #[error("failed to read or write app config, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("malformed app config, {0}")]
+    MalformedConfig(#[from] serde_json::Error),
+    #[error("app config is from a newer version of the app (version {0})")]
+    UnsupportedVersion(u64),
+    #[error("invalid app config, {0}")]
+    InvalidConfig(String),
+}
+
+/// An application added by the user to the "open with" menu.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomApp {
+    pub(crate) name: String,
+    /// Path to the executable, or the name of a command in `PATH`.
+    pub(crate) command: String,
+    /// Arguments passed to the command. Any `{path}` is replaced with the path of the file being
+    /// opened. If no argument contains `{path}`, the path is passed as the last argument.
+    pub(crate) args: Vec<String>,
+}
+
+/// Size and position of a window, in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) maximized: bool,
+}
+
+/// Global shortcuts that control audio previews, so they work while another app is focused.
+/// Shortcuts are accelerator strings like `CmdOrCtrl+Alt+Space`, `None` disables a shortcut.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewShortcuts {
+    pub(crate) stop: Option<String>,
+    /// Play the last previewed file again from the start.
+    pub(crate) replay: Option<String>,
+    pub(crate) volume_up: Option<String>,
+    pub(crate) volume_down: Option<String>,
+}
+
+/// How audio files are previewed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioPreviewConfig {
+    /// Play audio files when they are selected. Otherwise they are only played when requested.
+    pub(crate) auto_preview: bool,
+    /// Where previews start in the file, unless another offset is requested.
+    pub(crate) skip_milliseconds: u64,
+    /// Previews are stopped after this long, `None` plays the whole file.
+    pub(crate) max_duration_milliseconds: Option<u64>,
+    /// Play items at a similar loudness, see `loudness.rs`.
+    pub(crate) normalize_loudness: bool,
+}
+
+impl Default for AudioPreviewConfig {
+    fn default() -> Self {
+        Self {
+            auto_preview: true,
+            skip_milliseconds: 0,
+            max_duration_milliseconds: None,
+            normalize_loudness: false,
+        }
+    }
+}
+
+/// Whether the app uses a dark or light theme.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    Dark,
+    Light,
+    /// Follow the OS's setting.
+    #[default]
+    System,
+}
+
+/// Appearance of the app's windows. This is passed to each window when it is created, so the
+/// theme is applied before the frontend has loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub(crate) mode: ThemeMode,
+    /// CSS hex color like `#3b82f6`, or `None` to use the default color.
+    pub(crate) accent_color: Option<String>,
+    /// Base font size in pixels, other sizes are scaled to match. Changes apply to windows that
+    /// are opened afterwards.
+    pub(crate) font_size: u32,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            mode: ThemeMode::System,
+            accent_color: None,
+            font_size: 16,
+        }
+    }
+}
+
+impl ThemeConfig {
+    /// Script run in each window before the page loads, which makes the theme available to the
+    /// frontend as `window.__THEME__`.
+    pub(crate) fn initialization_script(&self) -> String {
+        // JSON is valid JS, and serde escapes anything that could end the literal
+        let json = serde_json::to_string(self).expect("theme config is always serializable");
+        format!("window.__THEME__ = {};", json)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(color) = &self.accent_color {
+            let hex = color.strip_prefix('#').unwrap_or("");
+            if !matches!(hex.len(), 3 | 6) || !hex.chars().all(|x| x.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "accent color must be a hex color like #3b82f6: {}",
+                    color
+                ));
+            }
+        }
+        if !(8..=32).contains(&self.font_size) {
+            return Err(format!(
+                "font size must be between 8 and 32: {}",
+                self.font_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A repo in the recent repos list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentRepo {
+    pub(crate) path: PathBuf,
+    /// Pinned repos are listed first, and are never removed to make space for other repos.
+    pub(crate) pinned: bool,
+}
+
+/// Settings that apply to the app as a whole rather than a single repo, stored in the app's config
+/// folder. See `RepoConfig` for settings of a single repo.
+///
+/// Missing fields are filled in with their defaults, so older config files can still be loaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Applications to show in the "open with" menu, by file extension. Extensions are lowercase
+    /// and don't include the leading dot, e.g. `wav`.
+    pub(crate) open_with: BTreeMap<String, Vec<CustomApp>>,
+    /// Settings for the HTTP server used by external tools, see `api_server`.
+    pub(crate) api_server: ApiServerConfig,
+    /// Size and position of each window when it was last closed, by window label. This is managed
+    /// by the app, changes made by the frontend are ignored.
+    pub(crate) windows: BTreeMap<String, WindowGeometry>,
+    /// Recently opened repos, most recent first. Like `windows`, this is managed by the app.
+    pub(crate) recent_repos: Vec<RecentRepo>,
+    /// Tags assigned to numbered slots, so shortcuts can add them to the selected items. Tags are
+    /// separated by spaces. This is changed with `set_quick_tag()`, changes made by the frontend
+    /// are ignored.
+    pub(crate) quick_tags: BTreeMap<u8, String>,
+    /// Global shortcuts for audio previews, all disabled by default.
+    pub(crate) preview_shortcuts: PreviewShortcuts,
+    pub(crate) audio_preview: AudioPreviewConfig,
+    pub(crate) theme: ThemeConfig,
+}
+
+impl AppConfig {
+    /// Load the config from the given folder, upgrading it if it is from an older version. If no
+    /// config file exists, the default config is returned.
+    pub(crate) fn load(config_dir: impl AsRef<Path>) -> Result<Self, AppConfigError> {
+        let config_path = config_dir.as_ref().join(CONFIG_FILE_NAME);
+        let text = match fs::read_to_string(config_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        Self::from_json(&text)
+    }
+
+    /// Save the config to the given folder, creating the folder if needed. The existing config
+    /// file is only replaced once the new one is fully written.
+    pub(crate) fn save(&self, config_dir: impl AsRef<Path>) -> Result<(), AppConfigError> {
+        let config_dir = config_dir.as_ref();
+        fs::create_dir_all(config_dir)?;
+        write_atomic(config_dir.join(CONFIG_FILE_NAME), self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Parse a config file, upgrading it if it is from an older version.
+    fn from_json(text: &str) -> Result<Self, AppConfigError> {
+        let mut value: Value = serde_json::from_str(text)?;
+        let version = match value.get("version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| serde::de::Error::custom("version must be an integer"))
+                .map_err(AppConfigError::MalformedConfig)?,
+        };
+        if version > CONFIG_VERSION {
+            return Err(AppConfigError::UnsupportedVersion(version));
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut value);
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// The config as the contents of a config file, with the current version.
+    fn to_json(&self) -> Result<String, AppConfigError> {
+        let mut value = serde_json::to_value(self)?;
+        value["version"] = CONFIG_VERSION.into();
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Write the config to a file chosen by the user, so it can be imported on another computer.
+    pub(crate) fn export(&self, path: impl AsRef<Path>) -> Result<(), AppConfigError> {
+        write_atomic(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Read a config exported with `export()`, possibly by an older version of the app. Unlike
+    /// `load()`, a missing file is an error, and the config is rejected if it has settings that
+    /// the app would refuse to set.
+    pub(crate) fn import(path: impl AsRef<Path>) -> Result<Self, AppConfigError> {
+        let config = Self::from_json(&fs::read_to_string(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the settings that serde can't, like the ones that are checked when they are changed
+    /// through the app.
+    pub(crate) fn validate(&self) -> Result<(), AppConfigError> {
+        let invalid = |msg: String| Err(AppConfigError::InvalidConfig(msg));
+        for (extension, apps) in &self.open_with {
+            if extension.starts_with('.') || extension.to_lowercase() != *extension {
+                return invalid(format!(
+                    "extension must be lowercase without a dot: {extension}"
+                ));
+            }
+            if let Some(app) = apps.iter().find(|x| x.command.trim().is_empty()) {
+                return invalid(format!("app has no command: {}", app.name));
+            }
+        }
+        if let Some(slot) = self.quick_tags.keys().find(|x| **x >= QUICK_TAG_SLOTS) {
+            return invalid(format!("quick tag slot doesn't exist: {slot}"));
+        }
+        if self.api_server.port == 0 {
+            return invalid(String::from("API server port can't be 0"));
+        }
+        if self.audio_preview.max_duration_milliseconds == Some(0) {
+            return invalid(String::from("max preview duration can't be 0"));
+        }
+        self.theme.validate().map_err(AppConfigError::InvalidConfig)
+    }
+
+    /// Restore the default settings. The recent repos list is kept since it isn't a setting, but
+    /// the saved window sizes are cleared in case a window was moved off screen.
+    pub(crate) fn reset(&mut self) {
+        let recent_repos = std::mem::take(&mut self.recent_repos);
+        *self = Self { recent_repos, ..Self::default() };
+    }
+
+    /// Copy the config file to a backup next to it, so a config that can't be loaded isn't lost
+    /// when the default config is saved over it. Returns the path of the backup.
+    pub(crate) fn backup(config_dir: impl AsRef<Path>) -> Result<PathBuf, AppConfigError> {
+        let config_dir = config_dir.as_ref();
+        let backup_path = config_dir.join(format!("{}.bak", CONFIG_FILE_NAME));
+        fs::copy(config_dir.join(CONFIG_FILE_NAME), &backup_path)?;
+        Ok(backup_path)
+    }
+
+    /// Recently opened repos, pinned repos first.
+    pub(crate) fn recent_repos(&self) -> Vec<RecentRepo> {
+        let (mut pinned, unpinned): (Vec<_>, Vec<_>) =
+            self.recent_repos.iter().cloned().partition(|x| x.pinned);
+        pinned.extend(unpinned);
+        pinned
+    }
+
+    /// Move a repo to the top of the recent repos list, adding it if needed. The oldest unpinned
+    /// repos are removed if the list is too long.
+    pub(crate) fn add_recent_repo(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let pinned = match self.recent_repos.iter().position(|x| x.path == path) {
+            Some(i) => self.recent_repos.remove(i).pinned,
+            None => false,
+        };
+        self.recent_repos
+            .insert(0, RecentRepo { path: path.to_path_buf(), pinned });
+        let mut unpinned_count = 0;
+        self.recent_repos.retain(|x| {
+            if x.pinned {
+                return true;
+            }
+            unpinned_count += 1;
+            unpinned_count <= MAX_RECENT_REPOS
+        });
+    }
+
+    /// Pin or unpin a repo in the recent repos list. Returns false if the repo isn't in the list.
+    pub(crate) fn pin_repo(&mut self, path: impl AsRef<Path>, pinned: bool) -> bool {
+        let path = path.as_ref();
+        match self.recent_repos.iter_mut().find(|x| x.path == path) {
+            Some(repo) => {
+                repo.pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a repo from the recent repos list, even if it is pinned. Returns false if the repo
+    /// isn't in the list.
+    pub(crate) fn remove_recent_repo(&mut self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        let old_len = self.recent_repos.len();
+        self.recent_repos.retain(|x| x.path != path);
+        self.recent_repos.len() != old_len
+    }
+
+    /// Replace the path of a repo that was moved or renamed, keeping its place in the list and
+    /// whether it is pinned. If the new path is already in the list, the old entry is removed
+    /// instead. Returns false if the old path isn't in the list.
+    pub(crate) fn relocate_recent_repo(
+        &mut self,
+        old_path: impl AsRef<Path>,
+        new_path: impl AsRef<Path>,
+    ) -> bool {
+        let (old_path, new_path) = (old_path.as_ref(), new_path.as_ref());
+        let Some(i) = self.recent_repos.iter().position(|x| x.path == old_path) else {
+            return false;
+        };
+        let pinned = self.recent_repos.remove(i).pinned;
+        match self.recent_repos.iter_mut().find(|x| x.path == new_path) {
+            Some(repo) => repo.pinned |= pinned,
+            None => self
+                .recent_repos
+                .insert(i, RecentRepo { path: new_path.to_path_buf(), pinned }),
+        }
+        true
+    }
+
+    /// Assign tags to a quick tag slot, or clear the slot if there are no tags. Returns false if
+    /// the slot doesn't exist.
+    pub(crate) fn set_quick_tag(&mut self, slot: u8, tags: &str) -> bool {
+        if slot >= QUICK_TAG_SLOTS {
+            return false;
+        }
+        let tags = tags.split_whitespace().collect::<Vec<_>>().join(" ");
+        if tags.is_empty() {
+            self.quick_tags.remove(&slot);
+        } else {
+            self.quick_tags.insert(slot, tags);
+        }
+        true
+    }
+
+    /// The tags assigned to a quick tag slot, if any.
+    pub(crate) fn quick_tag(&self, slot: u8) -> Option<&str> {
+        self.quick_tags.get(&slot).map(String::as_str)
+    }
+
+    /// The custom "open with" applications for a file.
+    pub(crate) fn apps_for(&self, path: impl AsRef<Path>) -> &[CustomApp] {
+        let extension = match path.as_ref().extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => String::new(),
+        };
+        match self.open_with.get(&extension) {
+            Some(apps) => apps.as_slice(),
+            None => &[],
+        }
+    }
+}
+
+/// Version 0 to 1: "open with" extensions were documented as lowercase without a leading dot, but
+/// this wasn't enforced, so hand-edited configs may contain e.g. `.WAV`.
+fn normalize_extensions(config: &mut Value) {
+    let Some(open_with) = config.get_mut("open_with").and_then(Value::as_object_mut) else {
+        return;
+    };
+    let old = std::mem::take(open_with);
+    for (extension, apps) in old {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        match (open_with.get_mut(&extension), apps) {
+            (Some(Value::Array(existing)), Value::Array(apps)) => existing.extend(apps),
+            (_, apps) => {
+                open_with.insert(extension, apps);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn app(name: &str) -> CustomApp {
+        CustomApp {
+            name: name.to_string(),
+            command: name.to_string(),
+            args: vec![],
+        }
+    }
+
+    #[test]
+    fn missing_config_is_default() {
+        let dir = tempdir().unwrap();
+        assert_eq!(AppConfig::load(&dir).unwrap(), AppConfig::default());
+    }
+
+    #[test]
+    fn can_save_and_load_config() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().join("nested");
+        let mut config = AppConfig::default();
+        config
+            .open_with
+            .insert(String::from("wav"), vec![app("audacity")]);
+        config.save(&config_dir).unwrap();
+
+        assert_eq!(AppConfig::load(&config_dir).unwrap(), config);
+    }
+
+    #[test]
+    fn saved_config_has_version() {
+        let dir = tempdir().unwrap();
+        AppConfig::default().save(&dir).unwrap();
+
+        let text = fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        let value: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["version"], CONFIG_VERSION);
+    }
+
+    #[test]
+    fn unversioned_config_is_migrated() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            r#"{
+                "open_with": {
+                    ".WAV": [{ "name": "audacity", "command": "audacity", "args": [] }],
+                    "wav": [{ "name": "vim", "command": "vim", "args": [] }]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let config = AppConfig::load(&dir).unwrap();
+        assert_eq!(config.open_with.len(), 1);
+        let names: Vec<_> = config.open_with["wav"].iter().map(|x| &x.name).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&&String::from("audacity")));
+        assert!(names.contains(&&String::from("vim")));
+    }
+
+    #[test]
+    fn newer_config_is_rejected() {
+        let dir = tempdir().unwrap();
+        let text = format!(r#"{{ "version": {} }}"#, CONFIG_VERSION + 1);
+        fs::write(dir.path().join(CONFIG_FILE_NAME), text).unwrap();
+
+        let rv = AppConfig::load(&dir);
+        assert!(matches!(rv, Err(AppConfigError::UnsupportedVersion(_))));
+
+        let backup_path = AppConfig::backup(&dir).unwrap();
+        assert!(backup_path.exists());
+    }
+
+    #[test]
+    fn can_export_and_import_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        let mut config = AppConfig::default();
+        config
+            .open_with
+            .insert(String::from("wav"), vec![app("audacity")]);
+        config.set_quick_tag(3, "kick");
+        config.export(&path).unwrap();
+
+        assert_eq!(AppConfig::import(&path).unwrap(), config);
+        assert!(AppConfig::import(dir.path().join("missing.json")).is_err());
+    }
+
+    #[test]
+    fn invalid_imported_config_is_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        let import = |text: &str| {
+            fs::write(&path, text).unwrap();
+            AppConfig::import(&path)
+        };
+
+        let rv = import(r#"{ "version": 1, "quick_tags": { "42": "kick" } }"#);
+        assert!(matches!(rv, Err(AppConfigError::InvalidConfig(_))));
+        let rv = import(r#"{ "audio_preview": { "max_duration_milliseconds": 0 } }"#);
+        assert!(matches!(rv, Err(AppConfigError::InvalidConfig(_))));
+        let rv = import(r#"{ "version": 1, "api_server": { "port": "http" } }"#);
+        assert!(matches!(rv, Err(AppConfigError::MalformedConfig(_))));
+        let rv =
+            import(r#"{ "open_with": { "wav": [{ "name": "x", "command": " ", "args": [] }] } }"#);
+        assert!(matches!(rv, Err(AppConfigError::InvalidConfig(_))));
+        // old exports are migrated before they are checked
+        let config = import(r#"{ "open_with": { ".WAV": [] } }"#).unwrap();
+        assert!(config.open_with.contains_key("wav"));
+    }
+
+    #[test]
+    fn reset_keeps_recent_repos() {
+        let mut config = AppConfig::default();
+        config.add_recent_repo("a");
+        config.set_quick_tag(1, "kick");
+        config.api_server.enabled = true;
+        config.reset();
+        assert_eq!(recent_paths(&config), vec!["a"]);
+        assert_eq!(config.quick_tag(1), None);
+        assert!(!config.api_server.enabled);
+    }
+
+    #[test]
+    fn theme_is_checked_and_escaped() {
+        let mut theme = ThemeConfig::default();
+        assert!(theme.validate().is_ok());
+        theme.accent_color = Some(String::from("#3B82F6"));
+        assert!(theme.validate().is_ok());
+        theme.accent_color = Some(String::from("red"));
+        assert!(theme.validate().is_err());
+        theme.accent_color = None;
+        theme.font_size = 100;
+        assert!(theme.validate().is_err());
+
+        theme.accent_color = Some(String::from("\"</script>"));
+        let script = theme.initialization_script();
+        assert!(script.starts_with("window.__THEME__ = {"));
+        assert!(script.contains(r#""accent_color":"\"</script>""#));
+    }
+
+    fn recent_paths(config: &AppConfig) -> Vec<&str> {
+        config
+            .recent_repos
+            .iter()
+            .map(|x| x.path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn recent_repos_are_most_recent_first() {
+        let mut config = AppConfig::default();
+        config.add_recent_repo("a");
+        config.add_recent_repo("b");
+        config.add_recent_repo("a");
+        assert_eq!(recent_paths(&config), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn old_recent_repos_are_removed() {
+        let mut config = AppConfig::default();
+        config.add_recent_repo("pinned");
+        assert!(config.pin_repo("pinned", true));
+        for i in 0..MAX_RECENT_REPOS + 5 {
+            config.add_recent_repo(i.to_string());
+        }
+        assert_eq!(config.recent_repos.len(), MAX_RECENT_REPOS + 1);
+        assert_eq!(config.recent_repos()[0].path, PathBuf::from("pinned"));
+        assert!(!recent_paths(&config).contains(&"0"));
+
+        // reopening a pinned repo keeps it pinned
+        config.add_recent_repo("pinned");
+        assert!(config.recent_repos[0].pinned);
+    }
+
+    #[test]
+    fn quick_tags_can_be_assigned() {
+        let dir = tempdir().unwrap();
+        let mut config = AppConfig::default();
+        assert!(config.set_quick_tag(1, "  drum   kick "));
+        assert!(config.set_quick_tag(2, "snare"));
+        assert!(!config.set_quick_tag(QUICK_TAG_SLOTS, "hat"));
+        assert!(config.set_quick_tag(2, ""));
+        assert_eq!(config.quick_tag(1), Some("drum kick"));
+        assert_eq!(config.quick_tag(2), None);
+
+        // slots are saved as strings in JSON
+        config.save(&dir).unwrap();
+        assert_eq!(AppConfig::load(&dir).unwrap(), config);
+    }
+
+    #[test]
+    fn recent_repos_can_be_removed() {
+        let mut config = AppConfig::default();
+        config.add_recent_repo("a");
+        config.add_recent_repo("b");
+        config.pin_repo("a", true);
+        assert!(config.remove_recent_repo("a"));
+        assert!(!config.remove_recent_repo("a"));
+        assert!(!config.pin_repo("a", true));
+        assert_eq!(recent_paths(&config), vec!["b"]);
+    }
+
+    #[test]
+    fn recent_repos_can_be_relocated() {
+        let mut config = AppConfig::default();
+        config.add_recent_repo("old");
+        config.add_recent_repo("b");
+        config.pin_repo("old", true);
+        assert!(config.relocate_recent_repo("old", "new"));
+        assert!(!config.relocate_recent_repo("old", "new"));
+        assert_eq!(recent_paths(&config), vec!["b", "new"]);
+        assert!(config.recent_repos[1].pinned);
+
+        // the repo was already opened at its new path
+        config.add_recent_repo("moved");
+        config.add_recent_repo("b");
+        assert!(config.relocate_recent_repo("b", "moved"));
+        assert_eq!(recent_paths(&config), vec!["moved", "new"]);
+    }
+
+    #[test]
+    fn apps_are_found_by_extension() {
+        let mut config = AppConfig::default();
+        config
+            .open_with
+            .insert(String::from("wav"), vec![app("audacity")]);
+        config.open_with.insert(String::from(""), vec![app("vim")]);
+
+        assert_eq!(config.apps_for("samples/Kick.WAV"), &[app("audacity")]);
+        assert_eq!(config.apps_for("README"), &[app("vim")]);
+        assert!(config.apps_for("song.mp3").is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Extracting the text of documents, so they can be searched with `content:text`.
+
+use std::path::Path;
+
+use crate::helpers::fs::read_text_snippet;
+
+/// Text longer than this is cut off, so huge files don't bloat the database.
+pub(crate) const MAX_CONTENT_LEN: usize = 1024 * 1024;
+
+/// Plain text files, stored as they are.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "c", "cpp", "csv", "cxx", "h", "hpp", "hxx", "ini", "java", "lua", "md", "txt",
+];
+
+/// Markup files, stored without their tags.
+const MARKUP_EXTENSIONS: &[&str] = &["htm", "html", "xml"];
+
+/// Lowercase extensions of the files that text can be extracted from, without the leading dot.
+pub(crate) fn extractable_extensions() -> Vec<&'static str> {
+    let mut rv = [TEXT_EXTENSIONS, MARKUP_EXTENSIONS].concat();
+    if cfg!(feature = "documents") {
+        rv.push("pdf");
+    }
+    rv
+}
+
+/// Extract the text of a document. Returns `None` if the file isn't a supported document, or its
+/// text can't be extracted.
+pub(crate) fn extract_text(path: impl AsRef<Path>) -> std::io::Result<Option<String>> {
+    let path = path.as_ref();
+    let Some(extension) = path.extension().and_then(|x| x.to_str()) else {
+        return Ok(None);
+    };
+    let extension = extension.to_lowercase();
+    let text = if TEXT_EXTENSIONS.contains(&extension.as_str()) {
+        read_text_snippet(path, MAX_CONTENT_LEN)?.map(|(text, _)| text)
+    } else if MARKUP_EXTENSIONS.contains(&extension.as_str()) {
+        read_text_snippet(path, MAX_CONTENT_LEN)?.map(|(text, _)| strip_markup(&text))
+    } else {
+        extract_binary_text(path, &extension)?
+    };
+    Ok(text)
+}
+
+#[cfg(feature = "documents")]
+fn extract_binary_text(path: &Path, extension: &str) -> std::io::Result<Option<String>> {
+    if extension != "pdf" {
+        return Ok(None);
+    }
+    // read the file first, so IO errors can be told apart from malformed documents
+    let bytes = std::fs::read(path)?;
+    // pdf-extract panics on some malformed files
+    let result = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes));
+    let Ok(Ok(mut text)) = result else {
+        return Ok(None);
+    };
+    if text.len() > MAX_CONTENT_LEN {
+        let mut len = MAX_CONTENT_LEN;
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        text.truncate(len);
+    }
+    Ok(Some(text))
+}
+
+#[cfg(not(feature = "documents"))]
+fn extract_binary_text(_path: &Path, _extension: &str) -> std::io::Result<Option<String>> {
+    Ok(None)
+}
+
+/// Remove the tags of HTML or XML, leaving the text between them. Common entities are decoded.
+fn strip_markup(markup: &str) -> String {
+    let mut rv = String::with_capacity(markup.len());
+    let mut rest = markup;
+    while let Some(start) = rest.find('<') {
+        rv.push_str(&rest[..start]);
+        // separate the text on each side of the tag, e.g. in `<td>a</td><td>b</td>`
+        rv.push(' ');
+        rest = match rest[start..].find('>') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    rv.push_str(rest);
+    let rv = rv
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    rv.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn markup_is_stripped() {
+        assert_eq!(
+            strip_markup("<p>Fish &amp; chips</p><ul><li>one</li><li>two</li></ul>"),
+            "Fish & chips one two"
+        );
+        assert_eq!(strip_markup("no tags"), "no tags");
+    }
+
+    #[test]
+    fn text_is_extracted() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("notes.TXT"), "kick drum\nnotes").unwrap();
+        fs::write(path("manual.html"), "<h1>Manual</h1><p>Tuning</p>").unwrap();
+        fs::write(path("binary.txt"), b"\0\0\0").unwrap();
+        fs::write(path("kick.wav"), "RIFF").unwrap();
+
+        let text = |name| extract_text(path(name)).unwrap();
+        assert_eq!(text("notes.TXT"), Some("kick drum\nnotes".to_string()));
+        assert_eq!(text("manual.html"), Some("Manual Tuning".to_string()));
+        assert_eq!(text("binary.txt"), None);
+        assert_eq!(text("kick.wav"), None);
+        assert!(extract_text(path("missing.txt")).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! A small repo of generated files with tags, so new users can try searching and tagging before
+//! opening their own library.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::repo::{CreateError, InsertError, Repo};
+use crate::repo_config::RepoConfig;
+
+/// Sample rate of the generated audio files.
+const SAMPLE_RATE: u32 = 44100;
+
+/// A file in the demo repo.
+enum DemoFile {
+    /// Silent audio, with a length in milliseconds.
+    Wav(u32),
+    Text(&'static str),
+}
+
+/// Files in the demo repo, with their tags.
+const DEMO_FILES: &[(&str, DemoFile, &str)] = &[
+    (
+        "drums/kicks/kick 808.wav",
+        DemoFile::Wav(600),
+        "drums kick 808 one-shot",
+    ),
+    (
+        "drums/kicks/kick acoustic.wav",
+        DemoFile::Wav(400),
+        "drums kick acoustic one-shot",
+    ),
+    (
+        "drums/snares/snare tight.wav",
+        DemoFile::Wav(300),
+        "drums snare one-shot",
+    ),
+    (
+        "drums/snares/snare rimshot.wav",
+        DemoFile::Wav(250),
+        "drums snare rimshot one-shot",
+    ),
+    (
+        "drums/hats/hat closed.wav",
+        DemoFile::Wav(100),
+        "drums hihat closed one-shot",
+    ),
+    (
+        "drums/hats/hat open.wav",
+        DemoFile::Wav(500),
+        "drums hihat open one-shot",
+    ),
+    (
+        "drums/loops/breakbeat 170bpm.wav",
+        DemoFile::Wav(2000),
+        "drums loop 170bpm dnb",
+    ),
+    (
+        "drums/loops/boom bap 90bpm.wav",
+        DemoFile::Wav(2000),
+        "drums loop 90bpm hiphop",
+    ),
+    (
+        "bass/sub 808 C.wav",
+        DemoFile::Wav(1000),
+        "bass 808 sub one-shot",
+    ),
+    (
+        "bass/reese loop 170bpm.wav",
+        DemoFile::Wav(2000),
+        "bass reese loop 170bpm dnb",
+    ),
+    ("fx/riser.wav", DemoFile::Wav(1500), "fx riser"),
+    (
+        "fx/vinyl crackle.wav",
+        DemoFile::Wav(2000),
+        "fx texture vinyl",
+    ),
+    (
+        "notes/readme.txt",
+        DemoFile::Text(
+            "This is a demo repo, the audio files are silent.\n\n\
+             Try searching for `drums kick`, `-one-shot` or `170bpm`, and adding or removing \
+             tags on the selected items.\n",
+        ),
+        "notes",
+    ),
+    (
+        "notes/mix checklist.txt",
+        DemoFile::Text("- gain staging\n- high pass everything but the kick and bass\n"),
+        "notes mixing",
+    ),
+];
+
+#[derive(Error, Debug)]
+pub enum DemoError {
+    #[error("failed to write demo files, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("folder {0:?} isn't empty")]
+    DestinationNotEmpty(PathBuf),
+    #[error("failed to create repo, {0}")]
+    CreateError(#[from] CreateError),
+    #[error("failed to tag demo files, {0}")]
+    InsertError(#[from] InsertError),
+}
+
+/// A WAV file of silent 16-bit mono audio.
+fn silent_wav(milliseconds: u32) -> Vec<u8> {
+    let data_len = SAMPLE_RATE * milliseconds / 1000 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    // PCM, 1 channel
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(SAMPLE_RATE.to_le_bytes());
+    // bytes per second, bytes per frame, bits per sample
+    wav.extend((SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    wav.resize(44 + data_len as usize, 0);
+    wav
+}
+
+/// Create a demo repo in an empty folder, creating the folder if needed. The files are already
+/// tagged, so the repo only needs to be opened.
+pub(crate) fn create_demo_repo(path: impl AsRef<Path>) -> Result<Repo, DemoError> {
+    let path = path.as_ref();
+    fs::create_dir_all(path)?;
+    if fs::read_dir(path)?.next().is_some() {
+        return Err(DemoError::DestinationNotEmpty(path.to_path_buf()));
+    }
+    for (file_path, file, _) in DEMO_FILES {
+        let file_path = path.join(file_path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match file {
+            DemoFile::Wav(milliseconds) => fs::write(file_path, silent_wav(*milliseconds))?,
+            DemoFile::Text(text) => fs::write(file_path, text)?,
+        }
+    }
+    let mut repo = Repo::create(path, &RepoConfig::default())?;
+    repo.insert_items(DEMO_FILES.iter().map(|(path, _, tags)| (*path, *tags)))?;
+    Ok(repo)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn can_create_demo_repo() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("demo");
+        let repo = create_demo_repo(&path).unwrap();
+        assert_eq!(repo.query_ids("").unwrap().len(), DEMO_FILES.len());
+        assert_eq!(repo.query_ids("drums kick").unwrap().len(), 2);
+        assert!(path.join("notes/readme.txt").is_file());
+
+        let wav = fs::read(path.join("fx/riser.wav")).unwrap();
+        assert_eq!(wav.len(), 44 + 44100 * 3);
+        assert_eq!(&wav[..4], b"RIFF");
+
+        // the folder must be empty
+        drop(repo);
+        let rv = create_demo_repo(&path);
+        assert!(matches!(rv, Err(DemoError::DestinationNotEmpty(_))));
+    }
+}
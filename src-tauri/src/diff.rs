@@ -1,6 +1,7 @@
 use futures::StreamExt;
 use relative_path::{RelativePath, RelativePathBuf};
 
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
@@ -57,13 +58,19 @@ fn paths_similarity(path1: &RelativePath, path2: &RelativePath) -> i32 {
 
 fn path_diff_to_name_map<'a>(
     paths: impl IntoIterator<Item = &'a RelativePath>,
-) -> Result<HashMap<&'a str, Vec<&'a RelativePath>>, DiffError> {
-    let mut map: HashMap<&str, Vec<&RelativePath>> = HashMap::new();
+    case_insensitive: bool,
+) -> Result<HashMap<Cow<'a, str>, Vec<&'a RelativePath>>, DiffError> {
+    let mut map: HashMap<Cow<str>, Vec<&RelativePath>> = HashMap::new();
     for path in paths {
         let file_name = path
             .file_name()
             .ok_or(DiffError::InvalidPath(path.to_relative_path_buf()))?;
-        match map.get_mut(file_name) {
+        let file_name = if case_insensitive {
+            Cow::Owned(file_name.to_lowercase())
+        } else {
+            Cow::Borrowed(file_name)
+        };
+        match map.get_mut(&file_name) {
             Some(paths) => paths.push(path),
             None => {
                 map.insert(file_name, vec![path]);
@@ -73,14 +80,23 @@ fn path_diff_to_name_map<'a>(
     Ok(map)
 }
 
+/// Compare two lists of paths. Paths that were moved are detected by their file name.
+///
+/// If `case_insensitive` is true, file names are compared case-insensitively, so a path that
+/// only changed in case is treated as a rename rather than a deletion and creation.
 pub(crate) fn diff_path_list<'a>(
     before: &'a HashSet<RelativePathBuf>,
     after: &'a HashSet<RelativePathBuf>,
+    case_insensitive: bool,
 ) -> Result<DiffPaths<'a>, DiffError> {
-    let deleted_map =
-        path_diff_to_name_map(before.difference(&after).into_iter().map(|x| x.as_ref()))?;
-    let mut created_map =
-        path_diff_to_name_map(after.difference(&before).into_iter().map(|x| x.as_ref()))?;
+    let deleted_map = path_diff_to_name_map(
+        before.difference(&after).into_iter().map(|x| x.as_ref()),
+        case_insensitive,
+    )?;
+    let mut created_map = path_diff_to_name_map(
+        after.difference(&before).into_iter().map(|x| x.as_ref()),
+        case_insensitive,
+    )?;
     let mut diff = DiffPaths::new();
     for (deleted_file_name, deleted_paths) in &deleted_map {
         let Some(created_paths) = created_map.get_mut(deleted_file_name) else {
@@ -160,6 +176,14 @@ mod tests {
     fn assert_diff_paths(
         input: (Vec<&str>, Vec<&str>),
         output: (Vec<&str>, Vec<&str>, Vec<(&str, &str)>),
+    ) {
+        assert_diff_paths_with_case(input, output, false)
+    }
+
+    fn assert_diff_paths_with_case(
+        input: (Vec<&str>, Vec<&str>),
+        output: (Vec<&str>, Vec<&str>, Vec<(&str, &str)>),
+        case_insensitive: bool,
     ) {
         let before: HashSet<_> = input
             .0
@@ -186,7 +210,8 @@ mod tests {
             .into_iter()
             .map(|(a, b)| (RelativePathBuf::from(a), RelativePathBuf::from(b)))
             .collect();
-        let diff = diff_path_list(&before, &after).expect("failed to diff pathlist");
+        let diff =
+            diff_path_list(&before, &after, case_insensitive).expect("failed to diff pathlist");
         let created: HashSet<_> = diff
             .created
             .into_iter()
@@ -207,6 +232,19 @@ mod tests {
         assert_eq!(expected_renamed, renamed, "renamed paths differ");
     }
 
+    #[test]
+    fn diff_case_only_rename() {
+        assert_diff_paths(
+            (vec!["Kick.wav", "a"], vec!["kick.wav", "a"]),
+            (vec!["kick.wav"], vec!["Kick.wav"], vec![]),
+        );
+        assert_diff_paths_with_case(
+            (vec!["Kick.wav", "a"], vec!["kick.wav", "a"]),
+            (vec![], vec![], vec![("Kick.wav", "kick.wav")]),
+            true,
+        );
+    }
+
     #[test]
     fn diff_1() {
         assert_diff_paths(
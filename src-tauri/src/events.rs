@@ -0,0 +1,105 @@
+//! Events emitted to the webview can also be forwarded to external tools, which subscribe to them
+//! with the API server's `/events` WebSocket, see `api_server`.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Number of events kept for each subscriber. Subscribers that fall further behind than this skip
+/// the oldest events.
+const EVENT_BUFFER: usize = 256;
+
+/// Events forwarded to external subscribers, as JSON messages like
+/// `{ "event": "status-changed", "payload": "Idle" }`. This is managed as Tauri state, so events
+/// can be forwarded from anywhere with an `AppHandle`.
+#[derive(Debug, Clone)]
+pub(crate) struct EventStream {
+    sender: broadcast::Sender<Arc<str>>,
+}
+
+impl EventStream {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    /// Receive the events sent after this is called.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, event: &str, payload: &impl Serialize) {
+        // don't serialize events that nobody is listening to
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        match event_message(event, payload) {
+            // this only fails if every subscriber has disconnected in the meantime
+            Ok(message) => drop(self.sender.send(message.into())),
+            Err(err) => warn!("failed to serialize event {}: {}", event, err),
+        }
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The JSON message sent to subscribers for an event.
+pub(crate) fn event_message(event: &str, payload: &impl Serialize) -> serde_json::Result<String> {
+    #[derive(Serialize)]
+    struct Message<'a, S> {
+        event: &'a str,
+        payload: S,
+    }
+
+    serde_json::to_string(&Message { event, payload })
+}
+
+pub(crate) trait EmitExt {
+    /// Emit an event to every window like `Manager::emit_all()`, and forward it to the
+    /// subscribers of the `EventStream`.
+    fn emit_all_and_forward<S: Serialize + Clone>(
+        &self,
+        event: &str,
+        payload: S,
+    ) -> tauri::Result<()>;
+}
+
+impl<R: Runtime> EmitExt for AppHandle<R> {
+    fn emit_all_and_forward<S: Serialize + Clone>(
+        &self,
+        event: &str,
+        payload: S,
+    ) -> tauri::Result<()> {
+        if let Some(events) = self.try_state::<EventStream>() {
+            events.send(event, &payload);
+        }
+        self.emit_all(event, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_sent_to_subscribers() {
+        let events = EventStream::new();
+        // nobody is listening yet, so this is dropped
+        events.send("status-changed", &"Idle");
+        let mut receiver = events.subscribe();
+        events.send("repo-changed", &[1, 2]);
+
+        assert_eq!(
+            &*receiver.try_recv().unwrap(),
+            r#"{"event":"repo-changed","payload":[1,2]}"#
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::repo::{Item, QueryError};
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("failed to query items, {0}")]
+    QueryError(#[from] QueryError),
+    #[error("failed to write export file, {0}")]
+    IOError(#[from] std::io::Error),
+}
+
+/// File format used when exporting query results.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// An extended M3U playlist encoded in UTF-8, with the absolute path of each item.
+    M3u,
+    /// A CSV file with the columns `path` and `tags`. Paths are relative to the repo root, and
+    /// tags are separated by spaces.
+    Csv,
+}
+
+/// Write the given items to a file at `path`.
+pub(crate) fn export_items(
+    items: &[Item],
+    repo_path: &Path,
+    format: ExportFormat,
+    path: impl AsRef<Path>,
+) -> Result<(), ExportError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::M3u => write_m3u(&mut writer, items, repo_path)?,
+        ExportFormat::Csv => write_csv(&mut writer, items)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_m3u(writer: &mut impl Write, items: &[Item], repo_path: &Path) -> std::io::Result<()> {
+    writeln!(writer, "#EXTM3U")?;
+    for item in items {
+        let path = RelativePath::new(&item.path);
+        let title = path.file_stem().unwrap_or(&item.path);
+        writeln!(writer, "#EXTINF:-1,{}", title)?;
+        writeln!(writer, "{}", path.to_path(repo_path).display())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_csv(writer: &mut impl Write, items: &[Item]) -> std::io::Result<()> {
+    // use CRLF line endings, as recommended by RFC 4180
+    write!(writer, "path,tags\r\n")?;
+    for item in items {
+        write!(
+            writer,
+            "{},{}\r\n",
+            csv_field(&item.path),
+            csv_field(&item.tags.join(" "))
+        )?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains any special characters.
+fn csv_field(text: &str) -> String {
+    if text.contains(['"', ',', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, tags: &str) -> Item {
+        Item {
+            id: 0,
+            path: path.to_string(),
+            tags: tags.split_whitespace().map(String::from).collect(),
+            meta_tags: String::from("all"),
+            updated_at: 0,
+            uid: String::new(),
+        }
+    }
+
+    #[test]
+    fn writes_m3u() {
+        let items = vec![item("a.wav", "drums"), item("loops/b c.mp3", "")];
+        let repo_path = Path::new("/music");
+        let mut output = vec![];
+        write_m3u(&mut output, &items, repo_path).unwrap();
+        let expected = format!(
+            "#EXTM3U\n#EXTINF:-1,a\n{}\n#EXTINF:-1,b c\n{}\n",
+            repo_path.join("a.wav").display(),
+            repo_path.join("loops").join("b c.mp3").display(),
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn writes_csv() {
+        let items = vec![item("a.wav", "drums kick"), item("b, \"c\".wav", "")];
+        let mut output = vec![];
+        write_csv(&mut output, &items).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "path,tags\r\na.wav,drums kick\r\n\"b, \"\"c\"\".wav\",\r\n"
+        );
+    }
+}
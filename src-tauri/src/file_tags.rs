@@ -0,0 +1,206 @@
+//! Writing an item's tags into the comment of its audio file, so the tags travel with the file when
+//! it is shared outside the app. Tags are written as a space-separated list, like in the repo.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// What to do when a file's comment already has other text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictStrategy {
+    /// Leave the file unchanged
+    Skip,
+    /// Replace the comment with the tags
+    Overwrite,
+    /// Add the tags that aren't in the comment yet, keeping the existing text
+    Merge,
+}
+
+/// What happened to a file, or would happen in a dry run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status")]
+pub enum WriteOutcome {
+    Written {
+        old: Option<String>,
+        new: String,
+    },
+    /// The comment already has the tags
+    Unchanged,
+    /// The comment has other text, and the strategy is `Skip`
+    Conflict {
+        existing: String,
+    },
+    /// The file can't be read or written, e.g. if it isn't an audio file
+    Failed {
+        error: String,
+    },
+}
+
+/// The outcome of writing the tags of an item to its file.
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteTagsReport {
+    pub(crate) id: i64,
+    pub(crate) path: String,
+    pub(crate) outcome: WriteOutcome,
+}
+
+impl WriteTagsReport {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn outcome(&self) -> &WriteOutcome {
+        &self.outcome
+    }
+}
+
+/// Decide the new comment of a file, given its existing comment.
+fn plan_comment(
+    existing: Option<&str>,
+    tags: &[String],
+    strategy: ConflictStrategy,
+) -> WriteOutcome {
+    let new = tags.join(" ");
+    let existing = existing.map(str::trim).filter(|x| !x.is_empty());
+    let Some(existing) = existing else {
+        return if new.is_empty() {
+            WriteOutcome::Unchanged
+        } else {
+            WriteOutcome::Written { old: None, new }
+        };
+    };
+    if existing == new {
+        return WriteOutcome::Unchanged;
+    }
+    let new = match strategy {
+        ConflictStrategy::Skip => {
+            return WriteOutcome::Conflict { existing: existing.to_string() };
+        }
+        ConflictStrategy::Overwrite => new,
+        ConflictStrategy::Merge => {
+            let words: HashSet<&str> = existing.split_whitespace().collect();
+            let missing: Vec<&str> = tags
+                .iter()
+                .map(String::as_str)
+                .filter(|tag| !words.contains(tag))
+                .collect();
+            if missing.is_empty() {
+                return WriteOutcome::Unchanged;
+            }
+            format!("{} {}", existing, missing.join(" "))
+        }
+    };
+    WriteOutcome::Written { old: Some(existing.to_string()), new }
+}
+
+/// Write tags into the comment of an audio file. If `dry_run` is set, the file isn't changed, but
+/// the outcome is the same as if it was written.
+pub fn write_tags_to_file(
+    path: impl AsRef<Path>,
+    tags: &[String],
+    strategy: ConflictStrategy,
+    dry_run: bool,
+) -> WriteOutcome {
+    match try_write_tags(path.as_ref(), tags, strategy, dry_run) {
+        Ok(outcome) => outcome,
+        Err(error) => WriteOutcome::Failed { error },
+    }
+}
+
+#[cfg(feature = "metadata")]
+fn try_write_tags(
+    path: &Path,
+    tags: &[String],
+    strategy: ConflictStrategy,
+    dry_run: bool,
+) -> Result<WriteOutcome, String> {
+    use lofty::{ItemKey, Tag, TagExt, TaggedFileExt};
+
+    let mut file = lofty::read_from_path(path).map_err(|err| err.to_string())?;
+    let tag_type = file.primary_tag_type();
+    if file.tag(tag_type).is_none() {
+        file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = file.tag_mut(tag_type).expect("tag was just inserted");
+    let outcome = plan_comment(tag.get_string(&ItemKey::Comment), tags, strategy);
+    if let WriteOutcome::Written { new, .. } = &outcome {
+        if !dry_run {
+            tag.insert_text(ItemKey::Comment, new.clone());
+            tag.save_to_path(path).map_err(|err| err.to_string())?;
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(not(feature = "metadata"))]
+fn try_write_tags(
+    _path: &Path,
+    _tags: &[String],
+    _strategy: ConflictStrategy,
+    _dry_run: bool,
+) -> Result<WriteOutcome, String> {
+    Err("writing tags to files requires the `metadata` feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(existing: Option<&str>, tags: &str, strategy: ConflictStrategy) -> WriteOutcome {
+        let tags: Vec<String> = tags.split_whitespace().map(String::from).collect();
+        plan_comment(existing, &tags, strategy)
+    }
+
+    fn written(old: Option<&str>, new: &str) -> WriteOutcome {
+        WriteOutcome::Written { old: old.map(String::from), new: new.to_string() }
+    }
+
+    #[test]
+    fn empty_comments_are_written() {
+        use ConflictStrategy::*;
+
+        assert_eq!(plan(None, "drum kick", Skip), written(None, "drum kick"));
+        assert_eq!(
+            plan(Some("  "), "drum kick", Skip),
+            written(None, "drum kick")
+        );
+        assert_eq!(plan(None, "", Overwrite), WriteOutcome::Unchanged);
+        assert_eq!(
+            plan(Some("drum kick"), "drum kick", Skip),
+            WriteOutcome::Unchanged
+        );
+    }
+
+    #[test]
+    fn conflicts_follow_the_strategy() {
+        use ConflictStrategy::*;
+
+        assert_eq!(
+            plan(Some("from a pack"), "drum kick", Skip),
+            WriteOutcome::Conflict { existing: "from a pack".to_string() }
+        );
+        assert_eq!(
+            plan(Some("from a pack"), "drum kick", Overwrite),
+            written(Some("from a pack"), "drum kick")
+        );
+        assert_eq!(
+            plan(Some("kick from a pack"), "drum kick", Merge),
+            written(Some("kick from a pack"), "kick from a pack drum")
+        );
+        assert_eq!(
+            plan(Some("kick drum 909"), "drum kick", Merge),
+            WriteOutcome::Unchanged
+        );
+    }
+
+    #[test]
+    fn unreadable_files_fail() {
+        let outcome = write_tags_to_file("missing.wav", &[], ConflictStrategy::Skip, true);
+        assert!(matches!(outcome, WriteOutcome::Failed { .. }));
+    }
+}
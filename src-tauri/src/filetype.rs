@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::error;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Audio,
+    Document,
+    Image,
+    Video,
+    Unknown,
+}
+
+macro_rules! file_types {
+    ($($file_type:tt),*) => {
+        [$(stringify!($file_type)),*]
+    };
+}
+
+const EXT_AUDIO: &'static [&'static str] = &file_types![
+    aac, ac3, aif, aifc, aiff, au, cda, dts, fla, flac, it, m1a, m2a, m3u, m4a, mid, midi, mka,
+    mod, mp2, mp3, mpa, ogg, opus, ra, rmi, snd, spc, umx, voc, wav, wma, xm
+];
+
+const EXT_DOCUMENT: &'static [&'static str] = &file_types![
+    c, chm, cpp, csv, cxx, doc, docm, docx, dot, dotm, dotx, h, hpp, htm, html, hxx, ini, java,
+    lua, mht, mhtml, odt, pdf, potm, potx, ppam, pps, ppsm, ppsx, ppt, pptm, pptx, rtf, sldm, sldx,
+    thmx, txt, vsd, wpd, wps, wri, xlam, xls, xlsb, xlsm, xlsx, xltm, xltx, xml
+];
+
+const EXT_IMAGE: &'static [&'static str] =
+    &file_types![ani, bmp, gif, ico, jpe, jpeg, jpg, pcx, png, psd, tga, tif, tiff, webp, wmf];
+
+const EXT_VIDEO: &'static [&'static str] = &file_types![
+    3g2, 3gp, 3gp2, 3gpp, amr, amv, asf, avi, bdmv, bik, d2v, divx, drc, dsa, dsm, dss, dsv, evo,
+    f4v, flc, fli, flic, flv, hdmov, ifo, ivf, m1v, m2p, m2t, m2ts, m2v, m4b, m4p, m4v, mkv, mov,
+    mp2v, mp4, mp4v, mpe, mpeg, mpg, mpls, mpv2, mpv4, mts, ogm, ogv, pss, pva, qt, ram, ratdvd,
+    rm, rmm, rmvb, roq, rpm, smil, smk, swf, tp, tpr, ts, vob, vp6, webm, wm, wmp, wmv
+];
+
+pub fn determine_filetype(path: impl AsRef<Path>) -> FileType {
+    let path: &Path = path.as_ref();
+    let Some(extension) = path.extension() else {
+        return FileType::Unknown;
+    };
+
+    let Some(extension) = extension.to_str() else {
+        error!("cannot determine filetype of malformed path: {:?}", path);
+        return FileType::Unknown;
+    };
+
+    let extension = extension.to_lowercase();
+
+    if EXT_AUDIO.contains(&extension.as_str()) {
+        FileType::Audio
+    } else if EXT_DOCUMENT.contains(&extension.as_str()) {
+        FileType::Document
+    } else if EXT_IMAGE.contains(&extension.as_str()) {
+        FileType::Image
+    } else if EXT_VIDEO.contains(&extension.as_str()) {
+        FileType::Video
+    } else {
+        FileType::Unknown
+    }
+}
+
+impl FileType {
+    /// Name of the type in the `type:` meta tag, e.g. `type:audio`. Unknown files have no type.
+    fn meta_tag_name(self) -> Option<&'static str> {
+        match self {
+            FileType::Audio => Some("audio"),
+            FileType::Document => Some("document"),
+            FileType::Image => Some("image"),
+            FileType::Video => Some("video"),
+            FileType::Unknown => None,
+        }
+    }
+}
+
+/// The meta tags of an item, which are derived from its path and stored in the `meta_tags`
+/// column, e.g. `all type:audio ext:wav`. Every item has the meta tag `all`, which is used to
+/// negate FTS queries.
+pub(crate) fn meta_tags(path: &str) -> String {
+    let mut tags = String::from("all");
+    if let Some(name) = determine_filetype(path).meta_tag_name() {
+        tags.push_str(" type:");
+        tags.push_str(name);
+    }
+    if let Some(ext) = Path::new(path).extension().and_then(|x| x.to_str()) {
+        tags.push_str(" ext:");
+        tags.push_str(&ext.to_lowercase());
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_tags_are_derived_from_path() {
+        assert_eq!(meta_tags("a/kick.WAV"), "all type:audio ext:wav");
+        assert_eq!(meta_tags("notes.txt"), "all type:document ext:txt");
+        assert_eq!(meta_tags("data.xyz"), "all ext:xyz");
+        assert_eq!(meta_tags("a.b/README"), "all");
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Hash the contents of a file, returning the hash as a hex string.
+///
+/// This is used to recognise a file after it has been moved or renamed, so it doesn't need to be
+/// cryptographically secure, but it must stay the same between versions of the app since hashes
+/// are stored in the database.
+pub(crate) fn hash_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn same_contents_have_same_hash() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        fs::write(dir.path().join("b.txt"), "hello").unwrap();
+        fs::write(dir.path().join("c.txt"), "world").unwrap();
+
+        let a = hash_file(dir.path().join("a.txt")).unwrap();
+        let b = hash_file(dir.path().join("b.txt")).unwrap();
+        let c = hash_file(dir.path().join("c.txt")).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn missing_file_fails() {
+        let dir = tempdir().unwrap();
+        assert!(hash_file(dir.path().join("missing.txt")).is_err());
+    }
+}
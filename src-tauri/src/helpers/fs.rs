@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+
+/// Write a file by writing to a temporary file next to it, then renaming it over the original. If
+/// the app crashes midway, the original file is left untouched instead of being half-written.
+pub(crate) fn write_atomic(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    // the temporary file must be on the same filesystem for the rename to be atomic
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(contents.as_ref())?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Read the start of a text file, up to `max_len` bytes. Returns the text and whether the file is
+/// longer than that, or `None` if the file isn't UTF-8 text.
+pub(crate) fn read_text_snippet(
+    path: impl AsRef<Path>,
+    max_len: usize,
+) -> std::io::Result<Option<(String, bool)>> {
+    let mut buf = Vec::with_capacity(max_len + 1);
+    File::open(path)?
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut buf)?;
+    let truncated = buf.len() > max_len;
+    buf.truncate(max_len);
+    let text = match std::str::from_utf8(&buf) {
+        Ok(text) => text,
+        // the snippet may end in the middle of a character
+        Err(err) if truncated && err.error_len().is_none() => {
+            std::str::from_utf8(&buf[..err.valid_up_to()]).unwrap()
+        }
+        Err(_) => return Ok(None),
+    };
+    if text.contains('\0') {
+        return Ok(None);
+    }
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    Ok(Some((text.to_string(), truncated)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn replaces_existing_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write_atomic(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn reads_text_snippets() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "\u{feff}short").unwrap();
+        let snippet = read_text_snippet(&path, 100).unwrap();
+        assert_eq!(snippet, Some(("short".to_string(), false)));
+
+        // "é" is 2 bytes, so the snippet can't include it
+        fs::write(&path, "abcé").unwrap();
+        let snippet = read_text_snippet(&path, 4).unwrap();
+        assert_eq!(snippet, Some(("abc".to_string(), true)));
+
+        fs::write(&path, b"RIFF\0\0\0\0WAVE").unwrap();
+        assert_eq!(read_text_snippet(&path, 100).unwrap(), None);
+        fs::write(&path, b"\xff\xfe").unwrap();
+        assert_eq!(read_text_snippet(&path, 100).unwrap(), None);
+    }
+}
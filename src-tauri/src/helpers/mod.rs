@@ -1 +1,3 @@
+pub(crate) mod fs;
+pub(crate) mod path;
 pub(crate) mod sql;
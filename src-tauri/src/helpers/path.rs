@@ -0,0 +1,125 @@
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+/// Returned when a command is given a path that isn't inside the open repo.
+#[derive(Error, Debug)]
+#[error("path is outside the open repo, {0}")]
+pub struct PathOutsideRepo(pub(crate) PathBuf);
+
+/// Resolve `.` and `..` in an absolute path without touching the filesystem. Returns `None` if
+/// the path is relative, or if it goes above the root.
+fn normalize_lexically(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            }
+            component => result.push(component),
+        }
+    }
+    Some(result)
+}
+
+/// Check that `path` is inside the repo at `root`, so commands from the webview can't launch or
+/// read arbitrary files.
+///
+/// Both paths are canonicalized first. Since scanning follows symlinks, a symlink inside the repo
+/// may point to a file outside of it. So if the canonical path is outside the repo, the path is
+/// still accepted if it is inside the repo without resolving symlinks. Callers use the original
+/// path, where `..` after a symlink leaves the folder the symlink points to, so such paths must
+/// not contain `..`.
+pub(crate) fn ensure_inside_repo(
+    root: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> Result<(), PathOutsideRepo> {
+    let root = root.as_ref();
+    let path = path.as_ref();
+    let outside = || PathOutsideRepo(path.to_path_buf());
+
+    if let (Ok(root), Ok(canonical_path)) = (root.canonicalize(), path.canonicalize()) {
+        if canonical_path.starts_with(root) {
+            return Ok(());
+        }
+    }
+    if path.components().any(|x| x == Component::ParentDir) {
+        return Err(outside());
+    }
+    let root = normalize_lexically(root).ok_or_else(outside)?;
+    let path = normalize_lexically(path).ok_or_else(outside)?;
+    // like when canonicalizing, the path must exist
+    if path.starts_with(root) && path.exists() {
+        Ok(())
+    } else {
+        Err(outside())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn accepts_paths_in_repo() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+        fs::create_dir_all(root.join("folder")).unwrap();
+        fs::write(root.join("folder/a.wav"), "").unwrap();
+
+        assert!(ensure_inside_repo(&root, root.join("folder/a.wav")).is_ok());
+        assert!(ensure_inside_repo(&root, root.join("folder/../folder/a.wav")).is_ok());
+        assert!(ensure_inside_repo(&root, root.join("folder")).is_ok());
+    }
+
+    #[test]
+    fn rejects_paths_outside_repo() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.path().join("secret.txt"), "").unwrap();
+
+        assert!(ensure_inside_repo(&root, dir.path().join("secret.txt")).is_err());
+        assert!(ensure_inside_repo(&root, root.join("../secret.txt")).is_err());
+        assert!(ensure_inside_repo(&root, "secret.txt").is_err());
+        // paths that don't exist can't be checked
+        assert!(ensure_inside_repo(&root, root.join("missing.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn accepts_symlinks_in_repo() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.path().join("outside.wav"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("outside.wav"), root.join("link.wav")).unwrap();
+
+        assert!(ensure_inside_repo(&root, root.join("link.wav")).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_parent_dirs_after_symlinks() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(dir.path().join("outside/folder")).unwrap();
+        fs::write(dir.path().join("outside/secret.txt"), "").unwrap();
+        fs::write(root.join("secret.txt"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("outside/folder"), root.join("link")).unwrap();
+
+        // opening this path reads `outside/secret.txt`, not `repo/secret.txt`
+        assert!(ensure_inside_repo(&root, root.join("link/../secret.txt")).is_err());
+    }
+}
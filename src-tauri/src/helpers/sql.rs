@@ -1,44 +1,76 @@
 use std::borrow::Cow;
 
+use rusqlite::Connection;
+
+thread_local! {
+    /// An empty FTS5 table, used to check the syntax of FTS5 queries.
+    static FTS5_CHECKER: Connection = {
+        let conn = Connection::open_in_memory().expect("failed to open in-memory database");
+        conn.execute_batch("CREATE VIRTUAL TABLE fts5_check USING fts5(tags)")
+            .expect("failed to create FTS5 table");
+        conn
+    };
+}
+
 // Cow optimisation from:
 // https://lise-henry.github.io/articles/optimising_strings.html
-/// Escape a string to be used in a FTS5 query. This duplicates all quotes (both single and double).
+/// Escape a string to be used in a FTS5 string. This duplicates all double quotes.
+///
+/// The returned string is not escaped for SQL, it must be bound as a parameter.
 pub(crate) fn escape_fts5_string<'a>(text: impl Into<Cow<'a, str>>) -> Cow<'a, str> {
     let text = text.into();
 
-    fn is_quote_char(c: char) -> bool {
-        c == '\'' || c == '"'
+    if text.contains('"') {
+        text.replace('"', r#""""#).into()
+    } else {
+        text
     }
-    if text.contains(is_quote_char) {
-        // add 1 to existing length, we already know it contains a quote
-        let mut result = String::with_capacity(text.len() + 1);
-        for char in text.chars() {
-            match char {
-                '\'' => {
-                    result.push_str("''");
-                }
-                '"' => {
-                    result.push_str(r#""""#);
-                }
-                _ => {
-                    result.push(char);
+}
+
+/// Check that an FTS5 query written by the user is valid, when it's used as `tags:(<query>)`.
+/// Returns a description of the error if it isn't.
+///
+/// Parentheses must be balanced outside of strings, otherwise the query could close `tags:(` and
+/// match other columns.
+pub(crate) fn check_fts5_query(query: &str) -> Result<(), String> {
+    let mut depth = 0;
+    let mut in_string = false;
+    for c in query.chars() {
+        match c {
+            // a doubled quote in a string is an escaped quote, this closes then reopens the string
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                depth -= 1;
+                if depth < 0 {
+                    return Err(String::from("unexpected closing parenthesis"));
                 }
             }
+            _ => {}
         }
-        result.into()
-    } else {
-        text.into()
     }
+    if depth > 0 {
+        return Err(String::from("unclosed parenthesis"));
+    }
+    FTS5_CHECKER.with(|conn| {
+        conn.query_row(
+            "SELECT count(*) FROM fts5_check WHERE fts5_check MATCH ?",
+            [format!("tags:({})", query)],
+            |_| Ok(()),
+        )
+        .map_err(|err| err.to_string())
+    })
 }
 
 /// Escape a string to be used in a LIKE query. This prefixes any percent ("%"), underscore ("_"),
-/// and escape characters with the escape character. This also duplicates any single quotes in the
-/// string. The escape character is provided by you in `escape_char`.
+/// and escape characters with the escape character. The escape character is provided by you in
+/// `escape_char`.
 ///
-/// The returned string must be used in conjunction with the given `escape_char` as follows:
+/// The returned string must be bound as a parameter, in conjunction with the given `escape_char`
+/// as follows:
 ///
 /// ```sql
-/// WHERE column LIKE '<returned string>' ESCAPE '<escape char>'
+/// WHERE column LIKE ? ESCAPE '<escape char>'
 /// ```
 pub(crate) fn escape_like_pattern(text: &str, escape_char: char) -> String {
     let mut result = String::with_capacity(text.len());
@@ -52,9 +84,6 @@ pub(crate) fn escape_like_pattern(text: &str, escape_char: char) -> String {
                 result.push(escape_char);
                 result.push('_');
             }
-            '\'' => {
-                result.push_str("''");
-            }
             _ if char == escape_char => {
                 result.push(escape_char);
                 result.push(escape_char);
@@ -78,7 +107,7 @@ mod test_fts5 {
 
     #[test]
     fn single_quotes() {
-        assert_eq!(escape_fts5_string("'as'd"), "''as''d",)
+        assert_eq!(escape_fts5_string("'as'd"), "'as'd",)
     }
 
     #[test]
@@ -88,7 +117,29 @@ mod test_fts5 {
 
     #[test]
     fn both_quotes() {
-        assert_eq!(escape_fts5_string(r#""a's"d'"#), r#"""a''s""d''"#,)
+        assert_eq!(escape_fts5_string(r#""a's"d'"#), r#"""a's""d'"#,)
+    }
+}
+
+#[cfg(test)]
+mod test_check_fts5 {
+    use super::*;
+
+    #[test]
+    fn valid_queries() {
+        assert_eq!(check_fts5_query("kick NOT snare"), Ok(()));
+        assert_eq!(check_fts5_query("NEAR(kick snare, 2)"), Ok(()));
+        assert_eq!(check_fts5_query(r#""a (b""#), Ok(()));
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert!(check_fts5_query("kick AND").is_err());
+        assert!(check_fts5_query("NEAR(").is_err());
+        assert!(check_fts5_query(r#""kick"#).is_err());
+        assert!(check_fts5_query("").is_err());
+        // other columns can't be matched by closing the parentheses early
+        assert!(check_fts5_query("a) OR meta_tags:(b").is_err());
     }
 }
 
@@ -108,7 +159,7 @@ mod test_like {
     }
     #[test]
     fn single_quotes() {
-        assert_escaped("'as'd", "''as''d");
+        assert_escaped("'as'd", "'as'd");
     }
     #[test]
     fn double_quotes() {
@@ -116,7 +167,7 @@ mod test_like {
     }
     #[test]
     fn both_quotes() {
-        assert_escaped(r#""a's"d'"#, r#""a''s"d''"#);
+        assert_escaped(r#""a's"d'"#, r#""a's"d'"#);
     }
     #[test]
     fn percent_1() {
@@ -1,10 +1,92 @@
+//! The tagging engine behind tag-repo, for building plugins or other frontends.
+//!
+//! A repo is a folder whose files can be tagged. Tags and other data are stored in a database in
+//! the folder's `.tagrepo` subfolder, see `Repo`:
+//!
+//! ```no_run
+//! use lib::{OpenMode, Repo};
+//!
+//! let repo = Repo::open("path/to/samples", OpenMode::ReadWrite).unwrap();
+//! repo.insert_item("drums/kick.wav", "drum kick").unwrap();
+//! for item in repo.query_items("drum -snare in:drums/").unwrap() {
+//!     println!("{} {:?}", item.path(), item.tags());
+//! }
+//! ```
+//!
+//! Queries are strings in the same language as the app's search bar, a `ParseError` is returned if
+//! a query is invalid. Use `normalize_query()` to check a query without running it.
+//!
+//! To keep a repo in sync with its folder, scan the folder with `scan_dir()` and pass the paths to
+//! `Repo::sync()`, then watch the folder for changes with `new_watcher()`.
+//!
+//! The desktop app, audio previews, folder watching, PDF text extraction and writing tags into audio
+//! files are behind the `gui`, `audio`, `watch`, `documents` and `metadata` features. Disable the
+//! default features to build only the core, e.g. on a headless server:
+//!
+//! ```toml
+//! tauri-app = { path = "...", default-features = false }
+//! ```
+
+#[cfg(feature = "gui")]
+mod api_server;
+#[cfg(feature = "gui")]
+mod config;
+mod content;
+mod demo;
 mod diff;
+#[cfg(feature = "gui")]
+mod events;
+mod export;
+mod file_tags;
+mod filetype;
+mod hash;
 mod helpers;
+mod logs;
+mod loudness;
+#[cfg(feature = "gui")]
 mod manager;
+#[cfg(feature = "audio")]
+mod media_session;
+#[cfg(feature = "gui")]
+mod open_with;
+mod perf;
+#[cfg(feature = "gui")]
+mod pool;
 mod query;
+mod recovery;
 mod repo;
+mod repo_config;
+mod repo_lock;
+mod rules;
 mod scan;
+mod similarity;
+mod subtree;
+mod tag_import;
+mod tag_pattern;
 #[cfg(test)]
 mod tests;
 mod tree;
+#[cfg(feature = "watch")]
 pub(crate) mod watch;
+
+#[cfg(feature = "watch")]
+pub use notify::{Event, EventHandler, RecursiveMode, Watcher};
+
+pub use crate::file_tags::{write_tags_to_file, ConflictStrategy, WriteOutcome, WriteTagsReport};
+pub use crate::query::{
+    explain as explain_query, normalize as normalize_query, ParseError, QueryExplanation,
+};
+pub use crate::repo::{
+    Activity, ActivityFilter, ActivityKind, ApplyTemplateError, Collection, CollectionError,
+    ConflictError, CreateError, DirChild, FolderTag, InsertError, InsertTagsError, IntoTags, Item,
+    OpenError, OpenMode, QueryError, RelocateError, Relocation, RelocationMatch, RemoveError,
+    RemoveTagsError, Repo, SearchError, SelectionSummary, SimilarError, SimilarItem, SyncError,
+    SyncReport, TagCount, TagStats, UpdateError,
+};
+pub use crate::repo_config::RepoConfig;
+pub use crate::scan::{scan_dir, Options as ScanOptions, ScanError};
+#[cfg(feature = "watch")]
+pub use crate::watch::{
+    best_for, new_repo_watcher, new_watcher, BestWatcher, EventInjector, InjectableNormWatcher,
+    PairingOptions, PollNormWatcher, RepoWatcher, WatchMethod, WatcherBackend, WatcherFactory,
+};
@@ -0,0 +1,185 @@
+//! Log files written to the app's config folder, so logs are available in release builds where
+//! there is no console.
+//!
+//! Each line of a log file is a JSON object written by `tracing_subscriber`'s JSON formatter. A new
+//! file is started every day, and only the newest `MAX_LOG_FILES` files are kept.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Name of the folder containing log files, inside the app's config folder.
+const LOG_FOLDER_NAME: &str = "logs";
+
+/// Prefix of log file names, the date is appended to this when a new file is started.
+pub(crate) const LOG_FILE_PREFIX: &str = "tagrepo.log";
+
+/// Number of log files kept, older files are deleted on startup.
+pub(crate) const MAX_LOG_FILES: usize = 7;
+
+#[derive(Error, Debug)]
+pub enum LogError {
+    #[error("failed to read log files, {0}")]
+    IOError(#[from] std::io::Error),
+}
+
+/// A single line of a log file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub(crate) timestamp: String,
+    pub(crate) level: String,
+    pub(crate) target: String,
+    /// The event's message and fields, the message is stored under `message`
+    #[serde(default)]
+    pub(crate) fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// The folder to store log files in.
+pub(crate) fn log_dir(config_dir: impl AsRef<Path>) -> PathBuf {
+    config_dir.as_ref().join(LOG_FOLDER_NAME)
+}
+
+/// Paths of all log files in the given folder, from oldest to newest.
+fn log_files(log_dir: &Path) -> Result<Vec<PathBuf>, LogError> {
+    let entries = match fs::read_dir(log_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut paths = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let is_log = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(LOG_FILE_PREFIX);
+        if is_log && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    // dates are appended as YYYY-MM-DD, so sorting by name sorts by date
+    paths.sort();
+    Ok(paths)
+}
+
+/// Delete all but the newest `keep` log files.
+pub(crate) fn prune_old_logs(log_dir: &Path, keep: usize) -> Result<(), LogError> {
+    let paths = log_files(log_dir)?;
+    let old_count = paths.len().saturating_sub(keep);
+    for path in &paths[..old_count] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// The last `n` log entries, from oldest to newest. Lines that can't be parsed are skipped, e.g. a
+/// line that is still being written.
+pub(crate) fn recent_logs(log_dir: &Path, n: usize) -> Result<Vec<LogEntry>, LogError> {
+    let mut entries = vec![];
+    for path in log_files(log_dir)?.iter().rev() {
+        if entries.len() >= n {
+            break;
+        }
+        let text = fs::read_to_string(path)?;
+        let file_entries = text
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<LogEntry>(line).ok())
+            .take(n - entries.len());
+        entries.extend(file_entries);
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn log_line(message: &str) -> String {
+        format!(
+            r#"{{"timestamp":"Jan 01 00:00:00.000","level":"INFO","fields":{{"message":"{}"}},"target":"tagrepo"}}"#,
+            message
+        )
+    }
+
+    fn write_log(dir: &Path, date: &str, messages: &[&str]) {
+        let lines: Vec<_> = messages.iter().map(|x| log_line(x)).collect();
+        let path = dir.join(format!("{}.{}", LOG_FILE_PREFIX, date));
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    fn messages(entries: &[LogEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|x| x.fields["message"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn missing_log_dir_has_no_logs() {
+        let dir = tempdir().unwrap();
+        let logs = recent_logs(&dir.path().join("logs"), 10).unwrap();
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn recent_logs_span_files() {
+        let dir = tempdir().unwrap();
+        write_log(dir.path(), "2023-03-01", &["a", "b", "c"]);
+        write_log(dir.path(), "2023-03-02", &["d", "e"]);
+        fs::write(dir.path().join("unrelated.txt"), "hello").unwrap();
+
+        assert_eq!(messages(&recent_logs(dir.path(), 1).unwrap()), vec!["e"]);
+        assert_eq!(
+            messages(&recent_logs(dir.path(), 3).unwrap()),
+            vec!["c", "d", "e"]
+        );
+        assert_eq!(
+            messages(&recent_logs(dir.path(), 10).unwrap()),
+            vec!["a", "b", "c", "d", "e"]
+        );
+    }
+
+    #[test]
+    fn unparsable_lines_are_skipped() {
+        let dir = tempdir().unwrap();
+        let text = format!(
+            "{}\nnot json\n{}\n{{\"timestamp\":",
+            log_line("a"),
+            log_line("b")
+        );
+        fs::write(
+            dir.path().join(format!("{}.2023-03-01", LOG_FILE_PREFIX)),
+            text,
+        )
+        .unwrap();
+
+        assert_eq!(
+            messages(&recent_logs(dir.path(), 10).unwrap()),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn old_logs_are_pruned() {
+        let dir = tempdir().unwrap();
+        for date in ["2023-03-01", "2023-03-02", "2023-03-03"] {
+            write_log(dir.path(), date, &[date]);
+        }
+        fs::write(dir.path().join("unrelated.txt"), "hello").unwrap();
+
+        prune_old_logs(dir.path(), 2).unwrap();
+        assert_eq!(
+            messages(&recent_logs(dir.path(), 10).unwrap()),
+            vec!["2023-03-02", "2023-03-03"]
+        );
+        assert!(dir.path().join("unrelated.txt").exists());
+    }
+}
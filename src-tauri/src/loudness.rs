@@ -0,0 +1,221 @@
+//! Loudness of audio files, used to play previews of quiet and loud files at a similar volume.
+//!
+//! Loudness is measured like ReplayGain 2 (ITU-R BS.1770): each channel is K-weighted to match
+//! how loud each frequency sounds, then the power of overlapping 400ms blocks is averaged. Blocks
+//! that are silent or much quieter than the rest of the file are ignored, so pauses don't make a
+//! file seem quieter.
+
+use std::f64::consts::PI;
+#[cfg(feature = "audio")]
+use std::path::Path;
+#[cfg(feature = "audio")]
+use std::time::Duration;
+
+/// Previews are scaled to this loudness, the reference level of ReplayGain 2.
+pub(crate) const TARGET_LUFS: f32 = -18.0;
+
+/// Length of a block, and the step between blocks, in seconds.
+const BLOCK_LEN: f64 = 0.4;
+const BLOCK_STEP: f64 = 0.1;
+
+/// Blocks quieter than this are ignored.
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Blocks this much quieter than the average of the other blocks are ignored.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Only the start of a file is decoded, like `similarity::fingerprint_file()`.
+#[cfg(feature = "audio")]
+const MAX_DURATION: Duration = Duration::from_secs(30);
+
+/// The loudness and peak of a sound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, a full scale 1kHz sine wave is about -3 LUFS.
+    pub(crate) lufs: f32,
+    /// Largest absolute sample, between 0 and 1.
+    pub(crate) peak: f32,
+}
+
+impl Loudness {
+    /// Volume to play the sound at so it is as loud as `target_lufs`. The sound is only made
+    /// louder as long as it won't clip.
+    pub(crate) fn gain(&self, target_lufs: f32) -> f32 {
+        let gain = 10f32.powf((target_lufs - self.lufs) / 20.0);
+        if self.peak > 0.0 {
+            gain.min((1.0 / self.peak).max(1.0))
+        } else {
+            gain
+        }
+    }
+}
+
+/// A second-order IIR filter.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// The two filters of the K-weighting curve, a high shelf then a high pass. BS.1770 only gives
+/// coefficients for 48kHz, so they are derived from the analog filters for other sample rates.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Loudness of a block from its mean square.
+fn block_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Measure the loudness of interleaved samples between -1 and 1. Returns `None` if the sound is
+/// silent. Sounds shorter than a block are measured as a single block.
+pub(crate) fn loudness(samples: &[f32], channels: u16, sample_rate: u32) -> Option<Loudness> {
+    let channels = channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return None;
+    }
+    let peak = samples.iter().fold(0f32, |peak, x| peak.max(x.abs()));
+
+    // sum of the weighted power of all channels, for each step between blocks
+    let step_len = ((sample_rate as f64 * BLOCK_STEP) as usize).max(1);
+    let mut steps = vec![0.0; (frames + step_len - 1) / step_len];
+    for channel in 0..channels {
+        let mut filters = k_weighting(sample_rate);
+        for (i, x) in samples.iter().skip(channel).step_by(channels).enumerate() {
+            let y = filters.iter_mut().fold(*x as f64, |x, f| f.process(x));
+            steps[i / step_len] += y * y;
+        }
+    }
+
+    let steps_per_block = (BLOCK_LEN / BLOCK_STEP).round() as usize;
+    let blocks: Vec<f64> = if steps.len() < steps_per_block {
+        vec![steps.iter().sum::<f64>() / frames as f64]
+    } else {
+        steps
+            .windows(steps_per_block)
+            .map(|x| x.iter().sum::<f64>() / (steps_per_block * step_len) as f64)
+            .collect()
+    };
+
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+    let blocks: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&x| x > 0.0 && block_lufs(x) > ABSOLUTE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+    let threshold = block_lufs(mean(&blocks)) + RELATIVE_GATE;
+    let blocks: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&x| block_lufs(x) > threshold)
+        .collect();
+    Some(Loudness { lufs: block_lufs(mean(&blocks)) as f32, peak })
+}
+
+/// Decode an audio file and measure its loudness. Returns `None` if the file can't be decoded or
+/// is silent.
+#[cfg(feature = "audio")]
+pub(crate) fn loudness_file(path: impl AsRef<Path>) -> std::io::Result<Option<Loudness>> {
+    use rodio::{Decoder, Source};
+
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let Ok(source) = Decoder::new(file) else {
+        return Ok(None);
+    };
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let samples: Vec<f32> = source
+        .take_duration(MAX_DURATION)
+        .map(|x: i16| x as f32 / i16::MAX as f32)
+        .collect();
+    Ok(loudness(&samples, channels, sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(freq: f64, amplitude: f32, seconds: f64) -> Vec<f32> {
+        let len = (SAMPLE_RATE as f64 * seconds) as usize;
+        (0..len)
+            .map(|i| amplitude * (2.0 * PI * freq * i as f64 / SAMPLE_RATE as f64).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn sine_has_reference_loudness() {
+        // BS.1770 defines a full scale 1kHz sine in one channel as -3.01 LUFS
+        let rv = loudness(&sine(1000.0, 1.0, 2.0), 1, SAMPLE_RATE).unwrap();
+        assert!((rv.lufs - -3.01).abs() < 0.1, "{}", rv.lufs);
+        assert!((rv.peak - 1.0).abs() < 1e-3);
+
+        let quiet = loudness(&sine(1000.0, 0.1, 2.0), 1, SAMPLE_RATE).unwrap();
+        assert!((quiet.lufs - -23.01).abs() < 0.1, "{}", quiet.lufs);
+        // a short one-shot is still measured
+        let short = loudness(&sine(1000.0, 1.0, 0.2), 1, SAMPLE_RATE).unwrap();
+        assert!((short.lufs - -3.01).abs() < 0.5, "{}", short.lufs);
+    }
+
+    #[test]
+    fn silence_is_ignored() {
+        assert_eq!(loudness(&[0.0; 48000], 1, SAMPLE_RATE), None);
+        assert_eq!(loudness(&[], 2, SAMPLE_RATE), None);
+
+        // a long pause doesn't make the sound quieter
+        let mut samples = sine(1000.0, 1.0, 3.0);
+        samples.extend(vec![0.0; SAMPLE_RATE as usize * 3]);
+        let rv = loudness(&samples, 1, SAMPLE_RATE).unwrap();
+        assert!((rv.lufs - -3.01).abs() < 0.5, "{}", rv.lufs);
+    }
+
+    #[test]
+    fn gain_does_not_clip() {
+        let loud = Loudness { lufs: -6.0, peak: 1.0 };
+        assert!((loud.gain(-18.0) - 0.251).abs() < 1e-3);
+        let quiet = Loudness { lufs: -38.0, peak: 0.5 };
+        assert_eq!(quiet.gain(-18.0), 2.0);
+    }
+}
@@ -1,34 +1,97 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use normpath::PathExt;
+use relative_path::{RelativePath, RelativePathBuf};
 
 use rodio::{Decoder, OutputStream, PlayError, Sink, Source, StreamError};
-use serde::{Serialize, Serializer};
-use tauri::{AppHandle, Manager, PhysicalSize, Wry};
+use serde::{Deserialize, Serialize, Serializer};
+use tauri::{
+    AppHandle, ClipboardManager, GlobalShortcutManager, Manager, PhysicalPosition, PhysicalSize, Runtime, Window, WindowBuilder,
+    WindowEvent, WindowUrl, Wry,
+};
 use thiserror::Error;
 use tokio::sync::{Mutex, RwLock};
-use tokio::time::sleep;
-use tracing::{error, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{error, info};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
 use window_shadows::{set_shadow, Error};
 
-use crate::manager::{FileType, ItemDetails, ManagerStatus, RepoManager};
-use crate::repo::{DirStructureError, QueryError, Repo, SearchError};
+use crate::api_server::{ApiServer, ApiServerError, SharedManager};
+use crate::config::{
+    AppConfig, AppConfigError, AudioPreviewConfig, PreviewShortcuts, RecentRepo, WindowGeometry,
+};
+use crate::demo::DemoError;
+use crate::events::{EmitExt, EventStream};
+use crate::export::{ExportError, ExportFormat};
+use crate::file_tags::{ConflictStrategy, WriteTagsReport};
+use crate::filetype::FileType;
+use crate::helpers::path::{ensure_inside_repo, PathOutsideRepo};
+use crate::logs::{LogEntry, LogError};
+use crate::loudness::TARGET_LUFS;
+use crate::manager::{
+    ChangeDetails, ConfirmSyncError, ItemDetails, ManagerStatus, Preview, PreviewError,
+    RepoManager, UnwatchError, WatchError, WriteTagsError,
+};
+use crate::media_session::{MediaCommand, MediaSession};
+use crate::meter::{LevelMeter, Levels, Metered};
+use crate::open_with::{OpenWithApp, OpenWithError};
+use crate::perf::PerfReport;
+use crate::query::{ParseError, QueryExplanation};
+use crate::repo::{
+    Activity, ActivityFilter, Collection, CreateError, DirChild, DirStructureError, FolderTag,
+    Item, MaintenanceError, OpenMode, QueryError, Relocation, Repo, SearchError, SelectionSummary,
+    SimilarError, SimilarItem, SyncDelta, SyncError, TagCount, TagStats,
+};
+use crate::repo_config::{RepoConfig, RepoConfigError};
+use crate::rules::{Condition, Rule, RuleMatch};
+use crate::subtree::{SubtreeError, SubtreeReport};
+use crate::tag_import::{CsvImportError, CsvImportOptions, CsvImportReport};
+use crate::tag_pattern::{PatternMatch, TagPatternError};
 use crate::tree::FolderBuf;
+use crate::watch::{PairingStats, WatcherBackend};
 
+mod api_server;
+mod config;
+mod content;
+mod demo;
 mod diff;
+mod events;
+mod export;
+mod file_tags;
+mod filetype;
+mod hash;
 mod helpers;
+mod logs;
+mod loudness;
 mod manager;
+mod media_session;
+mod meter;
+mod open_with;
+mod perf;
+mod pool;
 mod query;
+mod recovery;
 mod repo;
+mod repo_config;
+mod repo_lock;
+mod rules;
 mod scan;
+mod similarity;
+mod subtree;
+mod tag_import;
+mod tag_pattern;
 #[cfg(test)]
 mod tests;
 mod tree;
@@ -51,35 +114,297 @@ fn get_output_stream_and_sink() -> Result<(OutputStream, Sink), CreateAudioOutpu
 }
 
 struct AppState {
-    repo: Mutex<Option<Repo>>,
-    manager: RwLock<Option<RepoManager<Wry>>>,
+    manager: SharedManager<Wry>,
     // a wrapper around the audio stream? if this is dropped then audio will stop
     output_sink: Option<Sink>,
+    /// Path and gain of the last previewed file, for the replay shortcut
+    last_preview: std::sync::Mutex<Option<(String, f32)>>,
+    /// Incremented whenever a preview starts or stops, so a preview is only stopped by its own
+    /// timer
+    preview_seq: AtomicU64,
+    /// Levels of the audio preview that is playing
+    preview_meter: LevelMeter,
 }
 
 impl AppState {
     fn new(output_sink: Option<Sink>) -> Self {
         Self {
-            repo: Mutex::new(None),
-            manager: RwLock::new(None),
+            manager: Arc::new(RwLock::new(None)),
             output_sink,
+            last_preview: std::sync::Mutex::new(None),
+            preview_seq: AtomicU64::new(0),
+            preview_meter: LevelMeter::default(),
         }
     }
 }
 
-// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+/// Settings that apply to the app as a whole, loaded when the app starts.
+struct AppConfigState {
+    /// The app's config folder, where the config is saved
+    dir: PathBuf,
+    config: std::sync::RwLock<AppConfig>,
+    /// The API server, if it is enabled
+    api_server: Mutex<Option<ApiServer>>,
 }
 
-#[tauri::command]
-async fn temp() {
-    println!("Sleeping 3 seconds...");
-    sleep(Duration::from_secs(3)).await;
-    println!("Woke up!");
+/// Change the app config, save it, then tell every window about the new config.
+fn update_app_config<R: Runtime, T>(
+    manager: &impl Manager<R>,
+    f: impl FnOnce(&mut AppConfig) -> T,
+) -> Result<T, AppConfigError> {
+    let config_state = manager.state::<AppConfigState>();
+    let (rv, config) = {
+        let mut config = config_state.config.write().unwrap();
+        let rv = f(&mut config);
+        config.save(&config_state.dir)?;
+        (rv, config.clone())
+    };
+    emit_config_changed(manager, config);
+    Ok(rv)
+}
+
+/// Send the new app config to every window, so windows other than the one that changed it stay
+/// in sync. This isn't forwarded to the API server's event stream, since the config contains the
+/// server's token.
+fn emit_config_changed<R: Runtime>(manager: &impl Manager<R>, config: AppConfig) {
+    manager
+        .emit_all("config-changed", config)
+        .expect("Failed to emit event");
+}
+
+/// Start the API server if it is enabled in the app config.
+fn start_api_server(
+    config: &AppConfig,
+    manager: &SharedManager<Wry>,
+    events: &EventStream,
+) -> Result<Option<ApiServer>, ApiServerError> {
+    if !config.api_server.enabled {
+        return Ok(None);
+    }
+    let server = ApiServer::start(&config.api_server, manager.clone(), events.clone())?;
+    info!("API server listening on {}", server.addr());
+    Ok(Some(server))
+}
+
+/// The OS media session used to control audio previews, if it could be created.
+struct MediaSessionState(Option<MediaSession>);
+
+/// Handle of the window, used to create the media session on Windows.
+#[cfg(windows)]
+fn window_handle(window: &tauri::Window) -> Option<*mut c_void> {
+    window.hwnd().ok().map(|hwnd| hwnd.0 as *mut c_void)
+}
+
+#[cfg(not(windows))]
+fn window_handle(_window: &tauri::Window) -> Option<*mut c_void> {
+    None
+}
+
+/// Size of new windows, in logical pixels.
+const DEFAULT_WINDOW_SIZE: (f64, f64) = (950.0, 650.0);
+
+/// Create a window with the app's frontend. Windows are created here rather than in
+/// `tauri.conf.json`, so the theme from the app config is available before the page loads.
+fn build_window<R: Runtime>(manager: &impl Manager<R>, label: &str) -> tauri::Result<Window<R>> {
+    let script = manager
+        .state::<AppConfigState>()
+        .config
+        .read()
+        .unwrap()
+        .theme
+        .initialization_script();
+    let (width, height) = DEFAULT_WINDOW_SIZE;
+    WindowBuilder::new(manager, label, WindowUrl::App("index.html".into()))
+        .title("tag-repo")
+        .decorations(false)
+        .inner_size(width, height)
+        .initialization_script(&script)
+        .build()
+}
+
+/// Setup that applies to every window. The window's size and position are restored from the app
+/// config, and saved again when the window is closed.
+fn setup_window(window: &Window) {
+    window
+        .set_min_size(Some(PhysicalSize { width: 400, height: 270 }))
+        .expect("failed to set min size of window");
+    match set_shadow(window, true) {
+        Ok(_) => {}
+        Err(err) => {
+            error!("failed to set window shadows, unsupported system. {}", err);
+        }
+    }
+    if let Err(err) = restore_window_geometry(window) {
+        error!("failed to restore size of window. {}", err);
+    }
+    let app_handle = window.app_handle();
+    let label = window.label().to_string();
+    window.on_window_event(move |event| {
+        if let WindowEvent::CloseRequested { .. } = event {
+            if let Some(window) = app_handle.get_window(&label) {
+                save_window_geometry(&window);
+            }
+        }
+    });
+}
+
+fn restore_window_geometry(window: &Window) -> tauri::Result<()> {
+    let config_state = window.state::<AppConfigState>();
+    let config = config_state.config.read().unwrap();
+    let Some(geometry) = config.windows.get(window.label()) else {
+        return Ok(());
+    };
+    window.set_position(PhysicalPosition { x: geometry.x, y: geometry.y })?;
+    window.set_size(PhysicalSize { width: geometry.width, height: geometry.height })?;
+    if geometry.maximized {
+        window.maximize()?;
+    }
+    Ok(())
+}
+
+fn window_geometry(window: &Window) -> tauri::Result<WindowGeometry> {
+    let position = window.outer_position()?;
+    let size = window.inner_size()?;
+    Ok(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: window.is_maximized()?,
+    })
+}
+
+fn save_window_geometry(window: &Window) {
+    let geometry = match window_geometry(window) {
+        Ok(geometry) => geometry,
+        Err(err) => {
+            error!("failed to get size of window. {}", err);
+            return;
+        }
+    };
+    let label = window.label().to_string();
+    let rv = update_app_config(window, |config| {
+        config.windows.insert(label, geometry);
+    });
+    if let Err(err) = rv {
+        error!("failed to save size of window. {}", err);
+    }
+}
+
+/// Apply a media key press to the audio preview.
+fn handle_media_command(app_handle: &AppHandle, command: MediaCommand) {
+    let state = app_handle.state::<AppState>();
+    let Some(sink) = &state.output_sink else {
+        return;
+    };
+    match command {
+        MediaCommand::Play => sink.play(),
+        MediaCommand::Pause => sink.pause(),
+        MediaCommand::Toggle if sink.is_paused() => sink.play(),
+        MediaCommand::Toggle => sink.pause(),
+        MediaCommand::Stop => sink.stop(),
+        // the frontend chooses which item to preview next
+        MediaCommand::Next | MediaCommand::Previous => {
+            app_handle
+                .emit_all_and_forward("media-key-pressed", command)
+                .expect("Failed to emit event");
+            return;
+        }
+    }
+    // the session may not be managed yet if a key is pressed while the app is starting
+    let Some(media) = app_handle.try_state::<MediaSessionState>() else {
+        return;
+    };
+    if let Some(session) = &media.0 {
+        if command == MediaCommand::Stop {
+            session.set_stopped();
+        } else {
+            session.set_playing(!sink.is_paused());
+        }
+    }
+}
+
+/// Amount the volume changes each time a volume shortcut is pressed.
+const VOLUME_STEP: f32 = 0.05;
+
+/// An action triggered by one of the global shortcuts in `PreviewShortcuts`.
+#[derive(Debug, Clone, Copy)]
+enum PreviewAction {
+    Stop,
+    Replay,
+    VolumeUp,
+    VolumeDown,
+}
+
+#[derive(Error, Debug)]
+enum ShortcutError {
+    #[error("failed to register shortcut {0}, {1}")]
+    RegisterError(String, tauri::Error),
+    #[error("failed to unregister shortcuts, {0}")]
+    UnregisterError(#[from] tauri::Error),
+}
+
+/// Register the global shortcuts for audio previews, replacing the ones registered before. If a
+/// shortcut fails to register, the other shortcuts are still registered.
+fn register_preview_shortcuts(
+    app_handle: &AppHandle,
+    shortcuts: &PreviewShortcuts,
+) -> Result<(), ShortcutError> {
+    let mut manager = app_handle.global_shortcut_manager();
+    manager.unregister_all()?;
+    let actions = [
+        (&shortcuts.stop, PreviewAction::Stop),
+        (&shortcuts.replay, PreviewAction::Replay),
+        (&shortcuts.volume_up, PreviewAction::VolumeUp),
+        (&shortcuts.volume_down, PreviewAction::VolumeDown),
+    ];
+    let mut rv = Ok(());
+    for (accelerator, action) in actions {
+        let Some(accelerator) = accelerator else {
+            continue;
+        };
+        let handle = app_handle.clone();
+        let result = manager.register(accelerator, move || handle_preview_action(&handle, action));
+        if let Err(err) = result {
+            error!("failed to register shortcut {}, {}", accelerator, err);
+            if rv.is_ok() {
+                rv = Err(ShortcutError::RegisterError(accelerator.clone(), err));
+            }
+        }
+    }
+    rv
+}
+
+/// Apply a global shortcut press to the audio preview.
+fn handle_preview_action(app_handle: &AppHandle, action: PreviewAction) {
+    let state = app_handle.state::<AppState>();
+    let Some(sink) = &state.output_sink else {
+        return;
+    };
+    match action {
+        PreviewAction::Stop => stop_preview(app_handle, sink),
+        PreviewAction::Replay => {
+            let Some((path, gain)) = state.last_preview.lock().unwrap().clone() else {
+                return;
+            };
+            // the error is already logged
+            let _ = play_preview(app_handle, sink, &path, None, gain);
+        }
+        PreviewAction::VolumeUp | PreviewAction::VolumeDown => {
+            let step = match action {
+                PreviewAction::VolumeUp => VOLUME_STEP,
+                _ => -VOLUME_STEP,
+            };
+            let volume = (sink.volume() + step).clamp(0.0, 1.0);
+            sink.set_volume(volume);
+            app_handle
+                .emit_all_and_forward("preview-volume-changed", volume)
+                .expect("Failed to emit event");
+        }
+    }
 }
 
+// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
 async fn current_path(state: tauri::State<'_, AppState>) -> Result<Option<PathBuf>, ()> {
     // async commands that use state MUST return a Result:
@@ -96,7 +421,23 @@ async fn open_repo(
     state: tauri::State<'_, AppState>,
     app_handle: AppHandle<Wry>,
     path: &str,
+    read_only: Option<bool>,
 ) -> Result<(), String> {
+    let _perf = perf::measure("open_repo");
+    let mode = match read_only {
+        Some(true) => OpenMode::ReadOnly,
+        _ => OpenMode::ReadWrite,
+    };
+
+    // a recent repo that was moved or renamed since it was last opened, the frontend can ask the
+    // user where it is and call `relocate_repo`
+    if !Path::new(path).is_dir() && is_recent_repo(&app_handle, path) {
+        app_handle
+            .emit_all_and_forward("repo-missing", PathBuf::from(path))
+            .expect("Failed to emit event");
+        return Err(format!("repo no longer exists, {}", path));
+    }
+
     // discard the existing connection first
     {
         let mut opt = state.manager.write().await;
@@ -104,11 +445,12 @@ async fn open_repo(
     }
 
     app_handle
-        .emit_all("repo-path-changed", None::<PathBuf>)
+        .emit_all_and_forward("repo-path-changed", None::<PathBuf>)
         .expect("Failed to emit event");
 
     // then open the repo
-    let manager = RepoManager::new(&path, app_handle.clone()).map_err(|x| x.to_string())?;
+    let manager =
+        RepoManager::new(&path, mode, app_handle.clone()).map_err(|x| x.to_string())?;
 
     // assign manager to state NOW, to let #current_status() check the manager's status
     {
@@ -117,9 +459,13 @@ async fn open_repo(
     }
 
     app_handle
-        .emit_all("repo-path-changed", Some(PathBuf::from(path)))
+        .emit_all_and_forward("repo-path-changed", Some(PathBuf::from(path)))
         .expect("Failed to emit event");
 
+    if let Err(err) = update_app_config(&app_handle, |config| config.add_recent_repo(path)) {
+        error!("failed to add repo to recent repos. {}", err);
+    }
+
     // now try to resync the manager
     let rv = {
         let manager = state.manager.read().await;
@@ -128,8 +474,27 @@ async fn open_repo(
                 "race condition occurred! manager was deleted between this and the previous lock"
             ));
         };
-        manager.watch().await.unwrap();
-        manager.resync().await.map_err(|x| x.to_string())
+        // other programs may change shared repos, so this runs for read-only repos too
+        manager.start_change_feed().await;
+        if manager.read_only() {
+            // read-only repos can't be updated, just show the items as they are
+            Ok(())
+        } else {
+            // e.g. network drives may not support watching, they can be resynced periodically
+            // with `RepoConfig::auto_resync_interval` instead
+            if let Err(err) = manager.watch().await {
+                error!("failed to watch repo, changes won't be detected. {}", err);
+            }
+            let rv = manager.resync().await.map_err(|x| x.to_string());
+            if rv.is_ok() {
+                manager.start_consistency_check().await;
+                manager.start_auto_resync().await;
+                manager.start_hashing().await;
+                manager.start_fingerprinting().await;
+                manager.start_extraction().await;
+            }
+            rv.map(|_| ())
+        }
     };
 
     // if resyncing failed, discard the manager
@@ -138,14 +503,14 @@ async fn open_repo(
         Ok(_) => {
             // resync ok, emit event
             app_handle
-                .emit_all("repo-resynced", Some(PathBuf::from(path)))
+                .emit_all_and_forward("repo-resynced", Some(PathBuf::from(path)))
                 .expect("Failed to emit event");
         }
         Err(err) => {
             // error occurred, discard the manager from the app state
             let mut opt = state.manager.write().await;
             app_handle
-                .emit_all("repo-path-changed", None::<PathBuf>)
+                .emit_all_and_forward("repo-path-changed", None::<PathBuf>)
                 .expect("Failed to emit event");
             *opt = None;
             return Err(err);
@@ -155,6 +520,120 @@ async fn open_repo(
     Ok(())
 }
 
+/// Options for `create_repo`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CreateRepoOptions {
+    /// Config of the new repo, e.g. which paths are excluded from scans
+    config: RepoConfig,
+    /// Open the repo after creating it, which scans the folder
+    scan: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRepoSummary {
+    path: PathBuf,
+    /// Whether the repo was opened and scanned
+    scanned: bool,
+    /// Number of items found by the scan
+    item_count: i64,
+}
+
+#[derive(Error, Debug)]
+enum CreateRepoError {
+    #[error("failed to create repo, {0}")]
+    CreateError(#[from] CreateError),
+    #[error("failed to open new repo, {0}")]
+    OpenError(String),
+    #[error("failed to count items, {0}")]
+    QueryError(#[from] QueryError),
+    #[error("new repo was closed while it was being scanned")]
+    RepoClosed,
+}
+
+impl_serialize_to_string!(CreateRepoError);
+
+/// Whether a path is in the recent repos list.
+fn is_recent_repo(app_handle: &AppHandle, path: impl AsRef<Path>) -> bool {
+    let config_state = app_handle.state::<AppConfigState>();
+    let config = config_state.config.read().unwrap();
+    config.recent_repos.iter().any(|x| x.path == path.as_ref())
+}
+
+/// Open a repo that was moved or renamed, see the `repo-missing` event. The new path replaces the
+/// old one in the recent repos list, so the repo stays pinned.
+#[tauri::command]
+async fn relocate_repo(
+    state: tauri::State<'_, AppState>,
+    app_handle: AppHandle<Wry>,
+    old_path: PathBuf,
+    new_path: String,
+) -> Result<(), String> {
+    // if the new path isn't a repo, the old path is left in the list
+    open_repo(state, app_handle.clone(), &new_path, None).await?;
+    let rv = update_app_config(&app_handle, |config| {
+        config.relocate_recent_repo(&old_path, &new_path)
+    });
+    if let Err(err) = rv {
+        error!("failed to relocate repo in recent repos. {}", err);
+    }
+    Ok(())
+}
+
+/// Turn a folder into a new repo. Unlike `open_repo`, this fails if the folder is already a repo.
+#[tauri::command]
+async fn create_repo(
+    state: tauri::State<'_, AppState>,
+    app_handle: AppHandle<Wry>,
+    path: String,
+    options: CreateRepoOptions,
+) -> Result<CreateRepoSummary, CreateRepoError> {
+    // close the new repo, so it can be opened by a manager
+    drop(Repo::create(&path, &options.config)?);
+    if !options.scan {
+        return Ok(CreateRepoSummary { path: path.into(), scanned: false, item_count: 0 });
+    }
+
+    // this emits "scan-progress" events while the folder is being scanned
+    open_repo(state.clone(), app_handle, &path, None)
+        .await
+        .map_err(CreateRepoError::OpenError)?;
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CreateRepoError::RepoClosed);
+    };
+    let item_count = manager.count("").await?;
+    Ok(CreateRepoSummary { path: path.into(), scanned: true, item_count })
+}
+
+#[derive(Error, Debug)]
+enum CreateDemoRepoError {
+    #[error("{0}")]
+    DemoError(#[from] DemoError),
+    #[error("failed to open demo repo, {0}")]
+    OpenError(String),
+}
+
+impl_serialize_to_string!(CreateDemoRepoError);
+
+/// Create a small repo of silent audio files and notes with tags in an empty folder, then open
+/// it, so new users can try the app without opening their own library.
+#[tauri::command]
+async fn create_demo_repo(
+    state: tauri::State<'_, AppState>,
+    app_handle: AppHandle<Wry>,
+    path: String,
+) -> Result<(), CreateDemoRepoError> {
+    let repo_path = PathBuf::from(&path);
+    // close the new repo, so it can be opened by a manager
+    tokio::task::spawn_blocking(move || demo::create_demo_repo(repo_path).map(drop))
+        .await
+        .expect("failed to join with thread that's creating the demo repo")?;
+    open_repo(state, app_handle, &path, None)
+        .await
+        .map_err(CreateDemoRepoError::OpenError)
+}
+
 #[tauri::command]
 async fn close_repo(state: tauri::State<'_, AppState>) -> Result<(), ()> {
     let mut opt = state.manager.write().await;
@@ -171,6 +650,36 @@ async fn current_status(state: tauri::State<'_, AppState>) -> Result<Option<Mana
     Ok(Some(manager.status().await))
 }
 
+/// Counters of how the watcher paired deleted and created files into renames.
+#[tauri::command]
+async fn get_watcher_stats(
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<PairingStats>, ()> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Ok(None);
+    };
+    Ok(Some(manager.watcher_stats()))
+}
+
+/// The kind of watcher used for the open repo.
+#[tauri::command]
+async fn get_watcher_backend(
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<WatcherBackend>, ()> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Ok(None);
+    };
+    Ok(manager.watcher_backend().await)
+}
+
+/// Timings of slow operations, only recorded when built with the `profiling` feature.
+#[tauri::command]
+fn get_perf_report() -> PerfReport {
+    perf::report()
+}
+
 macro_rules! impl_serialize_to_string {
     ($t:ty) => {
         impl Serialize for $t {
@@ -184,126 +693,1085 @@ macro_rules! impl_serialize_to_string {
     };
 }
 
-#[derive(Error, Debug)]
-enum GetItemError {
-    #[error("no active repo")]
-    NoOpenRepo,
-    #[error("no item with given id found")]
-    SearchError(#[from] SearchError),
+#[derive(Error, Debug)]
+enum GetItemError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("no item with given id found")]
+    SearchError(#[from] SearchError),
+}
+
+impl_serialize_to_string!(GetItemError);
+
+#[tauri::command]
+async fn get_item_details(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<ItemDetails, GetItemError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetItemError::NoOpenRepo);
+    };
+    let item = manager.get_item_details(id).await?;
+    Ok(item)
+}
+
+#[tauri::command]
+async fn get_items_details(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+) -> Result<Vec<ItemDetails>, GetItemError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetItemError::NoOpenRepo);
+    };
+    let items = manager.get_items_details(ids).await?;
+    Ok(items)
+}
+
+#[derive(Error, Debug)]
+enum WriteTagsToFilesError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to write tags to files, {0}")]
+    WriteTagsError(#[from] WriteTagsError),
+}
+
+impl_serialize_to_string!(WriteTagsToFilesError);
+
+/// Write the tags of items into the comments of their audio files. With `dry_run`, files aren't
+/// changed, but the report shows what would be written.
+#[tauri::command]
+async fn write_tags_to_files(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+    strategy: ConflictStrategy,
+    dry_run: bool,
+) -> Result<Vec<WriteTagsReport>, WriteTagsToFilesError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(WriteTagsToFilesError::NoOpenRepo);
+    };
+    Ok(manager.write_tags_to_files(ids, strategy, dry_run).await?)
+}
+
+#[derive(Error, Debug)]
+enum FindSimilarError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to find similar items, {0}")]
+    SimilarError(#[from] SimilarError),
+}
+
+impl_serialize_to_string!(FindSimilarError);
+
+/// Items whose files sound most like an item's file, most similar first.
+#[tauri::command]
+async fn find_similar(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    limit: usize,
+) -> Result<Vec<SimilarItem>, FindSimilarError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(FindSimilarError::NoOpenRepo);
+    };
+    Ok(manager.find_similar(id, limit).await?)
+}
+
+#[derive(Error, Debug)]
+enum GetChangesError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to fetch changes, {0}")]
+    BackendError(#[from] rusqlite::Error),
+}
+
+impl_serialize_to_string!(GetChangesError);
+
+/// Items that have changed after the change `seq`, used by the frontend to catch up after a
+/// `repo-changed` event. If `seq` is null, only the current change sequence number is returned.
+#[tauri::command]
+async fn get_changes_since(
+    state: tauri::State<'_, AppState>,
+    seq: Option<i64>,
+) -> Result<ChangeDetails, GetChangesError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetChangesError::NoOpenRepo);
+    };
+    let changes = manager.get_changes_since(seq).await?;
+    Ok(changes)
+}
+
+#[derive(Error, Debug)]
+enum GetActivityError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to fetch activity, {0}")]
+    BackendError(#[from] rusqlite::Error),
+}
+
+impl_serialize_to_string!(GetActivityError);
+
+/// The latest files created, deleted or renamed while the repo was being watched, newest first.
+#[tauri::command]
+async fn get_activity(
+    state: tauri::State<'_, AppState>,
+    limit: usize,
+    filter: Option<ActivityFilter>,
+) -> Result<Vec<Activity>, GetActivityError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetActivityError::NoOpenRepo);
+    };
+    Ok(manager.activity(limit, filter.unwrap_or_default()).await?)
+}
+
+#[derive(Error, Debug)]
+enum WatchPathError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("{0}")]
+    WatchError(#[from] WatchError),
+    #[error("{0}")]
+    UnwatchError(#[from] UnwatchError),
+}
+
+impl_serialize_to_string!(WatchPathError);
+
+/// Watch an additional folder inside the repo, see `RepoManager::watch_path()`.
+#[tauri::command]
+async fn watch_path(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    recursive: bool,
+) -> Result<(), WatchPathError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(WatchPathError::NoOpenRepo);
+    };
+    manager.watch_path(path, recursive).await?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn unwatch_path(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<(), WatchPathError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(WatchPathError::NoOpenRepo);
+    };
+    manager.unwatch_path(path).await?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum ResyncFolderError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to sync folder, {0}")]
+    SyncError(#[from] SyncError),
+}
+
+impl_serialize_to_string!(ResyncFolderError);
+
+/// Rescan a folder of the repo and its subfolders. `path` is relative to the repo root.
+#[tauri::command]
+async fn resync_folder(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<SyncDelta, ResyncFolderError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ResyncFolderError::NoOpenRepo);
+    };
+    Ok(manager.resync_subtree(RelativePathBuf::from(path)).await?)
+}
+
+#[derive(Error, Debug)]
+enum CopyToClipboardError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("no item with given id found")]
+    SearchError(#[from] SearchError),
+    #[error("failed to write to clipboard, {0}")]
+    ClipboardError(#[from] tauri::Error),
+}
+
+impl_serialize_to_string!(CopyToClipboardError);
+
+/// Line ending used when copying multiple lines to the clipboard.
+const CLIPBOARD_LINE_ENDING: &str = if cfg!(windows) { "\r\n" } else { "\n" };
+
+/// Copy the paths of the given items to the clipboard, one path per line. If `absolute` is false,
+/// paths are relative to the repo root.
+#[tauri::command]
+async fn copy_paths_to_clipboard(
+    state: tauri::State<'_, AppState>,
+    app_handle: AppHandle<Wry>,
+    ids: Vec<i64>,
+    absolute: bool,
+) -> Result<(), CopyToClipboardError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CopyToClipboardError::NoOpenRepo);
+    };
+    let items = manager.get_items(ids).await?;
+    let paths: Vec<String> = items
+        .into_iter()
+        .map(|item| match absolute {
+            true => RelativePath::new(&item.path)
+                .to_path(manager.path())
+                .display()
+                .to_string(),
+            false => item.path,
+        })
+        .collect();
+    app_handle
+        .clipboard_manager()
+        .write_text(paths.join(CLIPBOARD_LINE_ENDING))?;
+    Ok(())
+}
+
+/// Copy the tags of the given items to the clipboard, with the tags of each item on a separate
+/// line.
+#[tauri::command]
+async fn copy_tags_to_clipboard(
+    state: tauri::State<'_, AppState>,
+    app_handle: AppHandle<Wry>,
+    ids: Vec<i64>,
+) -> Result<(), CopyToClipboardError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CopyToClipboardError::NoOpenRepo);
+    };
+    let items = manager.get_items(ids).await?;
+    let tags: Vec<String> = items.into_iter().map(|item| item.tags.join(" ")).collect();
+    app_handle
+        .clipboard_manager()
+        .write_text(tags.join(CLIPBOARD_LINE_ENDING))?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum QueryItemIdsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to query items, {0}")]
+    QueryError(#[from] QueryError),
+}
+
+// invalid queries are serialized as a structured `ParseError`, so the frontend can highlight the
+// offending part of the query
+impl Serialize for QueryItemIdsError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            QueryItemIdsError::QueryError(QueryError::InvalidQuery(err)) => {
+                err.serialize(serializer)
+            }
+            _ => serializer.serialize_str(self.to_string().as_str()),
+        }
+    }
+}
+
+/// Ids of items matching the query. If `scope_dir` is given, e.g. the folder selected in the
+/// folder tree, only items inside it are returned.
+#[tauri::command]
+async fn query_item_ids(
+    state: tauri::State<'_, AppState>,
+    query: String,
+    scope_dir: Option<String>,
+) -> Result<Vec<i64>, QueryItemIdsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(QueryItemIdsError::NoOpenRepo);
+    };
+    let item_ids = manager.query(query.as_str(), scope_dir).await?;
+    Ok(item_ids)
+}
+
+/// Like `query_item_ids`, but ids are sent in `query-results-chunk` events as they are read.
+/// Returns the stream id, which is included in every event of the stream.
+#[tauri::command]
+async fn query_item_ids_streamed(
+    state: tauri::State<'_, AppState>,
+    query: String,
+    scope_dir: Option<String>,
+    chunk_size: usize,
+) -> Result<u64, QueryItemIdsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(QueryItemIdsError::NoOpenRepo);
+    };
+    Ok(manager.query_streamed(query, scope_dir, chunk_size)?)
+}
+
+/// Stop a streamed query, returns false if it has already finished.
+#[tauri::command]
+async fn cancel_query_stream(
+    state: tauri::State<'_, AppState>,
+    stream_id: u64,
+) -> Result<bool, QueryItemIdsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(QueryItemIdsError::NoOpenRepo);
+    };
+    Ok(manager.cancel_query_stream(stream_id))
+}
+
+/// Check the syntax of a query without running it, returns the query in a normalized form. Like
+/// `query_item_ids`, invalid queries fail with a structured `ParseError`. No repo needs to be open.
+#[tauri::command]
+fn validate_query(query: String) -> Result<String, ParseError> {
+    query::normalize(&query)
+}
+
+/// The parsed and simplified forms of a query and the SQL it's converted to, for debugging queries
+/// and bug reports.
+#[tauri::command]
+fn explain_query(query: String) -> Result<QueryExplanation, ParseError> {
+    query::explain(&query)
+}
+
+#[derive(Error, Debug)]
+enum ExportQueryResultsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("{0}")]
+    ExportError(#[from] ExportError),
+}
+
+impl_serialize_to_string!(ExportQueryResultsError);
+
+/// Write the items matching the query to a playlist or CSV file at `path`. Returns the number of
+/// items written.
+#[tauri::command]
+async fn export_query_results(
+    state: tauri::State<'_, AppState>,
+    query: String,
+    format: ExportFormat,
+    path: PathBuf,
+) -> Result<usize, ExportQueryResultsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ExportQueryResultsError::NoOpenRepo);
+    };
+    Ok(manager.export_query_results(query, format, path).await?)
+}
+
+/// Image shown under the cursor while dragging items out of the app.
+const DRAG_ICON: &[u8] = include_bytes!("../icons/32x32.png");
+
+#[derive(Error, Debug)]
+enum StartDragError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("no items match the query")]
+    NoItems,
+    #[error("failed to query items, {0}")]
+    QueryError(#[from] QueryError),
+    #[error("failed to start drag, {0}")]
+    DragError(#[from] drag::Error),
+    #[error("failed to run on the main thread, {0}")]
+    TauriError(#[from] tauri::Error),
+}
+
+impl_serialize_to_string!(StartDragError);
+
+/// Start an OS drag of all items matching the query, so they can be dropped into other apps. The
+/// paths are resolved here instead of sending them through the frontend.
+#[tauri::command]
+async fn start_drag_for_query(
+    app_handle: AppHandle<Wry>,
+    window: tauri::Window<Wry>,
+    state: tauri::State<'_, AppState>,
+    query: String,
+) -> Result<usize, StartDragError> {
+    let paths = {
+        let manager = state.manager.read().await;
+        let Some(manager) = &*manager else {
+            return Err(StartDragError::NoOpenRepo);
+        };
+        manager.query_paths(query).await?
+    };
+    if paths.is_empty() {
+        return Err(StartDragError::NoItems);
+    }
+    let count = paths.len();
+    // drags must be started from the main thread, GTK panics otherwise
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    app_handle.run_on_main_thread(move || {
+        let _ = sender.send(start_drag(&window, paths));
+    })?;
+    receiver.await.expect("drag was never started")?;
+    Ok(count)
+}
+
+fn start_drag(window: &tauri::Window<Wry>, paths: Vec<PathBuf>) -> Result<(), StartDragError> {
+    // on Linux, the drag is started from the GTK window instead of the raw window handle
+    #[cfg(target_os = "linux")]
+    let window = &window.gtk_window()?;
+    drag::start_drag(
+        window,
+        drag::DragItem::Files(paths),
+        drag::Image::Raw(DRAG_ICON.to_vec()),
+        |_, _| {},
+        drag::Options::default(),
+    )?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn count_items(
+    state: tauri::State<'_, AppState>,
+    query: String,
+) -> Result<i64, QueryItemIdsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(QueryItemIdsError::NoOpenRepo);
+    };
+    let count = manager.count(query.as_str()).await?;
+    Ok(count)
+}
+
+#[derive(Error, Debug)]
+enum GetFoldersError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to query items, {0}")]
+    DirStructureError(#[from] DirStructureError),
+}
+
+impl_serialize_to_string!(GetFoldersError);
+
+#[tauri::command]
+async fn get_dir_structure(
+    state: tauri::State<'_, AppState>,
+) -> Result<FolderBuf, GetFoldersError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetFoldersError::NoOpenRepo);
+    };
+    let folders = manager.get_dir_structure().await?;
+    Ok(folders)
+}
+
+/// The folders directly inside a folder, `path` is relative to the repo root and is empty for
+/// the root itself.
+#[tauri::command]
+async fn get_dir_children(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<Vec<DirChild>, GetFoldersError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetFoldersError::NoOpenRepo);
+    };
+    Ok(manager.get_dir_children(path).await?)
+}
+
+#[derive(Error, Debug)]
+enum InsertTagsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to insert tags, {0}")]
+    InsertTagsError(#[from] repo::InsertTagsError),
+}
+
+impl_serialize_to_string!(InsertTagsError);
+
+/// Add tags to the given items. If `last_seen` is given, the `updated_at` of each item when it was
+/// last loaded, nothing is changed if any of the items have changed since.
+#[tauri::command]
+async fn insert_tags(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+    tags: String,
+    last_seen: Option<HashMap<i64, i64>>,
+) -> Result<(), InsertTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(InsertTagsError::NoOpenRepo);
+    };
+    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
+    if !tags.is_empty() {
+        manager.insert_tags(ids, tags, last_seen).await?;
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum SetTagsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to set tags, {0}")]
+    UpdateError(#[from] repo::UpdateError),
+}
+
+impl_serialize_to_string!(SetTagsError);
+
+/// Replace the tags of the given items with `tags`, returns the number of items changed. See
+/// `insert_tags()` for `last_seen`.
+#[tauri::command]
+async fn set_tags(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+    tags: String,
+    last_seen: Option<HashMap<i64, i64>>,
+) -> Result<usize, SetTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(SetTagsError::NoOpenRepo);
+    };
+    Ok(manager.set_tags(ids, tags, last_seen).await?)
+}
+
+#[derive(Error, Debug)]
+enum ApplyTemplateError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to apply tag template, {0}")]
+    ApplyTemplateError(#[from] repo::ApplyTemplateError),
+}
+
+impl_serialize_to_string!(ApplyTemplateError);
+
+/// Add the tags of a tag template to the given items, templates are stored in the repo config.
+#[tauri::command]
+async fn apply_template(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+    template: String,
+) -> Result<(), ApplyTemplateError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ApplyTemplateError::NoOpenRepo);
+    };
+    Ok(manager.apply_tag_template(ids, template).await?)
+}
+
+#[derive(Error, Debug)]
+enum TagListError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to list tags, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to rename tag, {0}")]
+    UpdateError(#[from] repo::UpdateError),
+}
+
+impl_serialize_to_string!(TagListError);
+
+/// Every tag used in the repo and the number of items that have it.
+#[tauri::command]
+async fn get_tag_counts(state: tauri::State<'_, AppState>) -> Result<Vec<TagCount>, TagListError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(TagListError::NoOpenRepo);
+    };
+    Ok(manager.tag_counts().await?)
+}
+
+/// Usage statistics of every tag used in the repo, for the library insights panel.
+#[tauri::command]
+async fn get_tag_stats(state: tauri::State<'_, AppState>) -> Result<Vec<TagStats>, TagListError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(TagListError::NoOpenRepo);
+    };
+    Ok(manager.tag_stats().await?)
+}
+
+/// The tags of the selected items, for showing which tags all or only some of them have.
+#[tauri::command]
+async fn get_selection_summary(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+) -> Result<SelectionSummary, TagListError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(TagListError::NoOpenRepo);
+    };
+    Ok(manager.selection_summary(ids).await?)
+}
+
+/// Rename a tag on every item that has it, returns the number of items changed.
+#[tauri::command]
+async fn rename_tag(
+    state: tauri::State<'_, AppState>,
+    old: String,
+    new: String,
+) -> Result<usize, TagListError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(TagListError::NoOpenRepo);
+    };
+    Ok(manager.rename_tag(old, new).await?)
+}
+
+#[derive(Error, Debug)]
+enum FolderTagsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to get folder tags, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to add folder tags, {0}")]
+    InsertTagsError(#[from] repo::InsertTagsError),
+    #[error("failed to remove folder tags, {0}")]
+    RemoveTagsError(#[from] repo::RemoveTagsError),
+}
+
+impl_serialize_to_string!(FolderTagsError);
+
+/// Tags that items in the folder inherit from it and the folders it is in. `path` is relative to
+/// the repo root.
+#[tauri::command]
+async fn get_folder_tags(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<Vec<FolderTag>, FolderTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(FolderTagsError::NoOpenRepo);
+    };
+    Ok(manager.get_folder_tags(path).await?)
+}
+
+#[tauri::command]
+async fn insert_folder_tags(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    tags: String,
+) -> Result<(), FolderTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(FolderTagsError::NoOpenRepo);
+    };
+    Ok(manager.insert_folder_tags(path, tags).await?)
+}
+
+#[tauri::command]
+async fn remove_folder_tags(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    tags: String,
+) -> Result<(), FolderTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(FolderTagsError::NoOpenRepo);
+    };
+    Ok(manager.remove_folder_tags(path, tags).await?)
+}
+
+#[derive(Error, Debug)]
+enum CollectionsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to list collections, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    CollectionError(#[from] repo::CollectionError),
+}
+
+impl_serialize_to_string!(CollectionsError);
+
+/// Every collection and the number of items in it.
+#[tauri::command]
+async fn get_collections(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Collection>, CollectionsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CollectionsError::NoOpenRepo);
+    };
+    Ok(manager.get_collections().await?)
+}
+
+#[tauri::command]
+async fn create_collection(
+    state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<(), CollectionsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CollectionsError::NoOpenRepo);
+    };
+    Ok(manager.create_collection(name).await?)
+}
+
+#[tauri::command]
+async fn delete_collection(
+    state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<(), CollectionsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CollectionsError::NoOpenRepo);
+    };
+    Ok(manager.delete_collection(name).await?)
+}
+
+/// Add items to a collection, returns the number of items that weren't in it yet.
+#[tauri::command]
+async fn add_to_collection(
+    state: tauri::State<'_, AppState>,
+    name: String,
+    ids: Vec<i64>,
+) -> Result<usize, CollectionsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CollectionsError::NoOpenRepo);
+    };
+    Ok(manager.add_to_collection(name, ids).await?)
+}
+
+/// Remove items from a collection, returns the number of items that were in it.
+#[tauri::command]
+async fn remove_from_collection(
+    state: tauri::State<'_, AppState>,
+    name: String,
+    ids: Vec<i64>,
+) -> Result<usize, CollectionsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(CollectionsError::NoOpenRepo);
+    };
+    Ok(manager.remove_from_collection(name, ids).await?)
+}
+
+#[derive(Error, Debug)]
+enum RemoveTagsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to remove tags, {0}")]
+    RemoveTagsError(#[from] repo::RemoveTagsError),
+}
+
+impl_serialize_to_string!(RemoveTagsError);
+
+/// Remove tags from the given items. See `insert_tags()` for `last_seen`.
+#[tauri::command]
+async fn remove_tags(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+    tags: String,
+    last_seen: Option<HashMap<i64, i64>>,
+) -> Result<(), RemoveTagsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RemoveTagsError::NoOpenRepo);
+    };
+    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
+    if !tags.is_empty() {
+        manager.remove_tags(ids, tags, last_seen).await?;
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum MissingItemsError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to check items, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to remove items, {0}")]
+    RemoveError(#[from] repo::RemoveError),
+    #[error("failed to scan repo, {0}")]
+    SyncError(#[from] SyncError),
+    #[error("failed to relocate item, {0}")]
+    RelocateError(#[from] repo::RelocateError),
+}
+
+impl_serialize_to_string!(MissingItemsError);
+
+/// Items whose files no longer exist, so the user can relocate or purge them.
+#[tauri::command]
+async fn verify_items(state: tauri::State<'_, AppState>) -> Result<Vec<Item>, MissingItemsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(MissingItemsError::NoOpenRepo);
+    };
+    Ok(manager.verify_items().await?)
+}
+
+#[tauri::command]
+async fn purge_items(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+) -> Result<(), MissingItemsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(MissingItemsError::NoOpenRepo);
+    };
+    Ok(manager.purge_items(ids).await?)
+}
+
+/// Files that missing items were probably moved to, matched by contents or file name.
+#[tauri::command]
+async fn suggest_relocations(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Relocation>, MissingItemsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(MissingItemsError::NoOpenRepo);
+    };
+    Ok(manager.suggest_relocations().await?)
+}
+
+#[tauri::command]
+async fn relocate_item(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    new_path: String,
+) -> Result<(), MissingItemsError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(MissingItemsError::NoOpenRepo);
+    };
+    Ok(manager.relocate_item(id, new_path).await?)
+}
+
+#[derive(Error, Debug)]
+enum ConfirmSyncCommandError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("{0}")]
+    ConfirmSyncError(#[from] ConfirmSyncError),
+}
+
+impl_serialize_to_string!(ConfirmSyncCommandError);
+
+#[tauri::command]
+async fn confirm_sync(
+    state: tauri::State<'_, AppState>,
+    accept: bool,
+) -> Result<(), ConfirmSyncCommandError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(ConfirmSyncCommandError::NoOpenRepo);
+    };
+    manager.confirm_sync(accept).await?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum OptimizeRepoError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to optimize repo, {0}")]
+    MaintenanceError(#[from] MaintenanceError),
+}
+
+impl_serialize_to_string!(OptimizeRepoError);
+
+#[tauri::command]
+async fn optimize_repo(state: tauri::State<'_, AppState>) -> Result<(), OptimizeRepoError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(OptimizeRepoError::NoOpenRepo);
+    };
+    manager.optimize().await?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum RepoConfigCommandError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to save repo config, {0}")]
+    RepoConfigError(#[from] RepoConfigError),
+}
+
+impl_serialize_to_string!(RepoConfigCommandError);
+
+#[tauri::command]
+async fn get_repo_config(
+    state: tauri::State<'_, AppState>,
+) -> Result<RepoConfig, RepoConfigCommandError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RepoConfigCommandError::NoOpenRepo);
+    };
+    Ok(manager.get_config().await)
+}
+
+#[tauri::command]
+async fn set_repo_config(
+    state: tauri::State<'_, AppState>,
+    config: RepoConfig,
+) -> Result<(), RepoConfigCommandError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RepoConfigCommandError::NoOpenRepo);
+    };
+    manager.set_config(config).await?;
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum RuleError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("{0}")]
+    RuleError(#[from] rules::RuleError),
+}
+
+impl_serialize_to_string!(RuleError);
+
+#[tauri::command]
+async fn get_rules(state: tauri::State<'_, AppState>) -> Result<Vec<Rule>, RuleError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RuleError::NoOpenRepo);
+    };
+    Ok(manager.get_rules().await?)
+}
+
+#[tauri::command]
+async fn add_rule(
+    state: tauri::State<'_, AppState>,
+    conditions: Vec<Condition>,
+    tags: String,
+) -> Result<Rule, RuleError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RuleError::NoOpenRepo);
+    };
+    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
+    Ok(manager.insert_rule(conditions, tags).await?)
 }
 
-impl_serialize_to_string!(GetItemError);
-
 #[tauri::command]
-async fn get_item_details(
+async fn update_rule(
     state: tauri::State<'_, AppState>,
     id: i64,
-) -> Result<ItemDetails, GetItemError> {
+    conditions: Vec<Condition>,
+    tags: String,
+    enabled: bool,
+) -> Result<Rule, RuleError> {
     let manager = state.manager.read().await;
     let Some(manager) = &*manager else {
-        return Err(GetItemError::NoOpenRepo);
+        return Err(RuleError::NoOpenRepo);
     };
-    let item = manager.get_item_details(id).await?;
-    Ok(item)
+    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
+    Ok(manager.update_rule(id, conditions, tags, enabled).await?)
 }
 
-#[derive(Error, Debug)]
-enum QueryItemIdsError {
-    #[error("no active repo")]
-    NoOpenRepo,
-    #[error("failed to query items, {0}")]
-    QueryError(#[from] QueryError),
+#[tauri::command]
+async fn remove_rule(state: tauri::State<'_, AppState>, id: i64) -> Result<(), RuleError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(RuleError::NoOpenRepo);
+    };
+    Ok(manager.remove_rule(id).await?)
 }
 
-impl_serialize_to_string!(QueryItemIdsError);
-
+/// Dry-run a rule, returning the items that would be tagged by it.
 #[tauri::command]
-async fn query_item_ids(
+async fn preview_rule(
     state: tauri::State<'_, AppState>,
-    query: String,
-) -> Result<Vec<i64>, QueryItemIdsError> {
+    conditions: Vec<Condition>,
+    tags: String,
+) -> Result<Vec<RuleMatch>, RuleError> {
     let manager = state.manager.read().await;
     let Some(manager) = &*manager else {
-        return Err(QueryItemIdsError::NoOpenRepo);
+        return Err(RuleError::NoOpenRepo);
     };
-    let item_ids = manager.query(query.as_str()).await?;
-    Ok(item_ids)
+    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
+    Ok(manager.preview_rule(conditions, tags).await?)
 }
 
 #[derive(Error, Debug)]
-enum GetFoldersError {
+enum ImportTagsFromPatternError {
     #[error("no active repo")]
     NoOpenRepo,
-    #[error("failed to query items, {0}")]
-    DirStructureError(#[from] DirStructureError),
+    #[error("{0}")]
+    TagPatternError(#[from] TagPatternError),
 }
 
-impl_serialize_to_string!(GetFoldersError);
+impl_serialize_to_string!(ImportTagsFromPatternError);
 
+/// Add tags extracted from file names to all items matching the pattern. If `dry_run` is true,
+/// only return the items that would be tagged.
 #[tauri::command]
-async fn get_dir_structure(
+async fn import_tags_from_pattern(
     state: tauri::State<'_, AppState>,
-) -> Result<FolderBuf, GetFoldersError> {
+    pattern: String,
+    dry_run: bool,
+) -> Result<Vec<PatternMatch>, ImportTagsFromPatternError> {
     let manager = state.manager.read().await;
     let Some(manager) = &*manager else {
-        return Err(GetFoldersError::NoOpenRepo);
+        return Err(ImportTagsFromPatternError::NoOpenRepo);
     };
-    let folders = manager.get_dir_structure().await?;
-    Ok(folders)
+    Ok(manager.import_tags_from_pattern(pattern, dry_run).await?)
 }
 
 #[derive(Error, Debug)]
-enum InsertTagsError {
+enum ImportTagsCsvError {
     #[error("no active repo")]
     NoOpenRepo,
-    #[error("failed to insert tags, {0}")]
-    InsertTagsError(#[from] repo::InsertTagsError),
+    #[error("{0}")]
+    CsvImportError(#[from] CsvImportError),
 }
 
-impl_serialize_to_string!(InsertTagsError);
+impl_serialize_to_string!(ImportTagsCsvError);
 
+/// Add tags to items from a CSV file with rows like `path,tag1 tag2`.
 #[tauri::command]
-async fn insert_tags(
+async fn import_tags_csv(
     state: tauri::State<'_, AppState>,
-    ids: Vec<i64>,
-    tags: String,
-) -> Result<(), InsertTagsError> {
+    path: PathBuf,
+    options: CsvImportOptions,
+) -> Result<CsvImportReport, ImportTagsCsvError> {
     let manager = state.manager.read().await;
     let Some(manager) = &*manager else {
-        return Err(InsertTagsError::NoOpenRepo);
+        return Err(ImportTagsCsvError::NoOpenRepo);
     };
-    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
-    if !tags.is_empty() {
-        manager.insert_tags(ids, tags).await?;
-    }
-    Ok(())
+    Ok(manager.import_tags_csv(path, options).await?)
 }
 
 #[derive(Error, Debug)]
-enum RemoveTagsError {
+enum SubtreeCommandError {
     #[error("no active repo")]
     NoOpenRepo,
-    #[error("failed to remove tags, {0}")]
-    RemoveTagsError(#[from] repo::RemoveTagsError),
+    #[error("{0}")]
+    SubtreeError(#[from] SubtreeError),
 }
 
-impl_serialize_to_string!(RemoveTagsError);
+impl_serialize_to_string!(SubtreeCommandError);
 
+/// Copy the items in a folder of the repo to `dest` along with a manifest of their tags.
 #[tauri::command]
-async fn remove_tags(
+async fn export_subtree(
     state: tauri::State<'_, AppState>,
-    ids: Vec<i64>,
-    tags: String,
-) -> Result<(), RemoveTagsError> {
+    dir: String,
+    dest: PathBuf,
+) -> Result<SubtreeReport, SubtreeCommandError> {
     let manager = state.manager.read().await;
     let Some(manager) = &*manager else {
-        return Err(RemoveTagsError::NoOpenRepo);
+        return Err(SubtreeCommandError::NoOpenRepo);
     };
-    let tags: Vec<_> = tags.split_whitespace().map(|x| x.to_string()).collect();
-    if !tags.is_empty() {
-        manager.remove_tags(ids, tags).await?;
-    }
-    Ok(())
+    Ok(manager.export_subtree(dir, dest).await?)
+}
+
+/// Copy a folder written by `export_subtree` into the folder `dir` of the repo, keeping its tags.
+#[tauri::command]
+async fn import_subtree(
+    state: tauri::State<'_, AppState>,
+    src: PathBuf,
+    dir: String,
+) -> Result<SubtreeReport, SubtreeCommandError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(SubtreeCommandError::NoOpenRepo);
+    };
+    Ok(manager.import_subtree(src, dir).await?)
 }
 
 #[derive(Error, Debug)]
@@ -314,15 +1782,31 @@ enum RevealFileError {
     IOError(#[from] std::io::Error),
     #[error("malformed path, {0}")]
     MalformedPath(PathBuf),
+    #[error("{0}")]
+    PathOutsideRepo(#[from] PathOutsideRepo),
 }
 
 impl_serialize_to_string!(RevealFileError);
 
+/// Check that a path given by the webview is inside the open repo, see `ensure_inside_repo()`.
+/// If no repo is open, all paths are rejected.
+async fn check_repo_path(state: &AppState, path: &str) -> Result<(), PathOutsideRepo> {
+    let manager = state.manager.read().await;
+    match &*manager {
+        Some(manager) => ensure_inside_repo(manager.path(), path),
+        None => Err(PathOutsideRepo(PathBuf::from(path))),
+    }
+}
+
 // for all target_os options, see:
 // https://doc.rust-lang.org/reference/conditional-compilation.html#target_os
 #[cfg(target_os = "windows")]
 #[tauri::command]
-fn reveal_file(path: String) -> Result<(), RevealFileError> {
+async fn reveal_file(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<(), RevealFileError> {
+    check_repo_path(&state, &path).await?;
     let path: &Path = path.as_ref();
     // explorer can't find the file if you use forward slashes
     // normalise the path to remove forward slashes
@@ -336,7 +1820,11 @@ fn reveal_file(path: String) -> Result<(), RevealFileError> {
 
 #[cfg(target_os = "macos")]
 #[tauri::command]
-fn reveal_file(path: String) -> Result<(), RevealFileError> {
+async fn reveal_file(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<(), RevealFileError> {
+    check_repo_path(&state, &path).await?;
     let path: &Path = path.as_ref();
     let path = path.normalize()?;
     let Some(path) = path.as_path().to_str() else {
@@ -348,7 +1836,10 @@ fn reveal_file(path: String) -> Result<(), RevealFileError> {
 
 #[cfg(not(any(target_os = "windows", target_os = "macos")))]
 #[tauri::command]
-fn reveal_file(path: String) -> Result<(), RevealFileError> {
+async fn reveal_file(
+    _state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<(), RevealFileError> {
     let path: &Path = path.as_ref();
     return Err(RevealFileError::OperatingSystemNotSupported);
 }
@@ -357,16 +1848,268 @@ fn reveal_file(path: String) -> Result<(), RevealFileError> {
 enum OpenFileError {
     #[error("failed to reveal file")]
     IOError(#[from] std::io::Error),
+    #[error("{0}")]
+    PathOutsideRepo(#[from] PathOutsideRepo),
 }
 
 impl_serialize_to_string!(OpenFileError);
 
 #[tauri::command]
-fn launch_file(path: String) -> Result<(), OpenFileError> {
+async fn launch_file(state: tauri::State<'_, AppState>, path: String) -> Result<(), OpenFileError> {
+    check_repo_path(&state, &path).await?;
     open::that(path)?;
     Ok(())
 }
 
+#[derive(Error, Debug)]
+enum NewWindowError {
+    #[error("failed to create window, {0}")]
+    TauriError(#[from] tauri::Error),
+}
+
+impl_serialize_to_string!(NewWindowError);
+
+/// Open another window on the open repo, e.g. to show a different query side by side. Returns the
+/// new window's label.
+#[tauri::command]
+async fn new_window(app_handle: AppHandle) -> Result<String, NewWindowError> {
+    // reuse the labels of closed windows, so their saved sizes are reused too
+    let label = (1..)
+        .map(|i| format!("window-{}", i))
+        .find(|label| app_handle.get_window(label).is_none())
+        .unwrap();
+    let window = build_window(&app_handle, &label)?;
+    setup_window(&window);
+    Ok(label)
+}
+
+/// Recently opened repos, pinned repos first.
+#[tauri::command]
+fn get_recent_repos(config_state: tauri::State<'_, AppConfigState>) -> Vec<RecentRepo> {
+    config_state.config.read().unwrap().recent_repos()
+}
+
+#[derive(Error, Debug)]
+enum RecentReposError {
+    #[error("repo isn't in the recent repos list, {0}")]
+    NotInList(PathBuf),
+    #[error("failed to save app config, {0}")]
+    AppConfigError(#[from] AppConfigError),
+}
+
+impl_serialize_to_string!(RecentReposError);
+
+#[tauri::command]
+fn pin_repo(app_handle: AppHandle, path: PathBuf, pinned: bool) -> Result<(), RecentReposError> {
+    if update_app_config(&app_handle, |config| config.pin_repo(&path, pinned))? {
+        Ok(())
+    } else {
+        Err(RecentReposError::NotInList(path))
+    }
+}
+
+#[tauri::command]
+fn remove_recent_repo(app_handle: AppHandle, path: PathBuf) -> Result<(), RecentReposError> {
+    if update_app_config(&app_handle, |config| config.remove_recent_repo(&path))? {
+        Ok(())
+    } else {
+        Err(RecentReposError::NotInList(path))
+    }
+}
+
+#[derive(Error, Debug)]
+enum QuickTagError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("quick tag slot doesn't exist: {0}")]
+    InvalidSlot(u8),
+    #[error("quick tag slot is empty: {0}")]
+    EmptySlot(u8),
+    #[error("failed to save app config, {0}")]
+    AppConfigError(#[from] AppConfigError),
+    #[error("failed to insert tags, {0}")]
+    InsertTagsError(#[from] repo::InsertTagsError),
+}
+
+impl_serialize_to_string!(QuickTagError);
+
+#[tauri::command]
+fn set_quick_tag(app_handle: AppHandle, slot: u8, tags: String) -> Result<(), QuickTagError> {
+    if update_app_config(&app_handle, |config| config.set_quick_tag(slot, &tags))? {
+        Ok(())
+    } else {
+        Err(QuickTagError::InvalidSlot(slot))
+    }
+}
+
+#[tauri::command]
+async fn apply_quick_tag(
+    state: tauri::State<'_, AppState>,
+    config_state: tauri::State<'_, AppConfigState>,
+    ids: Vec<i64>,
+    slot: u8,
+) -> Result<(), QuickTagError> {
+    let tags: Vec<_> = match config_state.config.read().unwrap().quick_tag(slot) {
+        Some(tags) => tags.split_whitespace().map(|x| x.to_string()).collect(),
+        None => return Err(QuickTagError::EmptySlot(slot)),
+    };
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(QuickTagError::NoOpenRepo);
+    };
+    manager.insert_tags(ids, tags, None).await?;
+    Ok(())
+}
+
+/// The current app config, for windows opened after the last `config-changed` event.
+#[tauri::command]
+fn get_app_config(config_state: tauri::State<'_, AppConfigState>) -> AppConfig {
+    config_state.config.read().unwrap().clone()
+}
+
+#[derive(Error, Debug)]
+enum AppConfigCommandError {
+    #[error("failed to save app config, {0}")]
+    AppConfigError(#[from] AppConfigError),
+    #[error("{0}")]
+    ApiServerError(#[from] ApiServerError),
+    #[error("{0}")]
+    ShortcutError(#[from] ShortcutError),
+}
+
+impl_serialize_to_string!(AppConfigCommandError);
+
+#[tauri::command]
+async fn set_app_config(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+    config_state: tauri::State<'_, AppConfigState>,
+    mut config: AppConfig,
+) -> Result<(), AppConfigCommandError> {
+    // these are managed by the backend, the frontend's copy may be outdated
+    {
+        let old_config = config_state.config.read().unwrap();
+        config.windows = old_config.windows.clone();
+        config.recent_repos = old_config.recent_repos.clone();
+        config.quick_tags = old_config.quick_tags.clone();
+    }
+    config.validate()?;
+    replace_app_config(&app_handle, &state, &config_state, config).await
+}
+
+/// Write the app config to a file, so it can be imported on another computer.
+#[tauri::command]
+fn export_settings(
+    config_state: tauri::State<'_, AppConfigState>,
+    path: PathBuf,
+) -> Result<(), AppConfigCommandError> {
+    Ok(config_state.config.read().unwrap().export(path)?)
+}
+
+/// Replace the app config with one written by `export_settings`. Window sizes are kept, since
+/// they depend on the computer's screens.
+#[tauri::command]
+async fn import_settings(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+    config_state: tauri::State<'_, AppConfigState>,
+    path: PathBuf,
+) -> Result<(), AppConfigCommandError> {
+    let mut config = AppConfig::import(path)?;
+    config.windows = config_state.config.read().unwrap().windows.clone();
+    replace_app_config(&app_handle, &state, &config_state, config).await
+}
+
+/// Restore the default app config, except for the recent repos list.
+#[tauri::command]
+async fn reset_settings(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+    config_state: tauri::State<'_, AppConfigState>,
+) -> Result<(), AppConfigCommandError> {
+    let mut config = config_state.config.read().unwrap().clone();
+    config.reset();
+    replace_app_config(&app_handle, &state, &config_state, config).await
+}
+
+/// Save a new app config and switch to it, restarting the parts of the app whose settings
+/// changed.
+async fn replace_app_config(
+    app_handle: &AppHandle,
+    state: &AppState,
+    config_state: &AppConfigState,
+    config: AppConfig,
+) -> Result<(), AppConfigCommandError> {
+    config.save(&config_state.dir)?;
+    let old_config = std::mem::replace(&mut *config_state.config.write().unwrap(), config.clone());
+    emit_config_changed(app_handle, config.clone());
+    if config.api_server != old_config.api_server {
+        let mut api_server = config_state.api_server.lock().await;
+        // wait for the old server to release its port, in case the new server uses it
+        if let Some(server) = api_server.take() {
+            server.stop().await;
+        }
+        let events = app_handle.state::<EventStream>();
+        *api_server = start_api_server(&config, &state.manager, &events)?;
+    }
+    if config.preview_shortcuts != old_config.preview_shortcuts {
+        register_preview_shortcuts(app_handle, &config.preview_shortcuts)?;
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+enum GetLogsError {
+    #[error("{0}")]
+    LogError(#[from] LogError),
+}
+
+impl_serialize_to_string!(GetLogsError);
+
+/// The last `n` lines of the log files, from oldest to newest.
+#[tauri::command]
+fn get_recent_logs(
+    config_state: tauri::State<'_, AppConfigState>,
+    n: usize,
+) -> Result<Vec<LogEntry>, GetLogsError> {
+    Ok(logs::recent_logs(&logs::log_dir(&config_state.dir), n)?)
+}
+
+/// List the applications that can open a file, both the ones added in the app config and the
+/// ones registered with the OS.
+#[tauri::command]
+fn list_open_with(
+    config_state: tauri::State<'_, AppConfigState>,
+    path: String,
+) -> Vec<OpenWithApp> {
+    let config = config_state.config.read().unwrap();
+    open_with::list_apps(&config, path)
+}
+
+#[derive(Error, Debug)]
+enum LaunchFileWithError {
+    #[error("{0}")]
+    OpenWithError(#[from] OpenWithError),
+    #[error("{0}")]
+    PathOutsideRepo(#[from] PathOutsideRepo),
+}
+
+impl_serialize_to_string!(LaunchFileWithError);
+
+/// Open a file with an application from `list_open_with`.
+#[tauri::command]
+async fn launch_file_with(
+    state: tauri::State<'_, AppState>,
+    config_state: tauri::State<'_, AppConfigState>,
+    path: String,
+    app_id: String,
+) -> Result<(), LaunchFileWithError> {
+    check_repo_path(&state, &path).await?;
+    let config = config_state.config.read().unwrap();
+    open_with::launch_with(&config, path, &app_id)?;
+    Ok(())
+}
+
 #[tauri::command]
 fn launch_manual() -> Result<(), OpenFileError> {
     open::that("https://jameswalker55.github.io/tag-repo-site/")
@@ -376,7 +2119,7 @@ fn launch_manual() -> Result<(), OpenFileError> {
 
 #[tauri::command]
 fn determine_filetype(path: String) -> FileType {
-    use crate::manager::determine_filetype;
+    use crate::filetype::determine_filetype;
 
     determine_filetype(path)
 }
@@ -394,6 +2137,8 @@ enum PreviewAudioError {
     IOError(#[from] std::io::Error),
     #[error("failed to decode file, {0}")]
     DecodeError(#[from] rodio::decoder::DecoderError),
+    #[error("{0}")]
+    PathOutsideRepo(#[from] PathOutsideRepo),
 }
 
 impl_serialize_to_string!(PreviewAudioError);
@@ -406,27 +2151,56 @@ fn load_music(path: impl AsRef<Path>) -> Result<Decoder<BufReader<File>>, Previe
     Ok(source)
 }
 
-#[tauri::command]
-fn preview_audio(
-    state: tauri::State<'_, AppState>,
-    path: String,
-    skip_milliseconds: u64,
+/// Play a file in the audio preview, replacing the current audio. The preview starts
+/// `skip_milliseconds` into the file, or at the offset in the app config if this is `None`, and is
+/// stopped after the max duration in the app config. The file's samples are scaled by `gain`.
+fn play_preview(
+    app_handle: &AppHandle,
+    sink: &Sink,
+    path: &str,
+    skip_milliseconds: Option<u64>,
+    gain: f32,
 ) -> Result<(), PreviewAudioError> {
-    let Some(sink) = &state.output_sink else {
-        return Err(PreviewAudioError::NoOutputStream)
-    };
+    let config = audio_preview_config(app_handle);
+    let skip_milliseconds = skip_milliseconds.unwrap_or(config.skip_milliseconds);
+    let max_duration = config.max_duration_milliseconds.map(Duration::from_millis);
     // stop all current audio without pausing
     sink.stop();
+    let state = app_handle.state::<AppState>();
+    let seq = state.preview_seq.fetch_add(1, Ordering::SeqCst) + 1;
     // try to load new audio
     match load_music(path) {
         Ok(music) => {
+            let meter = state.preview_meter.clone();
             if skip_milliseconds != 0 {
-                sink.append(music.skip_duration(Duration::from_millis(skip_milliseconds)));
+                let skip = Duration::from_millis(skip_milliseconds);
+                sink.append(Metered::new(music.skip_duration(skip).amplify(gain), meter));
             } else {
-                sink.append(music);
+                sink.append(Metered::new(music.amplify(gain), meter));
             }
             // ensure sink isn't paused
             sink.play();
+            *state.last_preview.lock().unwrap() = Some((path.to_string(), gain));
+            if let Some(max_duration) = max_duration {
+                let app_handle = app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    tokio::time::sleep(max_duration).await;
+                    let state = app_handle.state::<AppState>();
+                    // another file may have been previewed since
+                    if state.preview_seq.load(Ordering::SeqCst) != seq {
+                        return;
+                    }
+                    if let Some(sink) = &state.output_sink {
+                        stop_preview(&app_handle, sink);
+                    }
+                });
+            }
+            if let Some(session) = &app_handle.state::<MediaSessionState>().0 {
+                session.set_track(path);
+            }
+            app_handle
+                .emit_all_and_forward("preview-track-changed", Some(path))
+                .expect("Failed to emit event");
             Ok(())
         }
         Err(err) => {
@@ -436,16 +2210,115 @@ fn preview_audio(
     }
 }
 
+/// The audio preview settings in the app config.
+fn audio_preview_config(app_handle: &AppHandle) -> AudioPreviewConfig {
+    let config_state = app_handle.state::<AppConfigState>();
+    let config = config_state.config.read().unwrap();
+    config.audio_preview.clone()
+}
+
+/// Volume to play an item at so it is about as loud as other items, or 1 if it can't be measured.
+async fn preview_gain(manager: &RepoManager<Wry>, id: i64) -> f32 {
+    match manager.get_loudness(id).await {
+        Ok(loudness) => loudness.map_or(1.0, |x| x.gain(TARGET_LUFS)),
+        Err(err) => {
+            error!("failed to measure loudness of item {}, {}", id, err);
+            1.0
+        }
+    }
+}
+
+/// Stop the audio preview.
+fn stop_preview(app_handle: &AppHandle, sink: &Sink) {
+    // stop all current audio without pausing
+    sink.stop();
+    let state = app_handle.state::<AppState>();
+    state.preview_seq.fetch_add(1, Ordering::SeqCst);
+    state.preview_meter.reset();
+    if let Some(session) = &app_handle.state::<MediaSessionState>().0 {
+        session.set_stopped();
+    }
+    app_handle
+        .emit_all_and_forward("preview-track-changed", None::<String>)
+        .expect("Failed to emit event");
+}
+
 #[tauri::command]
-fn stop_audio(state: tauri::State<'_, AppState>) -> Result<(), PreviewAudioError> {
+async fn preview_audio(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+    path: String,
+    skip_milliseconds: Option<u64>,
+) -> Result<(), PreviewAudioError> {
+    check_repo_path(&state, &path).await?;
     let Some(sink) = &state.output_sink else {
         return Err(PreviewAudioError::NoOutputStream)
     };
-    // stop all current audio without pausing
-    sink.stop();
+    // the path may not be an item, so the loudness isn't normalized
+    play_preview(&app_handle, sink, &path, skip_milliseconds, 1.0)
+}
+
+#[tauri::command]
+fn stop_audio(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), PreviewAudioError> {
+    let Some(sink) = &state.output_sink else {
+        return Err(PreviewAudioError::NoOutputStream)
+    };
+    stop_preview(&app_handle, sink);
     Ok(())
 }
 
+#[derive(Error, Debug)]
+enum GetPreviewError {
+    #[error("no active repo")]
+    NoOpenRepo,
+    #[error("failed to preview item, {0}")]
+    PreviewError(#[from] PreviewError),
+    #[error("{0}")]
+    PreviewAudioError(#[from] PreviewAudioError),
+}
+
+impl_serialize_to_string!(GetPreviewError);
+
+/// Preview an item's file. Audio files are played if auto-preview is enabled in the app config, any
+/// other audio is stopped.
+#[tauri::command]
+async fn get_preview(
+    app_handle: AppHandle,
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<Preview, GetPreviewError> {
+    let manager = state.manager.read().await;
+    let Some(manager) = &*manager else {
+        return Err(GetPreviewError::NoOpenRepo);
+    };
+    let preview = manager.get_preview(id).await?;
+    let config = audio_preview_config(&app_handle);
+    match (&preview, &state.output_sink) {
+        (Preview::Audio { .. }, _) if !config.auto_preview => {}
+        (Preview::Audio { path }, Some(sink)) => {
+            let gain = match config.normalize_loudness {
+                true => preview_gain(manager, id).await,
+                false => 1.0,
+            };
+            play_preview(&app_handle, sink, &path.to_string_lossy(), None, gain)?
+        }
+        (Preview::Audio { .. }, None) => return Err(PreviewAudioError::NoOutputStream.into()),
+        (_, Some(sink)) => stop_preview(&app_handle, sink),
+        (_, None) => {}
+    }
+    Ok(preview)
+}
+
+/// The levels of the audio preview that is playing, for a level meter. The levels are 0 if nothing
+/// is playing.
+#[tauri::command]
+fn get_preview_levels(state: tauri::State<'_, AppState>) -> Levels {
+    state.preview_meter.levels()
+}
+
 #[tauri::command]
 fn get_audio_volume(state: tauri::State<'_, AppState>) -> Result<f32, PreviewAudioError> {
     let Some(sink) = &state.output_sink else {
@@ -467,16 +2340,44 @@ fn set_audio_volume(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let subscriber = FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
-        // will be written to stdout.
-        .with_max_level(Level::TRACE)
-        // completes the builder.
-        .finish();
+/// Write logs to stdout, and to log files in the given folder if possible. The returned guard must
+/// be kept alive until the app exits, otherwise buffered logs are lost.
+fn init_logging(log_dir: Option<PathBuf>) -> Option<WorkerGuard> {
+    // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
+    // will be written to stdout.
+    let subscriber = tracing_subscriber::registry()
+        .with(LevelFilter::TRACE)
+        .with(tracing_subscriber::fmt::layer());
+
+    let log_dir = log_dir.and_then(|log_dir| match std::fs::create_dir_all(&log_dir) {
+        Ok(_) => Some(log_dir),
+        Err(err) => {
+            eprintln!("failed to create log folder, {}", err);
+            None
+        }
+    });
+    let Some(log_dir) = log_dir else {
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("setting default subscriber failed");
+        return None;
+    };
 
+    if let Err(err) = logs::prune_old_logs(&log_dir, logs::MAX_LOG_FILES - 1) {
+        eprintln!("failed to delete old log files, {}", err);
+    }
+    let appender = tracing_appender::rolling::daily(&log_dir, logs::LOG_FILE_PREFIX);
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let file_layer = tracing_subscriber::fmt::layer().json().with_ansi(false).with_writer(writer);
+    let subscriber = subscriber.with(file_layer);
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    Some(guard)
+}
+
+#[tokio::main]
+async fn main() {
+    let context = tauri::generate_context!();
+    let log_dir = tauri::api::path::app_config_dir(context.config()).map(logs::log_dir);
+    let _log_guard = init_logging(log_dir);
 
     // "stream" is the output audio stream, if this is dropped then audio will stop
     let (_stream, sink) = match get_output_stream_and_sink() {
@@ -491,19 +2392,54 @@ async fn main() {
 
     tauri::Builder::default()
         .manage(app_state)
+        .manage(EventStream::new())
         .setup(|app| {
-            let window = app
-                .get_window("main")
-                .expect("failed to get window with name 'main'");
-            window
-                .set_min_size(Some(PhysicalSize { width: 400, height: 270 }))
-                .expect("failed to set min size of window");
-            match set_shadow(&window, true) {
-                Ok(_) => {}
-                Err(err) => {
-                    error!("failed to set window shadows, unsupported system. {}", err);
+            let config_dir = app
+                .path_resolver()
+                .app_config_dir()
+                .expect("failed to get app config folder");
+            let config = AppConfig::load(&config_dir).unwrap_or_else(|err| {
+                error!("failed to load app config, using defaults. {}", err);
+                match AppConfig::backup(&config_dir) {
+                    Ok(path) => info!("backed up old app config to {}", path.display()),
+                    Err(err) => error!("failed to back up old app config. {}", err),
                 }
-            }
+                AppConfig::default()
+            });
+            let api_server = start_api_server(
+                &config,
+                &app.state::<AppState>().manager,
+                &app.state::<EventStream>(),
+            )
+            .unwrap_or_else(|err| {
+                error!("failed to start API server. {}", err);
+                None
+            });
+            app.manage(AppConfigState {
+                dir: config_dir,
+                config: std::sync::RwLock::new(config),
+                api_server: Mutex::new(api_server),
+            });
+            // the app config must be loaded first, since it has the window's size and theme
+            let window = build_window(app, "main").expect("failed to create main window");
+            setup_window(&window);
+            // let the OS media keys control audio previews
+            let handle = app.handle();
+            let media_session = MediaSession::new(window_handle(&window), move |command| {
+                handle_media_command(&handle, command)
+            })
+            .map_err(|err| error!("failed to create media session. {}", err))
+            .ok();
+            app.manage(MediaSessionState(media_session));
+            let shortcuts = app
+                .state::<AppConfigState>()
+                .config
+                .read()
+                .unwrap()
+                .preview_shortcuts
+                .clone();
+            // errors are already logged
+            let _ = register_preview_shortcuts(&app.handle(), &shortcuts);
             // app.listen_global("cool", |evt| {
             //     tokio::spawn(async move {
             //         println!("Sleeping a bit...");
@@ -514,28 +2450,97 @@ async fn main() {
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
-            greet,
-            temp,
             current_path,
+            new_window,
             open_repo,
+            relocate_repo,
+            create_repo,
+            create_demo_repo,
             close_repo,
             current_status,
+            get_watcher_stats,
+            get_watcher_backend,
+            get_perf_report,
             query_item_ids,
+            query_item_ids_streamed,
+            cancel_query_stream,
+            validate_query,
+            explain_query,
+            count_items,
             get_item_details,
+            get_items_details,
+            find_similar,
+            write_tags_to_files,
+            get_changes_since,
+            get_activity,
+            watch_path,
+            unwatch_path,
+            resync_folder,
             reveal_file,
             launch_file,
             determine_filetype,
             insert_tags,
             remove_tags,
+            set_tags,
+            apply_template,
+            verify_items,
+            purge_items,
+            suggest_relocations,
+            relocate_item,
+            get_tag_counts,
+            get_tag_stats,
+            get_selection_summary,
+            rename_tag,
+            get_folder_tags,
+            insert_folder_tags,
+            remove_folder_tags,
+            get_collections,
+            create_collection,
+            delete_collection,
+            add_to_collection,
+            remove_from_collection,
             get_dir_structure,
+            get_dir_children,
             supports_audio_playback,
             preview_audio,
             stop_audio,
+            get_preview,
             get_audio_volume,
+            get_preview_levels,
             set_audio_volume,
             launch_manual,
+            get_app_config,
+            set_app_config,
+            export_settings,
+            import_settings,
+            reset_settings,
+            get_recent_logs,
+            get_recent_repos,
+            pin_repo,
+            remove_recent_repo,
+            set_quick_tag,
+            apply_quick_tag,
+            list_open_with,
+            launch_file_with,
+            get_rules,
+            add_rule,
+            update_rule,
+            remove_rule,
+            preview_rule,
+            import_tags_from_pattern,
+            import_tags_csv,
+            export_subtree,
+            import_subtree,
+            export_query_results,
+            start_drag_for_query,
+            copy_paths_to_clipboard,
+            copy_tags_to_clipboard,
+            get_repo_config,
+            set_repo_config,
+            optimize_repo,
+            confirm_sync,
         ])
-        .run(tauri::generate_context!())
+        .run(context)
         .expect("error while running tauri application");
 
     error!("main thread has dropped!");
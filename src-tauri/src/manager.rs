@@ -1,33 +1,99 @@
+use crate::content;
+use crate::events::EmitExt;
+use crate::export::{export_items, ExportError, ExportFormat};
+use crate::file_tags::{write_tags_to_file, ConflictStrategy, WriteTagsReport};
+use crate::filetype::{determine_filetype, FileType};
+use crate::hash::hash_file;
+use crate::helpers::fs::read_text_snippet;
+use crate::helpers::path::{ensure_inside_repo, PathOutsideRepo};
+#[cfg(feature = "audio")]
+use crate::loudness::{self, Loudness};
+use crate::perf;
+use crate::pool::ReadPool;
+use crate::query::to_sql;
 use crate::repo::{
-    DirStructureError, InsertTagsError, Item, OpenError, QueryError, RemoveTagsError, Repo,
-    SearchError, SyncError,
+    Activity, ActivityFilter, ActivityKind, ApplyTemplateError, Changes, Collection,
+    CollectionError, ConflictError, DirChild, DirStructureError, FolderTag, InsertTagsError, Item,
+    MaintenanceError, OpenError, OpenMode, QueryError, RelocateError, Relocation, RemoveError,
+    RemoveTagsError, Repo, RepoReadOnly, SearchError, SelectionSummary, SimilarError, SimilarItem,
+    SyncDelta, SyncError, SyncReport, TagCount, TagStats, UpdateError,
 };
-use crate::scan::{classify_path, scan_dir, to_relative_path, Options, PathType};
-use crate::tree::FolderBuf;
-use crate::watch::BestWatcher;
-use futures::executor::block_on;
+use crate::repo_config::{RepoConfig, RepoConfigError, WatcherStrategy};
+use crate::rules::{Condition, Rule, RuleError, RuleMatch};
+use crate::scan::{
+    classify_path, scan_dir, scan_dir_batched, scan_subdir, to_relative_path, top_level_dirs,
+    PathType,
+};
+#[cfg(feature = "audio")]
+use crate::similarity;
+use crate::subtree::{export_subtree, import_subtree, parse_folder, SubtreeError, SubtreeReport};
+use crate::tag_import::{parse_csv, CsvImportError, CsvImportOptions, CsvImportReport};
+use crate::tag_pattern::{PatternMatch, TagPatternError};
+use crate::tree::{DirTree, DirTreeDelta, FolderBuf};
+use crate::watch::{
+    best_for, HashLookup, PairingMetrics, PairingOptions, PairingStats, RepoWatcher,
+    WatcherBackend, WatcherFactory,
+};
+use itertools::Itertools;
 use notify::event::{ModifyKind, RenameMode};
 use notify::EventKind::{Create, Modify, Remove};
-use notify::{Config, Event, RecursiveMode, Watcher};
+use notify::{Event, RecursiveMode, Watcher};
+use relative_path::{RelativePath, RelativePathBuf};
 
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use tauri::{AppHandle, Manager, Runtime};
 use thiserror::Error;
 
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+use tracing::{debug, error, instrument, warn};
+
+/// Run maintenance on the database after a sync that changes at least this many items.
+const LARGE_SYNC_THRESHOLD: usize = 5000;
+
+/// Number of files hashed before the hashes are stored in the repo.
+const HASH_BATCH_SIZE: usize = 20;
+
+/// Time to wait before looking for newly tagged items to hash.
+const HASH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of documents read before their text is stored in the repo.
+const EXTRACT_BATCH_SIZE: usize = 10;
+
+/// Time to wait before looking for new documents to extract text from.
+const EXTRACT_INTERVAL: Duration = Duration::from_secs(60);
 
-use tracing::{debug, error, instrument};
+/// Number of files fingerprinted before the fingerprints are stored in the repo.
+#[cfg(feature = "audio")]
+const FINGERPRINT_BATCH_SIZE: usize = 10;
+
+/// Time to wait before looking for new items to fingerprint.
+#[cfg(feature = "audio")]
+const FINGERPRINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time the watcher waits for the stored hash of a deleted file before giving up, e.g. because the
+/// repo is busy syncing.
+const HASH_LOOKUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Time between checks for changes to the repo. Changes made within this time are sent to the
+/// frontend as a single `repo-changed` event.
+const CHANGE_FEED_INTERVAL: Duration = Duration::from_millis(200);
 
 #[derive(Debug, Copy, Clone, Serialize)]
 pub enum ManagerStatus {
     Idle,
     ScanningDirectory,
     UpdatingRepo,
+    AwaitingSyncConfirmation,
     // Querying,
 }
 
@@ -37,78 +103,243 @@ impl Default for ManagerStatus {
     }
 }
 
+/// An item along with values computed from it, so the item list doesn't have to compute them for
+/// every row.
 #[derive(Serialize, Clone)]
 pub struct ItemDetails {
     item: Item,
     filetype: FileType,
+    /// File name, e.g. `kick.wav`
+    name: String,
+    /// Extension without the leading dot, or an empty string if there is none
+    ext: String,
+    /// Folder containing the file relative to the repo root, an empty string for the root
+    dirname: String,
+    /// Size of the file in bytes, or None if it hasn't been read yet
+    size: Option<i64>,
 }
 
 impl ItemDetails {
-    fn from_item(item: Item) -> Self {
+    fn new(item: Item, size: Option<i64>) -> Self {
         let filetype = determine_filetype(&item.path);
-        Self { item, filetype }
+        let path = RelativePath::new(&item.path);
+        let name = path.file_name().unwrap_or_default().to_string();
+        let ext = path.extension().unwrap_or_default().to_string();
+        let dirname = path.parent().map(|x| x.to_string()).unwrap_or_default();
+        Self { item, filetype, name, ext, dirname, size }
+    }
+
+    /// Get the details of many items, reading their sizes with a single query.
+    fn from_items(repo: &Repo, items: Vec<Item>) -> Result<Vec<Self>, rusqlite::Error> {
+        let ids: Vec<_> = items.iter().map(|x| x.id).collect();
+        let sizes = repo.item_sizes(&ids)?;
+        Ok(items
+            .into_iter()
+            .map(|item| {
+                let size = sizes.get(&item.id).copied();
+                Self::new(item, size)
+            })
+            .collect())
     }
 }
 
+/// Payload of the `repo-changed` event, the range of change sequence numbers that are new. The
+/// changes themselves are fetched with `get_changes_since`.
+#[derive(Serialize, Clone, Copy)]
+struct ChangeRange {
+    from: i64,
+    to: i64,
+}
+
+/// Payload of the `query-results-chunk` event, ids of items matching a streamed query. The last
+/// event of a stream has `done` set, and `error` if the query failed.
 #[derive(Serialize, Clone)]
-pub enum FileType {
-    Audio,
-    Document,
-    Image,
-    Video,
-    Unknown,
+struct QueryResultsChunk {
+    stream_id: u64,
+    ids: Vec<i64>,
+    done: bool,
+    error: Option<String>,
 }
 
-macro_rules! file_types {
-    ($($file_type:tt),*) => {
-        [$(stringify!($file_type)),*]
-    };
+/// How serious an error reported with `report_error()` is.
+#[derive(Serialize, Clone, Copy, Debug)]
+enum ErrorSeverity {
+    /// The operation will be retried, or something else was done instead
+    Warning,
+    /// The operation failed and the repo may be out of date, e.g. the watcher stopped
+    Error,
+}
+
+/// Payload of the `app-error` event, an error in a background task that the user should know
+/// about.
+#[derive(Serialize, Clone)]
+struct AppError {
+    /// The task that failed, e.g. `watcher`
+    source: &'static str,
+    message: String,
+    severity: ErrorSeverity,
+}
+
+/// Items that have changed after a change sequence number, see `Repo::changes_since()`.
+#[derive(Serialize, Clone)]
+pub struct ChangeDetails {
+    to: i64,
+    items: Vec<ItemDetails>,
+    removed: Vec<i64>,
+}
+
+impl ChangeDetails {
+    fn from_changes(repo: &Repo, changes: Changes) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            to: changes.to,
+            items: ItemDetails::from_items(repo, changes.items)?,
+            removed: changes.removed,
+        })
+    }
 }
 
-const EXT_AUDIO: &'static [&'static str] = &file_types![
-    aac, ac3, aif, aifc, aiff, au, cda, dts, fla, flac, it, m1a, m2a, m3u, m4a, mid, midi, mka,
-    mod, mp2, mp3, mpa, ogg, opus, ra, rmi, snd, spc, umx, voc, wav, wma, xm
-];
+/// Number of bytes read from the start of a document for its preview.
+const TEXT_PREVIEW_LEN: usize = 4096;
+
+/// Images larger than this aren't previewed, since the whole file is sent to the frontend.
+const MAX_IMAGE_PREVIEW_LEN: u64 = 20 * 1024 * 1024;
 
-const EXT_DOCUMENT: &'static [&'static str] = &file_types![
-    c, chm, cpp, csv, cxx, doc, docm, docx, dot, dotm, dotx, h, hpp, htm, html, hxx, ini, java,
-    lua, mht, mhtml, odt, pdf, potm, potx, ppam, pps, ppsm, ppsx, ppt, pptm, pptx, rtf, sldm, sldx,
-    thmx, txt, vsd, wpd, wps, wri, xlam, xls, xlsb, xlsm, xlsx, xltm, xltx, xml
-];
+/// A preview of an item's file, for the preview panel. See `RepoManager::get_preview()`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum Preview {
+    /// An audio file, which is played with the app's audio output
+    Audio {
+        path: PathBuf,
+    },
+    Image {
+        mime: &'static str,
+        bytes: Vec<u8>,
+    },
+    /// The start of a text document, `truncated` if the document is longer than that
+    Text {
+        text: String,
+        truncated: bool,
+    },
+    /// The file can't be previewed, e.g. videos or binary documents
+    Unsupported,
+}
 
-const EXT_IMAGE: &'static [&'static str] =
-    &file_types![ani, bmp, gif, ico, jpe, jpeg, jpg, pcx, png, psd, tga, tif, tiff, webp, wmf];
+#[derive(Error, Debug)]
+pub enum PreviewError {
+    #[error("{0}")]
+    SearchError(#[from] SearchError),
+    #[error("failed to read file, {0}")]
+    IOError(#[from] std::io::Error),
+}
 
-const EXT_VIDEO: &'static [&'static str] = &file_types![
-    3g2, 3gp, 3gp2, 3gpp, amr, amv, asf, avi, bdmv, bik, d2v, divx, drc, dsa, dsm, dss, dsv, evo,
-    f4v, flc, fli, flic, flv, hdmov, ifo, ivf, m1v, m2p, m2t, m2ts, m2v, m4b, m4p, m4v, mkv, mov,
-    mp2v, mp4, mp4v, mpe, mpeg, mpg, mpls, mpv2, mpv4, mts, ogm, ogv, pss, pva, qt, ram, ratdvd,
-    rm, rmm, rmvb, roq, rpm, smil, smk, swf, tp, tpr, ts, vob, vp6, webm, wm, wmp, wmv
-];
+#[cfg(feature = "audio")]
+#[derive(Error, Debug)]
+pub enum LoudnessError {
+    #[error("{0}")]
+    SearchError(#[from] SearchError),
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to read file, {0}")]
+    IOError(#[from] std::io::Error),
+}
 
-pub fn determine_filetype(path: impl AsRef<Path>) -> FileType {
-    let path: &Path = path.as_ref();
-    let Some(extension) = path.extension() else {
-        return FileType::Unknown;
+/// MIME type of an image that the frontend's webview can display.
+fn image_mime(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let mime = match extension.as_str() {
+        "bmp" => "image/bmp",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "jpe" | "jpeg" | "jpg" => "image/jpeg",
+        "png" => "image/png",
+        "tif" | "tiff" => "image/tiff",
+        "webp" => "image/webp",
+        _ => return None,
     };
+    Some(mime)
+}
 
-    let Some(extension) = extension.to_str() else {
-        error!("cannot determine filetype of malformed path: {:?}", path);
-        return FileType::Unknown;
+/// Choose how to preview a file based on its type, reading its contents if needed.
+fn read_preview(path: PathBuf) -> Result<Preview, std::io::Error> {
+    let preview = match determine_filetype(&path) {
+        FileType::Audio => Preview::Audio { path },
+        FileType::Image => match image_mime(&path) {
+            Some(mime) if std::fs::metadata(&path)?.len() <= MAX_IMAGE_PREVIEW_LEN => {
+                Preview::Image { mime, bytes: std::fs::read(&path)? }
+            }
+            _ => Preview::Unsupported,
+        },
+        FileType::Document => match read_text_snippet(&path, TEXT_PREVIEW_LEN)? {
+            Some((text, truncated)) => Preview::Text { text, truncated },
+            None => Preview::Unsupported,
+        },
+        FileType::Video | FileType::Unknown => Preview::Unsupported,
     };
+    Ok(preview)
+}
+
+/// A mutation of the repo, run by the writer task.
+type WriteCommand = Box<dyn FnOnce(&mut Repo) + Send>;
+
+/// The writer task has stopped, e.g. because the repo was closed, or the command panicked.
+#[derive(Error, Debug)]
+#[error("the repo writer has stopped")]
+struct WriterStopped;
+
+/// Applies mutations of the repo one at a time, in the order they were queued. User edits and
+/// watcher events go through the same queue, so e.g. a rename from the watcher and a tag edit on
+/// the same item can't interleave and overwrite each other.
+#[derive(Debug, Clone)]
+struct RepoWriter {
+    sender: UnboundedSender<WriteCommand>,
+}
+
+impl RepoWriter {
+    /// Spawn the writer task. Every mutation of the repo goes through the writer, the lock is only
+    /// taken directly to read the repo config.
+    fn spawn(repo: Arc<Mutex<Repo>>) -> (Self, JoinHandle<()>) {
+        let (sender, mut receiver) = unbounded_channel::<WriteCommand>();
+        let handle = tokio::spawn(async move {
+            while let Some(command) = receiver.recv().await {
+                let mut repo = repo.clone().lock_owned().await;
+                // a panicking command doesn't stop the queue, its caller gets `WriterStopped`
+                // since the command's result is never sent
+                if let Err(err) = tokio::task::spawn_blocking(move || command(&mut repo)).await {
+                    error!("repo writer command failed: {}", err);
+                }
+            }
+        });
+        (Self { sender }, handle)
+    }
 
-    let extension = extension.to_lowercase();
+    /// Queue a mutation on a separate thread, and wait for it to run.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Repo) -> T + Send + 'static,
+    ) -> Result<T, WriterStopped> {
+        let (tx, rx) = oneshot::channel();
+        let command: WriteCommand = Box::new(move |repo| {
+            // the caller may have stopped waiting, then the result isn't needed
+            let _ = tx.send(f(repo));
+        });
+        self.sender.send(command).map_err(|_| WriterStopped)?;
+        rx.await.map_err(|_| WriterStopped)
+    }
 
-    if EXT_AUDIO.contains(&extension.as_str()) {
-        FileType::Audio
-    } else if EXT_DOCUMENT.contains(&extension.as_str()) {
-        FileType::Document
-    } else if EXT_IMAGE.contains(&extension.as_str()) {
-        FileType::Image
-    } else if EXT_VIDEO.contains(&extension.as_str()) {
-        FileType::Video
-    } else {
-        FileType::Unknown
+    /// Queue an operation from a thread outside the async runtime, and wait up to `timeout` for
+    /// it to run. Returns `None` if the writer is busy for longer, the operation still runs later.
+    fn run_with_timeout<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Repo) -> T + Send + 'static,
+        timeout: Duration,
+    ) -> Option<T> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let command: WriteCommand = Box::new(move |repo| {
+            let _ = tx.send(f(repo));
+        });
+        self.sender.send(command).ok()?;
+        rx.recv_timeout(timeout).ok()
     }
 }
 
@@ -116,335 +347,2211 @@ pub fn determine_filetype(path: impl AsRef<Path>) -> FileType {
 // either reduce the text or remove it entirely
 // #[tracing::instrument]
 async fn event_handler<R: Runtime>(
-    repo: Arc<Mutex<Repo>>,
+    writer: RepoWriter,
     repo_path: PathBuf,
-    app_handle: AppHandle<R>,
+    shared_watcher: SharedWatcher,
+    pause: SharedPause,
+    dirs: SharedDirs,
     mut receiver: UnboundedReceiver<notify::Result<Event>>,
-    options: Options,
+    app_handle: AppHandle<R>,
 ) {
     debug!("watcher started!");
-    let repo_path = repo_path.as_path();
     while let Some(evt) = receiver.recv().await {
         debug!("received event: {:?}", evt);
-        let evt = evt.expect("unknown event error");
-        match evt {
-            evt if evt.kind == Modify(ModifyKind::Any) => { /* ignore */ }
-            Event { kind: Create(_), mut paths, .. } => {
-                let path = paths.pop().expect("create event doesn't have a path");
-                let PathType::Item(path) = classify_path(path, repo_path, &options) else {
-                    continue;
+        let evt = match evt {
+            Ok(evt) => evt,
+            Err(err) => {
+                // the watcher may have stopped working, replace it with a new one. this drops the
+                // current watcher, so this handler stops as well
+                report_error(
+                    &app_handle,
+                    "watcher",
+                    ErrorSeverity::Warning,
+                    format!("watcher failed, restarting it: {}", err),
+                );
+                restart_watcher(&writer, &repo_path, &shared_watcher, &pause, &app_handle).await;
+                break;
+            }
+        };
+        // the app is changing files itself and updates the repo on its own, the skipped folders
+        // are rescanned when watching is resumed
+        {
+            let mut pause = pause.lock().unwrap();
+            if pause.count > 0 {
+                pause.skip(&evt.paths, &repo_path);
+                continue;
+            }
+        }
+        // events without paths can't say which folders to rescan, so all of them are
+        let watched = match evt.need_rescan() && evt.paths.is_empty() {
+            true => watched_dirs(&shared_watcher).await,
+            false => vec![],
+        };
+        let repo_path = repo_path.clone();
+        let dirs = dirs.clone();
+        let app_handle = app_handle.clone();
+        let applied = writer
+            .run(move |repo| {
+                let seq = repo.change_seq().ok();
+                let changes = apply_event(repo, evt, &repo_path, watched, &app_handle);
+                update_dir_tree(repo, &dirs, seq, changes, &app_handle);
+            })
+            .await;
+        if applied.is_err() {
+            break;
+        }
+    }
+    debug!("watcher ended!");
+}
+
+/// Items that a watcher event added or removed, used to update the folder tree.
+#[derive(Debug, Default)]
+struct PathChanges {
+    added: Vec<String>,
+    removed: Vec<String>,
+    /// Many items may have moved, so the tree should be rebuilt instead
+    rebuild: bool,
+}
+
+/// Apply a watcher event to the repo. `watched` are the folders to rescan if events were lost and
+/// the event doesn't say where.
+fn apply_event<R: Runtime>(
+    repo: &mut Repo,
+    evt: Event,
+    repo_path: &Path,
+    watched: Vec<PathBuf>,
+    app_handle: &AppHandle<R>,
+) -> PathChanges {
+    // files that are excluded from scans must be ignored here too, the options are read for
+    // every event so changes to the repo config apply straight away
+    let options = repo.config().scan_options();
+    let mut changes = PathChanges::default();
+    match evt {
+        evt if evt.need_rescan() => {
+            changes.rebuild = true;
+            // events were lost, e.g. the event queue overflowed
+            let mut dirs = vec![];
+            for path in &evt.paths {
+                let dir = match path.is_dir() {
+                    true => path.as_path(),
+                    false => path.parent().unwrap_or(path),
                 };
-                let repo = repo.lock().await;
-                let inserted_item = repo
-                    .insert_item(path.to_string(), "")
-                    .expect("failed to insert item");
-                app_handle
-                    .emit_all("item-added", ItemDetails::from_item(inserted_item))
-                    .expect("Failed to emit event");
+                dirs.push(dir.to_path_buf());
             }
-            Event { kind: Remove(_), mut paths, .. } => {
-                let path = paths.pop().expect("remove event doesn't have a path");
-                let path = to_relative_path(path.as_path(), repo_path);
-                let repo = repo.lock().await;
-                // TODO: Better handling here
-                // Since removals are delayed, the item we are trying to remove may not be in the repo
-                // Don't panic if the item isn't found
-                // Only panic if there is some rusqlite error
-                let removed_item = repo
-                    .remove_item_by_path(path.to_string())
-                    .expect("failed to remove item");
-                app_handle
-                    .emit_all("item-removed", ItemDetails::from_item(removed_item))
-                    .expect("Failed to emit event");
+            if dirs.is_empty() {
+                dirs = watched;
+            }
+            for dir in dirs {
+                let subdir = to_relative_path(&dir, repo_path);
+                match repo.sync_subtree(&subdir) {
+                    Ok(delta) => {
+                        debug!("rescanned {:?} after lost events: {:?}", subdir, delta)
+                    }
+                    Err(err) => report_error(
+                        app_handle,
+                        "watcher",
+                        ErrorSeverity::Error,
+                        format!("failed to rescan {} after lost events: {}", subdir, err),
+                    ),
+                }
             }
-            Event {
-                kind: Modify(ModifyKind::Name(RenameMode::Both)),
-                mut paths,
-                ..
-            } => {
-                let new_path = paths.pop().expect("rename event doesn't have any paths");
-                let old_path = paths.pop().expect("rename event only has one path");
-                let old_path = to_relative_path(old_path.as_path(), repo_path);
-                let PathType::Item(new_path) = classify_path(new_path, repo_path, &options) else {
+        }
+        Event {
+            kind: Modify(ModifyKind::Any | ModifyKind::Data(_)),
+            paths,
+            ..
+        } => {
+            // a file's contents may have changed, folders are ignored
+            for path in paths {
+                let PathType::Item(path) = classify_path(path, repo_path, &options) else {
                     continue;
                 };
-                let old_path = old_path.to_string();
-                let new_path = new_path.to_string();
-                let repo = repo.lock().await;
-                repo.rename_path(&old_path, &new_path)
-                    .expect("failed to rename item");
-                let renamed_item = repo
-                    .get_item_by_path(&new_path)
-                    .expect("failed to fetch renamed item");
-                app_handle
-                    .emit_all("item-renamed", ItemDetails::from_item(renamed_item))
-                    .expect("Failed to emit event");
+                match repo.refresh_mtime(&path) {
+                    Ok(true) => debug!("file was modified: {}", path),
+                    Ok(false) => {}
+                    Err(err) => report_error(
+                        app_handle,
+                        "watcher",
+                        ErrorSeverity::Warning,
+                        format!("failed to check if {} was modified: {}", path, err),
+                    ),
+                }
+            }
+        }
+        Event { kind: Create(_), mut paths, .. } => {
+            let path = paths.pop().expect("create event doesn't have a path");
+            let PathType::Item(path) = classify_path(path, repo_path, &options) else {
+                return changes;
+            };
+            match repo.insert_new_item(&path) {
+                Ok(_) => {
+                    log_activity(repo, app_handle, ActivityKind::Created, path.as_str(), None);
+                    changes.added.push(path.to_string());
+                }
+                Err(err) => report_error(
+                    app_handle,
+                    "watcher",
+                    ErrorSeverity::Error,
+                    format!("failed to add {}: {}", path, err),
+                ),
+            }
+        }
+        Event { kind: Remove(_), mut paths, .. } => {
+            let path = paths.pop().expect("remove event doesn't have a path");
+            let path = to_relative_path(path.as_path(), repo_path);
+            match repo.remove_item_by_path(path.to_string()) {
+                Ok(_) => {
+                    log_activity(repo, app_handle, ActivityKind::Deleted, path.as_str(), None);
+                    changes.removed.push(path.to_string());
+                }
+                // removals are delayed, so the item may have already been removed
+                Err(RemoveError::SearchError(SearchError::ItemNotFound)) => {
+                    debug!("removed file isn't in the repo: {}", path)
+                }
+                Err(err) => report_error(
+                    app_handle,
+                    "watcher",
+                    ErrorSeverity::Error,
+                    format!("failed to remove {}: {}", path, err),
+                ),
+            }
+        }
+        Event {
+            kind: Modify(ModifyKind::Name(RenameMode::Both)),
+            mut paths,
+            ..
+        } => {
+            let new_path = paths.pop().expect("rename event doesn't have any paths");
+            let old_path = paths.pop().expect("rename event only has one path");
+            let old_path = to_relative_path(old_path.as_path(), repo_path);
+            match classify_path(new_path, repo_path, &options) {
+                PathType::Item(new_path) => {
+                    let old_path = old_path.to_string();
+                    let new_path = new_path.to_string();
+                    match repo.rename_path(&old_path, &new_path) {
+                        Ok(_) => {
+                            log_activity(
+                                repo,
+                                app_handle,
+                                ActivityKind::Renamed,
+                                &new_path,
+                                Some(&old_path),
+                            );
+                            changes.removed.push(old_path);
+                            changes.added.push(new_path);
+                        }
+                        Err(err) => report_error(
+                            app_handle,
+                            "watcher",
+                            ErrorSeverity::Error,
+                            format!("failed to move {} to {}: {}", old_path, new_path, err),
+                        ),
+                    }
+                }
+                PathType::Directory(new_path) => {
+                    // a folder was renamed, move all items beneath it
+                    let new_path = to_relative_path(new_path.as_path(), repo_path);
+                    match repo.rename_dir(old_path.as_str(), new_path.as_str()) {
+                        Ok(count) => {
+                            debug!("moved {} items from {} to {}", count, old_path, new_path);
+                            changes.rebuild = true;
+                            log_activity(
+                                repo,
+                                app_handle,
+                                ActivityKind::Renamed,
+                                new_path.as_str(),
+                                Some(old_path.as_str()),
+                            );
+                        }
+                        Err(err) => report_error(
+                            app_handle,
+                            "watcher",
+                            ErrorSeverity::Error,
+                            format!(
+                                "failed to move folder {} to {}: {}",
+                                old_path, new_path, err
+                            ),
+                        ),
+                    }
+                }
+                PathType::Ignored => {}
             }
-            _ => (),
         }
+        _ => (),
     }
-    debug!("watcher ended!");
+    changes
 }
 
-#[derive(Error, Debug)]
-pub enum WatchError {
-    #[error("failed to watch path")]
-    CannotWatchPath(#[from] notify::Error),
-    #[error("already watching path")]
-    AlreadyWatching,
+/// Update the cached folder tree with the changes made by a watcher event, `seq` is the change
+/// sequence number from before the event. If anything else changed the items since the tree was
+/// last updated, the tree is rebuilt from the database instead.
+fn update_dir_tree<R: Runtime>(
+    repo: &Repo,
+    dirs: &SharedDirs,
+    seq: Option<i64>,
+    changes: PathChanges,
+    app_handle: &AppHandle<R>,
+) {
+    let mut dirs = dirs.lock().unwrap();
+    // the tree is only kept once it has been requested
+    let Some(cached) = &mut *dirs else {
+        return;
+    };
+    if changes.rebuild || seq != Some(cached.seq) {
+        if let Err(err) = refresh_dir_tree(repo, &mut dirs, app_handle) {
+            report_error(
+                app_handle,
+                "watcher",
+                ErrorSeverity::Warning,
+                format!("failed to rebuild folder tree: {}", err),
+            );
+        }
+        return;
+    }
+    let mut delta = DirTreeDelta::default();
+    // items are added first, so an item moving within a folder doesn't remove the folder
+    for path in &changes.added {
+        cached.tree.add_item(path, &mut delta);
+    }
+    for path in &changes.removed {
+        cached.tree.remove_item(path, &mut delta);
+    }
+    match repo.change_seq() {
+        Ok(seq) => cached.seq = seq,
+        // the tree is rebuilt next time
+        Err(_) => cached.seq = -1,
+    }
+    emit_dir_structure_changed(app_handle, delta);
 }
 
-#[derive(Error, Debug)]
-pub enum UnwatchError {
-    #[error("not watching path, cannot unwatch")]
-    NotWatching,
+/// Rebuild the cached folder tree if the items changed since it was last updated, and emit the
+/// folders that were added or removed.
+fn refresh_dir_tree<R: Runtime>(
+    repo: &Repo,
+    dirs: &mut Option<CachedDirTree>,
+    app_handle: &AppHandle<R>,
+) -> Result<(), DirStructureError> {
+    let seq = repo.change_seq()?;
+    if dirs.as_ref().is_some_and(|x| x.seq == seq) {
+        return Ok(());
+    }
+    let tree = repo.dir_tree()?;
+    if let Some(old) = dirs.take() {
+        emit_dir_structure_changed(app_handle, old.tree.diff(&tree));
+    }
+    *dirs = Some(CachedDirTree { seq, tree });
+    Ok(())
 }
 
-#[derive(Debug)]
-pub struct RepoManager<R: Runtime> {
-    repo: Arc<Mutex<Repo>>,
-    status: RwLock<ManagerStatus>,
-    path: PathBuf,
-    watcher: RwLock<Option<BestWatcher>>,
-    app_handle: AppHandle<R>,
+/// Record a change made by the watcher in the activity log. Failing to record it doesn't undo the
+/// change, so the error is only reported.
+fn log_activity<R: Runtime>(
+    repo: &Repo,
+    app_handle: &AppHandle<R>,
+    kind: ActivityKind,
+    path: &str,
+    old_path: Option<&str>,
+) {
+    if let Err(err) = repo.log_activity(kind, path, old_path) {
+        report_error(
+            app_handle,
+            "watcher",
+            ErrorSeverity::Warning,
+            format!(
+                "failed to record change to {} in the activity log: {}",
+                path, err
+            ),
+        );
+    }
 }
 
-impl<R: Runtime> RepoManager<R> {
-    pub fn new(path: impl AsRef<Path>, app_handle: AppHandle<R>) -> Result<Self, OpenError> {
-        let path = path.as_ref();
-        let repo = Repo::open(&path)?;
-        let manager = Self {
-            repo: Arc::new(Mutex::new(repo)),
-            status: RwLock::new(ManagerStatus::Idle),
-            path: path.to_path_buf(),
-            watcher: RwLock::new(None),
+/// Fail if any of the items have changed since the caller last saw them, given the `updated_at` of
+/// each item at the time. Does nothing if `last_seen` isn't given. This must run in the same write
+/// as the change it guards, so nothing can change the items in between.
+fn check_unchanged<E>(repo: &Repo, last_seen: Option<&HashMap<i64, i64>>) -> Result<(), E>
+where
+    E: From<rusqlite::Error> + From<ConflictError>,
+{
+    let Some(last_seen) = last_seen else {
+        return Ok(());
+    };
+    let ids = repo.stale_items(last_seen)?;
+    if !ids.is_empty() {
+        return Err(ConflictError { ids }.into());
+    }
+    Ok(())
+}
+
+/// Time to wait before restarting a watcher that has failed, so a watcher that keeps failing doesn't
+/// use up the CPU.
+const WATCHER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Create a watcher for the given paths, and spawn a task that applies its events to the repo.
+fn spawn_watcher<R: Runtime>(
+    writer: RepoWriter,
+    repo_path: PathBuf,
+    shared_watcher: SharedWatcher,
+    pause: SharedPause,
+    paths: Vec<(PathBuf, RecursiveMode)>,
+    settings: WatcherSettings,
+    app_handle: AppHandle<R>,
+) -> notify::Result<WatcherState> {
+    // new unbounded channel for communication
+    let (tx, rx) = unbounded_channel();
+
+    // create a new watcher
+    let sender_app_handle = app_handle.clone();
+    let handler = move |res: notify::Result<Event>| match tx.send(res) {
+        Ok(_) => {}
+        Err(err) => {
+            let evt = err.0;
+            report_error(
+                &sender_app_handle,
+                "watcher",
+                ErrorSeverity::Warning,
+                format!("failed to send event to watcher loop: {:?}", evt),
+            );
+        }
+    };
+    let watcher = match &settings.factory {
+        Some(factory) => factory.create(handler, &paths)?,
+        None => best_for(
+            handler,
+            &repo_path,
+            &paths,
+            settings.strategy,
+            settings.poll_interval,
+            settings.pairing.clone(),
+        )?,
+    };
+    debug!("watching {:?} with {:?}", repo_path, watcher.backend());
+
+    // no need to store this thread's handle
+    // the thread should stop when you drop the watcher
+    let dirs = settings.dirs.clone();
+    tokio::spawn(async move {
+        event_handler(
+            writer,
+            repo_path,
+            shared_watcher,
+            pause,
+            dirs,
+            rx,
             app_handle,
+        )
+        .await
+    });
+
+    Ok(WatcherState { watcher, paths, settings })
+}
+
+/// Look up the stored size and hash of deleted files, so the watcher can tell apart files with the
+/// same name. If a reader can't be opened, the lookup is queued on the writer, which is busy while
+/// the repo is syncing, so this gives up after `HASH_LOOKUP_TIMEOUT` instead of waiting for it.
+fn hash_lookup(writer: RepoWriter, read_pool: Arc<ReadPool>, repo_path: PathBuf) -> HashLookup {
+    Arc::new(move |path| {
+        let path = to_relative_path(path, &repo_path);
+        let lookup = move |repo: &Repo| repo.get_hash_by_path(path.as_str()).ok().flatten();
+        let (size, hash) = match read_pool.get() {
+            Ok(reader) => lookup(&reader),
+            Err(_) => writer
+                .run_with_timeout(move |repo| lookup(repo), HASH_LOOKUP_TIMEOUT)
+                .flatten(),
+        }?;
+        Some((u64::try_from(size).ok()?, hash))
+    })
+}
+
+/// Replace a failed watcher with a new one that watches the same paths. Events may have been
+/// missed while the watcher wasn't working, so the watched paths are resynced afterwards.
+async fn restart_watcher<R: Runtime>(
+    writer: &RepoWriter,
+    repo_path: &Path,
+    shared_watcher: &SharedWatcher,
+    pause: &SharedPause,
+    app_handle: &AppHandle<R>,
+) {
+    tokio::time::sleep(WATCHER_RESTART_DELAY).await;
+    let paths = {
+        let mut state = shared_watcher.write().await;
+        // the repo may have been unwatched in the meantime
+        let Some(old_state) = state.take() else {
+            return;
         };
-        Ok(manager)
+        let paths = old_state.paths.clone();
+        let settings = old_state.settings.clone();
+        drop(old_state);
+        let new_state = spawn_watcher(
+            writer.clone(),
+            repo_path.to_path_buf(),
+            shared_watcher.clone(),
+            pause.clone(),
+            paths.clone(),
+            settings,
+            app_handle.clone(),
+        );
+        match new_state {
+            Ok(new_state) => *state = Some(new_state),
+            Err(err) => {
+                report_error(
+                    app_handle,
+                    "watcher",
+                    ErrorSeverity::Error,
+                    format!("failed to restart watcher: {}", err),
+                );
+                return;
+            }
+        }
+        paths
+    };
+    let dirs = paths.into_iter().map(|(path, _)| path).collect();
+    rescan_dirs(writer, repo_path, dirs, "restarting watcher", app_handle).await;
+}
+
+/// Rescan folders whose events may have been missed, `reason` is used in log messages.
+async fn rescan_dirs<R: Runtime>(
+    writer: &RepoWriter,
+    repo_path: &Path,
+    dirs: Vec<PathBuf>,
+    reason: &'static str,
+    app_handle: &AppHandle<R>,
+) {
+    let repo_path = repo_path.to_path_buf();
+    let app_handle = app_handle.clone();
+    let rescanned = writer
+        .run(move |repo| {
+            for dir in dirs {
+                let subdir = to_relative_path(&dir, &repo_path);
+                match repo.sync_subtree(&subdir) {
+                    Ok(delta) => debug!("rescanned {:?} after {}: {:?}", subdir, reason, delta),
+                    Err(err) => report_error(
+                        &app_handle,
+                        "watcher",
+                        ErrorSeverity::Error,
+                        format!("failed to rescan {} after {}: {}", subdir, reason, err),
+                    ),
+                }
+            }
+        })
+        .await;
+    if rescanned.is_err() {
+        debug!("repo was closed before rescanning after {}", reason);
     }
+}
 
-    pub fn path(&self) -> &Path {
-        self.path.as_path()
+/// Folders registered with the watcher.
+async fn watched_dirs(shared_watcher: &SharedWatcher) -> Vec<PathBuf> {
+    match &*shared_watcher.read().await {
+        Some(state) => state.paths.iter().map(|(path, _)| path.clone()).collect(),
+        None => vec![],
     }
+}
 
-    pub async fn status(&self) -> ManagerStatus {
-        *self.status.read().await
+/// Log an error from a background task and emit it as `app-error`, so the frontend can show it to
+/// the user. If the event can't be emitted, the error is only logged.
+fn report_error<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    source: &'static str,
+    severity: ErrorSeverity,
+    message: String,
+) {
+    match severity {
+        ErrorSeverity::Warning => warn!("{}: {}", source, message),
+        ErrorSeverity::Error => error!("{}: {}", source, message),
+    }
+    let payload = AppError { source, message, severity };
+    if let Err(err) = app_handle.emit_all_and_forward("app-error", payload) {
+        error!("failed to emit error: {:?}", err);
     }
+}
 
-    pub async fn update_status(&self, status: ManagerStatus) {
-        *self.status.write().await = status;
-        self.app_handle
-            .emit_all("status-changed", status)
+/// Emit `repo-changed` if the repo has changed since the last event, `last_seq` is the latest
+/// change sequence number that has been emitted.
+fn emit_changes<R: Runtime>(repo: &Repo, app_handle: &AppHandle<R>, last_seq: &AtomicI64) {
+    let seq = match repo.change_seq() {
+        Ok(seq) => seq,
+        Err(err) => {
+            report_error(
+                app_handle,
+                "change-feed",
+                ErrorSeverity::Warning,
+                format!("failed to get change sequence number: {}", err),
+            );
+            return;
+        }
+    };
+    let from = last_seq.fetch_max(seq, Ordering::SeqCst);
+    if seq > from {
+        app_handle
+            .emit_all_and_forward("repo-changed", ChangeRange { from, to: seq })
             .expect("Failed to emit event");
     }
+}
 
-    pub async fn resync(&self) -> Result<(), SyncError> {
-        self.update_status(ManagerStatus::ScanningDirectory).await;
-        let path = self.path.clone();
-        let new_paths = tokio::task::spawn_blocking(move || scan_dir(path, Options::default()))
-            .await
-            .expect("failed to join with thread that's scanning a directory")?;
-
-        self.update_status(ManagerStatus::UpdatingRepo).await;
-        {
-            // clone a reference to the repo
-            let repo = self.repo.clone();
-            // move the sync() call to a separate blocking thread
-            tokio::task::spawn_blocking(move || {
-                let mut repo = block_on(async { repo.lock().await });
-                repo.sync(new_paths)
-            })
-            .await
-            .expect("failed to join with thread that's batch-updating the database")?;
-        }
+/// Emit `collections-changed` with the name of the collection that was created, deleted or had
+/// items added or removed.
+fn emit_collections_changed<R: Runtime>(app_handle: &AppHandle<R>, name: String) {
+    app_handle
+        .emit_all_and_forward("collections-changed", name)
+        .expect("Failed to emit event");
+}
 
-        self.update_status(ManagerStatus::Idle).await;
-        Ok(())
+/// Emit `dir-structure-changed` with the folders that were added or removed, if there are any.
+fn emit_dir_structure_changed<R: Runtime>(app_handle: &AppHandle<R>, delta: DirTreeDelta) {
+    if delta.is_empty() {
+        return;
     }
+    app_handle
+        .emit_all_and_forward("dir-structure-changed", delta)
+        .expect("Failed to emit event");
+}
 
-    pub async fn query(&self, query: &str) -> Result<Vec<i64>, QueryError> {
-        let items = {
-            // clone a reference to the repo
-            let repo = self.repo.clone();
-            let query = query.to_string();
-            tokio::task::spawn_blocking(move || {
-                let repo = block_on(async { repo.lock().await });
-                repo.query_ids(&query)
-            })
-            .await
-            .expect("failed to join with thread that's batch-updating the database")?
-        };
-        Ok(items)
-    }
+/// Emit `folder-tags-changed` with the path of the folder whose tags changed.
+fn emit_folder_tags_changed<R: Runtime>(app_handle: &AppHandle<R>, folder: String) {
+    app_handle
+        .emit_all_and_forward("folder-tags-changed", folder)
+        .expect("Failed to emit event");
+}
 
-    pub async fn get_dir_structure(&self) -> Result<FolderBuf, DirStructureError> {
-        let folders = {
-            // clone a reference to the repo
-            let repo = self.repo.clone();
-            tokio::task::spawn_blocking(move || {
-                let repo = block_on(async { repo.lock().await });
-                repo.dir_structure()
-            })
-            .await
-            .expect("failed to join with thread that's batch-updating the database")?
-        };
-        Ok(folders)
+/// Periodically emit `repo-changed` for changes made in the background, e.g. by the watcher. If
+/// the repo is busy, the changes are emitted once the writer gets to the check.
+async fn change_feed<R: Runtime>(
+    writer: RepoWriter,
+    app_handle: AppHandle<R>,
+    last_seq: Arc<AtomicI64>,
+) {
+    let mut interval = tokio::time::interval(CHANGE_FEED_INTERVAL);
+    loop {
+        interval.tick().await;
+        let app_handle = app_handle.clone();
+        let last_seq = last_seq.clone();
+        let emitted = writer
+            .run(move |repo| emit_changes(repo, &app_handle, &last_seq))
+            .await;
+        if emitted.is_err() {
+            break;
+        }
     }
+}
 
-    pub async fn get_item_details(&self, id: i64) -> Result<ItemDetails, SearchError> {
-        let item = {
-            let repo = self.repo.lock().await;
-            repo.get_item_by_id(id)
-        }?;
-        let details = ItemDetails::from_item(item);
-        Ok(details)
+/// Re-scan one slice of the repo and fix any items that are out of sync with the filesystem. The
+/// slice is scanned inside the writer, so queued watcher events can't make the scan stale.
+///
+/// If the fix would delete more tagged items than allowed by the repo config, e.g. because the
+/// drive of a folder was unmounted, the slice is left unchanged and the planned changes are
+/// returned as an error.
+fn check_slice(
+    repo: &mut Repo,
+    repo_path: &Path,
+    subdir: &RelativePathBuf,
+    recursive: bool,
+) -> Result<Result<SyncDelta, SyncDelta>, SyncError> {
+    let options = repo.config().scan_options();
+    let new_paths = scan_subdir(repo_path, subdir, recursive, options)?;
+    let threshold = repo.config().sync_confirmation_threshold;
+    let delta = repo.plan_sync_subdir(subdir, recursive, new_paths)?;
+    if threshold > 0 && delta.retagged_lost > threshold {
+        return Ok(Err(delta));
     }
+    repo.apply_sync(&delta, |_| {})?;
+    let modified = repo.refresh_mtimes(subdir, recursive)?;
+    if !modified.is_empty() {
+        debug!("{} files were modified in {:?}", modified.len(), subdir);
+    }
+    Ok(Ok(delta))
+}
 
-    pub async fn insert_tags(
-        &self,
-        ids: Vec<i64>,
-        tags: Vec<String>,
-    ) -> Result<(), InsertTagsError> {
-        if ids.len() == 0 {
-            return Ok(());
+/// List the slices to be checked by the consistency check: the files in the repo root, then
+/// every top-level folder either on disk or in the database.
+fn consistency_check_slices(
+    repo: &Repo,
+    repo_path: &Path,
+) -> Result<Vec<(RelativePathBuf, bool)>, SyncError> {
+    let mut dirs = top_level_dirs(repo_path, &repo.config().scan_options())?;
+    for folder in repo.top_level_folders()? {
+        let folder = RelativePathBuf::from(folder);
+        if !dirs.contains(&folder) {
+            dirs.push(folder);
         }
-        // clone a reference to the repo
-        let repo = self.repo.clone();
-        let app_handle = self.app_handle.clone();
-        tokio::task::spawn_blocking(move || {
-            let repo = block_on(async { repo.lock().await });
-            let ids = ids;
-            if ids.len() == 1 {
-                let rv = repo.insert_tags(*ids.get(0).unwrap(), tags);
-                match rv {
-                    Ok(_) => {
-                        let item = repo
-                            .get_item_by_id(*ids.get(0).unwrap())
-                            .expect("failed to get item after inserting tags");
-                        app_handle
-                            .emit_all("item-tags-added", ItemDetails::from_item(item))
-                            .expect("Failed to emit event");
-                        Ok(())
-                    }
-                    Err(err) => Err(err),
-                }
-            } else {
-                let rv = repo.batch_insert_tags(&ids, tags);
-                match rv {
-                    Ok(_) => {
-                        let items: Result<Vec<_>, _> = ids
-                            .iter()
-                            .map(|id| {
-                                Ok::<_, SearchError>(ItemDetails::from_item(
-                                    repo.get_item_by_id(*id)?,
-                                ))
-                            })
-                            .collect();
-                        let items = items.expect("failed to get items after batch-inserting tags");
-                        app_handle
-                            .emit_all("batch-item-tags-added", items)
-                            .expect("Failed to emit event");
-                        Ok(())
-                    }
-                    Err(err) => Err(err),
-                }
-            }
-        })
-        .await
-        .expect("failed to join with thread that's inserting tags")?;
-        Ok(())
     }
+    let mut slices = vec![(RelativePathBuf::new(), false)];
+    slices.extend(dirs.into_iter().map(|dir| (dir, true)));
+    // slices are popped from the end of the list
+    slices.reverse();
+    Ok(slices)
+}
 
-    #[instrument]
-    pub async fn remove_tags(
-        &self,
-        ids: Vec<i64>,
-        tags: Vec<String>,
-    ) -> Result<(), RemoveTagsError> {
-        // clone a reference to the repo
-        let repo = self.repo.clone();
-        let app_handle = self.app_handle.clone();
-        tokio::task::spawn_blocking(move || {
-            let repo = block_on(async { repo.lock().await });
-            let ids = ids;
-            if ids.len() == 1 {
-                let rv = repo.remove_tags(*ids.get(0).unwrap(), tags);
-                match rv {
-                    Ok(_) => {
-                        let item = repo
-                            .get_item_by_id(*ids.get(0).unwrap())
-                            .expect("failed to get item after removing tags");
-                        app_handle
-                            .emit_all("item-tags-removed", ItemDetails::from_item(item))
-                            .expect("Failed to emit event");
-                        Ok(())
-                    }
-                    Err(err) => Err(err),
+/// Periodically re-scan a small slice of the repo to catch any changes that the watcher missed.
+/// Each tick checks one slice, so the whole repo is checked after enough ticks. Like
+/// `auto_resync()`, ticks are skipped while the manager is busy with a sync or waiting for one to
+/// be confirmed.
+async fn consistency_check<R: Runtime>(
+    writer: RepoWriter,
+    repo_path: PathBuf,
+    status: Arc<RwLock<ManagerStatus>>,
+    app_handle: AppHandle<R>,
+    interval: Duration,
+) {
+    debug!("consistency check started!");
+    let mut slices: Vec<(RelativePathBuf, bool)> = vec![];
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately, skip it since the repo was just synced
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+
+        if !matches!(*status.read().await, ManagerStatus::Idle) {
+            debug!("manager is busy, skipping consistency check");
+            continue;
+        }
+
+        let repo_path = repo_path.clone();
+        let pending_slices = std::mem::take(&mut slices);
+        let rv = writer
+            .run(move |repo| {
+                let mut slices = pending_slices;
+                if slices.is_empty() {
+                    slices = consistency_check_slices(repo, &repo_path)?;
                 }
-            } else {
-                let rv = repo.batch_remove_tags(&ids, tags);
-                match rv {
-                    Ok(_) => {
-                        let items: Result<Vec<_>, _> = ids
-                            .iter()
-                            .map(|id| {
-                                Ok::<_, SearchError>(ItemDetails::from_item(
-                                    repo.get_item_by_id(*id)?,
-                                ))
-                            })
-                            .collect();
-                        let items = items.expect("failed to get items after batch-removing tags");
+                let Some((subdir, recursive)) = slices.pop() else {
+                    return Ok((slices, None));
+                };
+                let delta = check_slice(repo, &repo_path, &subdir, recursive)?;
+                Ok::<_, SyncError>((slices, Some((subdir, delta))))
+            })
+            .await;
+
+        match rv {
+            Ok(Ok((remaining_slices, checked))) => {
+                slices = remaining_slices;
+                match checked {
+                    Some((_, Ok(delta))) if !delta.is_empty() => {
+                        debug!("consistency check repaired items: {:?}", delta);
                         app_handle
-                            .emit_all("batch-item-tags-removed", items)
+                            .emit_all_and_forward("consistency-repaired", delta)
                             .expect("Failed to emit event");
-                        Ok(())
                     }
-                    Err(err) => Err(err),
+                    Some((subdir, Err(delta))) => report_error(
+                        &app_handle,
+                        "consistency-check",
+                        ErrorSeverity::Warning,
+                        format!(
+                            "skipped fixing {:?} since it would delete {} items with tags, \
+                             resync the repo manually to confirm it",
+                            subdir.as_str(),
+                            delta.retagged_lost
+                        ),
+                    ),
+                    _ => {}
                 }
             }
-        })
-        .await
-        .expect("failed to join with thread that's removing tags")?;
-        Ok(())
+            Ok(Err(err)) => report_error(
+                &app_handle,
+                "consistency-check",
+                ErrorSeverity::Warning,
+                format!("consistency check failed: {}", err),
+            ),
+            Err(WriterStopped) => break,
+        }
     }
+    debug!("consistency check ended!");
+}
 
-    pub async fn watch(&self) -> Result<(), WatchError> {
-        // check there isn't already a watcher
-        {
-            let watcher = self.watcher.read().await;
-            // if there is already watcher, raise error
-            if watcher.is_some() {
-                return Err(WatchError::AlreadyWatching);
+/// A random delay of up to a tenth of `interval`, so repos opened at the same time don't all scan
+/// a shared drive at once.
+fn resync_jitter(interval: Duration) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or(0);
+    let max = (interval / 10).as_millis().max(1) as u64;
+    Duration::from_millis(nanos as u64 % max)
+}
+
+/// Periodically resync the whole repo while it isn't being watched, e.g. on network drives where
+/// the watcher can't start. Ticks are skipped while the watcher is running or while the manager is
+/// busy with another sync. Syncs that would delete more tagged items than allowed by the repo
+/// config are skipped as well, since nobody is asked to confirm them.
+async fn auto_resync<R: Runtime>(
+    writer: RepoWriter,
+    repo_path: PathBuf,
+    shared_watcher: SharedWatcher,
+    status: Arc<RwLock<ManagerStatus>>,
+    app_handle: AppHandle<R>,
+    interval: Duration,
+) {
+    debug!("auto resync started!");
+    loop {
+        tokio::time::sleep(interval + resync_jitter(interval)).await;
+
+        if shared_watcher.read().await.is_some() {
+            continue;
+        }
+        if !matches!(*status.read().await, ManagerStatus::Idle) {
+            debug!("manager is busy, skipping auto resync");
+            continue;
+        }
+
+        let Ok(options) = writer.run(|repo| repo.config().scan_options()).await else {
+            break;
+        };
+        let path = repo_path.clone();
+        let new_paths = tokio::task::spawn_blocking(move || scan_dir(path, options))
+            .await
+            .expect("failed to join with thread that's scanning a directory");
+        let new_paths = match new_paths {
+            Ok(new_paths) => new_paths,
+            Err(err) => {
+                report_error(
+                    &app_handle,
+                    "auto-resync",
+                    ErrorSeverity::Warning,
+                    format!("failed to scan repo: {}", err),
+                );
+                continue;
+            }
+        };
+
+        let handle = app_handle.clone();
+        let rv = writer
+            .run(move |repo| {
+                let threshold = repo.config().sync_confirmation_threshold;
+                let delta = repo.plan_sync(new_paths)?;
+                if threshold > 0 && delta.retagged_lost > threshold {
+                    return Ok(Err(delta));
+                }
+                Ok::<_, SyncError>(Ok(apply_sync_delta(repo, &delta, &handle)?))
+            })
+            .await;
+        match rv {
+            Ok(Ok(Ok(report))) => {
+                if report.total() > 0 {
+                    debug!("auto resync changed {} items", report.total());
+                    app_handle
+                        .emit_all_and_forward("sync-report", report)
+                        .expect("Failed to emit event");
+                }
+            }
+            Ok(Ok(Err(delta))) => report_error(
+                &app_handle,
+                "auto-resync",
+                ErrorSeverity::Warning,
+                format!(
+                    "skipped resync that would delete {} items with tags, resync the repo \
+                     manually to confirm it",
+                    delta.retagged_lost
+                ),
+            ),
+            Ok(Err(err)) => report_error(
+                &app_handle,
+                "auto-resync",
+                ErrorSeverity::Warning,
+                format!("auto resync failed: {}", err),
+            ),
+            Err(WriterStopped) => break,
+        }
+    }
+    debug!("auto resync ended!");
+}
+
+/// Hash the files of tagged items in the background, so their tags can be restored if the files
+/// are moved out of the repo and back. Files are hashed outside the writer, so large files don't
+/// block other operations.
+async fn hash_items<R: Runtime>(writer: RepoWriter, repo_path: PathBuf, app_handle: AppHandle<R>) {
+    debug!("hashing started!");
+    let mut after_id = 0;
+    loop {
+        let items = writer
+            .run(move |repo| repo.unhashed_items(after_id, HASH_BATCH_SIZE))
+            .await;
+        let items = match items {
+            Ok(Ok(items)) => items,
+            Ok(Err(err)) => {
+                report_error(
+                    &app_handle,
+                    "hashing",
+                    ErrorSeverity::Warning,
+                    format!("failed to fetch items to hash: {}", err),
+                );
+                vec![]
+            }
+            Err(WriterStopped) => break,
+        };
+        let Some(&(last_id, _)) = items.last() else {
+            // every tagged item has been hashed, wait for more items to be tagged
+            after_id = 0;
+            tokio::time::sleep(HASH_INTERVAL).await;
+            continue;
+        };
+        after_id = last_id;
+
+        let repo_path = repo_path.clone();
+        let hashes = tokio::task::spawn_blocking(move || {
+            items
+                .into_iter()
+                .filter_map(|(id, path)| {
+                    // files that can't be read are skipped, and retried on the next pass
+                    let full_path = repo_path.join(path);
+                    let size = std::fs::metadata(&full_path).ok()?.len() as i64;
+                    let hash = hash_file(&full_path).ok()?;
+                    Some((id, size, hash))
+                })
+                .collect_vec()
+        })
+        .await
+        .expect("failed to join with thread that's hashing items");
+
+        let handle = app_handle.clone();
+        let stored = writer
+            .run(move |repo| {
+                for (id, size, hash) in hashes {
+                    if let Err(err) = repo.set_hash(id, size, &hash) {
+                        report_error(
+                            &handle,
+                            "hashing",
+                            ErrorSeverity::Warning,
+                            format!("failed to store hash of item {}: {}", id, err),
+                        );
+                    }
+                }
+            })
+            .await;
+        if stored.is_err() {
+            break;
+        }
+    }
+    debug!("hashing ended!");
+}
+
+/// Fingerprint the audio files of items in the background, so similar sounding items can be
+/// found. Like `hash_items()`, files are decoded outside the writer.
+#[cfg(feature = "audio")]
+async fn fingerprint_items<R: Runtime>(
+    writer: RepoWriter,
+    repo_path: PathBuf,
+    app_handle: AppHandle<R>,
+) {
+    debug!("fingerprinting started!");
+    let mut after_id = 0;
+    loop {
+        let items = writer
+            .run(move |repo| repo.unfingerprinted_items(after_id, FINGERPRINT_BATCH_SIZE))
+            .await;
+        let items = match items {
+            Ok(Ok(items)) => items,
+            Ok(Err(err)) => {
+                report_error(
+                    &app_handle,
+                    "fingerprinting",
+                    ErrorSeverity::Warning,
+                    format!("failed to fetch items to fingerprint: {}", err),
+                );
+                vec![]
+            }
+            Err(WriterStopped) => break,
+        };
+        let Some(&(last_id, _)) = items.last() else {
+            // every item has been fingerprinted, wait for more items to be added
+            after_id = 0;
+            tokio::time::sleep(FINGERPRINT_INTERVAL).await;
+            continue;
+        };
+        after_id = last_id;
+
+        let repo_path = repo_path.clone();
+        let fingerprints = tokio::task::spawn_blocking(move || {
+            items
+                .into_iter()
+                .filter_map(|(id, path)| {
+                    let full_path = repo_path.join(path);
+                    if !matches!(determine_filetype(&full_path), FileType::Audio) {
+                        return Some((id, None));
+                    }
+                    // files that can't be read are skipped, and retried on the next pass
+                    let fingerprint = similarity::fingerprint_file(&full_path).ok()?;
+                    Some((id, fingerprint))
+                })
+                .collect_vec()
+        })
+        .await
+        .expect("failed to join with thread that's fingerprinting items");
+
+        let handle = app_handle.clone();
+        let stored = writer
+            .run(move |repo| {
+                for (id, fingerprint) in fingerprints {
+                    if let Err(err) = repo.set_fingerprint(id, fingerprint.as_deref()) {
+                        report_error(
+                            &handle,
+                            "fingerprinting",
+                            ErrorSeverity::Warning,
+                            format!("failed to store fingerprint of item {}: {}", id, err),
+                        );
+                    }
+                }
+            })
+            .await;
+        if stored.is_err() {
+            break;
+        }
+    }
+    debug!("fingerprinting ended!");
+}
+
+/// Extract the text of documents in the background, so they can be searched with `content:`. Like
+/// `hash_items()`, files are read outside the writer.
+async fn extract_contents<R: Runtime>(
+    writer: RepoWriter,
+    repo_path: PathBuf,
+    app_handle: AppHandle<R>,
+) {
+    debug!("text extraction started!");
+    let mut after_id = 0;
+    loop {
+        let items = writer
+            .run(move |repo| repo.unextracted_items(after_id, EXTRACT_BATCH_SIZE))
+            .await;
+        let items = match items {
+            Ok(Ok(items)) => items,
+            Ok(Err(err)) => {
+                report_error(
+                    &app_handle,
+                    "text extraction",
+                    ErrorSeverity::Warning,
+                    format!("failed to fetch documents to extract: {}", err),
+                );
+                vec![]
+            }
+            Err(WriterStopped) => break,
+        };
+        let Some(&(last_id, _)) = items.last() else {
+            // every document has been extracted, wait for more documents to be added
+            after_id = 0;
+            tokio::time::sleep(EXTRACT_INTERVAL).await;
+            continue;
+        };
+        after_id = last_id;
+
+        let repo_path = repo_path.clone();
+        let contents = tokio::task::spawn_blocking(move || {
+            items
+                .into_iter()
+                .filter_map(|(id, path)| {
+                    // files that can't be read are skipped, and retried on the next pass
+                    let text = content::extract_text(repo_path.join(path)).ok()?;
+                    Some((id, text.unwrap_or_default()))
+                })
+                .collect_vec()
+        })
+        .await
+        .expect("failed to join with thread that's extracting text");
+
+        let handle = app_handle.clone();
+        let stored = writer
+            .run(move |repo| {
+                for (id, text) in contents {
+                    if let Err(err) = repo.set_content(id, &text) {
+                        report_error(
+                            &handle,
+                            "text extraction",
+                            ErrorSeverity::Warning,
+                            format!("failed to store text of item {}: {}", id, err),
+                        );
+                    }
+                }
+            })
+            .await;
+        if stored.is_err() {
+            break;
+        }
+    }
+    debug!("text extraction ended!");
+}
+
+/// Apply a planned sync to the repo, then run maintenance if many items were changed.
+fn apply_sync_delta<R: Runtime>(
+    repo: &mut Repo,
+    delta: &SyncDelta,
+    app_handle: &AppHandle<R>,
+) -> Result<SyncReport, SyncError> {
+    repo.apply_sync(delta, |progress| {
+        app_handle
+            .emit_all_and_forward("sync-progress", progress)
+            .expect("Failed to emit event");
+    })?;
+    // files may have been edited while the repo wasn't watched
+    let modified = repo.refresh_mtimes(RelativePath::new(""), true)?;
+    if !modified.is_empty() {
+        debug!("{} files were modified since the last sync", modified.len());
+    }
+    let report = delta.report();
+    if report.total() >= LARGE_SYNC_THRESHOLD {
+        debug!(
+            "large sync of {} items, running maintenance",
+            report.total()
+        );
+        // the sync itself succeeded, so don't fail the resync if this fails
+        if let Err(err) = repo.maintenance() {
+            report_error(
+                app_handle,
+                "maintenance",
+                ErrorSeverity::Warning,
+                format!("failed to run maintenance after sync: {}", err),
+            );
+        }
+    }
+    Ok(report)
+}
+
+/// Run a read-only operation on a connection from the read pool, or on the writer if a reader
+/// can't be opened.
+async fn read_with<T: Send + 'static, R: Runtime>(
+    read_pool: Arc<ReadPool>,
+    writer: &RepoWriter,
+    app_handle: &AppHandle<R>,
+    f: impl FnOnce(&Repo) -> T + Send + 'static,
+) -> Result<T, WriterStopped> {
+    let reader = tokio::task::spawn_blocking(move || read_pool.get())
+        .await
+        .expect("failed to join with thread that's opening a reader");
+    match reader {
+        Ok(reader) => Ok(tokio::task::spawn_blocking(move || f(&reader))
+            .await
+            .expect("failed to join with thread that's reading the database")),
+        Err(err) => {
+            report_error(
+                app_handle,
+                "database",
+                ErrorSeverity::Warning,
+                format!("failed to open reader, using main connection: {}", err),
+            );
+            writer.run(move |repo| f(repo)).await
+        }
+    }
+}
+
+/// Emit one chunk of a streamed query's results.
+fn emit_query_chunk<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    stream_id: u64,
+    ids: Vec<i64>,
+    done: bool,
+    error: Option<String>,
+) {
+    let chunk = QueryResultsChunk { stream_id, ids, done, error };
+    // results are only for the window that streamed the query, so they aren't forwarded to
+    // external subscribers
+    if let Err(err) = app_handle.emit_all("query-results-chunk", chunk) {
+        error!("failed to emit query results: {:?}", err);
+    }
+}
+
+enum SyncOutcome {
+    Applied(SyncReport),
+    /// The sync would delete too many tagged items. The scanned paths stay staged in the repo
+    /// until the user confirms or rejects the sync.
+    NeedsConfirmation(SyncDelta),
+}
+
+#[derive(Error, Debug)]
+pub enum ConfirmSyncError {
+    #[error("no sync is waiting for confirmation")]
+    NoPendingSync,
+    #[error("{0}")]
+    SyncError(#[from] SyncError),
+}
+
+#[derive(Error, Debug)]
+pub enum WatchError {
+    #[error("failed to watch path")]
+    CannotWatchPath(#[from] notify::Error),
+    #[error("already watching path")]
+    AlreadyWatching,
+    #[error("not watching repo, start watching the repo first")]
+    NotWatching,
+    #[error("{0}")]
+    PathOutsideRepo(#[from] PathOutsideRepo),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+#[derive(Error, Debug)]
+pub enum WriteTagsError {
+    #[error("{0}")]
+    SearchError(#[from] SearchError),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+#[derive(Error, Debug)]
+pub enum UnwatchError {
+    #[error("not watching path, cannot unwatch")]
+    NotWatching,
+    #[error("failed to unwatch path")]
+    CannotUnwatchPath(#[from] notify::Error),
+}
+
+/// The filesystem watcher of a repo, and the paths registered with it.
+#[derive(Debug)]
+struct WatcherState {
+    watcher: RepoWatcher,
+    paths: Vec<(PathBuf, RecursiveMode)>,
+    /// Reused when the watcher is restarted
+    settings: WatcherSettings,
+}
+
+/// How a watcher is created, see `best_for()`.
+#[derive(Debug, Clone)]
+struct WatcherSettings {
+    strategy: WatcherStrategy,
+    poll_interval: Duration,
+    pairing: PairingOptions,
+    /// Kept by the manager and updated from the watcher's events
+    dirs: SharedDirs,
+    /// Creates the watcher instead of `best_for()`, see `RepoManager::watch_with()`
+    factory: Option<WatcherFactory>,
+}
+
+/// The watcher is shared with its event handler, so the handler can replace it if it fails.
+type SharedWatcher = Arc<RwLock<Option<WatcherState>>>;
+
+/// Whether the event handler of the watcher is paused, see `RepoManager::pause_watching()`.
+#[derive(Debug, Default)]
+struct WatchPause {
+    /// Number of operations that paused watching, so overlapping operations don't resume it early
+    count: usize,
+    /// Folders that had events while watching was paused
+    skipped: HashSet<PathBuf>,
+}
+
+impl WatchPause {
+    /// Remember the folders of skipped events. Parent folders are used since the paths may have
+    /// been deleted, or be files.
+    fn skip(&mut self, paths: &[PathBuf], repo_path: &Path) {
+        if paths.is_empty() {
+            // the event doesn't say what changed, e.g. events were lost
+            self.skipped.insert(repo_path.to_path_buf());
+        }
+        for path in paths {
+            let dir = match path.parent() {
+                Some(dir) if dir.starts_with(repo_path) => dir,
+                _ => repo_path,
+            };
+            self.skipped.insert(dir.to_path_buf());
+        }
+    }
+
+    /// The skipped folders, without folders that are inside another skipped folder.
+    fn take_skipped(&mut self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self.skipped.drain().collect();
+        dirs.sort();
+        let mut rv: Vec<PathBuf> = vec![];
+        for dir in dirs {
+            if !rv.iter().any(|x| dir.starts_with(x)) {
+                rv.push(dir);
+            }
+        }
+        rv
+    }
+}
+
+/// The pause is kept by the manager and shared with each watcher, so it survives restarts.
+type SharedPause = Arc<std::sync::Mutex<WatchPause>>;
+
+/// The folder tree of the repo, and the change sequence number it's up to date with.
+#[derive(Debug)]
+struct CachedDirTree {
+    seq: i64,
+    tree: DirTree,
+}
+
+/// The folder tree is cached by the manager and updated from watcher events, see
+/// `RepoManager::get_dir_structure()`.
+type SharedDirs = Arc<std::sync::Mutex<Option<CachedDirTree>>>;
+
+#[derive(Debug)]
+pub struct RepoManager<R: Runtime> {
+    repo: Arc<Mutex<Repo>>,
+    /// Queue of mutations of the repo, see `RepoWriter`
+    writer: RepoWriter,
+    writer_task: JoinHandle<()>,
+    /// Connections used for reads, so they don't wait for the writer
+    read_pool: Arc<ReadPool>,
+    /// Shared with the auto resync task, so it can skip ticks while another sync is running
+    status: Arc<RwLock<ManagerStatus>>,
+    path: PathBuf,
+    mode: OpenMode,
+    watcher: SharedWatcher,
+    watch_pause: SharedPause,
+    dir_tree: SharedDirs,
+    /// Kept across watchers, so the stats cover the whole time the repo is open
+    pairing_metrics: Arc<PairingMetrics>,
+    consistency_check: RwLock<Option<JoinHandle<()>>>,
+    auto_resync: RwLock<Option<JoinHandle<()>>>,
+    hashing: RwLock<Option<JoinHandle<()>>>,
+    fingerprinting: RwLock<Option<JoinHandle<()>>>,
+    extraction: RwLock<Option<JoinHandle<()>>>,
+    change_feed: RwLock<Option<JoinHandle<()>>>,
+    /// The latest change sequence number sent to the frontend
+    change_seq: Arc<AtomicI64>,
+    /// Whether a resync is waiting for confirmation, its scanned paths are staged in the repo
+    pending_sync: Mutex<bool>,
+    /// Cancel flags of streamed queries that are still running, by stream id
+    query_streams: Arc<std::sync::Mutex<HashMap<u64, Arc<AtomicBool>>>>,
+    next_stream_id: AtomicU64,
+    app_handle: AppHandle<R>,
+}
+
+impl<R: Runtime> RepoManager<R> {
+    pub fn new(
+        path: impl AsRef<Path>,
+        mode: OpenMode,
+        app_handle: AppHandle<R>,
+    ) -> Result<Self, OpenError> {
+        let path = path.as_ref();
+        let repo = Repo::open(&path, mode)?;
+        let change_seq = repo.change_seq()?;
+        let read_pool = ReadPool::new(&repo);
+        let repo = Arc::new(Mutex::new(repo));
+        let (writer, writer_task) = RepoWriter::spawn(repo.clone());
+        let manager = Self {
+            repo,
+            writer,
+            writer_task,
+            read_pool,
+            status: Arc::new(RwLock::new(ManagerStatus::Idle)),
+            path: path.to_path_buf(),
+            mode,
+            watcher: Arc::new(RwLock::new(None)),
+            watch_pause: Default::default(),
+            dir_tree: Default::default(),
+            pairing_metrics: Default::default(),
+            consistency_check: RwLock::new(None),
+            auto_resync: RwLock::new(None),
+            hashing: RwLock::new(None),
+            fingerprinting: RwLock::new(None),
+            extraction: RwLock::new(None),
+            change_feed: RwLock::new(None),
+            change_seq: Arc::new(AtomicI64::new(change_seq)),
+            pending_sync: Mutex::new(false),
+            query_streams: Default::default(),
+            next_stream_id: AtomicU64::new(0),
+            app_handle,
+        };
+        Ok(manager)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.mode == OpenMode::ReadOnly
+    }
+
+    pub async fn status(&self) -> ManagerStatus {
+        *self.status.read().await
+    }
+
+    pub async fn update_status(&self, status: ManagerStatus) {
+        *self.status.write().await = status;
+        self.app_handle
+            .emit_all_and_forward("status-changed", status)
+            .expect("Failed to emit event");
+    }
+
+    /// Scan the repo folder and update the repo to match it. If the sync would delete more tagged
+    /// items than allowed by the repo config, the repo is left unchanged and `None` is returned.
+    /// The sync must then be confirmed with `confirm_sync()`.
+    pub async fn resync(&self) -> Result<Option<SyncReport>, SyncError> {
+        if self.read_only() {
+            return Err(RepoReadOnly.into());
+        }
+        self.update_status(ManagerStatus::ScanningDirectory).await;
+        let path = self.path.clone();
+        let options = self.repo.lock().await.config().scan_options();
+        let (tx, mut rx) = unbounded_channel();
+        let scan = tokio::task::spawn_blocking(move || {
+            let _perf = perf::measure("scan");
+            scan_dir_batched(path, options, |batch| {
+                // the receiver only stops listening if resync is cancelled
+                let _ = tx.send(batch);
+            })
+        });
+        // batches are staged in the repo while the scan continues, but the changes can only be
+        // planned once the scan is complete, since deleted and renamed paths can't be detected
+        // until all paths are known
+        self.write(|repo| repo.begin_staged_sync()).await?;
+        let mut count = 0;
+        while let Some(batch) = rx.recv().await {
+            count += batch.len();
+            self.app_handle
+                .emit_all_and_forward("scan-progress", count)
+                .expect("Failed to emit event");
+            self.write(move |repo| repo.stage_sync_paths(batch)).await?;
+        }
+        scan.await
+            .expect("failed to join with thread that's scanning a directory")?;
+
+        self.update_status(ManagerStatus::UpdatingRepo).await;
+        let outcome = {
+            let app_handle = self.app_handle.clone();
+            self.write(move |repo| {
+                let threshold = repo.config().sync_confirmation_threshold;
+                let delta = repo.plan_staged_sync()?;
+                if threshold > 0 && delta.retagged_lost > threshold {
+                    return Ok(SyncOutcome::NeedsConfirmation(delta));
+                }
+                let report = apply_sync_delta(repo, &delta, &app_handle)?;
+                repo.clear_staged_sync()?;
+                Ok::<_, SyncError>(SyncOutcome::Applied(report))
+            })
+            .await?
+        };
+
+        match outcome {
+            SyncOutcome::Applied(report) => {
+                self.finish_sync(report).await;
+                Ok(Some(report))
+            }
+            SyncOutcome::NeedsConfirmation(delta) => {
+                debug!(
+                    "sync would delete {} tagged items, waiting for confirmation",
+                    delta.retagged_lost
+                );
+                *self.pending_sync.lock().await = true;
+                self.update_status(ManagerStatus::AwaitingSyncConfirmation)
+                    .await;
+                self.app_handle
+                    .emit_all_and_forward("sync-confirmation-required", delta)
+                    .expect("Failed to emit event");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Apply or discard a resync that is waiting for confirmation. If the sync is accepted, it is
+    /// planned again in case the repo has changed since the scan.
+    pub async fn confirm_sync(&self, accept: bool) -> Result<Option<SyncReport>, ConfirmSyncError> {
+        if !std::mem::take(&mut *self.pending_sync.lock().await) {
+            return Err(ConfirmSyncError::NoPendingSync);
+        }
+        if !accept {
+            self.write(|repo| repo.clear_staged_sync())
+                .await
+                .map_err(SyncError::from)?;
+            self.update_status(ManagerStatus::Idle).await;
+            return Ok(None);
+        }
+
+        self.update_status(ManagerStatus::UpdatingRepo).await;
+        let report = {
+            let app_handle = self.app_handle.clone();
+            self.write(move |repo| {
+                let delta = repo.plan_staged_sync()?;
+                let report = apply_sync_delta(repo, &delta, &app_handle)?;
+                repo.clear_staged_sync()?;
+                Ok::<_, SyncError>(report)
+            })
+            .await?
+        };
+        self.finish_sync(report).await;
+        self.app_handle
+            .emit_all_and_forward("repo-resynced", Some(self.path.clone()))
+            .expect("Failed to emit event");
+        Ok(Some(report))
+    }
+
+    async fn finish_sync(&self, report: SyncReport) {
+        if report.retagged_lost > 0 {
+            warn!("sync deleted {} items with tags", report.retagged_lost);
+        }
+        self.app_handle
+            .emit_all_and_forward("sync-report", report)
+            .expect("Failed to emit event");
+        self.update_status(ManagerStatus::Idle).await;
+    }
+
+    /// Rescan a single folder of the repo and sync the items inside it, e.g. when the user knows
+    /// the folder has changed while the watcher wasn't running.
+    pub async fn resync_subtree(&self, subdir: RelativePathBuf) -> Result<SyncDelta, SyncError> {
+        if self.read_only() {
+            return Err(RepoReadOnly.into());
+        }
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        let delta = self
+            .write(move |repo| {
+                let delta = repo.sync_subtree(&subdir)?;
+                emit_changes(repo, &app_handle, &change_seq);
+                Ok::<_, SyncError>(delta)
+            })
+            .await?;
+        if delta.retagged_lost > 0 {
+            warn!("sync deleted {} items with tags", delta.retagged_lost);
+        }
+        Ok(delta)
+    }
+
+    /// Run an operation on the main connection on a separate thread, since database calls block.
+    /// The repo is locked before the thread is started, so blocking threads never wait for the
+    /// lock.
+    ///
+    /// Panics if the operation panics, like a panicking blocking task would. Other queued
+    /// operations still run.
+    async fn write<T: Send + 'static>(&self, f: impl FnOnce(&mut Repo) -> T + Send + 'static) -> T {
+        self.writer
+            .run(f)
+            .await
+            .expect("repo writer stopped or the operation panicked")
+    }
+
+    /// Run a read-only operation on a separate thread. This uses a connection from the read pool,
+    /// so it doesn't wait for writes on the main connection to finish.
+    async fn read<T: Send + 'static>(&self, f: impl FnOnce(&Repo) -> T + Send + 'static) -> T {
+        read_with(self.read_pool.clone(), &self.writer, &self.app_handle, f)
+            .await
+            .expect("repo writer stopped or the operation panicked")
+    }
+
+    /// Ids of items matching the query. If `scope_dir` is given, only items inside that folder
+    /// are returned.
+    pub async fn query(
+        &self,
+        query: &str,
+        scope_dir: Option<String>,
+    ) -> Result<Vec<i64>, QueryError> {
+        let query = query.to_string();
+        self.read(move |repo| repo.query_ids_in_dir(&query, scope_dir.as_deref()))
+            .await
+    }
+
+    /// Query item ids on a separate thread, emitting `query-results-chunk` events with up to
+    /// `chunk_size` ids as rows are read, so large results can be shown before the query finishes.
+    /// Returns the stream's id, which can be passed to `cancel_query_stream()`. Invalid queries
+    /// fail immediately instead of being streamed.
+    pub fn query_streamed(
+        &self,
+        query: String,
+        scope_dir: Option<String>,
+        chunk_size: usize,
+    ) -> Result<u64, QueryError> {
+        to_sql(&query)?;
+        let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.query_streams
+            .lock()
+            .unwrap()
+            .insert(stream_id, cancelled.clone());
+
+        let writer = self.writer.clone();
+        let read_pool = self.read_pool.clone();
+        let query_streams = self.query_streams.clone();
+        let app_handle = self.app_handle.clone();
+        tokio::spawn(async move {
+            let handle = app_handle.clone();
+            let stream = move |repo: &Repo| {
+                repo.query_ids_chunked(&query, scope_dir.as_deref(), chunk_size, |ids| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return false;
+                    }
+                    emit_query_chunk(&handle, stream_id, ids, false, None);
+                    true
+                })
+                .map_err(|err| err.to_string())
+            };
+            let result = read_with(read_pool, &writer, &app_handle, stream)
+                .await
+                .unwrap_or_else(|err| Err(err.to_string()));
+            query_streams.lock().unwrap().remove(&stream_id);
+            emit_query_chunk(&app_handle, stream_id, vec![], true, result.err());
+        });
+        Ok(stream_id)
+    }
+
+    /// Stop a streamed query, no more chunks are emitted except for the final `done` event.
+    /// Returns false if the stream has already finished.
+    pub fn cancel_query_stream(&self, stream_id: u64) -> bool {
+        match self.query_streams.lock().unwrap().get(&stream_id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Write the items matching the query to a playlist or CSV file, returning the number of
+    /// items written.
+    pub async fn export_query_results(
+        &self,
+        query: String,
+        format: ExportFormat,
+        path: PathBuf,
+    ) -> Result<usize, ExportError> {
+        let items = self.read(move |repo| repo.query_items(&query)).await?;
+        let repo_path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            export_items(&items, &repo_path, format, path)?;
+            Ok(items.len())
+        })
+        .await
+        .expect("failed to join with thread that's exporting items")
+    }
+
+    /// The absolute paths of the items matching the query.
+    pub async fn query_paths(&self, query: String) -> Result<Vec<PathBuf>, QueryError> {
+        let items = self.read(move |repo| repo.query_items(&query)).await?;
+        let paths = items
+            .iter()
+            .map(|item| RelativePath::new(&item.path).to_path(&self.path))
+            .collect();
+        Ok(paths)
+    }
+
+    /// Copy the items in the folder `dir` to the folder `dest` outside the repo, along with a
+    /// manifest of their tags, see `export_subtree()`. The copy can be merged into another repo
+    /// with `import_subtree()`.
+    pub async fn export_subtree(
+        &self,
+        dir: String,
+        dest: PathBuf,
+    ) -> Result<SubtreeReport, SubtreeError> {
+        let dir = parse_folder(&dir)?;
+        let items = {
+            let dir = dir.clone();
+            self.read(move |repo| repo.items_in_dir(&dir)).await?
+        };
+        let repo_path = self.path.clone();
+        tokio::task::spawn_blocking(move || export_subtree(&items, &dir, &repo_path, &dest))
+            .await
+            .expect("failed to join with thread that's exporting items")
+    }
+
+    /// Copy a folder written by `export_subtree()` into the folder `dir` of this repo, and add
+    /// its files as items with their tags. Files that already exist in the repo are skipped.
+    pub async fn import_subtree(
+        &self,
+        src: PathBuf,
+        dir: String,
+    ) -> Result<SubtreeReport, SubtreeError> {
+        if self.read_only() {
+            return Err(RepoReadOnly.into());
+        }
+        let dir = parse_folder(&dir)?;
+        let repo_path = self.path.clone();
+        // the items are added below, so the watcher doesn't need to add them
+        self.pause_watching();
+        let result = tokio::task::spawn_blocking(move || import_subtree(&src, &dir, &repo_path))
+            .await
+            .expect("failed to join with thread that's importing items");
+        let result = match result {
+            Ok((report, files)) => {
+                let app_handle = self.app_handle.clone();
+                let change_seq = self.change_seq.clone();
+                self.write(move |repo| {
+                    repo.insert_imported_files(&files)?;
+                    emit_changes(repo, &app_handle, &change_seq);
+                    Ok(report)
+                })
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        self.resume_watching().await;
+        result
+    }
+
+    pub async fn count(&self, query: &str) -> Result<i64, QueryError> {
+        let query = query.to_string();
+        self.read(move |repo| repo.count(&query)).await
+    }
+
+    pub async fn optimize(&self) -> Result<(), MaintenanceError> {
+        self.write(|repo| repo.maintenance()).await
+    }
+
+    /// The folders containing items. The tree is cached and kept up to date by the watcher, it's
+    /// only rebuilt if the items were changed some other way.
+    pub async fn get_dir_structure(&self) -> Result<FolderBuf, DirStructureError> {
+        let dirs = self.dir_tree.clone();
+        let app_handle = self.app_handle.clone();
+        // this runs in the writer, so the watcher can't update the tree while it's rebuilt
+        self.write(move |repo| -> Result<FolderBuf, DirStructureError> {
+            let mut dirs = dirs.lock().unwrap();
+            refresh_dir_tree(repo, &mut dirs, &app_handle)?;
+            let cached = dirs.as_ref().expect("folder tree wasn't built");
+            Ok(cached.tree.structure().clone())
+        })
+        .await
+    }
+
+    /// The folders directly inside the folder at `path`, for showing a large folder tree one
+    /// level at a time.
+    pub async fn get_dir_children(&self, path: String) -> Result<Vec<DirChild>, DirStructureError> {
+        Ok(self.read(move |repo| repo.dir_children(&path)).await?)
+    }
+
+    pub async fn get_item_details(&self, id: i64) -> Result<ItemDetails, SearchError> {
+        self.read(move |repo| {
+            let item = repo.get_item_by_id(id)?;
+            let mut details = ItemDetails::from_items(repo, vec![item])?;
+            Ok(details.remove(0))
+        })
+        .await
+    }
+
+    /// Details of multiple items at once, in the same order as the given ids. This is faster than
+    /// calling `get_item_details()` for each item.
+    pub async fn get_items_details(&self, ids: Vec<i64>) -> Result<Vec<ItemDetails>, SearchError> {
+        self.read(move |repo| {
+            let items: Vec<_> = ids
+                .into_iter()
+                .map(|id| repo.get_item_by_id(id))
+                .try_collect()?;
+            Ok(ItemDetails::from_items(repo, items)?)
+        })
+        .await
+    }
+
+    /// A preview of an item's file. Audio files aren't read, the caller should play them.
+    pub async fn get_preview(&self, id: i64) -> Result<Preview, PreviewError> {
+        let item = self.read(move |repo| repo.get_item_by_id(id)).await?;
+        let path = RelativePath::new(&item.path).to_path(&self.path);
+        let preview = tokio::task::spawn_blocking(move || read_preview(path))
+            .await
+            .expect("failed to join with thread that's reading a preview")?;
+        Ok(preview)
+    }
+
+    /// The loudness of an item's audio file, see `loudness::loudness()`. The file is measured the
+    /// first time this is called, then the result is stored in the repo unless it is read-only.
+    #[cfg(feature = "audio")]
+    pub async fn get_loudness(&self, id: i64) -> Result<Option<Loudness>, LoudnessError> {
+        if let Some(loudness) = self.read(move |repo| repo.loudness(id)).await? {
+            return Ok(loudness);
+        }
+        let item = self.read(move |repo| repo.get_item_by_id(id)).await?;
+        let path = RelativePath::new(&item.path).to_path(&self.path);
+        let loudness = tokio::task::spawn_blocking(move || loudness::loudness_file(path))
+            .await
+            .expect("failed to join with thread that's measuring loudness")?;
+        if !self.read_only() {
+            // this is only a cache, the preview can still be played if it can't be stored
+            if let Err(err) = self
+                .write(move |repo| repo.set_loudness(id, loudness))
+                .await
+            {
+                report_error(
+                    &self.app_handle,
+                    "loudness",
+                    ErrorSeverity::Warning,
+                    format!("failed to store loudness of item {}: {}", id, err),
+                );
+            }
+        }
+        Ok(loudness)
+    }
+
+    /// Write the tags of items into the comments of their audio files, see `write_tags_to_file()`.
+    /// Files are only read in a dry run, so it is allowed for read-only repos.
+    #[instrument]
+    pub async fn write_tags_to_files(
+        &self,
+        ids: Vec<i64>,
+        strategy: ConflictStrategy,
+        dry_run: bool,
+    ) -> Result<Vec<WriteTagsReport>, WriteTagsError> {
+        if !dry_run && self.read_only() {
+            return Err(RepoReadOnly.into());
+        }
+        let items = self.get_items(ids).await?;
+        let repo_path = self.path.clone();
+        if !dry_run {
+            self.pause_watching();
+        }
+        let reports = tokio::task::spawn_blocking(move || {
+            items
+                .into_iter()
+                .map(|item| {
+                    let path = RelativePath::new(&item.path).to_path(&repo_path);
+                    let outcome = write_tags_to_file(path, &item.tags, strategy, dry_run);
+                    WriteTagsReport { id: item.id, path: item.path, outcome }
+                })
+                .collect()
+        })
+        .await
+        .expect("failed to join with thread that's writing tags to files");
+        if !dry_run {
+            self.resume_watching().await;
+        }
+        Ok(reports)
+    }
+
+    /// Items whose files sound most like an item's file, see `Repo::find_similar()`.
+    pub async fn find_similar(
+        &self,
+        id: i64,
+        limit: usize,
+    ) -> Result<Vec<SimilarItem>, SimilarError> {
+        self.read(move |repo| repo.find_similar(id, limit)).await
+    }
+
+    /// Fetch multiple items at once, in the same order as the given ids.
+    pub async fn get_items(&self, ids: Vec<i64>) -> Result<Vec<Item>, SearchError> {
+        self.read(move |repo| ids.into_iter().map(|id| repo.get_item_by_id(id)).collect())
+            .await
+    }
+
+    /// Items that have changed after the change `seq`. If `seq` is `None`, no items are returned,
+    /// this can be used to get the current change sequence number.
+    pub async fn get_changes_since(
+        &self,
+        seq: Option<i64>,
+    ) -> Result<ChangeDetails, rusqlite::Error> {
+        self.read(move |repo| {
+            let changes = match seq {
+                Some(seq) => repo.changes_since(seq)?,
+                None => Changes {
+                    to: repo.change_seq()?,
+                    items: vec![],
+                    removed: vec![],
+                },
+            };
+            ChangeDetails::from_changes(repo, changes)
+        })
+        .await
+    }
+
+    pub async fn insert_tags(
+        &self,
+        ids: Vec<i64>,
+        tags: Vec<String>,
+        last_seen: Option<HashMap<i64, i64>>,
+    ) -> Result<(), InsertTagsError> {
+        if ids.len() == 0 {
+            return Ok(());
+        }
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            check_unchanged::<InsertTagsError>(repo, last_seen.as_ref())?;
+            if ids.len() == 1 {
+                repo.insert_tags(ids[0], tags)?;
+            } else {
+                repo.batch_insert_tags(&ids, tags)?;
+            }
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok::<_, InsertTagsError>(())
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn remove_tags(
+        &self,
+        ids: Vec<i64>,
+        tags: Vec<String>,
+        last_seen: Option<HashMap<i64, i64>>,
+    ) -> Result<(), RemoveTagsError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            check_unchanged::<RemoveTagsError>(repo, last_seen.as_ref())?;
+            if ids.len() == 1 {
+                repo.remove_tags(ids[0], tags)?;
+            } else {
+                repo.batch_remove_tags(&ids, tags)?;
+            }
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok::<_, RemoveTagsError>(())
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Replace the tags of the given items, returns the number of items whose tags changed.
+    #[instrument]
+    pub async fn set_tags(
+        &self,
+        ids: Vec<i64>,
+        tags: String,
+        last_seen: Option<HashMap<i64, i64>>,
+    ) -> Result<usize, UpdateError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            check_unchanged::<UpdateError>(repo, last_seen.as_ref())?;
+            let count = repo.set_tags(&ids, tags.as_str())?;
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Add the tags of a tag template from the repo config to the given items.
+    #[instrument]
+    pub async fn apply_tag_template(
+        &self,
+        ids: Vec<i64>,
+        name: String,
+    ) -> Result<(), ApplyTemplateError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            repo.apply_tag_template(&ids, &name)?;
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn tag_counts(&self) -> Result<Vec<TagCount>, rusqlite::Error> {
+        self.read(|repo| repo.tag_counts()).await
+    }
+
+    pub async fn tag_stats(&self) -> Result<Vec<TagStats>, rusqlite::Error> {
+        self.read(|repo| repo.tag_stats()).await
+    }
+
+    /// The latest changes noticed by the watcher, newest first.
+    pub async fn activity(
+        &self,
+        limit: usize,
+        filter: ActivityFilter,
+    ) -> Result<Vec<Activity>, rusqlite::Error> {
+        self.read(move |repo| repo.activity(limit, &filter)).await
+    }
+
+    pub async fn selection_summary(
+        &self,
+        ids: Vec<i64>,
+    ) -> Result<SelectionSummary, rusqlite::Error> {
+        self.read(move |repo| repo.selection_summary(&ids)).await
+    }
+
+    #[instrument]
+    pub async fn rename_tag(&self, old: String, new: String) -> Result<usize, UpdateError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            let count = repo.rename_tag(&old, &new)?;
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Items whose files no longer exist. They are only listed, not removed.
+    pub async fn verify_items(&self) -> Result<Vec<Item>, rusqlite::Error> {
+        self.read(|repo| repo.missing_items()).await
+    }
+
+    /// Remove items from the repo along with their tags, e.g. after `verify_items()` found that
+    /// their files are gone.
+    #[instrument]
+    pub async fn purge_items(&self, ids: Vec<i64>) -> Result<(), RemoveError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            for id in ids {
+                repo.remove_item_by_id(id)?;
             }
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Suggest the files that missing items were moved to, see `Repo::suggest_relocations()`. The
+    /// repo folder is scanned first, so this can take a while.
+    pub async fn suggest_relocations(&self) -> Result<Vec<Relocation>, SyncError> {
+        let path = self.path.clone();
+        let options = self.repo.lock().await.config().scan_options();
+        let paths = tokio::task::spawn_blocking(move || scan_dir(path, options))
+            .await
+            .expect("failed to join with thread that's scanning a directory")?;
+        Ok(self
+            .read(move |repo| repo.suggest_relocations(paths))
+            .await?)
+    }
+
+    /// Move a missing item to the file it was moved to, keeping its tags.
+    #[instrument]
+    pub async fn relocate_item(&self, id: i64, new_path: String) -> Result<(), RelocateError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            repo.relocate_item(id, &new_path)?;
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_folder_tags(&self, folder: String) -> Result<Vec<FolderTag>, rusqlite::Error> {
+        self.read(move |repo| repo.folder_tags(&folder)).await
+    }
+
+    /// Add tags to a folder. Items aren't changed, so this emits `folder-tags-changed` instead of
+    /// `repo-changed`, search results should be refreshed when it is received.
+    #[instrument]
+    pub async fn insert_folder_tags(
+        &self,
+        folder: String,
+        tags: String,
+    ) -> Result<(), InsertTagsError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            repo.insert_folder_tags(&folder, tags.as_str())?;
+            emit_folder_tags_changed(&app_handle, folder);
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument]
+    pub async fn remove_folder_tags(
+        &self,
+        folder: String,
+        tags: String,
+    ) -> Result<(), RemoveTagsError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            repo.remove_folder_tags(&folder, tags.as_str())?;
+            emit_folder_tags_changed(&app_handle, folder);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_collections(&self) -> Result<Vec<Collection>, rusqlite::Error> {
+        self.read(|repo| repo.collections()).await
+    }
+
+    #[instrument]
+    pub async fn create_collection(&self, name: String) -> Result<(), CollectionError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            repo.create_collection(&name)?;
+            emit_collections_changed(&app_handle, name);
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument]
+    pub async fn delete_collection(&self, name: String) -> Result<(), CollectionError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            repo.delete_collection(&name)?;
+            emit_collections_changed(&app_handle, name);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Add items to a collection, returns the number of items that weren't in it yet.
+    #[instrument]
+    pub async fn add_to_collection(
+        &self,
+        name: String,
+        ids: Vec<i64>,
+    ) -> Result<usize, CollectionError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            let count = repo.add_to_collection(&name, &ids)?;
+            emit_collections_changed(&app_handle, name);
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Remove items from a collection, returns the number of items that were in it.
+    #[instrument]
+    pub async fn remove_from_collection(
+        &self,
+        name: String,
+        ids: Vec<i64>,
+    ) -> Result<usize, CollectionError> {
+        let app_handle = self.app_handle.clone();
+        self.write(move |repo| {
+            let count = repo.remove_from_collection(&name, &ids)?;
+            emit_collections_changed(&app_handle, name);
+            Ok(count)
+        })
+        .await
+    }
+
+    pub async fn get_config(&self) -> RepoConfig {
+        let repo = self.repo.lock().await;
+        repo.config().clone()
+    }
+
+    pub async fn set_config(&self, config: RepoConfig) -> Result<(), RepoConfigError> {
+        let read_pool = self.read_pool.clone();
+        let (interval_changed, resync_interval_changed, old_tag_memory, tag_memory) = self
+            .write(move |repo| {
+                let old_interval = repo.config().consistency_check_interval;
+                let old_resync_interval = repo.config().auto_resync_interval;
+                let old_tag_memory = repo.config().tombstone_retention_days > 0;
+                repo.set_config(config)?;
+                read_pool.reset(repo.config());
+                Ok::<_, RepoConfigError>((
+                    repo.config().consistency_check_interval != old_interval,
+                    repo.config().auto_resync_interval != old_resync_interval,
+                    old_tag_memory,
+                    repo.config().tombstone_retention_days > 0,
+                ))
+            })
+            .await?;
+        // restart the consistency check with the new interval
+        if interval_changed && self.consistency_check.read().await.is_some() {
+            self.start_consistency_check().await;
         }
+        // the auto resync is off by default, so it may have to be started for the first time
+        if resync_interval_changed {
+            self.start_auto_resync().await;
+        }
+        if !tag_memory {
+            self.stop_hashing().await;
+        } else if !old_tag_memory {
+            self.start_hashing().await;
+        }
+        Ok(())
+    }
+
+    pub async fn get_rules(&self) -> Result<Vec<Rule>, RuleError> {
+        self.read(|repo| repo.rules()).await
+    }
+
+    pub async fn insert_rule(
+        &self,
+        conditions: Vec<Condition>,
+        tags: Vec<String>,
+    ) -> Result<Rule, RuleError> {
+        self.write(move |repo| repo.insert_rule(conditions, tags))
+            .await
+    }
+
+    pub async fn update_rule(
+        &self,
+        id: i64,
+        conditions: Vec<Condition>,
+        tags: Vec<String>,
+        enabled: bool,
+    ) -> Result<Rule, RuleError> {
+        self.write(move |repo| repo.update_rule(id, conditions, tags, enabled))
+            .await
+    }
+
+    pub async fn remove_rule(&self, id: i64) -> Result<(), RuleError> {
+        self.write(move |repo| repo.remove_rule(id)).await
+    }
+
+    pub async fn preview_rule(
+        &self,
+        conditions: Vec<Condition>,
+        tags: Vec<String>,
+    ) -> Result<Vec<RuleMatch>, RuleError> {
+        self.read(move |repo| repo.preview_rule(&conditions, tags))
+            .await
+    }
+
+    /// Add tags extracted from file names to all items matching the pattern, see `TagPattern`.
+    /// If `dry_run` is true, the repo isn't modified, and the items that would be tagged are
+    /// returned instead.
+    pub async fn import_tags_from_pattern(
+        &self,
+        pattern: String,
+        dry_run: bool,
+    ) -> Result<Vec<PatternMatch>, TagPatternError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            if dry_run {
+                return repo.preview_tag_pattern(&pattern);
+            }
+            let matches = repo.import_tags_from_pattern(&pattern)?;
+            emit_changes(repo, &app_handle, &change_seq);
+            Ok(matches)
+        })
+        .await
+    }
+
+    /// Add tags to items from a CSV file with rows like `path,tag1 tag2`, see
+    /// `Repo::import_tags_csv()`. Relative paths in the file are relative to the repo root.
+    pub async fn import_tags_csv(
+        &self,
+        path: PathBuf,
+        options: CsvImportOptions,
+    ) -> Result<CsvImportReport, CsvImportError> {
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        self.write(move |repo| {
+            let text = std::fs::read(path)?;
+            let text = String::from_utf8(text).map_err(|_| CsvImportError::NotText)?;
+            let rows = parse_csv(&text)?;
+            let report = repo.import_tags_csv(&rows, options)?;
+            if !options.dry_run {
+                emit_changes(repo, &app_handle, &change_seq);
+            }
+            Ok(report)
+        })
+        .await
+    }
+
+    pub async fn watch(&self) -> Result<(), WatchError> {
+        self.watch_with(None).await
+    }
 
-        // new unbounded channel for communication
-        let (tx, rx) = unbounded_channel();
+    /// Like `watch()`, but the watcher is created by `factory` if it's given, including when the
+    /// watcher is restarted. Tests use this with `EventInjector::factory()` to send exact events
+    /// to the event handler, instead of waiting for the OS to report changes.
+    pub async fn watch_with(&self, factory: Option<WatcherFactory>) -> Result<(), WatchError> {
+        // the watcher writes changes to the repo, so don't watch read-only repos
+        if self.read_only() {
+            return Err(RepoReadOnly.into());
+        }
 
-        // no need to store this thread's handle
-        // the thread should stop when you drop the watcher
+        // check there isn't already a watcher
         {
-            let repo = self.repo.clone();
-            let path = self.path.clone();
-            let new_handle = self.app_handle.clone();
-            tokio::spawn(async move {
-                event_handler(repo, path, new_handle, rx, Options::default()).await
-            });
-        }
-
-        // create a new watcher
-        let mut watcher = BestWatcher::new(
-            move |res: notify::Result<Event>| match tx.send(res) {
-                Ok(_) => {}
-                Err(err) => {
-                    let evt = err.0;
-                    error!("failed to send event to watcher loop: {:?}", evt);
-                }
-            },
-            Config::default(),
-        )
-        .unwrap();
-        watcher.watch(self.path.as_ref(), RecursiveMode::Recursive)?;
+            let watcher = self.watcher.read().await;
+            // if there is already watcher, raise error
+            if watcher.is_some() {
+                return Err(WatchError::AlreadyWatching);
+            }
+        }
+
+        let settings = {
+            let repo = self.repo.lock().await;
+            let config = repo.config();
+            WatcherSettings {
+                strategy: config.watcher_strategy,
+                poll_interval: Duration::from_secs(config.watcher_poll_interval),
+                pairing: PairingOptions {
+                    window: Duration::from_millis(config.rename_pairing_window_ms),
+                    lookup: Some(hash_lookup(
+                        self.writer.clone(),
+                        self.read_pool.clone(),
+                        self.path.clone(),
+                    )),
+                    metrics: self.pairing_metrics.clone(),
+                },
+                dirs: self.dir_tree.clone(),
+                factory,
+            }
+        };
+        let watcher = spawn_watcher(
+            self.writer.clone(),
+            self.path.clone(),
+            self.watcher.clone(),
+            self.watch_pause.clone(),
+            vec![(self.path.clone(), RecursiveMode::Recursive)],
+            settings,
+            self.app_handle.clone(),
+        )?;
 
         // drop the existing watcher
         {
@@ -455,6 +2562,243 @@ impl<R: Runtime> RepoManager<R> {
         Ok(())
     }
 
+    /// Watch an additional folder inside the repo, using the watcher started by `watch()`. Events
+    /// are handled the same way as events in the repo root.
+    ///
+    /// This can be combined with `unwatch_path()` to skip huge subtrees, by unwatching the repo
+    /// root and only watching the folders that are needed.
+    pub async fn watch_path(
+        &self,
+        path: impl AsRef<Path>,
+        recursive: bool,
+    ) -> Result<(), WatchError> {
+        let path = path.as_ref();
+        ensure_inside_repo(&self.path, path)?;
+        let mut state = self.watcher.write().await;
+        let Some(state) = &mut *state else {
+            return Err(WatchError::NotWatching);
+        };
+        if state.paths.iter().any(|(x, _)| x == path) {
+            return Err(WatchError::AlreadyWatching);
+        }
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        state.watcher.watch(path, mode)?;
+        state.paths.push((path.to_path_buf(), mode));
+        Ok(())
+    }
+
+    /// Stop watching a folder that was registered with `watch()` or `watch_path()`. The watcher
+    /// keeps running even if no folders are left, use `unwatch()` to stop it.
+    pub async fn unwatch_path(&self, path: impl AsRef<Path>) -> Result<(), UnwatchError> {
+        let path = path.as_ref();
+        let mut state = self.watcher.write().await;
+        let Some(state) = &mut *state else {
+            return Err(UnwatchError::NotWatching);
+        };
+        let Some(index) = state.paths.iter().position(|(x, _)| x == path) else {
+            return Err(UnwatchError::NotWatching);
+        };
+        state.watcher.unwatch(path)?;
+        state.paths.remove(index);
+        Ok(())
+    }
+
+    /// Stop applying watcher events to the repo, e.g. while the app moves or changes files and
+    /// updates the repo itself, so its own changes aren't applied twice. Each call must be
+    /// followed by `resume_watching()`; watching is paused until all of them have resumed.
+    pub fn pause_watching(&self) {
+        self.watch_pause.lock().unwrap().count += 1;
+    }
+
+    /// Undo a call to `pause_watching()`. Events may arrive a while after the files have changed,
+    /// so they keep being skipped for `RepoConfig::watcher_resume_delay_ms`. Folders that had
+    /// events while paused are then rescanned, to pick up changes made by other programs.
+    pub async fn resume_watching(&self) {
+        let delay = self.repo.lock().await.config().watcher_resume_delay_ms;
+        let writer = self.writer.clone();
+        let repo_path = self.path.clone();
+        let pause = self.watch_pause.clone();
+        let app_handle = self.app_handle.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            let dirs = {
+                let mut pause = pause.lock().unwrap();
+                pause.count = pause.count.saturating_sub(1);
+                if pause.count > 0 {
+                    return;
+                }
+                pause.take_skipped()
+            };
+            rescan_dirs(&writer, &repo_path, dirs, "pausing watcher", &app_handle).await;
+        });
+    }
+
+    /// The kind of watcher used for the repo, or `None` if it isn't being watched.
+    pub async fn watcher_backend(&self) -> Option<WatcherBackend> {
+        let watcher = self.watcher.read().await;
+        watcher.as_ref().map(|x| x.watcher.backend())
+    }
+
+    /// How the watcher has paired deleted and created files into renames since the repo was
+    /// opened. These are always zero on platforms that report renames directly.
+    pub fn watcher_stats(&self) -> PairingStats {
+        self.pairing_metrics.stats()
+    }
+
+    /// Start the background consistency check, replacing the existing one if it is running. Does
+    /// nothing if the consistency check is disabled in the repo config, or if the repo is
+    /// read-only.
+    pub async fn start_consistency_check(&self) {
+        self.stop_consistency_check().await;
+
+        // the consistency check writes to the repo
+        if self.read_only() {
+            return;
+        }
+
+        let minutes = self.repo.lock().await.config().consistency_check_interval;
+        if minutes == 0 {
+            return;
+        }
+
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        let app_handle = self.app_handle.clone();
+        let interval = Duration::from_secs(minutes * 60);
+        let status = self.status.clone();
+        let task = consistency_check(writer, path, status, app_handle, interval);
+        let handle = tokio::spawn(task);
+        *self.consistency_check.write().await = Some(handle);
+    }
+
+    pub async fn stop_consistency_check(&self) {
+        if let Some(handle) = self.consistency_check.write().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// Start resyncing the repo periodically while it isn't being watched, replacing the existing
+    /// task if it is running. Does nothing if auto resync is disabled in the repo config, or if
+    /// the repo is read-only.
+    pub async fn start_auto_resync(&self) {
+        self.stop_auto_resync().await;
+
+        if self.read_only() {
+            return;
+        }
+
+        let minutes = self.repo.lock().await.config().auto_resync_interval;
+        if minutes == 0 {
+            return;
+        }
+
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        let watcher = self.watcher.clone();
+        let status = self.status.clone();
+        let app_handle = self.app_handle.clone();
+        let interval = Duration::from_secs(minutes * 60);
+        let handle = tokio::spawn(async move {
+            auto_resync(writer, path, watcher, status, app_handle, interval).await
+        });
+        *self.auto_resync.write().await = Some(handle);
+    }
+
+    pub async fn stop_auto_resync(&self) {
+        if let Some(handle) = self.auto_resync.write().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// Start hashing tagged items in the background, replacing the existing task if it is
+    /// running. Does nothing if tag memory is disabled in the repo config, or if the repo is
+    /// read-only.
+    pub async fn start_hashing(&self) {
+        self.stop_hashing().await;
+
+        if self.read_only() || self.repo.lock().await.config().tombstone_retention_days == 0 {
+            return;
+        }
+
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        let app_handle = self.app_handle.clone();
+        let handle = tokio::spawn(async move { hash_items(writer, path, app_handle).await });
+        *self.hashing.write().await = Some(handle);
+    }
+
+    pub async fn stop_hashing(&self) {
+        if let Some(handle) = self.hashing.write().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// Start fingerprinting audio files in the background, replacing the existing task if it is
+    /// running. Does nothing if the repo is read-only.
+    #[cfg(feature = "audio")]
+    pub async fn start_fingerprinting(&self) {
+        self.stop_fingerprinting().await;
+
+        if self.read_only() {
+            return;
+        }
+
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        let app_handle = self.app_handle.clone();
+        let handle = tokio::spawn(async move { fingerprint_items(writer, path, app_handle).await });
+        *self.fingerprinting.write().await = Some(handle);
+    }
+
+    pub async fn stop_fingerprinting(&self) {
+        if let Some(handle) = self.fingerprinting.write().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// Start extracting the text of documents in the background, replacing the existing task if
+    /// it is running. Does nothing if the repo is read-only.
+    pub async fn start_extraction(&self) {
+        self.stop_extraction().await;
+
+        if self.read_only() {
+            return;
+        }
+
+        let writer = self.writer.clone();
+        let path = self.path.clone();
+        let app_handle = self.app_handle.clone();
+        let handle = tokio::spawn(async move { extract_contents(writer, path, app_handle).await });
+        *self.extraction.write().await = Some(handle);
+    }
+
+    pub async fn stop_extraction(&self) {
+        if let Some(handle) = self.extraction.write().await.take() {
+            handle.abort();
+        }
+    }
+
+    /// Start emitting `repo-changed` events for changes made in the background, replacing the
+    /// existing task if it is running.
+    pub async fn start_change_feed(&self) {
+        self.stop_change_feed().await;
+
+        let writer = self.writer.clone();
+        let app_handle = self.app_handle.clone();
+        let change_seq = self.change_seq.clone();
+        let handle = tokio::spawn(async move { change_feed(writer, app_handle, change_seq).await });
+        *self.change_feed.write().await = Some(handle);
+    }
+
+    pub async fn stop_change_feed(&self) {
+        if let Some(handle) = self.change_feed.write().await.take() {
+            handle.abort();
+        }
+    }
+
     pub async fn unwatch(&self) -> Result<(), UnwatchError> {
         let mut watcher = self.watcher.write().await;
         if watcher.is_none() {
@@ -465,3 +2809,28 @@ impl<R: Runtime> RepoManager<R> {
         }
     }
 }
+
+impl<R: Runtime> Drop for RepoManager<R> {
+    fn drop(&mut self) {
+        // the tasks hold the writer, so they must be stopped for the repo to close
+        if let Some(handle) = self.consistency_check.get_mut().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.auto_resync.get_mut().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.hashing.get_mut().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.fingerprinting.get_mut().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.extraction.get_mut().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.change_feed.get_mut().take() {
+            handle.abort();
+        }
+        self.writer_task.abort();
+    }
+}
@@ -0,0 +1,174 @@
+//! Exposes audio previews as a media session, so they can be controlled with the OS media keys.
+//! This uses MPRIS on Linux and the system media transport controls on Windows.
+//!
+//! The media controls are created and updated on their own thread, since they aren't `Send` on
+//! every platform. Updates are sent to the thread through a channel. On other platforms,
+//! `MediaSession::new()` returns an error.
+
+use std::ffi::c_void;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use serde::Serialize;
+#[cfg(any(windows, target_os = "linux"))]
+use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, PlatformConfig};
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Error, Debug)]
+pub enum MediaSessionError {
+    #[error("failed to create media session, {0}")]
+    PlatformError(String),
+    #[error("media sessions aren't supported on this platform")]
+    Unsupported,
+}
+
+/// A media key pressed by the user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum MediaCommand {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+impl MediaCommand {
+    fn from_event(event: MediaControlEvent) -> Option<Self> {
+        match event {
+            MediaControlEvent::Play => Some(Self::Play),
+            MediaControlEvent::Pause => Some(Self::Pause),
+            MediaControlEvent::Toggle => Some(Self::Toggle),
+            MediaControlEvent::Stop => Some(Self::Stop),
+            MediaControlEvent::Next => Some(Self::Next),
+            MediaControlEvent::Previous => Some(Self::Previous),
+            _ => None,
+        }
+    }
+}
+
+enum Update {
+    /// A new file is being previewed
+    Track(String),
+    Playing(bool),
+    Stopped,
+}
+
+/// The media session of the app, the session is closed when this is dropped.
+#[derive(Debug)]
+pub(crate) struct MediaSession {
+    sender: Mutex<Sender<Update>>,
+}
+
+impl MediaSession {
+    /// Create the media session. `hwnd` is the handle of the main window, it is only used on
+    /// Windows. `handler` is called on another thread whenever a media key is pressed.
+    pub(crate) fn new(
+        hwnd: Option<*mut c_void>,
+        handler: impl Fn(MediaCommand) + Send + 'static,
+    ) -> Result<Self, MediaSessionError> {
+        let (sender, receiver) = channel();
+        let (result_sender, result_receiver) = channel();
+        // raw pointers aren't `Send`
+        let hwnd = hwnd.map(|x| x as usize);
+        // the thread stops when the sender is dropped
+        thread::spawn(move || {
+            let hwnd = hwnd.map(|x| x as *mut c_void);
+            run_session(hwnd, handler, receiver, result_sender)
+        });
+        result_receiver
+            .recv()
+            .expect("media session thread stopped unexpectedly")?;
+        Ok(Self { sender: Mutex::new(sender) })
+    }
+
+    fn send(&self, update: Update) {
+        // the thread only stops when this is dropped, so sending can't fail
+        let _ = self.sender.lock().unwrap().send(update);
+    }
+
+    /// Show a file as the current track, using its file name as the title.
+    pub(crate) fn set_track(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let title = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => path.to_string_lossy().into_owned(),
+        };
+        self.send(Update::Track(title));
+    }
+
+    pub(crate) fn set_playing(&self, playing: bool) {
+        self.send(Update::Playing(playing));
+    }
+
+    pub(crate) fn set_stopped(&self) {
+        self.send(Update::Stopped);
+    }
+}
+
+/// Create the media controls, then apply updates until the session is dropped. The result of
+/// creating the controls is sent to `result_sender`.
+#[cfg(any(windows, target_os = "linux"))]
+fn run_session(
+    hwnd: Option<*mut c_void>,
+    handler: impl Fn(MediaCommand) + Send + 'static,
+    receiver: Receiver<Update>,
+    result_sender: Sender<Result<(), MediaSessionError>>,
+) {
+    let config = PlatformConfig {
+        dbus_name: "tagrepo",
+        display_name: "tagrepo",
+        hwnd,
+    };
+    let controls = MediaControls::new(config).and_then(|mut controls| {
+        controls.attach(move |event| {
+            if let Some(command) = MediaCommand::from_event(event) {
+                handler(command);
+            }
+        })?;
+        Ok(controls)
+    });
+    let mut controls = match controls {
+        Ok(controls) => {
+            let _ = result_sender.send(Ok(()));
+            controls
+        }
+        Err(err) => {
+            let err = MediaSessionError::PlatformError(format!("{:?}", err));
+            let _ = result_sender.send(Err(err));
+            return;
+        }
+    };
+
+    for update in receiver {
+        let rv = match update {
+            Update::Track(title) => controls
+                .set_metadata(MediaMetadata { title: Some(&title), ..Default::default() })
+                .and_then(|_| controls.set_playback(MediaPlayback::Playing { progress: None })),
+            Update::Playing(true) => {
+                controls.set_playback(MediaPlayback::Playing { progress: None })
+            }
+            Update::Playing(false) => {
+                controls.set_playback(MediaPlayback::Paused { progress: None })
+            }
+            Update::Stopped => controls.set_playback(MediaPlayback::Stopped),
+        };
+        if let Err(err) = rv {
+            error!("failed to update media session: {:?}", err);
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn run_session(
+    _hwnd: Option<*mut c_void>,
+    _handler: impl Fn(MediaCommand) + Send + 'static,
+    _receiver: Receiver<Update>,
+    result_sender: Sender<Result<(), MediaSessionError>>,
+) {
+    let _ = result_sender.send(Err(MediaSessionError::Unsupported));
+}
@@ -0,0 +1,143 @@
+//! Levels of the audio preview, for a level meter in the frontend.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::Source;
+use serde::Serialize;
+
+/// Levels are measured over windows of this length.
+const WINDOW: Duration = Duration::from_millis(50);
+
+/// Levels of the last window of played samples, between 0 and 1. These are measured before the
+/// preview's volume is applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub(crate) struct Levels {
+    pub(crate) rms: f32,
+    pub(crate) peak: f32,
+}
+
+/// Levels shared between a playing source and the app. Cloning this shares the same levels.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LevelMeter(Arc<Mutex<Levels>>);
+
+impl LevelMeter {
+    pub(crate) fn levels(&self) -> Levels {
+        *self.0.lock().unwrap()
+    }
+
+    /// Set the levels to 0, e.g. when the audio is stopped.
+    pub(crate) fn reset(&self) {
+        self.set(Levels::default());
+    }
+
+    fn set(&self, levels: Levels) {
+        *self.0.lock().unwrap() = levels;
+    }
+}
+
+/// A source that measures the levels of the samples that are played from it.
+pub(crate) struct Metered<S> {
+    source: S,
+    meter: LevelMeter,
+    /// Number of samples in a window, across all channels.
+    window_len: usize,
+    sum_sq: f32,
+    peak: f32,
+    count: usize,
+}
+
+impl<S: Source<Item = i16>> Metered<S> {
+    pub(crate) fn new(source: S, meter: LevelMeter) -> Self {
+        let samples_per_sec = source.sample_rate() as f32 * source.channels() as f32;
+        let window_len = ((samples_per_sec * WINDOW.as_secs_f32()) as usize).max(1);
+        Self {
+            source,
+            meter,
+            window_len,
+            sum_sq: 0.0,
+            peak: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Metered<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let Some(sample) = self.source.next() else {
+            // the source has ended, so nothing is playing
+            self.meter.reset();
+            return None;
+        };
+        let x = sample as f32 / i16::MAX as f32;
+        self.sum_sq += x * x;
+        self.peak = self.peak.max(x.abs());
+        self.count += 1;
+        if self.count == self.window_len {
+            let rms = (self.sum_sq / self.count as f32).sqrt();
+            self.meter.set(Levels { rms, peak: self.peak.min(1.0) });
+            self.sum_sq = 0.0;
+            self.peak = 0.0;
+            self.count = 0;
+        }
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Metered<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    #[test]
+    fn levels_are_measured_while_playing() {
+        let meter = LevelMeter::default();
+        // a square wave at half volume, 1 second long
+        let samples: Vec<i16> = (0..1000)
+            .map(|i| {
+                if i % 2 == 0 {
+                    i16::MAX / 2
+                } else {
+                    -i16::MAX / 2
+                }
+            })
+            .collect();
+        let mut source = Metered::new(SamplesBuffer::new(1, 1000, samples), meter.clone());
+        assert_eq!(meter.levels(), Levels::default());
+
+        // the first window is 50 samples long
+        source.by_ref().take(50).for_each(drop);
+        let levels = meter.levels();
+        assert!((levels.rms - 0.5).abs() < 1e-3);
+        assert!((levels.peak - 0.5).abs() < 1e-3);
+
+        // levels drop to 0 when the source ends
+        source.by_ref().for_each(drop);
+        assert_eq!(meter.levels(), Levels::default());
+    }
+}
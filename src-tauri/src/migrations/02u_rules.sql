@@ -0,0 +1,8 @@
+CREATE TABLE rules (
+  id INTEGER PRIMARY KEY,
+  -- a JSON array of conditions, all conditions must match for the rule to apply
+  conditions TEXT NOT NULL,
+  -- tags to add to matching items, in the same format as `items.tags`
+  tags TEXT NOT NULL,
+  enabled INTEGER NOT NULL DEFAULT 1
+);
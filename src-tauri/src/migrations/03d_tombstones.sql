@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS tombstones_size;
+DROP INDEX IF EXISTS tombstones_name;
+DROP TABLE IF EXISTS tombstones;
+ALTER TABLE items DROP COLUMN hash;
+ALTER TABLE items DROP COLUMN size;
@@ -0,0 +1,20 @@
+-- size and content hash of the file, used to recognise the file if it's deleted then re-appears
+-- elsewhere. these are `NULL` until the item is hashed, only items with tags are hashed.
+ALTER TABLE items ADD COLUMN size INTEGER;
+ALTER TABLE items ADD COLUMN hash TEXT;
+
+-- tags of deleted items, so they can be restored if the file re-appears
+CREATE TABLE tombstones (
+  id INTEGER PRIMARY KEY,
+  -- file name of the deleted item, without its folder
+  name TEXT NOT NULL,
+  -- copied from `items.size` and `items.hash`, may be `NULL`
+  size INTEGER,
+  hash TEXT,
+  tags TEXT NOT NULL,
+  -- unix timestamp in seconds
+  deleted_at INTEGER NOT NULL
+);
+
+CREATE INDEX tombstones_name ON tombstones(name);
+CREATE INDEX tombstones_size ON tombstones(size);
@@ -0,0 +1,12 @@
+DROP TABLE tag_query;
+
+CREATE VIRTUAL TABLE tag_query USING fts5 (
+  id UNINDEXED,
+  tags,
+  meta_tags,
+  content=items,
+  content_rowid=id,
+  tokenize="ascii"
+);
+
+INSERT INTO tag_query(tag_query) VALUES ('rebuild');
@@ -0,0 +1,18 @@
+-- normalize existing tags to Unicode NFC, see `normalize_tag()`
+UPDATE items SET tags = normalize_tags(tags, 0) WHERE tags != normalize_tags(tags, 0);
+
+-- recreate the FTS table with the unicode61 tokenizer, so the case of all letters is ignored when
+-- searching, not just ASCII letters. Diacritics are kept, so "é" doesn't match "e".
+DROP TABLE tag_query;
+
+CREATE VIRTUAL TABLE tag_query USING fts5 (
+  id UNINDEXED,
+  tags,
+  meta_tags,
+  content=items,
+  content_rowid=id,
+  tokenize="unicode61 remove_diacritics 0"
+);
+
+-- the triggers on `items` keep working, they refer to the table by name
+INSERT INTO tag_query(tag_query) VALUES ('rebuild');
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS items_trigram_au;
+DROP TRIGGER IF EXISTS items_trigram_ad;
+DROP TRIGGER IF EXISTS items_trigram_ai;
+DROP TABLE IF EXISTS tag_trigram;
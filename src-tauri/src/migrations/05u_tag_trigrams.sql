@@ -0,0 +1,23 @@
+-- a second FTS index that splits tags into trigrams, used to search for text inside tags. CJK text
+-- isn't separated into words, so `tag_query` can only match CJK tags exactly.
+CREATE VIRTUAL TABLE tag_trigram USING fts5 (
+  tags,
+  content=items,
+  content_rowid=id,
+  tokenize="trigram"
+);
+
+CREATE TRIGGER items_trigram_ai AFTER INSERT ON items BEGIN
+  INSERT INTO tag_trigram(rowid, tags) VALUES (NEW.id, NEW.tags);
+END;
+
+CREATE TRIGGER items_trigram_ad AFTER DELETE ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags) VALUES('delete', OLD.id, OLD.tags);
+END;
+
+CREATE TRIGGER items_trigram_au AFTER UPDATE OF tags ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags) VALUES('delete', OLD.id, OLD.tags);
+  INSERT INTO tag_trigram(rowid, tags) VALUES (NEW.id, NEW.tags);
+END;
+
+INSERT INTO tag_trigram(tag_trigram) VALUES ('rebuild');
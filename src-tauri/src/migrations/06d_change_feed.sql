@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS items_changes_au;
+DROP TRIGGER IF EXISTS items_changes_ad;
+DROP TRIGGER IF EXISTS items_changes_ai;
+DROP TABLE IF EXISTS item_changes;
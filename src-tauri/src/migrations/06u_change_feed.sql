@@ -0,0 +1,18 @@
+-- the latest change to each item, so the frontend can catch up on changes it has missed. every
+-- change is given a new, larger `change_seq`, replacing the item's previous change.
+CREATE TABLE item_changes (
+  change_seq INTEGER PRIMARY KEY AUTOINCREMENT,
+  item_id INTEGER UNIQUE NOT NULL
+);
+
+CREATE TRIGGER items_changes_ai AFTER INSERT ON items BEGIN
+  INSERT OR REPLACE INTO item_changes(item_id) VALUES (NEW.id);
+END;
+
+CREATE TRIGGER items_changes_ad AFTER DELETE ON items BEGIN
+  INSERT OR REPLACE INTO item_changes(item_id) VALUES (OLD.id);
+END;
+
+CREATE TRIGGER items_changes_au AFTER UPDATE OF path, tags, meta_tags ON items BEGIN
+  INSERT OR REPLACE INTO item_changes(item_id) VALUES (NEW.id);
+END;
@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS items_changes_au_mtime;
+ALTER TABLE items DROP COLUMN mtime;
@@ -0,0 +1,10 @@
+-- last modification time of the file in milliseconds since the unix epoch, used to tell when a
+-- file's contents have changed. `NULL` until the file has been checked.
+ALTER TABLE items ADD COLUMN mtime INTEGER;
+
+-- modified files are sent to the frontend, so it can refresh anything it shows about them. the
+-- first time a file is checked isn't a modification.
+CREATE TRIGGER items_changes_au_mtime AFTER UPDATE OF mtime ON items
+WHEN OLD.mtime IS NOT NULL AND NEW.mtime > OLD.mtime BEGIN
+  INSERT OR REPLACE INTO item_changes(item_id) VALUES (NEW.id);
+END;
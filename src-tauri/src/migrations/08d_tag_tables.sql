@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS item_tags_ad;
+DROP TRIGGER IF EXISTS items_tags_au;
+DROP TRIGGER IF EXISTS items_tags_ad;
+DROP TRIGGER IF EXISTS items_tags_ai;
+DROP TABLE IF EXISTS item_tags;
+DROP TABLE IF EXISTS tags;
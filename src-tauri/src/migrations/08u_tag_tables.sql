@@ -0,0 +1,45 @@
+-- each distinct tag, and the items that have it. `items.tags` is still the source of truth and is
+-- what the FTS indexes search, these tables are kept in sync with it by triggers. they are used to
+-- list and count tags, and to find the items with a tag without a full-text search.
+CREATE TABLE tags (
+  id INTEGER PRIMARY KEY,
+  name TEXT UNIQUE NOT NULL
+);
+
+CREATE TABLE item_tags (
+  item_id INTEGER NOT NULL,
+  tag_id INTEGER NOT NULL,
+  PRIMARY KEY (item_id, tag_id)
+) WITHOUT ROWID;
+
+CREATE INDEX item_tags_tag_id ON item_tags(tag_id);
+
+-- `split_tags()` converts `items.tags` into a JSON array, so it can be read with `json_each()`
+CREATE TRIGGER items_tags_ai AFTER INSERT ON items BEGIN
+  INSERT OR IGNORE INTO tags(name) SELECT value FROM json_each(split_tags(NEW.tags));
+  INSERT OR IGNORE INTO item_tags(item_id, tag_id)
+    SELECT NEW.id, tags.id FROM json_each(split_tags(NEW.tags)) JOIN tags ON tags.name = value;
+END;
+
+CREATE TRIGGER items_tags_ad AFTER DELETE ON items BEGIN
+  DELETE FROM item_tags WHERE item_id = OLD.id;
+END;
+
+CREATE TRIGGER items_tags_au AFTER UPDATE OF tags ON items BEGIN
+  DELETE FROM item_tags WHERE item_id = OLD.id;
+  INSERT OR IGNORE INTO tags(name) SELECT value FROM json_each(split_tags(NEW.tags));
+  INSERT OR IGNORE INTO item_tags(item_id, tag_id)
+    SELECT NEW.id, tags.id FROM json_each(split_tags(NEW.tags)) JOIN tags ON tags.name = value;
+END;
+
+-- tags that are no longer used by any item are removed
+CREATE TRIGGER item_tags_ad AFTER DELETE ON item_tags
+WHEN NOT EXISTS (SELECT 1 FROM item_tags WHERE tag_id = OLD.tag_id) BEGIN
+  DELETE FROM tags WHERE id = OLD.tag_id;
+END;
+
+INSERT OR IGNORE INTO tags(name)
+  SELECT DISTINCT value FROM items, json_each(split_tags(items.tags));
+INSERT OR IGNORE INTO item_tags(item_id, tag_id)
+  SELECT items.id, tags.id FROM items, json_each(split_tags(items.tags))
+  JOIN tags ON tags.name = value;
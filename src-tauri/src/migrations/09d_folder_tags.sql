@@ -0,0 +1,61 @@
+DROP TRIGGER IF EXISTS folder_tags_ad;
+DROP TRIGGER IF EXISTS folder_tags_bd;
+DROP TRIGGER IF EXISTS folder_tags_ai;
+DROP TRIGGER IF EXISTS folder_tags_bi;
+DROP TRIGGER IF EXISTS items_trigram_au;
+DROP TRIGGER IF EXISTS items_trigram_bu;
+DROP TRIGGER IF EXISTS items_trigram_bd;
+DROP TRIGGER IF EXISTS items_trigram_ai;
+DROP TRIGGER IF EXISTS items_trigger_au;
+DROP TRIGGER IF EXISTS items_trigger_bu;
+DROP TRIGGER IF EXISTS items_trigger_bd;
+DROP TRIGGER IF EXISTS items_trigger_ai;
+DROP TABLE IF EXISTS tag_trigram;
+DROP TABLE IF EXISTS tag_query;
+DROP VIEW IF EXISTS items_search;
+DROP TABLE IF EXISTS folder_tags;
+
+CREATE VIRTUAL TABLE tag_query USING fts5 (
+  id UNINDEXED,
+  tags,
+  meta_tags,
+  content=items,
+  content_rowid=id,
+  tokenize="unicode61 remove_diacritics 0"
+);
+
+CREATE VIRTUAL TABLE tag_trigram USING fts5 (
+  tags,
+  content=items,
+  content_rowid=id,
+  tokenize="trigram"
+);
+
+CREATE TRIGGER items_trigger_ai AFTER INSERT ON items BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags) VALUES (NEW.id, NEW.tags, NEW.meta_tags);
+END;
+
+CREATE TRIGGER items_trigger_ad AFTER DELETE ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags) VALUES('delete', OLD.id, OLD.tags, OLD.meta_tags);
+END;
+
+CREATE TRIGGER items_trigger_au AFTER UPDATE ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags) VALUES('delete', OLD.id, OLD.tags, OLD.meta_tags);
+  INSERT INTO tag_query(rowid, tags, meta_tags) VALUES (NEW.id, NEW.tags, NEW.meta_tags);
+END;
+
+CREATE TRIGGER items_trigram_ai AFTER INSERT ON items BEGIN
+  INSERT INTO tag_trigram(rowid, tags) VALUES (NEW.id, NEW.tags);
+END;
+
+CREATE TRIGGER items_trigram_ad AFTER DELETE ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags) VALUES('delete', OLD.id, OLD.tags);
+END;
+
+CREATE TRIGGER items_trigram_au AFTER UPDATE OF tags ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags) VALUES('delete', OLD.id, OLD.tags);
+  INSERT INTO tag_trigram(rowid, tags) VALUES (NEW.id, NEW.tags);
+END;
+
+INSERT INTO tag_query(tag_query) VALUES ('rebuild');
+INSERT INTO tag_trigram(tag_trigram) VALUES ('rebuild');
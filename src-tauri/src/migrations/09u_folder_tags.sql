@@ -0,0 +1,136 @@
+-- tags that every item in a folder implicitly has, e.g. everything in `Samples/Vinyl/` has the tag
+-- `vinyl`. `path` is the folder's path relative to the repo root, ending with a slash. rows are
+-- only inserted and deleted, never updated.
+CREATE TABLE folder_tags (
+  path TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  PRIMARY KEY (path, tag)
+) WITHOUT ROWID;
+
+-- the tags that are searched for each item, i.e. its own tags followed by the tags of the folders
+-- it is in. this is the content table of both FTS indexes, so rebuilding them keeps folder tags.
+CREATE VIEW items_search AS
+SELECT
+  i.id,
+  trim(i.tags || ' ' || coalesce((
+    SELECT group_concat(f.tag, ' ')
+    FROM folder_tags f
+    WHERE substr(i.path, 1, length(f.path)) = f.path
+  ), '')) AS tags,
+  i.meta_tags
+FROM items i;
+
+-- recreate the FTS tables to read from the view
+DROP TRIGGER items_trigger_ai;
+DROP TRIGGER items_trigger_ad;
+DROP TRIGGER items_trigger_au;
+DROP TRIGGER items_trigram_ai;
+DROP TRIGGER items_trigram_ad;
+DROP TRIGGER items_trigram_au;
+DROP TABLE tag_query;
+DROP TABLE tag_trigram;
+
+CREATE VIRTUAL TABLE tag_query USING fts5 (
+  id UNINDEXED,
+  tags,
+  meta_tags,
+  content=items_search,
+  content_rowid=id,
+  tokenize="unicode61 remove_diacritics 0"
+);
+
+CREATE VIRTUAL TABLE tag_trigram USING fts5 (
+  tags,
+  content=items_search,
+  content_rowid=id,
+  tokenize="trigram"
+);
+
+-- the values removed from an FTS index must be exactly the values that were added, so they are
+-- read from the view before a row changes, and added again after it changes
+CREATE TRIGGER items_trigger_ai AFTER INSERT ON items BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT id, tags, meta_tags FROM items_search WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER items_trigger_bd BEFORE DELETE ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', id, tags, meta_tags FROM items_search WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER items_trigger_bu BEFORE UPDATE ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', id, tags, meta_tags FROM items_search WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER items_trigger_au AFTER UPDATE ON items BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT id, tags, meta_tags FROM items_search WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER items_trigram_ai AFTER INSERT ON items BEGIN
+  INSERT INTO tag_trigram(rowid, tags) SELECT id, tags FROM items_search WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER items_trigram_bd BEFORE DELETE ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags)
+    SELECT 'delete', id, tags FROM items_search WHERE id = OLD.id;
+END;
+
+-- moving an item can change its folder tags
+CREATE TRIGGER items_trigram_bu BEFORE UPDATE OF path, tags ON items BEGIN
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags)
+    SELECT 'delete', id, tags FROM items_search WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER items_trigram_au AFTER UPDATE OF path, tags ON items BEGIN
+  INSERT INTO tag_trigram(rowid, tags) SELECT id, tags FROM items_search WHERE id = NEW.id;
+END;
+
+-- reindex the items in a folder when its tags change
+CREATE TRIGGER folder_tags_bi BEFORE INSERT ON folder_tags BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', s.id, s.tags, s.meta_tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(NEW.path)) = NEW.path;
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags)
+    SELECT 'delete', s.id, s.tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(NEW.path)) = NEW.path;
+END;
+
+CREATE TRIGGER folder_tags_ai AFTER INSERT ON folder_tags BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT s.id, s.tags, s.meta_tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(NEW.path)) = NEW.path;
+  INSERT INTO tag_trigram(rowid, tags)
+    SELECT s.id, s.tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(NEW.path)) = NEW.path;
+END;
+
+CREATE TRIGGER folder_tags_bd BEFORE DELETE ON folder_tags BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', s.id, s.tags, s.meta_tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(OLD.path)) = OLD.path;
+  INSERT INTO tag_trigram(tag_trigram, rowid, tags)
+    SELECT 'delete', s.id, s.tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(OLD.path)) = OLD.path;
+END;
+
+CREATE TRIGGER folder_tags_ad AFTER DELETE ON folder_tags BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT s.id, s.tags, s.meta_tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(OLD.path)) = OLD.path;
+  INSERT INTO tag_trigram(rowid, tags)
+    SELECT s.id, s.tags
+    FROM items i JOIN items_search s ON s.id = i.id
+    WHERE substr(i.path, 1, length(OLD.path)) = OLD.path;
+END;
+
+INSERT INTO tag_query(tag_query) VALUES ('rebuild');
+INSERT INTO tag_trigram(tag_trigram) VALUES ('rebuild');
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS collection_items_item_id;
+DROP TABLE IF EXISTS collection_items;
+DROP TABLE IF EXISTS collections;
@@ -0,0 +1,13 @@
+-- hand-picked sets of items that can't be expressed as a query, searched with `collection:name`
+CREATE TABLE collections (
+  id INTEGER PRIMARY KEY,
+  name TEXT UNIQUE NOT NULL
+);
+
+CREATE TABLE collection_items (
+  collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+  item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+  PRIMARY KEY (collection_id, item_id)
+) WITHOUT ROWID;
+
+CREATE INDEX collection_items_item_id ON collection_items(item_id);
@@ -0,0 +1,12 @@
+DROP TRIGGER IF EXISTS item_tags_history_bd;
+DROP TRIGGER IF EXISTS item_tags_history_ai;
+DROP TRIGGER IF EXISTS items_tags_au;
+DROP INDEX IF EXISTS tag_history_tag;
+DROP TABLE IF EXISTS tag_history;
+
+CREATE TRIGGER items_tags_au AFTER UPDATE OF tags ON items BEGIN
+  DELETE FROM item_tags WHERE item_id = OLD.id;
+  INSERT OR IGNORE INTO tags(name) SELECT value FROM json_each(split_tags(NEW.tags));
+  INSERT OR IGNORE INTO item_tags(item_id, tag_id)
+    SELECT NEW.id, tags.id FROM json_each(split_tags(NEW.tags)) JOIN tags ON tags.name = value;
+END;
@@ -0,0 +1,34 @@
+-- every time a tag is added to or removed from an item, used for tag statistics. tags are stored by
+-- name, since unused tags are removed from `tags`. `time` is a unix timestamp in seconds.
+CREATE TABLE tag_history (
+  id INTEGER PRIMARY KEY,
+  tag TEXT NOT NULL,
+  item_id INTEGER NOT NULL,
+  added INTEGER NOT NULL,
+  time INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE INDEX tag_history_tag ON tag_history(tag, time);
+
+-- only remove and add the tags that changed, so unchanged tags aren't recorded in the history
+DROP TRIGGER items_tags_au;
+
+CREATE TRIGGER items_tags_au AFTER UPDATE OF tags ON items BEGIN
+  DELETE FROM item_tags WHERE item_id = OLD.id AND tag_id NOT IN (
+    SELECT tags.id FROM json_each(split_tags(NEW.tags)) JOIN tags ON tags.name = value
+  );
+  INSERT OR IGNORE INTO tags(name) SELECT value FROM json_each(split_tags(NEW.tags));
+  INSERT OR IGNORE INTO item_tags(item_id, tag_id)
+    SELECT NEW.id, tags.id FROM json_each(split_tags(NEW.tags)) JOIN tags ON tags.name = value;
+END;
+
+CREATE TRIGGER item_tags_history_ai AFTER INSERT ON item_tags BEGIN
+  INSERT INTO tag_history(tag, item_id, added)
+    SELECT name, NEW.item_id, 1 FROM tags WHERE id = NEW.tag_id;
+END;
+
+-- this must run before `item_tags_ad` removes the tag when it is no longer used
+CREATE TRIGGER item_tags_history_bd BEFORE DELETE ON item_tags BEGIN
+  INSERT INTO tag_history(tag, item_id, added)
+    SELECT name, OLD.item_id, 0 FROM tags WHERE id = OLD.tag_id;
+END;
@@ -0,0 +1,6 @@
+-- audio fingerprints used to find similar sounding items, see `similarity.rs`. `fingerprint` is
+-- null if the file can't be fingerprinted, so it isn't tried again until the file is modified.
+CREATE TABLE fingerprints (
+  item_id INTEGER PRIMARY KEY REFERENCES items(id) ON DELETE CASCADE,
+  fingerprint BLOB
+);
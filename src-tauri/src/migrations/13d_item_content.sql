@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS items_content_ad;
+DROP TABLE IF EXISTS item_content;
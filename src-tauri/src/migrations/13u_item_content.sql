@@ -0,0 +1,11 @@
+-- text extracted from documents, searched with `content:text`. the rowid is the item's id. items
+-- whose text can't be extracted have empty content, so they aren't tried again until the file is
+-- modified.
+CREATE VIRTUAL TABLE item_content USING fts5 (
+  content,
+  tokenize="unicode61 remove_diacritics 2"
+);
+
+CREATE TRIGGER items_content_ad AFTER DELETE ON items BEGIN
+  DELETE FROM item_content WHERE rowid = OLD.id;
+END;
@@ -0,0 +1,15 @@
+DROP TRIGGER IF EXISTS items_updated_at_au;
+DROP TRIGGER items_trigger_bu;
+DROP TRIGGER items_trigger_au;
+
+CREATE TRIGGER items_trigger_bu BEFORE UPDATE ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', id, tags, meta_tags FROM items_search WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER items_trigger_au AFTER UPDATE ON items BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT id, tags, meta_tags FROM items_search WHERE id = NEW.id;
+END;
+
+ALTER TABLE items DROP COLUMN updated_at;
@@ -0,0 +1,33 @@
+-- when each item's path or tags last changed, in milliseconds since the unix epoch. callers pass
+-- the value they last saw when changing an item, so changes made in the meantime aren't
+-- overwritten, see `Repo::stale_items()`. new items are given the time they were added.
+ALTER TABLE items ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+
+-- the search index only depends on these columns, so it doesn't need to be updated when
+-- `updated_at` or file data like `mtime` changes
+DROP TRIGGER items_trigger_bu;
+DROP TRIGGER items_trigger_au;
+
+CREATE TRIGGER items_trigger_bu BEFORE UPDATE OF path, tags, meta_tags ON items BEGIN
+  INSERT INTO tag_query(tag_query, rowid, tags, meta_tags)
+    SELECT 'delete', id, tags, meta_tags FROM items_search WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER items_trigger_au AFTER UPDATE OF path, tags, meta_tags ON items BEGIN
+  INSERT INTO tag_query(rowid, tags, meta_tags)
+    SELECT id, tags, meta_tags FROM items_search WHERE id = NEW.id;
+END;
+
+UPDATE items SET updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER);
+
+-- it always increases, even if two changes happen in the same millisecond or the clock goes back
+CREATE TRIGGER items_updated_at_au AFTER UPDATE OF path, tags, meta_tags ON items
+WHEN OLD.path IS NOT NEW.path OR OLD.tags IS NOT NEW.tags OR OLD.meta_tags IS NOT NEW.meta_tags
+BEGIN
+  UPDATE items
+  SET updated_at = MAX(
+    OLD.updated_at + 1,
+    CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+  )
+  WHERE id = NEW.id;
+END;
@@ -0,0 +1 @@
+UPDATE items SET meta_tags = 'all';
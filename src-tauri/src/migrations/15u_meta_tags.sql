@@ -0,0 +1,4 @@
+-- derived tags like `type:audio ext:wav` are stored in `meta_tags` after the tag `all`, which
+-- every item has. they only depend on the path, so they are set with `meta_tags(path)` whenever an
+-- item is added or moved. `meta_tags()` is defined in `add_functions()`.
+UPDATE items SET meta_tags = meta_tags(path);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS activity_log_time;
+DROP TABLE IF EXISTS activity_log;
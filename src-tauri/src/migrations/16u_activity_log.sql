@@ -0,0 +1,12 @@
+-- files created, deleted and renamed while the repo was being watched, so users can see what changed
+-- in their library. only the latest `ACTIVITY_LOG_SIZE` entries are kept. `old_path` is only set
+-- for renames, `time` is a unix timestamp in seconds.
+CREATE TABLE activity_log (
+  id INTEGER PRIMARY KEY,
+  kind TEXT NOT NULL,
+  path TEXT NOT NULL,
+  old_path TEXT,
+  time INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE INDEX activity_log_time ON activity_log(time);
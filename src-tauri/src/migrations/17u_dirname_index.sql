@@ -0,0 +1,2 @@
+-- items by the folder they're in, so the folder tree can be listed one folder at a time
+CREATE INDEX items_dirname ON items(dirname(path));
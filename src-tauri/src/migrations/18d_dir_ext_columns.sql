@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS items_ext;
+DROP INDEX IF EXISTS items_dir;
+
+ALTER TABLE items DROP COLUMN ext;
+ALTER TABLE items DROP COLUMN dir;
+
+CREATE INDEX items_dirname ON items(dirname(path));
@@ -0,0 +1,10 @@
+-- the folder and extension of each item, so listing folders and `ext:` queries can use an index
+-- instead of calling `dirname()` and `extname()` on every row. the columns are virtual, so they're
+-- computed from the path and can't be out of date. extensions are compared case-insensitively.
+DROP INDEX IF EXISTS items_dirname;
+
+ALTER TABLE items ADD COLUMN dir TEXT GENERATED ALWAYS AS (dirname(path)) VIRTUAL;
+ALTER TABLE items ADD COLUMN ext TEXT COLLATE NOCASE GENERATED ALWAYS AS (extname(path)) VIRTUAL;
+
+CREATE INDEX items_dir ON items(dir);
+CREATE INDEX items_ext ON items(ext);
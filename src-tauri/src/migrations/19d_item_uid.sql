@@ -0,0 +1,5 @@
+ALTER TABLE tombstones DROP COLUMN uid;
+
+DROP TRIGGER IF EXISTS items_uid_ai;
+DROP INDEX IF EXISTS items_uid;
+ALTER TABLE items DROP COLUMN uid;
@@ -0,0 +1,13 @@
+-- an identifier of each item that stays the same when its file is renamed or relocated, or when
+-- it's deleted and its tags are restored from a tombstone. rowids may be reused by new items, so
+-- the frontend should use this to recognise items it has cached.
+ALTER TABLE items ADD COLUMN uid TEXT;
+UPDATE items SET uid = lower(hex(randomblob(16)));
+CREATE UNIQUE INDEX items_uid ON items(uid);
+
+CREATE TRIGGER items_uid_ai AFTER INSERT ON items WHEN NEW.uid IS NULL BEGIN
+  UPDATE items SET uid = lower(hex(randomblob(16))) WHERE id = NEW.id;
+END;
+
+-- copied from `items.uid`, may be `NULL` for items deleted before this migration
+ALTER TABLE tombstones ADD COLUMN uid TEXT;
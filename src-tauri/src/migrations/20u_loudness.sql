@@ -0,0 +1,8 @@
+-- loudness of audio files, measured the first time they are previewed, see `loudness.rs`. `lufs`
+-- and `peak` are null if the file can't be measured, so it isn't tried again until the file is
+-- modified.
+CREATE TABLE loudness (
+  item_id INTEGER PRIMARY KEY REFERENCES items(id) ON DELETE CASCADE,
+  lufs REAL,
+  peak REAL
+);
@@ -0,0 +1,459 @@
+use std::path::Path;
+use std::process::Command;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::config::{AppConfig, CustomApp};
+
+#[derive(Error, Debug)]
+pub enum OpenWithError {
+    #[error("failed to launch application, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("application not found, {0}")]
+    AppNotFound(String),
+}
+
+/// An application that can open a file, either added by the user in the app config, or
+/// registered with the OS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpenWithApp {
+    /// Used to launch the application with `launch_with()`.
+    pub(crate) id: String,
+    pub(crate) name: String,
+}
+
+/// Prefix of the ids of applications added by the user, followed by the name of the app.
+const CUSTOM_PREFIX: &str = "custom:";
+/// Prefix of the ids of applications registered with the OS, followed by an id that depends on
+/// the OS.
+const SYSTEM_PREFIX: &str = "system:";
+
+/// List the applications that can open the given file. Applications added by the user are listed
+/// first, followed by applications registered with the OS.
+///
+/// Registered applications are found using `gio` on Linux, and the registry on Windows. On macOS,
+/// only applications added by the user are listed.
+pub(crate) fn list_apps(config: &AppConfig, path: impl AsRef<Path>) -> Vec<OpenWithApp> {
+    let path = path.as_ref();
+    let custom_apps = config.apps_for(path).iter().map(|app| OpenWithApp {
+        id: format!("{}{}", CUSTOM_PREFIX, app.name),
+        name: app.name.clone(),
+    });
+    let system_apps = system::list_apps(path)
+        .into_iter()
+        .map(|(id, name)| OpenWithApp { id: format!("{}{}", SYSTEM_PREFIX, id), name });
+    custom_apps.chain(system_apps).collect()
+}
+
+/// Open a file with an application returned by `list_apps()`.
+pub(crate) fn launch_with(
+    config: &AppConfig,
+    path: impl AsRef<Path>,
+    app_id: &str,
+) -> Result<(), OpenWithError> {
+    let path = path.as_ref();
+    if let Some(name) = app_id.strip_prefix(CUSTOM_PREFIX) {
+        let Some(app) = config.apps_for(path).iter().find(|app| app.name == name) else {
+            return Err(OpenWithError::AppNotFound(app_id.to_string()));
+        };
+        custom_command(app, path).spawn()?;
+        Ok(())
+    } else if let Some(id) = app_id.strip_prefix(SYSTEM_PREFIX) {
+        system::launch(id, path)
+    } else {
+        Err(OpenWithError::AppNotFound(app_id.to_string()))
+    }
+}
+
+/// Build the command that opens a file with an application added by the user.
+fn custom_command(app: &CustomApp, path: &Path) -> Command {
+    let path = path.to_string_lossy();
+    let mut command = Command::new(&app.command);
+    if app.args.iter().any(|arg| arg.contains("{path}")) {
+        command.args(app.args.iter().map(|arg| arg.replace("{path}", &path)));
+    } else {
+        command.args(&app.args).arg(path.as_ref());
+    }
+    command
+}
+
+/// Expand environment variables in a registry value, e.g. `%SystemRoot%\notepad.exe`. Unknown
+/// variables are left as they are.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn expand_env_vars(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('%').and_then(|end| {
+            let name = &after[..end];
+            let is_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '(' || c == ')');
+            let value = std::env::var(name).ok().filter(|_| is_name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                result.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('%');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Split a Windows shell command, e.g. `"C:\vlc.exe" --started-from-file "%1"`, into the program
+/// and its arguments, with the file path filled in. The arguments are returned as a single string,
+/// since Windows programs parse their own command line.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn split_shell_command(command: &str, path: &Path) -> (String, String) {
+    let path = path.to_string_lossy();
+    let command = command.trim();
+    let (program, args) = match command.strip_prefix('"') {
+        Some(rest) => match rest.find('"') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, ""),
+        },
+        None => match command.find(char::is_whitespace) {
+            Some(end) => (&command[..end], &command[end..]),
+            None => (command, ""),
+        },
+    };
+    let mut args = args.trim().to_string();
+    if args.contains("%1") || args.contains("%L") {
+        args = args.replace("%1", &path).replace("%L", &path);
+    } else {
+        if !args.is_empty() {
+            args.push(' ');
+        }
+        args.push_str(&format!("\"{}\"", path));
+    }
+    args = args.replace("%*", "");
+    (program.to_string(), args.trim().to_string())
+}
+
+#[cfg(windows)]
+mod system {
+    use std::os::windows::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    use winreg::enums::HKEY_CLASSES_ROOT;
+    use winreg::RegKey;
+
+    use super::{expand_env_vars, split_shell_command, OpenWithError};
+
+    /// The command used to open files with the given ProgID.
+    fn open_command(classes: &RegKey, progid: &str) -> Option<String> {
+        let key = classes
+            .open_subkey(format!("{}\\shell\\open\\command", progid))
+            .ok()?;
+        key.get_value::<String, _>("").ok()
+    }
+
+    /// A readable name for the given ProgID. Names that refer to resource files, e.g.
+    /// `@%SystemRoot%\system32\shell32.dll,-123`, are skipped.
+    fn app_name(classes: &RegKey, progid: &str) -> Option<String> {
+        let friendly_name = classes
+            .open_subkey(format!("{}\\shell\\open", progid))
+            .and_then(|key| key.get_value::<String, _>("FriendlyAppName"));
+        let description = || classes.open_subkey(progid)?.get_value::<String, _>("");
+        friendly_name
+            .or_else(|_| description())
+            .ok()
+            .filter(|name| !name.is_empty() && !name.starts_with('@'))
+    }
+
+    /// List the ProgIDs registered for the file's extension, which have an "open" command.
+    pub(super) fn list_apps(path: &Path) -> Vec<(String, String)> {
+        let Some(extension) = path.extension() else {
+            return vec![];
+        };
+        let classes = RegKey::predef(HKEY_CLASSES_ROOT);
+        let subkey = format!(".{}\\OpenWithProgids", extension.to_string_lossy());
+        let Ok(progids) = classes.open_subkey(subkey) else {
+            return vec![];
+        };
+        progids
+            .enum_values()
+            .filter_map(|value| value.ok())
+            .filter(|(progid, _)| open_command(&classes, progid).is_some())
+            .map(|(progid, _)| {
+                let name = app_name(&classes, &progid).unwrap_or_else(|| progid.clone());
+                (progid, name)
+            })
+            .collect()
+    }
+
+    pub(super) fn launch(progid: &str, path: &Path) -> Result<(), OpenWithError> {
+        let classes = RegKey::predef(HKEY_CLASSES_ROOT);
+        let Some(command) = open_command(&classes, progid) else {
+            return Err(OpenWithError::AppNotFound(progid.to_string()));
+        };
+        let (program, args) = split_shell_command(&expand_env_vars(&command), path);
+        Command::new(program).raw_arg(args).spawn()?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod system {
+    use std::path::Path;
+
+    use super::OpenWithError;
+
+    pub(super) fn list_apps(_path: &Path) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    pub(super) fn launch(id: &str, _path: &Path) -> Result<(), OpenWithError> {
+        Err(OpenWithError::AppNotFound(id.to_string()))
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod system {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::OpenWithError;
+
+    /// The MIME type of a file, e.g. `audio/x-wav`.
+    fn mime_type(path: &Path) -> Option<String> {
+        let output = Command::new("xdg-mime")
+            .args(["query", "filetype"])
+            .arg(path)
+            .output()
+            .ok()?;
+        let mime = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !mime.is_empty()).then_some(mime)
+    }
+
+    /// Parse the output of `gio mime <type>`, returning the ids of the registered desktop files.
+    pub(super) fn parse_gio_mime(output: &str) -> Vec<String> {
+        output
+            .lines()
+            .skip_while(|line| !line.starts_with("Registered applications:"))
+            .skip(1)
+            .take_while(|line| line.starts_with(char::is_whitespace))
+            .map(|line| line.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect()
+    }
+
+    /// Find the `Name` of a desktop file, in the `[Desktop Entry]` section.
+    pub(super) fn parse_desktop_name(contents: &str) -> Option<String> {
+        contents
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| *line != "[Desktop Entry]")
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .find_map(|line| line.strip_prefix("Name="))
+            .map(String::from)
+    }
+
+    /// Folders containing desktop files, following the XDG base directory spec.
+    fn application_dirs() -> Vec<PathBuf> {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+        let data_dirs = std::env::var("XDG_DATA_DIRS")
+            .unwrap_or_else(|_| String::from("/usr/local/share:/usr/share"));
+        data_home
+            .into_iter()
+            .chain(
+                data_dirs
+                    .split(':')
+                    .filter(|x| !x.is_empty())
+                    .map(PathBuf::from),
+            )
+            .map(|dir| dir.join("applications"))
+            .collect()
+    }
+
+    fn desktop_file(id: &str) -> Option<PathBuf> {
+        application_dirs()
+            .into_iter()
+            .map(|dir| dir.join(id))
+            .find(|path| path.is_file())
+    }
+
+    pub(super) fn list_apps(path: &Path) -> Vec<(String, String)> {
+        let Some(mime) = mime_type(path) else {
+            return vec![];
+        };
+        let Ok(output) = Command::new("gio").args(["mime", &mime]).output() else {
+            return vec![];
+        };
+        parse_gio_mime(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .map(|id| {
+                let name = desktop_file(&id)
+                    .and_then(|file| parse_desktop_name(&fs::read_to_string(file).ok()?))
+                    .unwrap_or_else(|| id.trim_end_matches(".desktop").to_string());
+                (id, name)
+            })
+            .collect()
+    }
+
+    pub(super) fn launch(id: &str, path: &Path) -> Result<(), OpenWithError> {
+        let Some(file) = desktop_file(id) else {
+            return Err(OpenWithError::AppNotFound(id.to_string()));
+        };
+        Command::new("gio")
+            .arg("launch")
+            .arg(file)
+            .arg(path)
+            .spawn()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn app(args: &[&str]) -> CustomApp {
+        CustomApp {
+            name: String::from("Editor"),
+            command: String::from("editor"),
+            args: args.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    fn command_args(command: &Command) -> Vec<&OsStr> {
+        command.get_args().collect()
+    }
+
+    #[test]
+    fn custom_command_args() {
+        let path = Path::new("a b.wav");
+        let command = custom_command(&app(&[]), path);
+        assert_eq!(command.get_program(), "editor");
+        assert_eq!(command_args(&command), vec!["a b.wav"]);
+
+        let command = custom_command(&app(&["--new-window"]), path);
+        assert_eq!(command_args(&command), vec!["--new-window", "a b.wav"]);
+
+        let command = custom_command(&app(&["--file={path}", "--quiet"]), path);
+        assert_eq!(command_args(&command), vec!["--file=a b.wav", "--quiet"]);
+    }
+
+    #[test]
+    fn unknown_apps_are_not_launched() {
+        let config = AppConfig::default();
+        assert!(matches!(
+            launch_with(&config, "a.wav", "custom:Editor"),
+            Err(OpenWithError::AppNotFound(_))
+        ));
+        assert!(matches!(
+            launch_with(&config, "a.wav", "Editor"),
+            Err(OpenWithError::AppNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn lists_custom_apps_first() {
+        let mut config = AppConfig::default();
+        config
+            .open_with
+            .insert(String::from("zzz-unknown"), vec![app(&[])]);
+        let apps = list_apps(&config, "a.zzz-unknown");
+        assert_eq!(
+            apps.first(),
+            Some(&OpenWithApp {
+                id: String::from("custom:Editor"),
+                name: String::from("Editor")
+            })
+        );
+    }
+
+    #[test]
+    fn splits_shell_commands() {
+        let path = Path::new(r"C:\a b.wav");
+        assert_eq!(
+            split_shell_command(
+                r#""C:\Program Files\vlc.exe" --started-from-file "%1""#,
+                path
+            ),
+            (
+                String::from(r"C:\Program Files\vlc.exe"),
+                String::from(r#"--started-from-file "C:\a b.wav""#)
+            ),
+        );
+        assert_eq!(
+            split_shell_command(r"C:\Windows\notepad.exe %1", path),
+            (
+                String::from(r"C:\Windows\notepad.exe"),
+                String::from(r"C:\a b.wav")
+            ),
+        );
+        assert_eq!(
+            split_shell_command(r"C:\app.exe", path),
+            (String::from(r"C:\app.exe"), String::from(r#""C:\a b.wav""#)),
+        );
+        assert_eq!(
+            split_shell_command(r#""C:\app.exe" "%1" %*"#, path),
+            (String::from(r"C:\app.exe"), String::from(r#""C:\a b.wav""#)),
+        );
+    }
+
+    #[test]
+    fn expands_env_vars() {
+        std::env::set_var("TAGREPO_TEST_ROOT", r"C:\Windows");
+        assert_eq!(
+            expand_env_vars(r#"%TAGREPO_TEST_ROOT%\notepad.exe "%1""#),
+            r#"C:\Windows\notepad.exe "%1""#
+        );
+        assert_eq!(
+            expand_env_vars("%TAGREPO_TEST_MISSING%"),
+            "%TAGREPO_TEST_MISSING%"
+        );
+        assert_eq!(expand_env_vars("100% %L"), "100% %L");
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    #[test]
+    fn parses_gio_output() {
+        let output = "Default application for “audio/x-wav”: vlc.desktop\n\
+            Registered applications:\n\
+            \tvlc.desktop\n\
+            \torg.gnome.Totem.desktop\n\
+            Recommended applications:\n\
+            \tvlc.desktop\n";
+        assert_eq!(
+            system::parse_gio_mime(output),
+            vec!["vlc.desktop", "org.gnome.Totem.desktop"]
+        );
+        assert!(system::parse_gio_mime("No default applications for “a/b”\n").is_empty());
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    #[test]
+    fn parses_desktop_name() {
+        let contents = "[Desktop Entry]\n\
+            Type=Application\n\
+            Name=VLC media player\n\
+            Name[de]=VLC Media Player\n\
+            [Desktop Action new]\n\
+            Name=New window\n";
+        assert_eq!(
+            system::parse_desktop_name(contents),
+            Some(String::from("VLC media player"))
+        );
+        assert_eq!(system::parse_desktop_name("[Other]\nName=x\n"), None);
+    }
+}
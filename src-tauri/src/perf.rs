@@ -0,0 +1,178 @@
+//! Timings of slow operations, e.g. scanning, syncing and querying, so users can report slowness
+//! with real numbers.
+//!
+//! Timings are only recorded when the app is built with the `profiling` feature. Each timed
+//! operation is also logged in a `perf` tracing span.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tracing::{debug, debug_span, Span};
+
+/// Number of timings kept for each operation, older timings are discarded.
+const MAX_SAMPLES: usize = 1000;
+
+lazy_static! {
+    static ref RECORDER: Recorder = Recorder::default();
+}
+
+/// Whether timings are being recorded.
+pub(crate) fn enabled() -> bool {
+    cfg!(feature = "profiling")
+}
+
+/// Time an operation until the returned guard is dropped.
+pub(crate) fn measure(op: &'static str) -> PerfGuard {
+    if !enabled() {
+        return PerfGuard { timer: None };
+    }
+    let span = debug_span!("perf", op);
+    PerfGuard { timer: Some((op, span, Instant::now())) }
+}
+
+/// Summary of all recorded timings.
+pub(crate) fn report() -> PerfReport {
+    RECORDER.report()
+}
+
+/// Records the time taken when dropped, see `measure()`.
+#[must_use]
+pub(crate) struct PerfGuard {
+    timer: Option<(&'static str, Span, Instant)>,
+}
+
+impl Drop for PerfGuard {
+    fn drop(&mut self) {
+        if let Some((op, span, start)) = self.timer.take() {
+            let elapsed = start.elapsed();
+            span.in_scope(|| debug!(elapsed_ms = elapsed.as_secs_f64() * 1000.0, "finished"));
+            RECORDER.record(op, elapsed);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    samples: Mutex<HashMap<&'static str, VecDeque<Duration>>>,
+}
+
+impl Recorder {
+    fn record(&self, op: &'static str, elapsed: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(op).or_default();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(elapsed);
+    }
+
+    fn report(&self) -> PerfReport {
+        let samples = self.samples.lock().unwrap();
+        let mut operations: Vec<_> = samples
+            .iter()
+            .map(|(op, samples)| OperationStats::new(op, samples.iter().copied().collect()))
+            .collect();
+        operations.sort_by(|a, b| a.op.cmp(b.op));
+        PerfReport { enabled: enabled(), operations }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PerfReport {
+    /// False if the app wasn't built with the `profiling` feature
+    pub(crate) enabled: bool,
+    pub(crate) operations: Vec<OperationStats>,
+}
+
+/// Timings of one kind of operation, in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OperationStats {
+    pub(crate) op: &'static str,
+    pub(crate) count: usize,
+    pub(crate) p50: f64,
+    pub(crate) p95: f64,
+    pub(crate) max: f64,
+}
+
+impl OperationStats {
+    fn new(op: &'static str, mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let millis = |x: Option<&Duration>| x.map_or(0.0, |x| x.as_secs_f64() * 1000.0);
+        Self {
+            op,
+            count: samples.len(),
+            p50: millis(percentile(&samples, 50)),
+            p95: millis(percentile(&samples, 95)),
+            max: millis(samples.last()),
+        }
+    }
+}
+
+/// The sample at the given percentile of sorted samples, using the nearest-rank method.
+fn percentile<T>(sorted: &[T], percent: usize) -> Option<&T> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100);
+    sorted.get(rank.max(1) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let samples: Vec<_> = (1..=20).collect();
+        assert_eq!(percentile(&samples, 50), Some(&10));
+        assert_eq!(percentile(&samples, 95), Some(&19));
+        assert_eq!(percentile(&samples, 100), Some(&20));
+        assert_eq!(percentile(&samples, 0), Some(&1));
+        assert_eq!(percentile(&[5], 95), Some(&5));
+        assert_eq!(percentile::<i32>(&[], 50), None);
+    }
+
+    #[test]
+    fn recorder_summarises_each_operation() {
+        let recorder = Recorder::default();
+        for ms in [30, 10, 20] {
+            recorder.record("query", Duration::from_millis(ms));
+        }
+        recorder.record("scan", Duration::from_millis(500));
+
+        let report = recorder.report();
+        assert_eq!(
+            report.operations,
+            vec![
+                OperationStats {
+                    op: "query",
+                    count: 3,
+                    p50: 20.0,
+                    p95: 30.0,
+                    max: 30.0
+                },
+                OperationStats {
+                    op: "scan",
+                    count: 1,
+                    p50: 500.0,
+                    p95: 500.0,
+                    max: 500.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn old_samples_are_discarded() {
+        let recorder = Recorder::default();
+        for ms in 0..MAX_SAMPLES + 10 {
+            recorder.record("query", Duration::from_millis(ms as u64));
+        }
+        let report = recorder.report();
+        assert_eq!(report.operations[0].count, MAX_SAMPLES);
+        assert_eq!(report.operations[0].max.round(), (MAX_SAMPLES + 9) as f64);
+    }
+}
@@ -0,0 +1,157 @@
+//! Read-only connections to a repo's database, so queries don't have to wait for the main
+//! connection while it is busy, e.g. during a big sync.
+//!
+//! In `LockingMode::Exclusive`, readers see the database as of the last commit, since it uses a
+//! WAL journal. In `LockingMode::Shared`, readers wait for the main connection to finish each
+//! write, since it uses a rollback journal.
+
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::repo::{OpenError, Repo};
+use crate::repo_config::RepoConfig;
+
+/// Number of unused connections kept open.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+#[derive(Debug)]
+pub(crate) struct ReadPool {
+    repo_path: PathBuf,
+    /// Copy of the main connection's config, used by new readers
+    config: Mutex<RepoConfig>,
+    idle: Mutex<Vec<Repo>>,
+}
+
+impl ReadPool {
+    /// Create a pool of readers for the given repo.
+    pub(crate) fn new(repo: &Repo) -> Arc<Self> {
+        Arc::new(Self {
+            repo_path: repo.path().to_path_buf(),
+            config: Mutex::new(repo.config().clone()),
+            idle: Mutex::new(vec![]),
+        })
+    }
+
+    /// Take an unused connection, or open a new one if there are none.
+    pub(crate) fn get(self: &Arc<Self>) -> Result<PooledRepo, OpenError> {
+        let idle = self.idle.lock().unwrap().pop();
+        let repo = match idle {
+            Some(repo) => repo,
+            None => {
+                let config = self.config.lock().unwrap().clone();
+                Repo::open_reader(&self.repo_path, config)?
+            }
+        };
+        Ok(PooledRepo { repo: Some(repo), pool: self.clone() })
+    }
+
+    /// Close all unused connections, and use the given config for new connections. Must be
+    /// called when the config changes, since readers use a copy of the config.
+    pub(crate) fn reset(&self, config: &RepoConfig) {
+        *self.config.lock().unwrap() = config.clone();
+        self.idle.lock().unwrap().clear();
+    }
+
+    fn put(&self, repo: Repo) {
+        let mut idle = self.idle.lock().unwrap();
+        // connections opened before a reset use the old config
+        if idle.len() < MAX_IDLE_CONNECTIONS && repo.config() == &*self.config.lock().unwrap() {
+            idle.push(repo);
+        }
+    }
+}
+
+/// A connection from a `ReadPool`, it is returned to the pool when dropped.
+#[derive(Debug)]
+pub(crate) struct PooledRepo {
+    repo: Option<Repo>,
+    pool: Arc<ReadPool>,
+}
+
+impl Deref for PooledRepo {
+    type Target = Repo;
+
+    fn deref(&self) -> &Repo {
+        self.repo.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledRepo {
+    fn drop(&mut self) {
+        if let Some(repo) = self.repo.take() {
+            self.pool.put(repo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::repo::OpenMode;
+    use crate::repo_config::{LockingMode, RepoConfig};
+
+    use super::*;
+
+    fn open_repo(locking_mode: LockingMode) -> (Repo, tempfile::TempDir) {
+        let dir = tempdir().unwrap();
+        let data_path = dir.path().join(".tagrepo");
+        std::fs::create_dir(&data_path).unwrap();
+        let config = RepoConfig { locking_mode, ..Default::default() };
+        config.save(&data_path).unwrap();
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        (repo, dir)
+    }
+
+    #[test]
+    fn exclusive_repos_can_be_read() {
+        let (repo, _dir) = open_repo(LockingMode::Exclusive);
+        let pool = ReadPool::new(&repo);
+        repo.insert_item("kick", "drum").unwrap();
+        let reader = pool.get().unwrap();
+        assert_eq!(reader.query_ids("drum").unwrap().len(), 1);
+
+        // the reader sees the latest commit
+        repo.insert_item("snare", "drum").unwrap();
+        assert_eq!(reader.query_ids("drum").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn readers_see_writes() {
+        let (repo, _dir) = open_repo(LockingMode::Shared);
+        let pool = ReadPool::new(&repo);
+        repo.insert_item("kick", "drum").unwrap();
+        {
+            let reader = pool.get().unwrap();
+            assert!(reader.read_only());
+            assert_eq!(reader.query_ids("drum").unwrap().len(), 1);
+        }
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+
+        // the idle reader is reused
+        repo.insert_item("snare", "drum").unwrap();
+        let reader = pool.get().unwrap();
+        assert!(pool.idle.lock().unwrap().is_empty());
+        assert_eq!(reader.query_ids("drum").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reset_closes_idle_readers() {
+        let (mut repo, _dir) = open_repo(LockingMode::Shared);
+        let pool = ReadPool::new(&repo);
+        let old_reader = pool.get().unwrap();
+        drop(pool.get().unwrap());
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+
+        let config = RepoConfig { auto_tag: false, ..repo.config().clone() };
+        repo.set_config(config).unwrap();
+        pool.reset(repo.config());
+        assert!(pool.idle.lock().unwrap().is_empty());
+
+        // readers with the old config aren't reused
+        drop(old_reader);
+        assert!(pool.idle.lock().unwrap().is_empty());
+        assert!(!pool.get().unwrap().config().auto_tag);
+    }
+}
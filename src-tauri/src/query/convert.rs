@@ -2,7 +2,9 @@
 
 use super::parser::Expr;
 use crate::helpers::sql::{escape_fts5_string, escape_like_pattern};
+use crate::repo::normalize_tag;
 use itertools::Itertools;
+use rusqlite::types::Value;
 use std::borrow::{Borrow, Cow};
 use std::cmp::Ordering;
 
@@ -12,13 +14,38 @@ pub(crate) enum WhereClause<'a> {
     InDir(Cow<'a, str>),
     HasExt(Cow<'a, str>),
     InPath(Cow<'a, str>),
+    InName(Cow<'a, str>),
     ChildrenOf(Cow<'a, str>),
     LeadingPath(Cow<'a, str>),
+    TagContains(Cow<'a, str>),
+    IsTagged(bool),
+    TagCount(u32),
+    InCollection(Cow<'a, str>),
+    ContentMatches(Cow<'a, str>),
     And(Vec<WhereClause<'a>>),
     Or(Vec<WhereClause<'a>>),
     Not(Box<WhereClause<'a>>),
 }
 
+/// Whether the text contains Chinese, Japanese or Korean characters. CJK text isn't separated into
+/// words, so searching for it with FTS only matches whole tags.
+fn contains_cjk(text: &str) -> bool {
+    text.chars().any(|c| {
+        matches!(c,
+            '\u{1100}'..='\u{11FF}'     // Hangul Jamo
+            | '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+            | '\u{3130}'..='\u{318F}'   // Hangul Compatibility Jamo
+            | '\u{31F0}'..='\u{31FF}'   // Katakana Phonetic Extensions
+            | '\u{3400}'..='\u{4DBF}'   // CJK Unified Ideographs Extension A
+            | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+            | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+            | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+            | '\u{FF66}'..='\u{FF9F}'   // Halfwidth Katakana
+            | '\u{20000}'..='\u{2FA1F}' // CJK Unified Ideographs Extension B onwards
+        )
+    })
+}
+
 /// Since paths are always stored using "/" in the database, we need to convert searches with "\"
 /// into "/" on Windows.
 fn convert_from_os_path(path: &str) -> String {
@@ -29,68 +56,137 @@ fn convert_from_os_path(path: &str) -> String {
     }
 }
 
+/// A where clause converted to SQL. Values from the query are never written into the SQL, they are
+/// bound to the `?` placeholders in order, so queries with the same structure use the same SQL.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SqlClause {
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+}
+
 impl<'a> WhereClause<'a> {
-    pub(crate) fn to_sql_clause(&self) -> String {
-        self.to_sql_subclause(true)
+    pub(crate) fn to_sql_clause(&self) -> SqlClause {
+        let mut params = vec![];
+        let sql = self.to_sql_subclause(true, &mut params);
+        SqlClause { sql, params }
+    }
+
+    /// Like `to_sql_clause()`, but the FTS query that every result must match is written as
+    /// `tq.tag_query = ?`, so results can be ordered by `tq.rank`. Returns None if there is no such
+    /// FTS query.
+    pub(crate) fn to_ranked_sql_clause(&self) -> Option<SqlClause> {
+        use WhereClause::*;
+
+        match self {
+            FTS(_) => Some(self.to_sql_clause()),
+            // `generate_clause` puts the only FTS query of an AND group first
+            And(clauses) if matches!(clauses.first(), Some(FTS(_))) => {
+                let mut params = vec![];
+                let inner = clauses
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| x.to_sql_subclause(i == 0, &mut params))
+                    .join(" AND ");
+                Some(SqlClause { sql: format!("({})", inner), params })
+            }
+            _ => None,
+        }
     }
 
-    fn to_sql_subclause(&self, is_root: bool) -> String {
+    fn to_sql_subclause(&self, is_root: bool, params: &mut Vec<Value>) -> String {
         use WhereClause::*;
 
         match self {
             FTS(part) => {
-                let fts_query = part.to_fts_query();
+                params.push(Value::Text(part.to_fts_query()));
                 if is_root {
                     // only 1 FTS query is allowed to use this form in an SQL statement
                     // we'll use it for the FTS query at the root level (there should only be 1)
-                    format!("tq.tag_query = '{}'", fts_query)
+                    String::from("tq.tag_query = ?")
                 } else {
-                    format!("i.id IN (SELECT id FROM tag_query('{}'))", fts_query)
+                    String::from("i.id IN (SELECT id FROM tag_query(?))")
                 }
             }
             InDir(path) => {
-                let path = convert_from_os_path(path.borrow());
-                let mut escaped_path = escape_like_pattern(&path, '\\');
-                if !escaped_path.ends_with("/") {
-                    escaped_path.push('/');
+                let mut path = convert_from_os_path(path.borrow());
+                if !path.ends_with('/') {
+                    path.push('/');
                 }
-                format!("i.path LIKE '{}%' ESCAPE '\\'", escaped_path)
+                params.push(Value::Text(path));
+                // follows the repo's path case sensitivity, see `repo::set_path_case()`
+                String::from("has_path_prefix(i.path, ?)")
             }
             HasExt(ext) => {
-                let escaped_ext = escape_like_pattern(ext, '\\');
-                format!("extname(i.path) LIKE '{}' ESCAPE '\\'", escaped_ext)
+                // `i.ext` is NOCASE, so this is case-insensitive and can use its index
+                params.push(Value::Text(ext.to_string()));
+                String::from("i.ext = ?")
             }
             InPath(path) => {
                 let escaped_path = escape_like_pattern(path.borrow(), '\\');
-                format!("i.path LIKE '%{}%' ESCAPE '\\'", escaped_path)
+                params.push(Value::Text(format!("%{}%", escaped_path)));
+                String::from("i.path LIKE ? ESCAPE '\\'")
+            }
+            InName(name) => {
+                let escaped_name = escape_like_pattern(name.borrow(), '\\');
+                params.push(Value::Text(format!("%{}%", escaped_name)));
+                String::from("basename(i.path) LIKE ? ESCAPE '\\'")
             }
             ChildrenOf(path) => {
                 let path = convert_from_os_path(path.borrow());
-                let mut escaped_path = escape_like_pattern(&path, '\\');
-                if !escaped_path.ends_with("/") {
-                    escaped_path.push('/');
-                }
-                format!(
-                    "i.path LIKE '{}%' ESCAPE '\\' AND NOT i.path LIKE '{}%/%' ESCAPE '\\'",
-                    escaped_path, escaped_path
-                )
+                params.push(Value::Text(path.trim_end_matches('/').to_string()));
+                // follows the repo's path case sensitivity, see `repo::set_path_case()`
+                String::from("is_same_path(i.dir, ?)")
             }
             LeadingPath(path) => {
                 let path = convert_from_os_path(path.borrow());
-                let escaped_path = escape_like_pattern(&path, '\\');
-                format!("i.path LIKE '{}%' ESCAPE '\\'", escaped_path)
+                params.push(Value::Text(path));
+                String::from("has_path_prefix(i.path, ?)")
+            }
+            IsTagged(true) => String::from("i.tags != ''"),
+            IsTagged(false) => String::from("i.tags = ''"),
+            TagCount(count) => {
+                params.push(Value::Integer(*count as i64));
+                // tags are stored separated by single spaces, so count the spaces
+                String::from(
+                    "(CASE WHEN i.tags = '' THEN 0 ELSE length(i.tags) - length(replace(i.tags, ' ', '')) + 1 END) = ?",
+                )
+            }
+            InCollection(name) => {
+                params.push(Value::Text(name.trim().to_string()));
+                String::from(
+                    "i.id IN (SELECT ci.item_id FROM collection_items ci JOIN collections c ON c.id = ci.collection_id WHERE c.name = ?)",
+                )
+            }
+            ContentMatches(text) => {
+                // search for the words as a phrase, so FTS syntax in the text isn't interpreted
+                params.push(Value::Text(format!(
+                    "\"{}\"",
+                    escape_fts5_string(text.as_ref())
+                )));
+                String::from("i.id IN (SELECT rowid FROM item_content WHERE item_content MATCH ?)")
+            }
+            TagContains(text) => {
+                let escaped_text = escape_like_pattern(text.borrow(), '\\');
+                // the trigram index can only be used if there is no ESCAPE clause
+                let sql = if escaped_text == *text {
+                    "i.id IN (SELECT rowid FROM tag_trigram WHERE tags LIKE ?)"
+                } else {
+                    "i.id IN (SELECT rowid FROM tag_trigram WHERE tags LIKE ? ESCAPE '\\')"
+                };
+                params.push(Value::Text(format!("%{}%", escaped_text)));
+                String::from(sql)
             }
             And(clauses) => {
                 let inner = clauses
                     .iter()
-                    .map(|x| x.to_sql_subclause(false))
+                    .map(|x| x.to_sql_subclause(false, params))
                     .join(" AND ");
                 format!("({})", inner)
             }
             Or(clauses) => {
                 let inner = clauses
                     .iter()
-                    .map(|x| x.to_sql_subclause(false))
+                    .map(|x| x.to_sql_subclause(false, params))
                     .join(" OR ");
                 format!("({})", inner)
             }
@@ -106,16 +202,17 @@ impl<'a> WhereClause<'a> {
 
                         // code copied from: FTSPart::to_fts_query
                         let fts_query = format!(r#"(meta_tags:"all" NOT {})"#, part.to_fts_query());
+                        params.push(Value::Text(fts_query));
 
                         // code copied from: WhereClause::to_sql_clause
                         if is_root {
-                            format!("tq.tag_query = '{}'", fts_query)
+                            String::from("tq.tag_query = ?")
                         } else {
-                            format!("i.id IN ( SELECT id FROM tag_query('{}') )", fts_query)
+                            String::from("i.id IN ( SELECT id FROM tag_query(?) )")
                         }
                     }
                     clause => {
-                        let sql = clause.to_sql_subclause(false);
+                        let sql = clause.to_sql_subclause(false, params);
                         format!("NOT ({})", sql)
                     }
                 }
@@ -127,6 +224,11 @@ impl<'a> WhereClause<'a> {
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum FTSPart<'a> {
     Phrase(Cow<'a, str>),
+    Prefix(Cow<'a, str>),
+    /// An FTS5 query written by the user, only matching the `tags` column
+    Raw(Cow<'a, str>),
+    /// A meta tag derived from the item's path, e.g. `type:audio`, see `meta_tags()`
+    MetaTag(Cow<'a, str>),
     And(Vec<FTSPart<'a>>),
     Or(Vec<FTSPart<'a>>),
     Not(Box<FTSPart<'a>>),
@@ -176,6 +278,13 @@ impl<'a> FTSPart<'a> {
             Phrase(name) => {
                 format!("tags:\"{}\"", escape_fts5_string(name.as_ref()))
             }
+            Prefix(name) => {
+                format!("tags:\"{}\"*", escape_fts5_string(name.as_ref()))
+            }
+            Raw(query) => format!("tags:({})", query),
+            MetaTag(name) => {
+                format!("meta_tags:\"{}\"", escape_fts5_string(name.as_ref()))
+            }
             And(parts) => {
                 let mut parts_contain_pos = false;
                 let mut parts_contain_neg = false;
@@ -255,6 +364,16 @@ impl<'a> FTSPart<'a> {
     }
 }
 
+/// Whether the expression is converted into a single FTS query by `generate_clause`.
+pub(crate) fn is_fts(expr: &Expr) -> bool {
+    match expr {
+        Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().all(is_fts),
+        Expr::Not(expr) => is_fts(expr),
+        Expr::Tag(name) | Expr::TagPrefix(name) => !contains_cjk(name),
+        Expr::KeyValue(key, _) => key == "fts" || key == "type",
+    }
+}
+
 /// The main endpoint of this module.
 /// This receives the root of an expression tree and generates SQL where clauses.
 ///
@@ -339,10 +458,12 @@ pub(crate) fn generate_clause<'a>(root: &'a Expr<'a>) -> WhereClause<'a> {
                 WhereClause::Not(Box::new(clause))
             }
         }
-        Expr::Tag(name) => {
-            let name: &str = name.borrow();
-            WhereClause::FTS(FTSPart::Phrase(Cow::from(name)))
+        // since CJK tags can't be split into words, search for CJK text anywhere in the tags
+        Expr::Tag(name) | Expr::TagPrefix(name) if contains_cjk(name) => {
+            WhereClause::TagContains(normalize_tag(name))
         }
+        Expr::Tag(name) => WhereClause::FTS(FTSPart::Phrase(normalize_tag(name))),
+        Expr::TagPrefix(name) => WhereClause::FTS(FTSPart::Prefix(normalize_tag(name))),
         Expr::KeyValue(key, val) => match key.as_ref() {
             "in" => {
                 let val: &str = val.borrow();
@@ -356,6 +477,18 @@ pub(crate) fn generate_clause<'a>(root: &'a Expr<'a>) -> WhereClause<'a> {
                 let val: &str = val.borrow();
                 WhereClause::InPath(Cow::from(val))
             }
+            "fts" => {
+                let val: &str = val.borrow();
+                WhereClause::FTS(FTSPart::Raw(Cow::from(val)))
+            }
+            "name" => {
+                let val: &str = val.borrow();
+                WhereClause::InName(Cow::from(val))
+            }
+            "type" => WhereClause::FTS(FTSPart::MetaTag(Cow::from(format!(
+                "type:{}",
+                val.to_lowercase()
+            )))),
             "children" => {
                 let val: &str = val.borrow();
                 WhereClause::ChildrenOf(Cow::from(val))
@@ -364,6 +497,25 @@ pub(crate) fn generate_clause<'a>(root: &'a Expr<'a>) -> WhereClause<'a> {
                 let val: &str = val.borrow();
                 WhereClause::LeadingPath(Cow::from(val))
             }
+            "collection" => {
+                let val: &str = val.borrow();
+                WhereClause::InCollection(Cow::from(val))
+            }
+            "content" => {
+                let val: &str = val.borrow();
+                WhereClause::ContentMatches(Cow::from(val))
+            }
+            "is" => match val.as_ref() {
+                "tagged" => WhereClause::IsTagged(true),
+                "untagged" => WhereClause::IsTagged(false),
+                _ => panic!("Unrecognised value for 'is' received: {:?}", val),
+            },
+            "tagcount" => {
+                let count = val
+                    .parse()
+                    .expect("Parser should only accept numeric tag counts");
+                WhereClause::TagCount(count)
+            }
             _ => panic!(
                 "Unrecognised key-value pair received: {:?} = {:?}",
                 key, val
@@ -652,59 +804,182 @@ mod test_to_sql {
     use crate::query::parser::parse;
     use super::*;
 
-    fn assert_sql(query: &str, expected: &str) {
+    fn assert_sql(query: &str, expected: &str, expected_params: Vec<&str>) {
         let expr = parse(query).unwrap();
         let clause = generate_clause(&expr);
-        let sql_clause = clause.to_sql_subclause(true);
-        assert_eq!(sql_clause, expected);
+        let sql_clause = clause.to_sql_clause();
+        let params: Vec<_> = sql_clause
+            .params
+            .iter()
+            .map(|x| match x {
+                Value::Text(text) => text.clone(),
+                Value::Integer(number) => number.to_string(),
+                x => panic!("unexpected param {:?}", x),
+            })
+            .collect();
+        assert_eq!(sql_clause.sql, expected);
+        assert_eq!(params, expected_params);
     }
 
     #[test]
     fn fts_1() { assert_sql(
         "a b",
-        r#"tq.tag_query = '(tags:"a" AND tags:"b")'"#) }
+        "tq.tag_query = ?",
+        vec![r#"(tags:"a" AND tags:"b")"#]) }
 
     #[test]
     fn fts_2() { assert_sql(
         "-(a b)",
-        r#"tq.tag_query = '(meta_tags:"all" NOT (tags:"a" AND tags:"b"))'"#) }
+        "tq.tag_query = ?",
+        vec![r#"(meta_tags:"all" NOT (tags:"a" AND tags:"b"))"#]) }
 
     #[test]
     fn fts_3() { assert_sql(
-        "'mc''donalds' b",
-        r#"tq.tag_query = '(tags:"mc''donalds" AND tags:"b")'"#) }
+        r#"'mc''donalds' b 'say "hi"'"#,
+        "tq.tag_query = ?",
+        vec![r#"(tags:"mc'donalds" AND tags:"b" AND tags:"say ""hi""")"#]) }
 
     #[test]
     fn indir_1() { assert_sql(
         "in:asd",
-        r#"i.path LIKE 'asd/%' ESCAPE '\'"#) }
+        "has_path_prefix(i.path, ?)",
+        vec!["asd/"]) }
 
     #[test]
     fn indir_2() { assert_sql(
         r#"in:'c:\program files\'"#,
-        r#"i.path LIKE 'c:/program files/%' ESCAPE '\'"#) }
+        "has_path_prefix(i.path, ?)",
+        vec!["c:/program files/"]) }
 
     #[test]
     fn indir_3() { assert_sql(
         r#"in:'path''/wi''th/q""uotes/'"#,
-        r#"i.path LIKE 'path''/wi''th/q""uotes/%' ESCAPE '\'"#) }
+        "has_path_prefix(i.path, ?)",
+        vec![r#"path'/wi'th/q""uotes/"#]) }
 
     #[test]
     fn indir_4() { assert_sql(
         r#"-in:asd"#,
-        r#"NOT (i.path LIKE 'asd/%' ESCAPE '\')"#) }
+        "NOT (has_path_prefix(i.path, ?))",
+        vec!["asd/"]) }
 
     #[test]
     fn indir_5() { assert_sql(
         r#"in:a -in:b"#,
-        r#"(i.path LIKE 'a/%' ESCAPE '\' AND NOT (i.path LIKE 'b/%' ESCAPE '\'))"#) }
+        "(has_path_prefix(i.path, ?) AND NOT (has_path_prefix(i.path, ?)))",
+        vec!["a/", "b/"]) }
+
+    #[test]
+    fn children_1() { assert_sql(
+        "children:a_b/",
+        "is_same_path(i.dir, ?)",
+        vec!["a_b"]) }
+
+    #[test]
+    fn ext_1() { assert_sql(
+        "ext:wav",
+        "i.ext = ?",
+        vec!["wav"]) }
 
     #[test]
     fn common_1() { assert_sql(
         r#"kick -snare in:'Drum Collection\'"#,
-        r#"(i.id IN (SELECT id FROM tag_query('(tags:"kick" NOT tags:"snare")')) AND i.path LIKE 'Drum Collection/%' ESCAPE '\')"#) }
+        "(i.id IN (SELECT id FROM tag_query(?)) AND has_path_prefix(i.path, ?))",
+        vec![r#"(tags:"kick" NOT tags:"snare")"#, "Drum Collection/"]) }
+
+    #[test]
+    fn name_1() { assert_sql(
+        "name:100%",
+        r#"basename(i.path) LIKE ? ESCAPE '\'"#,
+        vec![r#"%100\%%"#]) }
+
+    #[test]
+    fn normalized_1() { assert_sql(
+        "cafe\u{301}",
+        "tq.tag_query = ?",
+        vec!["tags:\"caf\u{e9}\""]) }
+
+    #[test]
+    fn raw_1() { assert_sql(
+        r#"a -fts:"NEAR(b c's, 2)""#,
+        "tq.tag_query = ?",
+        vec![r#"(tags:"a" NOT tags:(NEAR(b c's, 2)))"#]) }
+
+    #[test]
+    fn type_1() { assert_sql(
+        "type:Audio -drum",
+        "tq.tag_query = ?",
+        vec![r#"(meta_tags:"type:audio" NOT tags:"drum")"#]) }
+
+    #[test]
+    fn cjk_1() { assert_sql(
+        "ドラム -'100%キック'",
+        r#"(i.id IN (SELECT rowid FROM tag_trigram WHERE tags LIKE ?) AND NOT (i.id IN (SELECT rowid FROM tag_trigram WHERE tags LIKE ? ESCAPE '\')))"#,
+        vec!["%ドラム%", r#"%100\%キック%"#]) }
+
+    #[test]
+    fn prefix_1() { assert_sql(
+        "kick* -'snare*'",
+        "tq.tag_query = ?",
+        vec![r#"(tags:"kick"* NOT tags:"snare*")"#]) }
+
+    #[test]
+    fn flags_1() { assert_sql(
+        "is:untagged",
+        "i.tags = ''",
+        vec![]) }
+
+    #[test]
+    fn flags_2() { assert_sql(
+        "kick is:tagged",
+        "(i.id IN (SELECT id FROM tag_query(?)) AND i.tags != '')",
+        vec![r#"tags:"kick""#]) }
+
+    #[test]
+    fn flags_3() { assert_sql(
+        "-tagcount:2",
+        "NOT ((CASE WHEN i.tags = '' THEN 0 ELSE length(i.tags) - length(replace(i.tags, ' ', '')) + 1 END) = ?)",
+        vec!["2"]) }
+
+    fn assert_ranked_sql(query: &str, expected: Option<&str>) {
+        let expr = parse(query).unwrap();
+        let clause = generate_clause(&expr);
+        assert_eq!(clause.to_ranked_sql_clause().map(|x| x.sql).as_deref(), expected);
+    }
+
+    #[test]
+    fn ranked_1() { assert_ranked_sql("a -b", Some("tq.tag_query = ?")) }
+
+    #[test]
+    fn ranked_2() { assert_ranked_sql(
+        "a in:x",
+        Some("(tq.tag_query = ? AND has_path_prefix(i.path, ?))")) }
+
+    #[test]
+    fn ranked_3() { assert_ranked_sql("a | in:x", None) }
+
+    #[test]
+    fn ranked_4() { assert_ranked_sql("in:x", None) }
+
+    #[test]
+    fn collection_1() { assert_sql(
+        "a collection:'my picks'",
+        "(i.id IN (SELECT id FROM tag_query(?)) AND i.id IN (SELECT ci.item_id FROM collection_items ci JOIN collections c ON c.id = ci.collection_id WHERE c.name = ?))",
+        vec![r#"tags:"a""#, "my picks"]) }
+
+    #[test]
+    fn content_1() { assert_sql(
+        r#"a content:'user "manual"'"#,
+        "(i.id IN (SELECT id FROM tag_query(?)) AND i.id IN (SELECT rowid FROM item_content WHERE item_content MATCH ?))",
+        vec![r#"tags:"a""#, r#""user ""manual""""#]) }
+
+    #[test]
+    fn same_structure_1() {
+        let sql = |query| generate_clause(&parse(query).unwrap()).to_sql_clause().sql;
+        assert_eq!(sql("a -b in:x"), sql("c -'d e' in:'y''; DROP TABLE items; --'"));
+    }
 
     // #[test]
     // fn temp() { assert_sql(
-    //     r#"a -b | in:"item 2""#, "") }
+    //     r#"a -b | in:"item 2""#, "", vec![]) }
 }
@@ -1,27 +1,216 @@
 mod convert;
 mod parser;
+mod simplify;
 
-pub(crate) use parser::ParseError;
+use std::borrow::Cow;
 
-pub(crate) fn to_sql(query: &str) -> Result<String, ParseError> {
+pub(crate) use convert::SqlClause;
+use parser::Expr;
+pub use parser::ParseError;
+use rusqlite::types::Value;
+use serde::Serialize;
+
+/// How the items matching a query are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Order {
+    Path,
+    /// Best matching items first, set with `sort:relevance`. The query's SQL joins `tag_query` as
+    /// `tq`, so FTS5's rank is available as `tq.rank`.
+    Relevance,
+}
+
+impl Order {
+    /// The `ORDER BY` expression for this order.
+    pub(crate) fn to_sql(self) -> &'static str {
+        match self {
+            Order::Path => "i.path",
+            Order::Relevance => "tq.rank, i.path",
+        }
+    }
+}
+
+/// Convert a query to an SQL where clause. `sort:` terms are ignored, use `to_ordered_sql()` to
+/// get the order they set.
+pub(crate) fn to_sql(query: &str) -> Result<SqlClause, ParseError> {
+    Ok(to_ordered_sql(query)?.0)
+}
+
+/// Convert a query to an SQL where clause, along with the order of the results. Results are only
+/// ordered by relevance if the query has tags that every result must match, otherwise the rank is
+/// the same for every result.
+pub(crate) fn to_ordered_sql(query: &str) -> Result<(SqlClause, Order), ParseError> {
+    to_scoped_sql(query, None)
+}
+
+/// Like `to_ordered_sql()`, but if `scope_dir` is given, only items inside that folder match, as
+/// if the query had an `in:` term. The folder isn't part of the query text, so it doesn't need to
+/// be quoted. An empty folder is the repo root, which doesn't restrict the results.
+pub(crate) fn to_scoped_sql(
+    query: &str,
+    scope_dir: Option<&str>,
+) -> Result<(SqlClause, Order), ParseError> {
+    let parsed = parse_query(query)?;
+    let mut order = Order::Path;
+    let mut expr = parsed.and_then(|x| remove_sort_terms(x, &mut order));
+    if let Some(dir) = scope_dir.filter(|x| !x.trim_matches('/').is_empty()) {
+        expr = Some(add_scope(expr, dir));
+    }
+    Ok(convert_expr(expr.map(simplify::simplify).as_ref(), order))
+}
+
+/// Combine an expression with an `in:` term for the folder `dir`.
+fn add_scope<'a>(expr: Option<Expr<'a>>, dir: &'a str) -> Expr<'a> {
+    let scope = Expr::KeyValue(Cow::from("in"), Cow::from(dir));
+    match expr {
+        None => scope,
+        Some(Expr::And(mut exprs)) => {
+            exprs.push(scope);
+            Expr::And(exprs)
+        }
+        Some(expr) => Expr::And(vec![expr, scope]),
+    }
+}
+
+/// Parse a query, returns None if the query is empty.
+fn parse_query(query: &str) -> Result<Option<Expr>, ParseError> {
     if query.trim().is_empty() {
-        Ok(String::from("true"))
-    } else {
-        let expr = parser::parse(query)?;
-        let clause = convert::generate_clause(&expr);
-        Ok(clause.to_sql_clause())
+        return Ok(None);
+    }
+    Ok(Some(parser::parse(query)?))
+}
+
+/// Convert a simplified expression to SQL, a missing expression matches every item.
+fn convert_expr(expr: Option<&Expr>, order: Order) -> (SqlClause, Order) {
+    let Some(expr) = expr else {
+        let match_all = SqlClause { sql: String::from("true"), params: vec![] };
+        return (match_all, Order::Path);
+    };
+    let clause = convert::generate_clause(expr);
+    if order == Order::Relevance {
+        if let Some(ranked_clause) = clause.to_ranked_sql_clause() {
+            return (ranked_clause, Order::Relevance);
+        }
+    }
+    (clause.to_sql_clause(), Order::Path)
+}
+
+/// The steps of converting a query to SQL, see `explain()`.
+#[derive(Debug, Serialize)]
+pub struct QueryExplanation {
+    /// The query as it was parsed, or None if it's empty
+    pub(crate) parsed: Option<Expr<'static>>,
+    /// The query without `sort:` terms after it's simplified, this is what's converted to SQL. None
+    /// if it matches every item.
+    pub(crate) simplified: Option<Expr<'static>>,
+    /// The where clause, `i` is the `items` table and `tq` is the `tag_query` table
+    pub(crate) sql: String,
+    /// Values bound to the `?` placeholders in `sql`
+    pub(crate) params: Vec<serde_json::Value>,
+    pub(crate) order_by: &'static str,
+}
+
+/// Explain how a query is converted to SQL, for debugging queries that match the wrong items.
+pub fn explain(query: &str) -> Result<QueryExplanation, ParseError> {
+    let parsed = parse_query(query)?;
+    let mut order = Order::Path;
+    let simplified = parsed
+        .clone()
+        .and_then(|x| remove_sort_terms(x, &mut order))
+        .map(simplify::simplify);
+    let (clause, order) = convert_expr(simplified.as_ref(), order);
+    Ok(QueryExplanation {
+        parsed: parsed.map(Expr::into_owned),
+        simplified: simplified.map(Expr::into_owned),
+        sql: clause.sql,
+        params: clause.params.iter().map(param_to_json).collect(),
+        order_by: order.to_sql(),
+    })
+}
+
+fn param_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(x) => (*x).into(),
+        Value::Real(x) => (*x).into(),
+        Value::Text(x) => x.as_str().into(),
+        Value::Blob(x) => x.as_slice().into(),
+    }
+}
+
+/// Parse a query and write it back in a normalized form, with redundant spaces, parentheses and
+/// quotes removed. This doesn't simplify the query or touch the database, so it's cheap enough to
+/// check a query while it's being typed.
+pub fn normalize(query: &str) -> Result<String, ParseError> {
+    if query.trim().is_empty() {
+        return Ok(String::new());
+    }
+    Ok(parser::parse(query)?.to_string())
+}
+
+/// Remove `sort:` terms from an expression since they don't filter items, storing the order they
+/// set in `order`. Returns None if the expression only contains `sort:` terms.
+fn remove_sort_terms<'a>(expr: Expr<'a>, order: &mut Order) -> Option<Expr<'a>> {
+    match expr {
+        Expr::KeyValue(key, _) if key == "sort" => {
+            *order = Order::Relevance;
+            None
+        }
+        Expr::And(exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .filter_map(|x| remove_sort_terms(x, order));
+            // removing terms may leave a group with 1 item, which is unwrapped into its parent
+            let mut flattened = vec![];
+            for expr in exprs {
+                match expr {
+                    Expr::And(inner) => flattened.extend(inner),
+                    expr => flattened.push(expr),
+                }
+            }
+            match flattened.len() {
+                0 => None,
+                1 => flattened.pop(),
+                _ => Some(Expr::And(flattened)),
+            }
+        }
+        Expr::Or(exprs) => {
+            let exprs = exprs
+                .into_iter()
+                .filter_map(|x| remove_sort_terms(x, order));
+            let mut flattened = vec![];
+            for expr in exprs {
+                match expr {
+                    Expr::Or(inner) => flattened.extend(inner),
+                    expr => flattened.push(expr),
+                }
+            }
+            match flattened.len() {
+                0 => None,
+                1 => flattened.pop(),
+                _ => Some(Expr::Or(flattened)),
+            }
+        }
+        Expr::Not(expr) => remove_sort_terms(*expr, order).map(|x| Expr::Not(Box::new(x))),
+        expr => Some(expr),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use rusqlite::types::Value;
+
     use super::*;
 
     #[test]
     fn common_1() {
         assert_eq!(
             to_sql("a b c").unwrap(),
-            r#"tq.tag_query = '(tags:"a" AND tags:"b" AND tags:"c")'"#,
+            SqlClause {
+                sql: String::from("tq.tag_query = ?"),
+                params: vec![Value::from(String::from(
+                    r#"(tags:"a" AND tags:"b" AND tags:"c")"#
+                ))],
+            }
         )
     }
 
@@ -29,7 +218,15 @@ mod tests {
     fn common_2() {
         assert_eq!(
             to_sql("a -b in:samples/").unwrap(),
-            r#"(i.id IN (SELECT id FROM tag_query('(tags:"a" NOT tags:"b")')) AND i.path LIKE 'samples/%' ESCAPE '\')"#,
+            SqlClause {
+                sql: String::from(
+                    "(i.id IN (SELECT id FROM tag_query(?)) AND has_path_prefix(i.path, ?))"
+                ),
+                params: vec![
+                    Value::from(String::from(r#"(tags:"a" NOT tags:"b")"#)),
+                    Value::from(String::from("samples/")),
+                ],
+            }
         )
     }
 
@@ -37,12 +234,85 @@ mod tests {
     fn common_3() {
         assert_eq!(
             to_sql("   a    - b   in:samples/    ").unwrap(),
-            r#"(i.id IN (SELECT id FROM tag_query('(tags:"a" NOT tags:"b")')) AND i.path LIKE 'samples/%' ESCAPE '\')"#,
+            SqlClause {
+                sql: String::from(
+                    "(i.id IN (SELECT id FROM tag_query(?)) AND has_path_prefix(i.path, ?))"
+                ),
+                params: vec![
+                    Value::from(String::from(r#"(tags:"a" NOT tags:"b")"#)),
+                    Value::from(String::from("samples/")),
+                ],
+            }
         )
     }
 
     #[test]
     fn empty() {
-        assert_eq!(to_sql("").unwrap(), r#"true"#,)
+        assert_eq!(to_sql("").unwrap().sql, r#"true"#,)
+    }
+
+    #[test]
+    fn sort_terms_are_removed() {
+        assert_eq!(to_sql("a sort:relevance b"), to_sql("a b"));
+        assert_eq!(to_sql("a (sort:relevance | b c)"), to_sql("a b c"));
+        assert_eq!(to_sql("sort:relevance").unwrap().sql, "true");
+    }
+
+    #[test]
+    fn relevance_needs_tags() {
+        let order = |query| to_ordered_sql(query).unwrap().1;
+        assert_eq!(order("a b"), Order::Path);
+        assert_eq!(order("a b sort:relevance"), Order::Relevance);
+        assert_eq!(order("a in:x sort:relevance"), Order::Relevance);
+        assert_eq!(order("in:x sort:relevance"), Order::Path);
+        assert_eq!(order("sort:relevance"), Order::Path);
+    }
+
+    #[test]
+    fn queries_can_be_scoped() {
+        let scoped = |query, dir| to_scoped_sql(query, dir).unwrap().0;
+        assert_eq!(
+            scoped("a -b", Some("samples/")),
+            to_sql("a -b in:samples/").unwrap()
+        );
+        assert_eq!(
+            scoped("a | b", Some("my 'samples'")),
+            to_sql(r#"(a | b) in:"my 'samples'""#).unwrap()
+        );
+        assert_eq!(scoped("", Some("samples")), to_sql("in:samples").unwrap());
+        assert_eq!(scoped("a", Some("")), to_sql("a").unwrap());
+        assert_eq!(scoped("a", None), to_sql("a").unwrap());
+    }
+
+    #[test]
+    fn queries_are_normalized() {
+        assert_eq!(normalize("   ").unwrap(), "");
+        assert_eq!(normalize(" (a  'b') | -( c ) ").unwrap(), "a b | -c");
+        assert_eq!(normalize("in:'x y/'").unwrap(), r#"in:"x y/""#);
+        let err = normalize("a (b").unwrap_err();
+        assert_eq!((err.offset, err.end), (2, 3));
+    }
+
+    #[test]
+    fn queries_are_explained() {
+        let explanation = explain("(a | b) in:x/ sort:relevance").unwrap();
+        assert_eq!(
+            serde_json::to_value(&explanation.parsed).unwrap(),
+            serde_json::json!({"And": [
+                {"Or": [{"Tag": "a"}, {"Tag": "b"}]},
+                {"KeyValue": ["in", "x/"]},
+                {"KeyValue": ["sort", "relevance"]},
+            ]})
+        );
+        let (clause, order) = to_ordered_sql("(a | b) in:x/ sort:relevance").unwrap();
+        assert_eq!(explanation.sql, clause.sql);
+        assert_eq!(explanation.params, vec![r#"(tags:"a" OR tags:"b")"#, "x/"]);
+        assert_eq!(explanation.order_by, order.to_sql());
+
+        let explanation = explain(" sort:relevance ").unwrap();
+        assert!(explanation.parsed.is_some());
+        assert_eq!(explanation.simplified, None);
+        assert_eq!(explanation.sql, "true");
+        assert_eq!(explanation.order_by, "i.path");
     }
 }
@@ -1,9 +1,9 @@
 //! This code is based on nom's arithmetic example:
 //! https://github.com/rust-bakery/nom/blob/main/tests/arithmetic.rs
 //!
-//! TODO: Simplify expressions using
-//! [Disjunctive normal form](https://en.wikipedia.org/wiki/Disjunctive_normal_form). This
-//! simplifies expressions into many AND groups, joined by a single OR group.
+//! Expressions are simplified using
+//! [Disjunctive normal form](https://en.wikipedia.org/wiki/Disjunctive_normal_form) in
+//! `simplify.rs`. This simplifies expressions into many AND groups, joined by a single OR group.
 //!
 //! You can test DNF with Sympy:
 //!
@@ -18,15 +18,20 @@
 //! ```
 
 use nom::branch::alt;
-use nom::bytes::complete::{is_not, tag as nom_tag};
-use nom::character::complete::{char as nom_char, none_of, one_of};
-use nom::combinator::{map, opt, recognize, value};
+use nom::bytes::complete::{is_not, tag as nom_tag, take_till};
+use nom::character::complete::{anychar, char as nom_char, one_of};
+use nom::combinator::{map, opt, recognize, value, verify};
 use nom::multi::fold_many0;
 use nom::sequence::{delimited, pair, preceded, separated_pair};
 use nom::IResult;
 use nom_unicode::complete::{space0, space1};
 use nom_unicode::is_whitespace;
+use serde::Serialize;
 use std::borrow::Cow;
+use std::fmt;
+use thiserror::Error;
+
+use crate::helpers::sql::check_fts5_query;
 
 fn double_quoted_string_fragment(input: &str) -> IResult<&str, Cow<str>> {
     alt((
@@ -68,10 +73,17 @@ fn string(input: &str) -> IResult<&str, String> {
 ///
 /// Literals cannot start with a quote (', "), a minus (-), or parentheses ("(", ")").
 ///
-/// Literals cannot contain spaces (" "), or parentheses ("(", ")").
+/// Literals cannot contain whitespace, or parentheses ("(", ")"). This includes Unicode whitespace
+/// like CJK spaces ("　") and non-breaking spaces.
 fn literal(input: &str) -> IResult<&str, &str> {
-    // this also checks for CJK whitespaces, that space at the end of the string is a CJK space
-    let (new_input, name) = recognize(pair(none_of("　\"' -()"), opt(is_not("　 ()"))))(input)?;
+    fn is_separator(c: char) -> bool {
+        is_whitespace(c) || c == '(' || c == ')'
+    }
+
+    let (new_input, name) = recognize(pair(
+        verify(anychar, |&c| !is_separator(c) && !"\"'-".contains(c)),
+        take_till(is_separator),
+    ))(input)?;
 
     // disallow operators as tags
     if ["|", "(", ")"].contains(&name) {
@@ -91,21 +103,104 @@ fn string_or_literal<'a>(input: &'a str) -> IResult<&str, Cow<'a, str>> {
     ))(input)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum Expr<'a> {
     And(Vec<Expr<'a>>),
     Or(Vec<Expr<'a>>),
     Not(Box<Expr<'a>>),
     Tag(Cow<'a, str>),
+    /// Matches any tag starting with the given text, written as `kick*`
+    TagPrefix(Cow<'a, str>),
     KeyValue(Cow<'a, str>, Cow<'a, str>),
 }
 
-/// tag = string | literal
+impl Expr<'_> {
+    /// Copy any strings borrowed from the query, so the expression can outlive the query.
+    pub(crate) fn into_owned(self) -> Expr<'static> {
+        let owned = |x: Cow<str>| Cow::Owned(x.into_owned());
+        match self {
+            Expr::And(exprs) => Expr::And(exprs.into_iter().map(Expr::into_owned).collect()),
+            Expr::Or(exprs) => Expr::Or(exprs.into_iter().map(Expr::into_owned).collect()),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.into_owned())),
+            Expr::Tag(name) => Expr::Tag(owned(name)),
+            Expr::TagPrefix(prefix) => Expr::TagPrefix(owned(prefix)),
+            Expr::KeyValue(key, value) => Expr::KeyValue(owned(key), owned(value)),
+        }
+    }
+}
+
+/// Writes the expression as a query that parses back to the same expression. Strings are only
+/// quoted if they need to be, and groups are only wrapped in parentheses if they need to be.
+impl fmt::Display for Expr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::And(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    match expr {
+                        Expr::And(_) | Expr::Or(_) => write!(f, "({})", expr)?,
+                        expr => write!(f, "{}", expr)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Or(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" | ")?;
+                    }
+                    // AND has a higher precedence, so only OR groups need parentheses
+                    match expr {
+                        Expr::Or(_) => write!(f, "({})", expr)?,
+                        expr => write!(f, "{}", expr)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Not(expr) => match **expr {
+                Expr::And(_) | Expr::Or(_) => write!(f, "-({})", expr),
+                _ => write!(f, "-{}", expr),
+            },
+            Expr::Tag(name) => match parse(name) {
+                Ok(Expr::Tag(parsed)) if parsed == *name => f.write_str(name),
+                _ => write_quoted(f, name),
+            },
+            Expr::TagPrefix(prefix) => write!(f, "{}*", prefix),
+            Expr::KeyValue(key, value) => {
+                write!(f, "{}:", key)?;
+                match literal(value) {
+                    Ok(("", _)) => f.write_str(value),
+                    _ => write_quoted(f, value),
+                }
+            }
+        }
+    }
+}
+
+/// Write a double-quoted string, escaping quotes by doubling them.
+fn write_quoted(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"{}\"", text.replace('"', "\"\""))
+}
+
+/// tag_prefix = literal "*"
+///
+/// Quoted strings are never treated as prefixes, so `"kick*"` still matches the tag `kick*`.
+fn tag_prefix(input: &str) -> IResult<&str, Expr> {
+    map(
+        verify(literal, |x: &str| x.len() > 1 && x.ends_with('*')),
+        |x| Expr::TagPrefix(Cow::from(&x[..x.len() - 1])),
+    )(input)
+}
+
+/// tag = tag_prefix | string | literal
 fn tag(input: &str) -> IResult<&str, Expr> {
-    map(string_or_literal, Expr::Tag)(input)
+    alt((tag_prefix, map(string_or_literal, Expr::Tag)))(input)
 }
 
-/// allowed_key = "in" | "ext" | "inpath" | "children" | "leading"
+/// allowed_key = "in" | "ext" | "inpath" | "children" | "leading" | "name" | "collection"
+///     | "content" | "type"
 fn allowed_key(input: &str) -> IResult<&str, &str> {
     alt((
         // 'inpath' must occur before 'in' to ensure nom checks for it
@@ -114,17 +209,85 @@ fn allowed_key(input: &str) -> IResult<&str, &str> {
         nom_tag("ext"),
         nom_tag("children"),
         nom_tag("leading"),
+        nom_tag("name"),
+        nom_tag("collection"),
+        nom_tag("content"),
+        nom_tag("type"),
     ))(input)
 }
 
-/// key_val = allowed_key ":" (string | literal)
-fn key_val<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
+/// is_flag = "is" ":" ("tagged" | "untagged")
+fn is_flag<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
+    map(
+        separated_pair(
+            nom_tag("is"),
+            nom_char(':'),
+            verify(string_or_literal, |x: &str| {
+                x == "tagged" || x == "untagged"
+            }),
+        ),
+        |(k, v)| Expr::KeyValue(Cow::from(k), v),
+    )(input)
+}
+
+/// tag_count = "tagcount" ":" [0-9]+
+fn tag_count<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
     map(
-        separated_pair(allowed_key, nom_char(':'), string_or_literal),
+        separated_pair(
+            nom_tag("tagcount"),
+            nom_char(':'),
+            verify(literal, |x: &str| x.parse::<u32>().is_ok()),
+        ),
+        |(k, v)| Expr::KeyValue(Cow::from(k), Cow::from(v)),
+    )(input)
+}
+
+/// sort = "sort" ":" "relevance"
+fn sort<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
+    map(
+        separated_pair(
+            nom_tag("sort"),
+            nom_char(':'),
+            verify(string_or_literal, |x: &str| x == "relevance"),
+        ),
         |(k, v)| Expr::KeyValue(Cow::from(k), v),
     )(input)
 }
 
+/// raw_fts = "fts" ":" (string | literal)
+///
+/// The value is an FTS5 query, which is checked here so an invalid query fails to parse instead of
+/// failing when it's run. This is the only parser that fails without letting other parsers try,
+/// see `describe_fts_error()`.
+fn raw_fts<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
+    let (value_input, _) = pair(nom_tag("fts"), nom_char(':'))(input)?;
+    let (input, value) = string_or_literal(value_input)?;
+    if check_fts5_query(&value).is_err() {
+        return Err(nom::Err::Failure(nom::error::Error {
+            input: value_input,
+            code: nom::error::ErrorKind::Verify,
+        }));
+    }
+    Ok((input, Expr::KeyValue(Cow::from("fts"), value)))
+}
+
+/// key_val = is_flag | tag_count | sort | raw_fts | allowed_key ":" (string | literal)
+///
+/// If the value of `is:`, `tagcount:` or `sort:` is invalid, the term is parsed as a normal tag
+/// instead.
+fn key_val<'a>(input: &'a str) -> IResult<&str, Expr<'a>> {
+    alt((
+        is_flag,
+        tag_count,
+        sort,
+        raw_fts,
+        map(
+            separated_pair(allowed_key, nom_char(':'), string_or_literal),
+            |(k, v)| Expr::KeyValue(Cow::from(k), v),
+        ),
+    ))(input)
+}
+
 /// Parse an expression wrapped with parenthesis "(...)"
 ///
 /// parens = "(" or_terms ")"
@@ -235,26 +398,173 @@ fn or_terms(input: &str) -> IResult<&str, Expr> {
     }
 }
 
-#[derive(Debug)]
-pub(crate) enum ParseError<'a> {
-    NomError(nom::Err<nom::error::Error<&'a str>>),
-    InputNotFullyConsumed(&'a str, Expr<'a>),
+/// A syntax error in a query, with the location of the offending part of the query.
+///
+/// Offsets are byte offsets into the original query string.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[error("{message} (at position {offset})")]
+pub struct ParseError {
+    pub(crate) message: String,
+    /// Start of the offending part of the query
+    pub(crate) offset: usize,
+    /// End of the offending part of the query (exclusive)
+    pub(crate) end: usize,
+    /// Tokens that would have been valid at the start of the offending part
+    pub(crate) expected: Vec<&'static str>,
 }
 
-impl<'a> From<nom::Err<nom::error::Error<&'a str>>> for ParseError<'a> {
-    fn from(value: nom::Err<nom::error::Error<&'a str>>) -> Self {
-        ParseError::NomError(value)
+const EXPECTED_TERM: [&str; 4] = ["tag", "key:value", "\"(\"", "\"-\""];
+
+impl ParseError {
+    fn new(message: &str, offset: usize, end: usize, expected: Vec<&'static str>) -> Self {
+        Self {
+            message: message.to_string(),
+            offset,
+            end,
+            expected,
+        }
+    }
+
+    /// Move the error's position forward by the given number of bytes. Used when the error is
+    /// found by parsing a substring of the original query.
+    fn shifted(mut self, by: usize) -> Self {
+        self.offset += by;
+        self.end += by;
+        self
     }
 }
 
+/// Find the position of the closing parenthesis that matches the opening parenthesis at the
+/// start of `input`. Parentheses in quoted strings are skipped.
+fn find_closing_paren(input: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote_char = None;
+    for (i, c) in input.char_indices() {
+        match quote_char {
+            // a doubled quote is an escaped quote, this is handled correctly by treating it as
+            // closing then reopening the string
+            Some(q) if c == q => quote_char = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' if i != 0 && !is_literal_char(input, i) => quote_char = Some(c),
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    None
+}
+
+/// Whether the character at `pos` is inside a literal, i.e. it does not start a quoted string.
+/// Quotes only start a string at the beginning of a term, e.g. `m'lady` is a literal.
+fn is_literal_char(input: &str, pos: usize) -> bool {
+    match input[..pos].chars().last() {
+        Some(c) => !(is_whitespace(c) || c == '(' || c == '-' || c == ':'),
+        None => false,
+    }
+}
+
+/// Determine why parsing failed, given the original query and the unparsed remainder of the
+/// query at the point where the parser stopped.
+fn describe_error(query: &str, remaining: &str) -> ParseError {
+    let trimmed = remaining.trim_start_matches(is_whitespace);
+    let offset = query.len() - trimmed.len();
+    let end_of_query = query.len();
+
+    let Some(first_char) = trimmed.chars().next() else {
+        return ParseError::new(
+            "unexpected end of query",
+            offset,
+            offset,
+            EXPECTED_TERM.to_vec(),
+        );
+    };
+    let first_char_end = offset + first_char.len_utf8();
+
+    match first_char {
+        ')' => ParseError::new(
+            "unexpected closing parenthesis",
+            offset,
+            first_char_end,
+            vec!["tag", "\"|\"", "end of query"],
+        ),
+        '(' => match find_closing_paren(trimmed) {
+            None => ParseError::new(
+                "unclosed parenthesis",
+                offset,
+                first_char_end,
+                vec!["\")\""],
+            ),
+            Some(close) => {
+                let inner = &trimmed[1..close];
+                if inner.trim_matches(is_whitespace).is_empty() {
+                    return ParseError::new(
+                        "empty parentheses",
+                        offset,
+                        offset + close + 1,
+                        EXPECTED_TERM.to_vec(),
+                    );
+                }
+                match parse(inner) {
+                    Err(err) => err.shifted(offset + 1),
+                    Ok(_) => ParseError::new("unexpected input", offset, end_of_query, vec![]),
+                }
+            }
+        },
+        '"' | '\'' if string(trimmed).is_err() => {
+            let expected = if first_char == '"' { "'\"'" } else { "\"'\"" };
+            ParseError::new("unclosed quote", offset, end_of_query, vec![expected])
+        }
+        '-' => ParseError::new(
+            "expected a term after \"-\"",
+            offset,
+            first_char_end,
+            EXPECTED_TERM.to_vec(),
+        ),
+        '|' => {
+            let message = if offset == 0 {
+                "expected a term before \"|\""
+            } else {
+                "expected a term after \"|\""
+            };
+            ParseError::new(message, offset, first_char_end, EXPECTED_TERM.to_vec())
+        }
+        _ => ParseError::new("unexpected input", offset, end_of_query, vec![]),
+    }
+}
+
+/// Describe why the value of an `fts:` term is invalid, given the original query and the query
+/// from the start of the value.
+fn describe_fts_error(query: &str, value_input: &str) -> ParseError {
+    let offset = query.len() - value_input.len();
+    let (rest, value) = string_or_literal(value_input).expect("fts: value was already parsed");
+    let message = match check_fts5_query(&value) {
+        Err(err) => format!("invalid FTS5 query, {}", err),
+        Ok(()) => unreachable!("fts: value was already found to be invalid"),
+    };
+    ParseError::new(&message, offset, query.len() - rest.len(), vec![])
+}
+
 /// Main entry point for the parser.
 /// Calls `or_terms` and skips padded spaces in the beginning and end of input.
 pub(crate) fn parse(input: &str) -> Result<Expr, ParseError> {
-    let (unparsed_input, expr) = delimited(space0, or_terms, space0)(input)?;
-    if unparsed_input.len() > 0 {
-        Err(ParseError::InputNotFullyConsumed(unparsed_input, expr))
-    } else {
-        Ok(expr)
+    match delimited(space0, or_terms, space0)(input) {
+        Ok((unparsed_input, expr)) => {
+            if unparsed_input.len() > 0 {
+                Err(describe_error(input, unparsed_input))
+            } else {
+                Ok(expr)
+            }
+        }
+        Err(nom::Err::Error(err)) => Err(describe_error(input, err.input)),
+        Err(nom::Err::Failure(err)) => Err(describe_fts_error(input, err.input)),
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never return Incomplete"),
     }
 }
 
@@ -316,6 +626,22 @@ mod tests {
             ("in", "quote in path for some reason\""),
         );
         assert_parse_fails(r#""spaced key":hello"#);
+        assert_parse(
+            r#"fts:"NEAR(kick snare, 2)""#,
+            ("fts", "NEAR(kick snare, 2)"),
+        );
+
+        assert_parse("is:untagged", ("is", "untagged"));
+        assert_parse("is:'tagged'", ("is", "tagged"));
+        assert_parse("tagcount:3", ("tagcount", "3"));
+        assert_parse_fails("is:something");
+        assert_parse_fails("tagcount:-1");
+        assert_parse_fails("tagcount:many");
+        assert_parse("sort:relevance", ("sort", "relevance"));
+        assert_parse("collection:'my picks'", ("collection", "my picks"));
+        assert_parse("content:'user manual'", ("content", "user manual"));
+        assert_parse("type:audio", ("type", "audio"));
+        assert_parse_fails("sort:name");
     }
 
     #[test]
@@ -352,6 +678,8 @@ mod tests {
         assert_parse("abc", "abc");
         assert_parse("mc'donalds", "mc'donalds");
         assert_parse("'tag with spaces'", "tag with spaces");
+        assert_parse("'kick*'", "kick*");
+        assert_parse("*", "*");
         assert_parse_fails("'mlady");
     }
 }
@@ -369,6 +697,8 @@ mod expr_tests {
 
     fn t(name: &str) -> Expr { Expr::Tag(name.into()) }
 
+    fn prefix(name: &str) -> Expr { Expr::TagPrefix(name.into()) }
+
     fn kv<'a, 'b>(key: &'a str, val: &'a str) -> Expr<'b> {
         Expr::KeyValue(key.to_string().into(), val.to_string().into())
     }
@@ -413,6 +743,28 @@ mod expr_tests {
         ]),
     ); }
 
+    #[test] fn prefix_1() { assert_expr("kick* -snare* a*b",
+        and(vec![prefix("kick"), not(prefix("snare")), t("a*b")]),
+    ); }
+
+    #[test] fn flags_1() { assert_expr("a is:untagged | tagcount:2",
+        or(vec![and(vec![t("a"), kv("is", "untagged")]), kv("tagcount", "2")]),
+    ); }
+    #[test] fn flags_2() { assert_expr("is:foo tagcount:x", and(vec![t("is:foo"), t("tagcount:x")])); }
+
+    fn assert_display(input: &str, expected: &str) {
+        let expr = parse(input).unwrap();
+        assert_eq!(expr.to_string(), expected);
+        assert_eq!(parse(expected).unwrap(), expr);
+    }
+
+    #[test] fn display_1() { assert_display("  a   ( b  c )  d", "a b c d"); }
+    #[test] fn display_2() { assert_display("a (b | c) | (d e)", "a (b | c) | d e"); }
+    #[test] fn display_3() { assert_display("-(a | b) -c -(d e)", "-(a | b) -c -(d e)"); }
+    #[test] fn display_4() { assert_display(r#"'a b' "c" "in:x" "-d" "|" "e""f" '' a|"#, r#""a b" c "in:x" "-d" "|" e"f "" a|"#); }
+    #[test] fn display_5() { assert_display("kick* \"kick*\" in:'a b/' ext:wav is:foo sort:relevance", "kick* \"kick*\" in:\"a b/\" ext:wav is:foo sort:relevance"); }
+    #[test] fn display_6() { assert_display("你好　'안녕 잘 지내?'", "你好 \"안녕 잘 지내?\""); }
+
     #[test]
     fn common_1() {
         assert_expr(
@@ -469,7 +821,37 @@ mod expr_tests {
         and(vec![t("你好"), t("亞視啲")]),
     ); }
 
+    #[test] fn unicode_spaces() { assert_expr("a\u{a0}b\tc\u{2003}(d\u{3000}| e)",
+        and(vec![t("a"), t("b"), t("c"), or(vec![t("d"), t("e")])]),
+    ); }
+
     #[test] fn cjk02() { assert_expr("   normal   no-break　　　'안녕 잘 지내?'",
         and(vec![t("normal"), t("no-break"), t("안녕 잘 지내?")]),
     ); }
 }
+
+#[rustfmt::skip]
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    fn assert_error(input: &str, message: &str, span: (usize, usize)) {
+        let err = parse(input).unwrap_err();
+        assert_eq!(err.message, message);
+        assert_eq!((err.offset, err.end), span, "error span differs");
+    }
+
+    #[test] fn unexpected_close_paren() { assert_error("a b )", "unexpected closing parenthesis", (4, 5)); }
+    #[test] fn unclosed_paren() { assert_error("a (b c", "unclosed parenthesis", (2, 3)); }
+    #[test] fn empty_parens() { assert_error("a ( ) b", "empty parentheses", (2, 5)); }
+    #[test] fn nested_error() { assert_error("a (b (c) | )", "expected a term after \"|\"", (9, 10)); }
+    #[test] fn unclosed_quote() { assert_error("a 'b c", "unclosed quote", (2, 6)); }
+    #[test] fn quote_in_literal() { assert_error("m'lady (b", "unclosed parenthesis", (7, 8)); }
+    #[test] fn trailing_minus() { assert_error("a -", "expected a term after \"-\"", (2, 3)); }
+    #[test] fn leading_or() { assert_error("| a", "expected a term before \"|\"", (0, 1)); }
+    #[test] fn trailing_or() { assert_error("a |", "expected a term after \"|\"", (2, 3)); }
+    #[test] fn only_close_paren() { assert_error("  )", "unexpected closing parenthesis", (2, 3)); }
+    #[test] fn unicode_offsets() { assert_error("你好 )", "unexpected closing parenthesis", (7, 8)); }
+    #[test] fn invalid_fts() { assert_error("a fts:'kick AND' b", "invalid FTS5 query, fts5: syntax error near \")\"", (6, 16)); }
+    #[test] fn unbalanced_fts() { assert_error("(a -fts:\"NEAR(\")", "invalid FTS5 query, unclosed parenthesis", (8, 15)); }
+}
@@ -0,0 +1,282 @@
+//! Rewrites query expressions to reduce the number of FTS subqueries in the generated SQL.
+//!
+//! Each AND/OR group is converted into at most 1 FTS query, but groups that mix tags with other
+//! terms like `in:` end up with a separate `tag_query(...)` subquery per group. This tries a few
+//! equivalent forms of the expression, including its
+//! [disjunctive normal form](https://en.wikipedia.org/wiki/Disjunctive_normal_form), and keeps the
+//! one with the fewest FTS subqueries. For example:
+//!
+//! ```text
+//! (a | in:x) (b | in:x)  =>  (a b) | in:x
+//! a in:x | a in:y        =>  a (in:x | in:y)
+//! ```
+//!
+//! Parts of the expression that only contain tags are treated as a single term, since they are
+//! always combined into 1 FTS query anyway.
+
+use super::convert::{generate_clause, is_fts, WhereClause};
+use super::parser::Expr;
+
+/// Maximum number of AND groups in the disjunctive normal form. Converting to DNF can make an
+/// expression exponentially larger, so it is skipped beyond this size.
+const MAX_DNF_TERMS: usize = 64;
+
+/// Return the equivalent expression with the fewest FTS subqueries. If there is a tie, the
+/// original expression is preferred.
+pub(crate) fn simplify(expr: Expr) -> Expr {
+    let nnf = to_nnf(expr.clone(), false);
+    let mut candidates = vec![expr, nnf.clone()];
+    if let Some(dnf) = to_dnf(&nnf) {
+        let dnf = absorb(dnf);
+        candidates.push(factor(&dnf));
+        candidates.push(or_of(dnf.into_iter().map(and_of).collect()));
+    }
+
+    let mut best = None;
+    for candidate in candidates {
+        let cost = fts_count(&generate_clause(&candidate));
+        match best {
+            Some((best_cost, _)) if best_cost <= cost => {}
+            _ => best = Some((cost, candidate)),
+        }
+    }
+    best.unwrap().1
+}
+
+/// Number of FTS queries in a where clause.
+fn fts_count(clause: &WhereClause) -> usize {
+    match clause {
+        WhereClause::FTS(_) => 1,
+        WhereClause::And(clauses) | WhereClause::Or(clauses) => clauses.iter().map(fts_count).sum(),
+        WhereClause::Not(clause) => fts_count(clause),
+        _ => 0,
+    }
+}
+
+/// Whether the expression is a single term, i.e. it can't be split into smaller parts.
+fn is_term(expr: &Expr) -> bool {
+    match expr {
+        Expr::And(_) | Expr::Or(_) => is_fts(expr),
+        Expr::Not(inner) => is_term(inner),
+        _ => true,
+    }
+}
+
+/// Convert to negation normal form, where NOT is only applied to single terms. `negate` is true if
+/// the expression is inside an odd number of NOTs.
+fn to_nnf(expr: Expr, negate: bool) -> Expr {
+    if is_term(&expr) {
+        return match (expr, negate) {
+            (Expr::Not(inner), true) => *inner,
+            (expr, true) => Expr::Not(Box::new(expr)),
+            (expr, false) => expr,
+        };
+    }
+    match expr {
+        Expr::Not(inner) => to_nnf(*inner, !negate),
+        // De Morgan's laws: -(a b) = -a | -b, -(a | b) = -a -b
+        Expr::And(exprs) if negate => or_of(exprs.into_iter().map(|x| to_nnf(x, true)).collect()),
+        Expr::Or(exprs) if negate => and_of(exprs.into_iter().map(|x| to_nnf(x, true)).collect()),
+        Expr::And(exprs) => and_of(exprs.into_iter().map(|x| to_nnf(x, false)).collect()),
+        Expr::Or(exprs) => or_of(exprs.into_iter().map(|x| to_nnf(x, false)).collect()),
+        _ => unreachable!("single terms are handled above"),
+    }
+}
+
+/// Convert an expression in negation normal form to disjunctive normal form, a list of AND groups
+/// that are joined by OR. Returns `None` if the result would be too large.
+fn to_dnf<'a>(expr: &Expr<'a>) -> Option<Vec<Vec<Expr<'a>>>> {
+    if is_term(expr) {
+        return Some(vec![vec![expr.clone()]]);
+    }
+    match expr {
+        Expr::Or(exprs) => {
+            let mut groups = vec![];
+            for expr in exprs {
+                groups.extend(to_dnf(expr)?);
+                if groups.len() > MAX_DNF_TERMS {
+                    return None;
+                }
+            }
+            Some(groups)
+        }
+        Expr::And(exprs) => {
+            let mut groups = vec![vec![]];
+            for expr in exprs {
+                let mut new_groups = vec![];
+                for other in to_dnf(expr)? {
+                    for group in &groups {
+                        let mut group: Vec<Expr> = group.clone();
+                        for term in &other {
+                            if !group.contains(term) {
+                                group.push(term.clone());
+                            }
+                        }
+                        new_groups.push(group);
+                    }
+                }
+                if new_groups.len() > MAX_DNF_TERMS {
+                    return None;
+                }
+                groups = new_groups;
+            }
+            Some(groups)
+        }
+        _ => unreachable!("expression must be in negation normal form"),
+    }
+}
+
+/// Remove AND groups that contain all terms of another group, e.g. `a | a b` is the same as `a`.
+fn absorb(groups: Vec<Vec<Expr>>) -> Vec<Vec<Expr>> {
+    let mut result: Vec<Vec<Expr>> = vec![];
+    for (i, group) in groups.iter().enumerate() {
+        let is_absorbed = groups.iter().enumerate().any(|(j, other)| {
+            let is_subset = other.iter().all(|x| group.contains(x));
+            // if both groups are equal, keep the first one
+            is_subset && (other.len() < group.len() || j < i)
+        });
+        if !is_absorbed {
+            result.push(group.clone());
+        }
+    }
+    result
+}
+
+/// Move terms shared by all AND groups out of the OR, e.g. `a b | a c` becomes `a (b | c)`.
+fn factor<'a>(groups: &[Vec<Expr<'a>>]) -> Expr<'a> {
+    let common: Vec<Expr> = groups[0]
+        .iter()
+        .filter(|x| groups.iter().all(|group| group.contains(x)))
+        .cloned()
+        .collect();
+    if common.is_empty() || groups.len() == 1 {
+        return or_of(groups.iter().map(|x| and_of(x.clone())).collect());
+    }
+    let rest = groups
+        .iter()
+        .map(|group| {
+            let rest = group.iter().filter(|x| !common.contains(x)).cloned();
+            and_of(rest.collect())
+        })
+        .collect();
+    let mut terms = common;
+    terms.push(or_of(rest));
+    and_of(terms)
+}
+
+/// Join expressions with AND, flattening nested AND groups and removing duplicates.
+fn and_of(exprs: Vec<Expr>) -> Expr {
+    let mut result = vec![];
+    for expr in exprs {
+        let inner = match expr {
+            Expr::And(inner) => inner,
+            expr => vec![expr],
+        };
+        for expr in inner {
+            if !result.contains(&expr) {
+                result.push(expr);
+            }
+        }
+    }
+    match result.len() {
+        1 => result.pop().unwrap(),
+        _ => Expr::And(result),
+    }
+}
+
+/// Join expressions with OR, flattening nested OR groups and removing duplicates.
+fn or_of(exprs: Vec<Expr>) -> Expr {
+    let mut result = vec![];
+    for expr in exprs {
+        let inner = match expr {
+            Expr::Or(inner) => inner,
+            expr => vec![expr],
+        };
+        for expr in inner {
+            if !result.contains(&expr) {
+                result.push(expr);
+            }
+        }
+    }
+    match result.len() {
+        1 => result.pop().unwrap(),
+        _ => Expr::Or(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse;
+    use super::*;
+
+    /// Evaluate an expression for an item with the given tags, in the folder `folder`.
+    fn eval(expr: &Expr, tags: &[&str], folder: &str) -> bool {
+        match expr {
+            Expr::And(exprs) => exprs.iter().all(|x| eval(x, tags, folder)),
+            Expr::Or(exprs) => exprs.iter().any(|x| eval(x, tags, folder)),
+            Expr::Not(expr) => !eval(expr, tags, folder),
+            Expr::Tag(tag) => tags.contains(&tag.as_ref()),
+            Expr::KeyValue(key, value) if key == "in" => value == folder,
+            expr => panic!("unsupported expression {:?}", expr),
+        }
+    }
+
+    fn assert_simplified(query: &str, expected: &str) {
+        let expr = parse(query).unwrap();
+        let simplified = simplify(parse(query).unwrap());
+        assert_eq!(simplified, parse(expected).unwrap());
+
+        // check that both expressions match the same items
+        let all_tags = ["a", "b", "c"];
+        for folder in ["x", "y", "z"] {
+            for n in 0..(1 << all_tags.len()) {
+                let tags: Vec<_> = (0..all_tags.len())
+                    .filter(|i| n & (1 << i) != 0)
+                    .map(|i| all_tags[i])
+                    .collect();
+                assert_eq!(
+                    eval(&expr, &tags, folder),
+                    eval(&simplified, &tags, folder),
+                    "{:?} in {:?}",
+                    tags,
+                    folder
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_simple_queries() {
+        assert_simplified("a b -c", "a b -c");
+        assert_simplified("a | b in:x", "a | b in:x");
+        assert_simplified("(a | b) in:x", "(a | b) in:x");
+    }
+
+    #[test]
+    fn absorbs_groups() {
+        assert_simplified("(a | in:x) (b | in:x)", "a b | in:x");
+    }
+
+    #[test]
+    fn factors_common_terms() {
+        assert_simplified("a in:x | a in:y", "a (in:x | in:y)");
+        assert_simplified("a b in:x | a in:y b", "a b (in:x | in:y)");
+    }
+
+    #[test]
+    fn pushes_negation_down() {
+        assert_simplified("-(in:x | -a) b", "-in:x a b");
+        // the original query is kept if it has as few FTS queries
+        assert_simplified("-(-(a in:x) | c in:y)", "-(-(a in:x) | c in:y)");
+    }
+
+    #[test]
+    fn counts_fts_queries() {
+        fn count(query: &str) -> usize {
+            fts_count(&generate_clause(&parse(query).unwrap()))
+        }
+        assert_eq!(count("a b -c"), 1);
+        assert_eq!(count("(a | in:x) (b | in:x)"), 2);
+        assert_eq!(count("in:x in:y"), 0);
+    }
+}
@@ -0,0 +1,331 @@
+//! Recovery of corrupted repo databases, e.g. after a crash or a power cut while the database was
+//! being written.
+//!
+//! The corrupted database is moved aside instead of being deleted, then every row that can still be
+//! read is copied into a new database. Rows on damaged pages are lost, but the scanner will add
+//! their files back as untagged items.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indoc::indoc;
+use rusqlite::{params, Connection, ErrorCode, Row};
+use tracing::warn;
+
+use crate::repo::{open_database, OpenError, OpenMode};
+use crate::repo_config::LockingMode;
+
+/// Number of rows read at once when salvaging a table. If a chunk can't be read, only the rows in
+/// that chunk are lost.
+const SALVAGE_CHUNK_SIZE: i64 = 1000;
+
+/// Stop salvaging a table after this many chunks failed to be read.
+const MAX_FAILED_CHUNKS: usize = 1000;
+
+/// Suffixes of the files SQLite stores next to a database.
+const JOURNAL_SUFFIXES: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+/// Whether an error means that the database file is damaged.
+pub(crate) fn is_corrupt(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+/// Run SQLite's quick integrity check, returns false if the database is damaged. This is much
+/// faster than a full integrity check since indexes aren't compared with their tables.
+pub(crate) fn quick_check(conn: &Connection) -> Result<bool, rusqlite::Error> {
+    match conn.query_row("PRAGMA quick_check(1)", [], |row| row.get::<_, String>(0)) {
+        Ok(result) => Ok(result == "ok"),
+        Err(err) if is_corrupt(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Move a corrupted database aside, then create a new database at the same path and copy every
+/// row that can still be read into it. Returns `OpenError::Corrupted` if successful, since the repo
+/// must be opened again afterwards.
+pub(crate) fn recover_database(db_path: &Path, locking_mode: LockingMode) -> OpenError {
+    let backup_path = backup_path(db_path);
+    if let Err(err) = move_database(db_path, &backup_path) {
+        return OpenError::FailedToRecover(err);
+    }
+    let new_conn = match open_database(db_path, OpenMode::ReadWrite, locking_mode) {
+        Ok(conn) => conn,
+        Err(err) => return err,
+    };
+    // the old database may be too damaged to open at all, then nothing can be recovered
+    let recovered_items = match Connection::open(&backup_path) {
+        Ok(old_conn) => match copy_rows(&old_conn, &new_conn) {
+            Ok(count) => count,
+            Err(err) => return err.into(),
+        },
+        Err(err) => {
+            warn!("failed to open corrupted database, {}", err);
+            0
+        }
+    };
+    OpenError::Corrupted { recovered_items, backup_path }
+}
+
+/// Path to move a corrupted database to, next to the original.
+fn backup_path(db_path: &Path) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let mut file_name = db_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".corrupt-{}", timestamp));
+    db_path.with_file_name(file_name)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Move a database and its journal files. The journal files are renamed along with the database,
+/// so SQLite still finds them when the moved database is opened.
+fn move_database(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::rename(from, to)?;
+    for suffix in JOURNAL_SUFFIXES {
+        let journal_path = with_suffix(from, suffix);
+        if journal_path.exists() {
+            fs::rename(&journal_path, with_suffix(to, suffix))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy items, rules, folder tags and collections from a corrupted database into a new one,
+/// returns the number of items copied. Other tables are only caches, and are rebuilt when the repo is scanned.
+fn copy_rows(old_conn: &Connection, new_conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let items = salvage_rows(old_conn, "items", "id, path, tags", |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    });
+    let rules = salvage_rows(old_conn, "rules", "id, conditions, tags, enabled", |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, bool>(3)?,
+        ))
+    });
+    let folder_tags = salvage_small_table(old_conn, "folder_tags", "path, tag", |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    });
+    let collections = salvage_rows(old_conn, "collections", "id, name", |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    });
+    let collection_items = salvage_small_table(
+        old_conn,
+        "collection_items",
+        "collection_id, item_id",
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+    );
+
+    let mut recovered_items = 0;
+    let tx = new_conn.unchecked_transaction()?;
+    {
+        let mut stmt =
+            tx.prepare(
+            "INSERT OR IGNORE INTO items (id, path, tags, meta_tags) VALUES (?1, ?2, ?3, meta_tags(?2))",
+        )?;
+        for (id, path, tags) in items {
+            recovered_items += stmt.execute(params![id, path, tags])?;
+        }
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO rules (id, conditions, tags, enabled) VALUES (?, ?, ?, ?)",
+        )?;
+        for (id, conditions, tags, enabled) in rules {
+            stmt.execute(params![id, conditions, tags, enabled])?;
+        }
+        let mut stmt = tx.prepare("INSERT OR IGNORE INTO folder_tags (path, tag) VALUES (?, ?)")?;
+        for (path, tag) in folder_tags {
+            stmt.execute(params![path, tag])?;
+        }
+        let mut stmt = tx.prepare("INSERT OR IGNORE INTO collections (id, name) VALUES (?, ?)")?;
+        for (id, name) in collections {
+            stmt.execute(params![id, name])?;
+        }
+        // skip items that couldn't be recovered, since they would violate the foreign keys
+        let mut stmt = tx.prepare(indoc! {"
+            INSERT OR IGNORE INTO collection_items (collection_id, item_id)
+            SELECT c.id, i.id FROM collections c, items i WHERE c.id = ?1 AND i.id = ?2
+        "})?;
+        for (collection_id, item_id) in collection_items {
+            stmt.execute(params![collection_id, item_id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(recovered_items)
+}
+
+/// Read every row of a table that can still be read. The table is read in chunks of ids, so a
+/// damaged page only loses the rows in its chunk.
+fn salvage_rows<T>(
+    conn: &Connection,
+    table: &str,
+    columns: &str,
+    f: impl Fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Vec<T> {
+    let max_id_sql = format!("SELECT max(id) FROM {}", table);
+    let max_id = match conn.query_row(&max_id_sql, [], |row| row.get::<_, Option<i64>>(0)) {
+        Ok(Some(max_id)) => max_id,
+        Ok(None) => return vec![],
+        Err(err) => {
+            warn!("failed to read {} from corrupted database, {}", table, err);
+            return vec![];
+        }
+    };
+
+    let sql = format!(
+        "SELECT {} FROM {} WHERE id >= ? ORDER BY id LIMIT {}",
+        columns, table, SALVAGE_CHUNK_SIZE
+    );
+    let mut rows = vec![];
+    let mut failed_chunks = 0;
+    let mut start = i64::MIN;
+    while start <= max_id && failed_chunks < MAX_FAILED_CHUNKS {
+        let chunk = conn.prepare(&sql).and_then(|mut stmt| {
+            let ids_and_rows =
+                stmt.query_map([start], |row| Ok((row.get::<_, i64>(0)?, f(row)?)))?;
+            ids_and_rows.collect::<Result<Vec<_>, _>>()
+        });
+        match chunk {
+            Ok(chunk) => {
+                let Some((last_id, _)) = chunk.last() else {
+                    break;
+                };
+                start = last_id.saturating_add(1);
+                rows.extend(chunk.into_iter().map(|(_, row)| row));
+            }
+            Err(err) => {
+                warn!("failed to read {} from corrupted database, {}", table, err);
+                failed_chunks += 1;
+                start = start.max(0).saturating_add(SALVAGE_CHUNK_SIZE);
+            }
+        }
+    }
+    rows
+}
+
+/// Read every row of a small table without an `id` column at once. If any row can't be read, the
+/// whole table is lost.
+fn salvage_small_table<T>(
+    conn: &Connection,
+    table: &str,
+    columns: &str,
+    f: impl Fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Vec<T> {
+    let sql = format!("SELECT {} FROM {}", columns, table);
+    let rows = conn
+        .prepare(&sql)
+        .and_then(|mut stmt| stmt.query_map([], |row| f(row))?.collect());
+    match rows {
+        Ok(rows) => rows,
+        Err(err) => {
+            warn!("failed to read {} from corrupted database, {}", table, err);
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use tempfile::tempdir;
+
+    use crate::repo::Repo;
+
+    use super::*;
+
+    #[test]
+    fn healthy_database_passes_check() {
+        let dir = tempdir().unwrap();
+        let conn = open_database(
+            dir.path().join("tags.db"),
+            OpenMode::ReadWrite,
+            LockingMode::Shared,
+        )
+        .unwrap();
+        assert!(quick_check(&conn).unwrap());
+    }
+
+    #[test]
+    fn unreadable_database_is_replaced() {
+        let dir = tempdir().unwrap();
+        drop(Repo::open(&dir, OpenMode::ReadWrite).unwrap());
+        let db_path = dir.path().join(".tagrepo/tags.db");
+        fs::write(&db_path, "definitely not a database").unwrap();
+
+        let Err(OpenError::Corrupted { recovered_items, backup_path }) =
+            Repo::open(&dir, OpenMode::ReadWrite)
+        else {
+            panic!("corruption wasn't detected");
+        };
+        assert_eq!(recovered_items, 0);
+        assert_eq!(
+            fs::read_to_string(backup_path).unwrap(),
+            "definitely not a database"
+        );
+
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        assert_eq!(repo.count("").unwrap(), 0);
+    }
+
+    #[test]
+    fn readable_rows_are_recovered() {
+        let dir = tempdir().unwrap();
+        let item_count = 5000;
+        {
+            let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+            for i in 0..item_count {
+                repo.insert_item(format!("folder/file {:05}.wav", i), "drum kick")
+                    .unwrap();
+            }
+            repo.insert_folder_tags("folder", "vinyl").unwrap();
+            repo.create_collection("picks").unwrap();
+            let ids = repo.query_ids("").unwrap();
+            repo.add_to_collection("picks", &ids).unwrap();
+        }
+        // overwrite the pages that store an item in the middle of the repo
+        let db_path = dir.path().join(".tagrepo/tags.db");
+        let mut bytes = fs::read(&db_path).unwrap();
+        let page_size = 4096;
+        let needle = b"folder/file 02500.wav";
+        let pages: Vec<_> = bytes
+            .windows(needle.len())
+            .positions(|x| x == needle)
+            .map(|pos| pos / page_size * page_size)
+            .collect();
+        assert!(!pages.is_empty());
+        for page in pages {
+            bytes[page..page + page_size].fill(0xff);
+        }
+        fs::write(&db_path, bytes).unwrap();
+
+        let Err(OpenError::Corrupted { recovered_items, .. }) =
+            Repo::open(&dir, OpenMode::ReadWrite)
+        else {
+            panic!("corruption wasn't detected");
+        };
+        assert!(recovered_items > 0);
+        assert!(recovered_items < item_count);
+
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        assert_eq!(repo.count("").unwrap(), recovered_items as i64);
+        assert_eq!(repo.count("kick").unwrap(), recovered_items as i64);
+        assert_eq!(repo.count("vinyl").unwrap(), recovered_items as i64);
+        assert_eq!(
+            repo.count("collection:picks").unwrap(),
+            recovered_items as i64
+        );
+    }
+}
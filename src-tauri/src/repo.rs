@@ -1,28 +1,60 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use std::fs;
 use std::fs::create_dir;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use indoc::indoc;
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use relative_path::RelativePathBuf;
+use relative_path::{RelativePath, RelativePathBuf};
 use rusqlite::functions::FunctionFlags;
 use rusqlite::Error::{QueryReturnedNoRows, SqliteFailure};
-use rusqlite::{ffi, params, Connection, ErrorCode, Row};
+use rusqlite::{ffi, params, Connection, ErrorCode, OpenFlags, OptionalExtension, Row};
 use rusqlite_migration::{Migrations, M};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 use tempfile::{tempdir, TempDir};
 use thiserror::Error;
 use tracing::debug;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
+use crate::content;
 use crate::diff::{diff_path_list, DiffError};
-use crate::query::to_sql;
+use crate::filetype::meta_tags;
+use crate::hash::hash_file;
+use crate::loudness::Loudness;
+use crate::perf;
+use crate::query::{to_scoped_sql, to_sql, ParseError};
+use crate::recovery::{is_corrupt, quick_check, recover_database};
+use crate::repo_config::{LockingMode, RepoConfig, RepoConfigError, TagPolicy};
+use crate::repo_lock::{LockGuard, RepoLock};
+use crate::rules::{validate_rule, Condition, Rule, RuleError, RuleMatch, RuleSet};
+
+use crate::scan::{scan_dir, scan_subdir, ScanError};
+use crate::similarity;
+use crate::subtree::{ImportedFile, SubtreeError};
+use crate::tag_import::{
+    is_header, resolve_path, CsvImportError, CsvImportOptions, CsvImportReport, CsvRow, CsvRowError,
+};
+use crate::tag_pattern::{PatternMatch, TagPattern, TagPatternError};
+use crate::tree::{from_ordered_paths, DirTree, FolderBuf, PathTreeError};
+
+/// Returned when attempting to modify a repo that was opened in read-only mode.
+#[derive(Error, Debug)]
+#[error("repo is opened in read-only mode")]
+pub struct RepoReadOnly;
 
-use crate::scan::{scan_dir, Options, ScanError};
-use crate::tree::{from_ordered_paths, FolderBuf, PathTreeError};
+/// Returned when attempting to change items that have changed since the caller last saw them, e.g.
+/// by the watcher or another window, see `Repo::stale_items()`.
+#[derive(Error, Debug)]
+#[error("items {ids:?} have changed since they were last loaded")]
+pub struct ConflictError {
+    pub(crate) ids: Vec<i64>,
+}
 
 #[derive(Error, Debug)]
 pub enum OpenError {
@@ -34,17 +66,53 @@ pub enum OpenError {
     FailedToCreateDatabase(#[from] rusqlite::Error),
     #[error("failed to migrate database")]
     FailedToMigrateDatabase(#[from] rusqlite_migration::Error),
+    #[error("failed to load repo config, {0}")]
+    FailedToLoadConfig(RepoConfigError),
+    #[error("repo has not been created yet, it cannot be opened in read-only mode")]
+    RepoNotCreated,
+    #[error("repo is already open in another program, {0}")]
+    RepoLocked(String),
+    #[error("failed to write repo lock file, {0}")]
+    FailedToWriteLock(std::io::Error),
+    #[error(
+        "repo database was corrupted, {recovered_items} items were recovered into a new database \
+        and the old database was kept at {}. Open the repo again to continue",
+        backup_path.display()
+    )]
+    Corrupted {
+        recovered_items: usize,
+        backup_path: PathBuf,
+    },
+    #[error("repo database is corrupted, failed to move it aside, {0}")]
+    FailedToRecover(std::io::Error),
+}
+
+impl OpenError {
+    /// Whether the database couldn't be opened because it is damaged.
+    fn is_corrupt(&self) -> bool {
+        match self {
+            OpenError::FailedToCreateDatabase(err) => is_corrupt(err),
+            OpenError::FailedToMigrateDatabase(rusqlite_migration::Error::RusqliteError {
+                err,
+                ..
+            }) => is_corrupt(err),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
-#[deprecated]
-pub enum DatabaseError {
-    #[error("an error occurred in rusqlite")]
-    BackendError(#[from] rusqlite::Error),
-    #[error("attempted to insert path, but path already exists in database")]
-    DuplicatePathError(String),
-    #[error("failed to find item")]
-    ItemNotFound,
+pub enum CreateError {
+    #[error("repo path does not exist")]
+    PathDoesNotExist,
+    #[error("folder is already a repo")]
+    AlreadyExists,
+    #[error("failed to create .tagrepo folder, {0}")]
+    FailedToCreateFolder(#[from] std::io::Error),
+    #[error("failed to save repo config, {0}")]
+    FailedToSaveConfig(#[from] RepoConfigError),
+    #[error("{0}")]
+    OpenError(#[from] OpenError),
 }
 
 #[derive(Error, Debug)]
@@ -55,6 +123,10 @@ pub enum InsertError {
     DuplicatePathError(String),
     #[error("failed to retrieve item data after inserting into database")]
     SearchError(#[from] SearchError),
+    #[error("failed to load auto-tagging rules, {0}")]
+    RuleError(#[from] RuleError),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
 }
 
 #[derive(Error, Debug)]
@@ -71,20 +143,36 @@ pub enum RemoveError {
     BackendError(#[from] rusqlite::Error),
     #[error("failed to fetch item, {0}")]
     SearchError(#[from] SearchError),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
 }
 
 #[derive(Error, Debug)]
 pub enum UpdateError {
     #[error("an error occurred in rusqlite, {0}")]
     BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("{0}")]
+    InvalidTag(#[from] InvalidTagError),
+    #[error("{0}")]
+    Conflict(#[from] ConflictError),
 }
 
 #[derive(Error, Debug)]
 pub enum QueryError {
     #[error("an error occurred in rusqlite, {0}")]
     BackendError(#[from] rusqlite::Error),
-    #[error("invalid search query")]
-    InvalidQuery,
+    #[error("invalid search query, {0}")]
+    InvalidQuery(#[from] ParseError),
+}
+
+#[derive(Error, Debug)]
+pub enum MaintenanceError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
 }
 
 #[derive(Error, Debug)]
@@ -97,18 +185,42 @@ pub enum SyncError {
     SearchError(#[from] SearchError),
     #[error("failed to scan directory for a list of files, {0}")]
     ScanError(#[from] ScanError),
+    #[error("failed to load auto-tagging rules, {0}")]
+    RuleError(#[from] RuleError),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
 }
 
 #[derive(Error, Debug)]
 pub enum InsertTagsError {
     #[error("an error occurred in rusqlite, {0}")]
     BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("{0}")]
+    InvalidTag(#[from] InvalidTagError),
+    #[error("{0}")]
+    Conflict(#[from] ConflictError),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum InvalidTagError {
+    #[error("tags can't be empty")]
+    Empty,
+    #[error("tag {0:?} contains whitespace")]
+    Whitespace(String),
+    #[error("tag {tag:?} contains {char:?}, which isn't allowed in tags")]
+    InvalidChar { tag: String, char: char },
 }
 
 #[derive(Error, Debug)]
 pub enum RemoveTagsError {
     #[error("an error occurred in rusqlite, {0}")]
     BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("{0}")]
+    Conflict(#[from] ConflictError),
 }
 
 #[derive(Error, Debug)]
@@ -119,18 +231,458 @@ pub enum DirStructureError {
     MalformedPath(PathBuf),
 }
 
+#[derive(Error, Debug)]
+pub enum ApplyTemplateError {
+    #[error("tag template {0:?} doesn't exist")]
+    NotFound(String),
+    #[error("failed to insert tags, {0}")]
+    InsertTagsError(#[from] InsertTagsError),
+}
+
+#[derive(Error, Debug)]
+pub enum RelocateError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("item {0} doesn't exist")]
+    ItemNotFound(i64),
+    #[error("file {0:?} doesn't exist")]
+    FileNotFound(String),
+    #[error("{0:?} already belongs to an item with tags")]
+    PathTaken(String),
+}
+
+#[derive(Error, Debug)]
+pub enum SimilarError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("item {0} has no fingerprint, it may not be an audio file")]
+    NoFingerprint(i64),
+}
+
+#[derive(Error, Debug)]
+pub enum CollectionError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("collection names can't be empty")]
+    EmptyName,
+    #[error("collection {0:?} already exists")]
+    AlreadyExists(String),
+    #[error("collection {0:?} doesn't exist")]
+    NotFound(String),
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct Item {
     pub(crate) id: i64,
     pub(crate) path: String,
     pub(crate) tags: Vec<String>,
     pub(crate) meta_tags: String,
+    pub(crate) updated_at: i64,
+    pub(crate) uid: String,
+}
+
+impl Item {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// An identifier that is kept when the item is renamed or relocated, or deleted then restored
+    /// from a tombstone. Unlike `id()`, it's never reused by another item.
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+
+    /// Path of the item's file, relative to the repo root and separated by forward slashes.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The item's tags, sorted.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// When the item's path or tags last changed, in milliseconds since the unix epoch. Pass this
+    /// when changing the item, to fail instead of overwriting changes made in the meantime.
+    pub fn updated_at(&self) -> i64 {
+        self.updated_at
+    }
+}
+
+/// A tag that every item in a folder implicitly has, see `Repo::folder_tags()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct FolderTag {
+    /// Path of the folder relative to the repo root, ending with a slash
+    pub(crate) path: String,
+    pub(crate) tag: String,
+}
+
+/// A hand-picked set of items and the number of items in it, see `Repo::collections()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Collection {
+    pub(crate) name: String,
+    pub(crate) count: i64,
+}
+
+impl Collection {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+}
+
+/// How a missing item was matched to a file, see `Repo::suggest_relocations()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum RelocationMatch {
+    /// The file has the same contents as the item's file
+    Hash,
+    /// The file has the same name as the item's file, and it's the only file with that name
+    Name,
+}
+
+/// A file that a missing item has probably been moved to, see `Repo::suggest_relocations()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub(crate) id: i64,
+    pub(crate) old_path: String,
+    pub(crate) new_path: String,
+    pub(crate) matched_by: RelocationMatch,
+}
+
+impl Relocation {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn old_path(&self) -> &str {
+        &self.old_path
+    }
+
+    pub fn new_path(&self) -> &str {
+        &self.new_path
+    }
+
+    pub fn matched_by(&self) -> RelocationMatch {
+        self.matched_by
+    }
+}
+
+/// An item whose file sounds like another item's, see `Repo::find_similar()`.
+#[derive(Debug, Serialize, Clone)]
+pub struct SimilarItem {
+    pub(crate) item: Item,
+    /// From -1 to 1, where 1 is identical
+    pub(crate) similarity: f32,
+}
+
+impl SimilarItem {
+    pub fn item(&self) -> &Item {
+        &self.item
+    }
+
+    pub fn similarity(&self) -> f32 {
+        self.similarity
+    }
+}
+
+impl FolderTag {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+}
+
+/// A tag and the number of items that have it, see `Repo::tag_counts()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct TagCount {
+    pub(crate) name: String,
+    pub(crate) count: i64,
+}
+
+impl TagCount {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+}
+
+/// Number of days counted by `TagStats::added_recently` and `TagStats::removed_recently`.
+pub(crate) const TAG_TREND_DAYS: i64 = 30;
+
+/// Number of tags in `TagStats::related`.
+const MAX_RELATED_TAGS: usize = 5;
+
+/// Usage statistics of a tag, see `Repo::tag_stats()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct TagStats {
+    pub(crate) name: String,
+    /// Number of items that have the tag
+    pub(crate) count: i64,
+    /// Unix timestamps of when the tag was first and last added to an item. None if the tag was
+    /// only added before tag history was recorded.
+    pub(crate) first_used: Option<i64>,
+    pub(crate) last_used: Option<i64>,
+    /// Number of times the tag was added to and removed from items in the last `TAG_TREND_DAYS`
+    pub(crate) added_recently: i64,
+    pub(crate) removed_recently: i64,
+    /// Tags that are most often on the same items as this tag, and the number of items with both
+    pub(crate) related: Vec<TagCount>,
+}
+
+impl TagStats {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    pub fn first_used(&self) -> Option<i64> {
+        self.first_used
+    }
+
+    pub fn last_used(&self) -> Option<i64> {
+        self.last_used
+    }
+
+    pub fn added_recently(&self) -> i64 {
+        self.added_recently
+    }
+
+    pub fn removed_recently(&self) -> i64 {
+        self.removed_recently
+    }
+
+    pub fn related(&self) -> &[TagCount] {
+        &self.related
+    }
+}
+
+/// The tags of a selection of items, see `Repo::selection_summary()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct SelectionSummary {
+    /// Number of selected items that exist
+    pub(crate) items: i64,
+    /// Every tag on at least one of the items and the number of items that have it, sorted by
+    /// descending count then name
+    pub(crate) tags: Vec<TagCount>,
+    /// Tags that are on every item, sorted by name
+    pub(crate) common: Vec<String>,
+}
+
+impl SelectionSummary {
+    pub fn items(&self) -> i64 {
+        self.items
+    }
+
+    pub fn tags(&self) -> &[TagCount] {
+        &self.tags
+    }
+
+    pub fn common(&self) -> &[String] {
+        &self.common
+    }
+}
+
+/// A folder directly inside another folder, see `Repo::dir_children()`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DirChild {
+    pub(crate) name: String,
+    /// Number of items directly inside the folder
+    pub(crate) direct: usize,
+    /// Number of items inside the folder and its subfolders
+    pub(crate) recursive: usize,
+    /// Number of folders directly inside the folder
+    pub(crate) subfolders: usize,
+}
+
+impl DirChild {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn direct(&self) -> usize {
+        self.direct
+    }
+
+    pub fn recursive(&self) -> usize {
+        self.recursive
+    }
+
+    pub fn subfolders(&self) -> usize {
+        self.subfolders
+    }
+}
+
+/// Items that have changed after a change sequence number, see `Repo::changes_since()`.
+#[derive(Debug, Clone)]
+pub struct Changes {
+    /// The latest change sequence number, pass this to the next call to catch up again.
+    pub(crate) to: i64,
+    /// Items that have been added or modified.
+    pub(crate) items: Vec<Item>,
+    /// Ids of items that have been removed.
+    pub(crate) removed: Vec<i64>,
+}
+
+/// Number of changes made in each transaction when syncing.
+const SYNC_CHUNK_SIZE: usize = 5000;
+
+/// Changes to the items in a repo when syncing it with the filesystem.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncDelta {
+    pub(crate) created: Vec<String>,
+    pub(crate) deleted: Vec<String>,
+    pub(crate) renamed: Vec<(String, String)>,
+    /// Number of deleted items that had tags
+    pub(crate) retagged_lost: usize,
+}
+
+impl SyncDelta {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total number of items that were created, deleted or renamed.
+    pub(crate) fn len(&self) -> usize {
+        self.created.len() + self.deleted.len() + self.renamed.len()
+    }
+
+    pub(crate) fn report(&self) -> SyncReport {
+        SyncReport {
+            created: self.created.len(),
+            deleted: self.deleted.len(),
+            renamed: self.renamed.len(),
+            retagged_lost: self.retagged_lost,
+        }
+    }
+}
+
+/// Number of items changed by a sync.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    pub(crate) created: usize,
+    pub(crate) deleted: usize,
+    pub(crate) renamed: usize,
+    /// Number of deleted items that had tags. The tags of these items are lost.
+    pub(crate) retagged_lost: usize,
+}
+
+impl SyncReport {
+    pub(crate) fn total(&self) -> usize {
+        self.created + self.deleted + self.renamed
+    }
+}
+
+/// Progress of a sync, reported after each transaction is committed.
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct SyncProgress {
+    pub(crate) done: usize,
+    pub(crate) total: usize,
+}
+
+/// Tags restored from the tombstone of a deleted item.
+struct RestoredTags {
+    tags: Vec<String>,
+    /// Size and hash of the new file, if it was hashed while looking for the tombstone.
+    hash: Option<(i64, String)>,
+    /// The uid of the deleted item, which the new item is given
+    uid: Option<String>,
+}
+
+/// A single change made by a sync.
+enum SyncOp<'a> {
+    Delete(&'a str),
+    Create(&'a str),
+    Rename(&'a str, &'a str),
+}
+
+/// Number of entries kept in the activity log, older entries are removed.
+const ACTIVITY_LOG_SIZE: i64 = 10000;
+
+/// A kind of change in the activity log.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActivityKind {
+    Created,
+    Deleted,
+    Renamed,
+}
+
+impl ActivityKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ActivityKind::Created => "created",
+            ActivityKind::Deleted => "deleted",
+            ActivityKind::Renamed => "renamed",
+        }
+    }
+
+    fn from_str(kind: &str) -> Option<Self> {
+        match kind {
+            "created" => Some(ActivityKind::Created),
+            "deleted" => Some(ActivityKind::Deleted),
+            "renamed" => Some(ActivityKind::Renamed),
+            _ => None,
+        }
+    }
+}
+
+/// A change to the files in a repo noticed by the watcher, see `Repo::activity()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Activity {
+    pub(crate) id: i64,
+    pub(crate) kind: ActivityKind,
+    pub(crate) path: String,
+    /// The previous path of a renamed file or folder
+    pub(crate) old_path: Option<String>,
+    /// Unix timestamp in seconds
+    pub(crate) time: i64,
+}
+
+/// Which entries of the activity log to return, see `Repo::activity()`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ActivityFilter {
+    /// Only return changes of this kind
+    pub(crate) kind: Option<ActivityKind>,
+    /// Only return changes made at or after this unix timestamp in seconds
+    pub(crate) since: Option<i64>,
+}
+
+/// How a repo is opened. In read-only mode, the database is opened with `SQLITE_OPEN_READ_ONLY`
+/// and all methods that modify the repo return a `RepoReadOnly` error.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenMode {
+    #[default]
+    ReadWrite,
+    ReadOnly,
 }
 
 #[derive(Debug)]
 pub struct Repo {
     path: PathBuf,
     conn: Connection,
+    config: RepoConfig,
+    mode: OpenMode,
+    /// The lock file written by this repo, if any. It is removed when the repo is closed.
+    lock: Option<RepoLock>,
+    /// Keeps other programs from opening the repo in `LockingMode::Exclusive`. Released after the
+    /// lock file is removed, since fields are dropped after `drop()` runs.
+    guard: Option<LockGuard>,
 }
 
 fn repeat_vars(count: usize) -> String {
@@ -141,23 +693,81 @@ fn repeat_vars(count: usize) -> String {
     s
 }
 
+/// Convert a folder path into the form stored in `folder_tags`, separated by forward slashes and
+/// ending with a slash. The repo root is an empty string.
+fn folder_prefix(folder: &str) -> String {
+    let folder = if cfg!(target_os = "windows") {
+        folder.replace('\\', "/")
+    } else {
+        folder.to_string()
+    };
+    let folder = folder.trim_matches('/');
+    if folder.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", folder)
+    }
+}
+
 pub trait IntoTags {
     fn into_tags(self) -> Vec<String>;
 }
 
+/// Normalize a tag to Unicode NFC, so tags that look the same are stored the same way, e.g. an
+/// "é" typed as one character or as "e" followed by an accent.
+pub(crate) fn normalize_tag(tag: &str) -> Cow<str> {
+    if is_nfc(tag) {
+        Cow::from(tag)
+    } else {
+        Cow::from(tag.nfc().collect::<String>())
+    }
+}
+
+/// A single tag that has been checked against the repo's tag policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tag(String);
+
+impl Tag {
+    /// Check a tag before it is added to an item. Tags can never be empty or contain whitespace,
+    /// since tags are stored separated by spaces. Characters in `invalid_chars` are handled
+    /// according to `policy`.
+    pub(crate) fn parse(
+        text: &str,
+        policy: TagPolicy,
+        invalid_chars: &str,
+    ) -> Result<Self, InvalidTagError> {
+        if text.is_empty() {
+            return Err(InvalidTagError::Empty);
+        }
+        if text.contains(char::is_whitespace) {
+            return Err(InvalidTagError::Whitespace(text.to_string()));
+        }
+        let is_invalid = |c: char| invalid_chars.contains(c);
+        match policy {
+            TagPolicy::Allow => Ok(Self(text.to_string())),
+            TagPolicy::Reject => match text.chars().find(|&c| is_invalid(c)) {
+                Some(char) => Err(InvalidTagError::InvalidChar { tag: text.to_string(), char }),
+                None => Ok(Self(text.to_string())),
+            },
+            TagPolicy::Replace => Ok(Self(text.replace(is_invalid, "_"))),
+        }
+    }
+
+    pub(crate) fn into_string(self) -> String {
+        self.0
+    }
+}
+
 impl IntoTags for String {
     fn into_tags(self) -> Vec<String> {
-        self.split_whitespace()
-            .map(|x| x.to_string())
-            .sorted()
-            .collect()
+        self.as_str().into_tags()
     }
 }
 
 impl IntoTags for &str {
     fn into_tags(self) -> Vec<String> {
         self.split_whitespace()
-            .map(|x| x.to_string())
+            .map(|x| normalize_tag(x).into_owned())
             .sorted()
             .collect()
     }
@@ -165,35 +775,77 @@ impl IntoTags for &str {
 
 impl IntoTags for Vec<String> {
     fn into_tags(self) -> Vec<String> {
-        self.iter().cloned().sorted().collect()
+        self.iter()
+            .map(|x| normalize_tag(x).into_owned())
+            .sorted()
+            .collect()
     }
 }
 
 impl IntoTags for &Vec<String> {
     fn into_tags(self) -> Vec<String> {
-        self.iter().cloned().sorted().collect()
+        self.iter()
+            .map(|x| normalize_tag(x).into_owned())
+            .sorted()
+            .collect()
     }
 }
 
 impl IntoTags for Vec<&str> {
     fn into_tags(self) -> Vec<String> {
-        self.iter().map(|x| x.to_string()).sorted().collect()
+        self.iter()
+            .map(|x| normalize_tag(x).into_owned())
+            .sorted()
+            .collect()
     }
 }
 
 impl IntoTags for &Vec<&str> {
     fn into_tags(self) -> Vec<String> {
-        self.iter().map(|x| x.to_string()).sorted().collect()
+        self.iter()
+            .map(|x| normalize_tag(x).into_owned())
+            .sorted()
+            .collect()
+    }
+}
+
+/// Convert tags to the form they are stored in. If `case_insensitive` is true, tags are
+/// lowercased and any duplicates are removed.
+pub(crate) fn prepare_tags(tags: impl IntoTags, case_insensitive: bool) -> Vec<String> {
+    let tags = tags.into_tags();
+    if case_insensitive {
+        tags.into_iter()
+            .map(|x| x.to_lowercase())
+            .sorted()
+            .dedup()
+            .collect()
+    } else {
+        tags
     }
 }
 
 impl Repo {
+    /// Convert tags added by the user to the form they are stored in, checking each tag with
+    /// `Tag::parse()`. Empty tags are skipped.
+    fn prepare_new_tags(&self, tags: impl IntoTags) -> Result<Vec<String>, InvalidTagError> {
+        let config = &self.config;
+        let tags = prepare_tags(tags, config.case_insensitive_tags);
+        let tags: Vec<_> = tags
+            .iter()
+            .filter(|x| !x.is_empty())
+            .map(|x| Tag::parse(x, config.tag_policy, &config.invalid_tag_chars))
+            .map_ok(Tag::into_string)
+            .try_collect()?;
+        // replacing characters may have created duplicates
+        Ok(tags.into_iter().sorted().dedup().collect())
+    }
+
     /// Common function used to convert a query row into an item.
     ///
     /// Queried columns must be:
     ///
     /// ```sql
-    /// SELECT i.id, i.path, i.tags, i.meta_tags
+    /// SELECT i.id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid
     /// ```
     fn row_to_item(row: &Row) -> Result<Item, rusqlite::Error> {
         Ok(Item {
@@ -201,6 +853,8 @@ impl Repo {
             path: row.get::<_, String>(1)?,
             tags: Self::convert_raw_tags(row.get::<_, String>(2)?),
             meta_tags: row.get::<_, String>(3)?,
+            updated_at: row.get::<_, i64>(4)?,
+            uid: row.get::<_, String>(5)?,
         })
     }
 
@@ -226,35 +880,169 @@ impl Repo {
         }
     }
 
-    pub fn open(repo_path: impl AsRef<Path>) -> Result<Repo, OpenError> {
+    pub fn open(repo_path: impl AsRef<Path>, mode: OpenMode) -> Result<Repo, OpenError> {
         let repo_path = repo_path.as_ref();
         if !repo_path.exists() {
             return Err(OpenError::PathDoesNotExist);
         }
         let data_path = repo_path.join(".tagrepo");
-        if !data_path.exists() {
+        let db_path = data_path.join("tags.db");
+        if mode == OpenMode::ReadOnly {
+            // the database can't be created in read-only mode
+            if !db_path.exists() {
+                return Err(OpenError::RepoNotCreated);
+            }
+        } else if !data_path.exists() {
             create_dir(&data_path)?;
         }
-        let db_path = data_path.join("tags.db");
-        let conn = open_database(db_path)?;
-        let repo = Self { path: PathBuf::from(repo_path), conn };
+        let config = RepoConfig::load(&data_path).map_err(OpenError::FailedToLoadConfig)?;
+        // taken before the database is opened, since a damaged database is replaced while opening
+        let guard = if mode == OpenMode::ReadWrite && config.locking_mode == LockingMode::Exclusive
+        {
+            match LockGuard::acquire(&data_path).map_err(OpenError::FailedToWriteLock)? {
+                Some(guard) => Some(guard),
+                None => {
+                    let owner = match RepoLock::read(&data_path) {
+                        Some(lock) => lock.to_string(),
+                        None => String::from("unknown program"),
+                    };
+                    return Err(OpenError::RepoLocked(owner));
+                }
+            }
+        } else {
+            None
+        };
+        let conn = match open_database(&db_path, mode, config.locking_mode) {
+            Ok(conn) => conn,
+            Err(OpenError::FailedToCreateDatabase(err)) if is_busy(&err) => {
+                let owner = match RepoLock::read(&data_path) {
+                    Some(lock) => lock.to_string(),
+                    None => String::from("unknown program"),
+                };
+                return Err(OpenError::RepoLocked(owner));
+            }
+            // read-only repos can't be repaired, they fail when a damaged page is read instead
+            Err(err) if mode == OpenMode::ReadWrite && err.is_corrupt() => {
+                return Err(recover_database(&db_path, config.locking_mode));
+            }
+            Err(err) => return Err(err),
+        };
+        if mode == OpenMode::ReadWrite && !quick_check(&conn)? {
+            drop(conn);
+            return Err(recover_database(&db_path, config.locking_mode));
+        }
+        set_path_case(&conn, config.case_insensitive_paths)?;
+
+        // the guard was acquired, so any existing lock file is stale
+        let lock = if guard.is_some() {
+            let lock = RepoLock::current();
+            lock.write(&data_path)
+                .map_err(OpenError::FailedToWriteLock)?;
+            Some(lock)
+        } else {
+            None
+        };
+
+        let repo = Self {
+            path: PathBuf::from(repo_path),
+            conn,
+            config,
+            mode,
+            lock,
+            guard,
+        };
         Ok(repo)
     }
 
+    /// Create a new repo in a folder with the given config. Unlike `open()`, this fails if the
+    /// folder is already a repo.
+    pub fn create(repo_path: impl AsRef<Path>, config: &RepoConfig) -> Result<Repo, CreateError> {
+        let repo_path = repo_path.as_ref();
+        if !repo_path.is_dir() {
+            return Err(CreateError::PathDoesNotExist);
+        }
+        let data_path = repo_path.join(".tagrepo");
+        if data_path.exists() {
+            return Err(CreateError::AlreadyExists);
+        }
+        create_dir(&data_path)?;
+        config.save(&data_path)?;
+        Ok(Self::open(repo_path, OpenMode::ReadWrite)?)
+    }
+
+    /// Open a read-only connection to an open repo's database, using the given config instead of
+    /// loading it, see `ReadPool`.
+    pub(crate) fn open_reader(repo_path: &Path, config: RepoConfig) -> Result<Repo, OpenError> {
+        let db_path = repo_path.join(".tagrepo").join("tags.db");
+        let conn = open_database(db_path, OpenMode::ReadOnly, config.locking_mode)?;
+        set_path_case(&conn, config.case_insensitive_paths)?;
+        // wait for the main connection to finish writing
+        conn.busy_timeout(Duration::from_secs(5))?;
+        Ok(Self {
+            path: repo_path.to_path_buf(),
+            conn,
+            config,
+            mode: OpenMode::ReadOnly,
+            lock: None,
+            guard: None,
+        })
+    }
+
     pub fn path(&self) -> &Path {
         self.path.as_path()
     }
 
-    pub(crate) fn insert_item<T, U>(&self, path: T, tags: U) -> Result<Item, InsertError>
+    pub fn read_only(&self) -> bool {
+        self.mode == OpenMode::ReadOnly
+    }
+
+    fn check_writable(&self) -> Result<(), RepoReadOnly> {
+        if self.read_only() {
+            Err(RepoReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn config(&self) -> &RepoConfig {
+        &self.config
+    }
+
+    /// Replace the repo config, and save it to `.tagrepo/config.json`.
+    pub fn set_config(&mut self, config: RepoConfig) -> Result<(), RepoConfigError> {
+        self.check_writable()?;
+        config.save(self.path.join(".tagrepo"))?;
+        set_path_case(&self.conn, config.case_insensitive_paths)?;
+        if config.case_insensitive_tags && !self.config.case_insensitive_tags {
+            self.conn.execute(
+                "UPDATE items SET tags = normalize_tags(tags, 1) WHERE tags != normalize_tags(tags, 1)",
+                [],
+            )?;
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    /// Collation used when comparing paths, depending on whether paths are case-insensitive.
+    fn path_collation(&self) -> &'static str {
+        if self.config.case_insensitive_paths {
+            "NOCASE"
+        } else {
+            "BINARY"
+        }
+    }
+
+    pub fn insert_item<T, U>(&self, path: T, tags: U) -> Result<Item, InsertError>
     where
         T: AsRef<str>,
         U: IntoTags,
     {
+        self.check_writable()?;
         let path = path.as_ref();
-        let tags = tags.into_tags();
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
         let result = self.conn.execute(
-            "INSERT INTO items (path, tags) VALUES (?1, ?2)",
-            (&path, tags.join(" ")),
+            "INSERT INTO items (path, tags, meta_tags, updated_at) VALUES (?1, ?2, meta_tags(?1), ?3)",
+            (&path, tags.join(" "), unix_timestamp_ms()),
         );
 
         match result {
@@ -272,6 +1060,53 @@ impl Repo {
         }
     }
 
+    /// Insert a newly discovered path, tagging it with any matching auto-tagging rules.
+    pub(crate) fn insert_new_item(
+        &self,
+        path: impl AsRef<RelativePath>,
+    ) -> Result<Item, InsertError> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        // the item is only added once its tags are restored, so a failed insert is retried by the
+        // next sync instead of leaving an untagged item
+        let tx = self.conn.unchecked_transaction()?;
+        let mut tags = self.rule_set()?.tags_for(path);
+        let restored = match self.tag_memory_enabled() {
+            true => take_tombstone(&self.conn, &self.path, path.as_str(), self.path_collation())?,
+            false => None,
+        };
+        let item = match restored {
+            Some(restored) => {
+                tags.extend(restored.tags);
+                let mut item =
+                    self.insert_item(path.as_str(), tags.into_iter().unique().collect_vec())?;
+                if let Some((size, hash)) = restored.hash {
+                    self.conn.execute(
+                        "UPDATE items SET size = ?2, hash = ?3 WHERE id = ?1",
+                        params![item.id, size, hash],
+                    )?;
+                }
+                if let Some(uid) = restored.uid {
+                    self.conn.execute(
+                        "UPDATE items SET uid = ?2 WHERE id = ?1",
+                        params![item.id, uid],
+                    )?;
+                    item.uid = uid;
+                }
+                item
+            }
+            None => self.insert_item(path.as_str(), tags)?,
+        };
+        if let Some(mtime) = file_mtime(&path.to_path(&self.path)) {
+            self.conn.execute(
+                "UPDATE items SET mtime = ?2 WHERE id = ?1",
+                params![item.id, mtime],
+            )?;
+        }
+        tx.commit()?;
+        Ok(item)
+    }
+
     pub(crate) fn insert_items<T, U>(
         &mut self,
         items_params: impl Iterator<Item = (T, U)>,
@@ -280,27 +1115,33 @@ impl Repo {
         T: AsRef<str>,
         U: IntoTags,
     {
+        self.check_writable()?;
         // I attempted to optimise this following this guide:
         // https://avi.im/blag/2021/fast-sqlite-inserts/
 
+        let case_insensitive = self.config.case_insensitive_tags;
         let tx = self.conn.transaction()?;
         {
-            let mut stmt = tx.prepare_cached("INSERT INTO items (path, tags) VALUES (?1, ?2)")?;
+            let mut stmt = tx
+                .prepare_cached(
+                    "INSERT INTO items (path, tags, meta_tags, updated_at) VALUES (?1, ?2, meta_tags(?1), ?3)",
+                )?;
+            let now = unix_timestamp_ms();
             for (path, tags) in items_params {
                 let path = path.as_ref();
-                let tags = tags.into_tags();
-                stmt.execute(params![path, tags.join(" ")])?;
+                let tags = prepare_tags(tags, case_insensitive);
+                stmt.execute(params![path, tags.join(" "), now])?;
             }
         }
         tx.commit()?;
         Ok(())
     }
 
-    pub(crate) fn get_item_by_path(&self, path: impl AsRef<str>) -> Result<Item, SearchError> {
+    pub fn get_item_by_path(&self, path: impl AsRef<str>) -> Result<Item, SearchError> {
         let path = path.as_ref();
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, path, tags, meta_tags FROM items WHERE path = :path LIMIT 1")?;
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, tags, meta_tags, updated_at, uid FROM items WHERE path = :path LIMIT 1",
+        )?;
         let item = stmt.query_row([&path], Self::row_to_item);
         if let Err(QueryReturnedNoRows) = item {
             return Err(SearchError::ItemNotFound);
@@ -309,10 +1150,10 @@ impl Repo {
         Ok(item?)
     }
 
-    pub(crate) fn get_item_by_id(&self, id: i64) -> Result<Item, SearchError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, path, tags, meta_tags FROM items WHERE id = :id LIMIT 1")?;
+    pub fn get_item_by_id(&self, id: i64) -> Result<Item, SearchError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, tags, meta_tags, updated_at, uid FROM items WHERE id = :id LIMIT 1",
+        )?;
         let item = stmt.query_row([id], Self::row_to_item);
         if let Err(QueryReturnedNoRows) = item {
             return Err(SearchError::ItemNotFound);
@@ -321,24 +1162,58 @@ impl Repo {
         Ok(item?)
     }
 
-    pub(crate) fn remove_item_by_path(&self, path: impl AsRef<str>) -> Result<Item, RemoveError> {
+    /// Items that have changed since they were last seen, given the `updated_at` of each item when
+    /// it was last seen. Items that no longer exist have changed too.
+    pub(crate) fn stale_items(
+        &self,
+        last_seen: &HashMap<i64, i64>,
+    ) -> Result<Vec<i64>, rusqlite::Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT updated_at FROM items WHERE id = ?1")?;
+        let mut rv = vec![];
+        for (&id, &seen) in last_seen {
+            let updated_at: Option<i64> = stmt.query_row([id], |row| row.get(0)).optional()?;
+            if updated_at != Some(seen) {
+                rv.push(id);
+            }
+        }
+        rv.sort();
+        Ok(rv)
+    }
+
+    pub fn remove_item_by_path(&self, path: impl AsRef<str>) -> Result<Item, RemoveError> {
+        self.check_writable()?;
         let removed_item = self.get_item_by_path(&path)?;
         let path = path.as_ref();
+        if self.tag_memory_enabled() {
+            write_tombstone(&self.conn, path)?;
+        }
         self.conn
             .execute("DELETE FROM items WHERE path = :path", [path])?;
         Ok(removed_item)
     }
 
     pub(crate) fn remove_item_by_id(&self, id: i64) -> Result<(), RemoveError> {
+        self.check_writable()?;
+        if self.tag_memory_enabled() {
+            match self.get_item_by_id(id) {
+                Ok(item) => write_tombstone(&self.conn, &item.path)?,
+                Err(SearchError::ItemNotFound) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
         self.conn
             .execute("DELETE FROM items WHERE id = :id", [id])?;
         Ok(())
     }
 
-    pub(crate) fn update_tags(&self, item_id: i64, tags: impl IntoTags) -> Result<(), UpdateError> {
+    pub fn update_tags(&self, item_id: i64, tags: impl IntoTags) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        let tags = self.prepare_new_tags(tags)?;
         let rv = self.conn.execute(
             "UPDATE items SET tags = :tags WHERE id = :id",
-            params![tags.into_tags().join(" "), item_id],
+            params![tags.join(" "), item_id],
         );
         match rv {
             Ok(_) => Ok(()),
@@ -346,14 +1221,412 @@ impl Repo {
         }
     }
 
+    /// Replace the tags of every given item with `tags` in a single transaction, e.g. when tags are
+    /// edited as text. Returns the number of items whose tags changed.
+    pub fn set_tags(&self, item_ids: &[i64], tags: impl IntoTags) -> Result<usize, UpdateError> {
+        self.check_writable()?;
+        let tags = self.prepare_new_tags(tags)?.join(" ");
+        let tx = self.conn.unchecked_transaction()?;
+        let mut count = 0;
+        {
+            // unchanged items are skipped, so they aren't sent to the frontend as changes
+            let mut stmt =
+                tx.prepare_cached("UPDATE items SET tags = ?2 WHERE id = ?1 AND tags != ?2")?;
+            for item_id in item_ids {
+                count += stmt.execute(params![item_id, tags])?;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// The sequence number of the latest change to the repo, or 0 if no item has ever changed.
+    /// Every change to an item is given a larger number than the previous change.
+    pub(crate) fn change_seq(&self) -> Result<i64, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COALESCE(MAX(change_seq), 0) FROM item_changes",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// Items that have been added, modified or removed after the change `seq`. If an item has
+    /// changed several times, only its current state is returned.
+    pub(crate) fn changes_since(&self, seq: i64) -> Result<Changes, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT c.item_id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid FROM item_changes c
+            LEFT JOIN items i ON i.id = c.item_id
+            WHERE c.change_seq > ?
+            ORDER BY c.change_seq
+        "})?;
+        let mut rows = stmt.query([seq])?;
+        let mut changes = Changes {
+            to: self.change_seq()?,
+            items: vec![],
+            removed: vec![],
+        };
+        while let Some(row) = rows.next()? {
+            match row.get::<_, Option<String>>(1)? {
+                Some(_) => changes.items.push(Self::row_to_item(row)?),
+                None => changes.removed.push(row.get(0)?),
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Tagged items that haven't been hashed yet, with an id greater than `after_id`. Returns
+    /// `(id, path)` pairs ordered by id.
+    pub(crate) fn unhashed_items(
+        &self,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, String)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT i.id, i.path FROM items i
+            WHERE i.id > ?1 AND i.tags != '' AND i.hash IS NULL
+            ORDER BY i.id
+            LIMIT ?2
+        "})?;
+        let rows = stmt.query_map(params![after_id, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// The stored size and content hash of an item's file, or `None` if the item doesn't exist or
+    /// hasn't been hashed yet.
+    pub(crate) fn get_hash_by_path(
+        &self,
+        path: &str,
+    ) -> Result<Option<(i64, String)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT size, hash FROM items
+            WHERE path = ?1 AND size IS NOT NULL AND hash IS NOT NULL
+        "})?;
+        stmt.query_row([path], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+    }
+
+    /// The stored file sizes of the given items. Items that don't exist or haven't been hashed yet
+    /// are left out.
+    pub(crate) fn item_sizes(
+        &self,
+        item_ids: &[i64],
+    ) -> Result<HashMap<i64, i64>, rusqlite::Error> {
+        let mut sizes = HashMap::with_capacity(item_ids.len());
+        for chunk in item_ids.chunks(SYNC_CHUNK_SIZE) {
+            let sql = format!(
+                "SELECT id, size FROM items WHERE size IS NOT NULL AND id IN ({})",
+                repeat_vars(chunk.len()),
+            );
+            let mut stmt = self.conn.prepare(&sql)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            for row in rows {
+                let (id, size) = row?;
+                sizes.insert(id, size);
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Check whether an item's file was modified since it was last checked, by comparing its
+    /// modification time. The stored size and hash of a modified file are cleared, so the file is
+    /// hashed again, and the item is sent to the frontend through the change feed. The first check
+    /// of a file only stores its modification time.
+    ///
+    /// Returns whether the file was modified. Paths that aren't in the repo are ignored.
+    pub(crate) fn refresh_mtime(&self, path: &RelativePath) -> Result<bool, UpdateError> {
+        self.check_writable()?;
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, mtime FROM items WHERE path = ?1")?;
+        let row: Option<(i64, Option<i64>)> = stmt
+            .query_row([path.as_str()], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let Some((id, old_mtime)) = row else {
+            return Ok(false);
+        };
+        let Some(new_mtime) = file_mtime(&path.to_path(&self.path)) else {
+            return Ok(false);
+        };
+        Ok(update_mtime(&self.conn, id, old_mtime, new_mtime)?)
+    }
+
+    /// Same as `refresh_mtime()`, for every item in a subfolder. If `recursive` is false, only
+    /// items directly inside the subfolder are checked. Returns the ids of modified items.
+    pub(crate) fn refresh_mtimes(
+        &mut self,
+        subdir: &RelativePath,
+        recursive: bool,
+    ) -> Result<Vec<i64>, SyncError> {
+        self.check_writable()?;
+        let prefix = if subdir.as_str().is_empty() {
+            String::new()
+        } else {
+            format!("{}/", subdir)
+        };
+        let tx = self.conn.transaction()?;
+        let mut modified = vec![];
+        {
+            let mut stmt = tx.prepare_cached(
+                "SELECT id, path, mtime FROM items WHERE substr(path, 1, length(?1)) = ?1",
+            )?;
+            let rows: Vec<(i64, String, Option<i64>)> = stmt
+                .query_map(params![prefix], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            for (id, path, old_mtime) in rows {
+                if !recursive && path[prefix.len()..].contains('/') {
+                    continue;
+                }
+                let Some(new_mtime) = file_mtime(&RelativePath::new(&path).to_path(&self.path))
+                else {
+                    continue;
+                };
+                if update_mtime(&tx, id, old_mtime, new_mtime)? {
+                    modified.push(id);
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(modified)
+    }
+
+    /// Store the size and content hash of an item's file, see `hash_file()`.
+    pub(crate) fn set_hash(&self, item_id: i64, size: i64, hash: &str) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        self.conn.execute(
+            "UPDATE items SET size = ?2, hash = ?3 WHERE id = ?1",
+            params![item_id, size, hash],
+        )?;
+        Ok(())
+    }
+
+    /// Items that haven't been fingerprinted yet, with an id greater than `after_id`. Returns
+    /// `(id, path)` pairs ordered by id.
+    pub(crate) fn unfingerprinted_items(
+        &self,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, String)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT i.id, i.path FROM items i
+            LEFT JOIN fingerprints f ON f.item_id = i.id
+            WHERE i.id > ?1 AND f.item_id IS NULL
+            ORDER BY i.id
+            LIMIT ?2
+        "})?;
+        let rows = stmt.query_map(params![after_id, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Store the fingerprint of an item's file, see `similarity::fingerprint()`. `None` marks a
+    /// file that can't be fingerprinted. Items that no longer exist are ignored.
+    pub(crate) fn set_fingerprint(
+        &self,
+        item_id: i64,
+        fingerprint: Option<&[f32]>,
+    ) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO fingerprints (item_id, fingerprint) SELECT id, ?2 FROM items WHERE id = ?1",
+            params![item_id, fingerprint.map(similarity::to_bytes)],
+        )?;
+        Ok(())
+    }
+
+    /// The stored loudness of an item's file, see `loudness::loudness()`. Returns `None` if it
+    /// hasn't been measured yet, and `Some(None)` if the file can't be measured.
+    pub(crate) fn loudness(
+        &self,
+        item_id: i64,
+    ) -> Result<Option<Option<Loudness>>, rusqlite::Error> {
+        self.conn
+            .query_row(
+                "SELECT lufs, peak FROM loudness WHERE item_id = ?1",
+                [item_id],
+                |row| {
+                    let lufs: Option<f32> = row.get(0)?;
+                    let peak: Option<f32> = row.get(1)?;
+                    Ok(lufs.zip(peak).map(|(lufs, peak)| Loudness { lufs, peak }))
+                },
+            )
+            .optional()
+    }
+
+    /// Store the loudness of an item's file. `None` marks a file that can't be measured. Items
+    /// that no longer exist are ignored.
+    pub(crate) fn set_loudness(
+        &self,
+        item_id: i64,
+        loudness: Option<Loudness>,
+    ) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO loudness (item_id, lufs, peak) SELECT id, ?2, ?3 FROM items WHERE id = ?1",
+            params![item_id, loudness.map(|x| x.lufs), loudness.map(|x| x.peak)],
+        )?;
+        Ok(())
+    }
+
+    /// Documents whose text hasn't been extracted yet, with an id greater than `after_id`. Returns
+    /// `(id, path)` pairs ordered by id.
+    pub(crate) fn unextracted_items(
+        &self,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, String)>, rusqlite::Error> {
+        let extensions = serde_json::to_string(&content::extractable_extensions())
+            .expect("failed to serialize extensions");
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT i.id, i.path FROM items i
+            WHERE i.id > ?1
+              AND i.ext IN (SELECT value FROM json_each(?3))
+              AND i.id NOT IN (SELECT rowid FROM item_content)
+            ORDER BY i.id
+            LIMIT ?2
+        "})?;
+        let rows = stmt.query_map(params![after_id, limit, extensions], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Store the text extracted from an item's file, searched with `content:text`. An empty
+    /// string marks a file whose text can't be extracted. Items that no longer exist are ignored.
+    pub(crate) fn set_content(&self, item_id: i64, text: &str) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO item_content (rowid, content) SELECT id, ?2 FROM items WHERE id = ?1",
+            params![item_id, text],
+        )?;
+        Ok(())
+    }
+
+    /// Items whose files sound most like an item's file, most similar first. Only items that have
+    /// been fingerprinted are compared.
+    pub fn find_similar(
+        &self,
+        item_id: i64,
+        limit: usize,
+    ) -> Result<Vec<SimilarItem>, SimilarError> {
+        let target: Option<Option<Vec<u8>>> = self
+            .conn
+            .query_row(
+                "SELECT fingerprint FROM fingerprints WHERE item_id = ?1",
+                [item_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(Some(target)) = target else {
+            return Err(SimilarError::NoFingerprint(item_id));
+        };
+        let target = similarity::from_bytes(&target);
+        let mut stmt = self.conn.prepare(indoc! {"
+            SELECT i.id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid, f.fingerprint
+            FROM fingerprints f
+            JOIN items i ON i.id = f.item_id
+            WHERE f.item_id != ?1 AND f.fingerprint IS NOT NULL
+        "})?;
+        let rows = stmt.query_map([item_id], |row| {
+            let fingerprint: Vec<u8> = row.get(6)?;
+            Ok(SimilarItem {
+                item: Self::row_to_item(row)?,
+                similarity: similarity::similarity(&target, &similarity::from_bytes(&fingerprint)),
+            })
+        })?;
+        let mut items: Vec<SimilarItem> = rows.collect::<Result<_, _>>()?;
+        items.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    /// Whether the tags of deleted items are remembered, see `write_tombstone()`.
+    fn tag_memory_enabled(&self) -> bool {
+        self.config.tombstone_retention_days > 0
+    }
+
+    /// Remove tombstones older than the retention period in the config.
+    fn prune_tombstones(&self) -> Result<usize, rusqlite::Error> {
+        let retention = self.config.tombstone_retention_days * 24 * 60 * 60;
+        let cutoff = unix_timestamp().saturating_sub(retention);
+        let count = if self.tag_memory_enabled() {
+            self.conn
+                .execute("DELETE FROM tombstones WHERE deleted_at < ?1", [cutoff])?
+        } else {
+            self.conn.execute("DELETE FROM tombstones", [])?
+        };
+        Ok(count)
+    }
+
+    /// Record a change noticed by the watcher in the activity log, removing the oldest entries
+    /// once there are more than `ACTIVITY_LOG_SIZE`.
+    pub(crate) fn log_activity(
+        &self,
+        kind: ActivityKind,
+        path: &str,
+        old_path: Option<&str>,
+    ) -> Result<(), UpdateError> {
+        self.check_writable()?;
+        self.conn.execute(
+            "INSERT INTO activity_log(kind, path, old_path) VALUES (?1, ?2, ?3)",
+            params![kind.as_str(), path, old_path],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.conn.execute(
+            "DELETE FROM activity_log WHERE id <= ?1",
+            [id - ACTIVITY_LOG_SIZE],
+        )?;
+        Ok(())
+    }
+
+    /// The latest entries of the activity log that match the filter, newest first.
+    pub fn activity(
+        &self,
+        limit: usize,
+        filter: &ActivityFilter,
+    ) -> Result<Vec<Activity>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT id, kind, path, old_path, time FROM activity_log
+            WHERE (?1 IS NULL OR kind = ?1) AND (?2 IS NULL OR time >= ?2)
+            ORDER BY id DESC
+            LIMIT ?3
+        "})?;
+        let kind = filter.kind.map(|x| x.as_str());
+        let rows = stmt.query_map(params![kind, filter.since, limit], |row| {
+            let kind: String = row.get(1)?;
+            let Some(kind) = ActivityKind::from_str(&kind) else {
+                return Err(rusqlite::Error::InvalidColumnType(
+                    1,
+                    "kind".into(),
+                    rusqlite::types::Type::Text,
+                ));
+            };
+            Ok(Activity {
+                id: row.get(0)?,
+                kind,
+                path: row.get(2)?,
+                old_path: row.get(3)?,
+                time: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
     pub(crate) fn update_path(
         &self,
         item_id: i64,
         path: impl AsRef<str>,
     ) -> Result<(), UpdateError> {
+        self.check_writable()?;
         let path = path.as_ref();
         let rv = self.conn.execute(
-            "UPDATE items SET path = :path WHERE id = :id",
+            "UPDATE items SET path = ?1, meta_tags = meta_tags(?1) WHERE id = ?2",
             params![path, item_id],
         );
         match rv {
@@ -367,21 +1640,43 @@ impl Repo {
         old_path: impl AsRef<str>,
         new_path: impl AsRef<str>,
     ) -> Result<(), UpdateError> {
+        self.check_writable()?;
         let old_path = old_path.as_ref();
         let new_path = new_path.as_ref();
-        self.conn.execute(
-            "UPDATE items SET path = ?2 WHERE path = ?1",
-            params![old_path, new_path],
-        )?;
+        let sql = format!(
+            "UPDATE items SET path = ?2, meta_tags = meta_tags(?2) WHERE path = ?1 COLLATE {}",
+            self.path_collation()
+        );
+        self.conn.execute(&sql, params![old_path, new_path])?;
         Ok(())
     }
 
-    pub(crate) fn insert_tags(
+    /// Move all items under the folder `old_dir` to the folder `new_dir`. Returns the number of
+    /// items that were moved.
+    pub(crate) fn rename_dir(
         &self,
-        item_id: i64,
-        tags: impl IntoTags,
-    ) -> Result<(), InsertTagsError> {
-        let tags = tags.into_tags();
+        old_dir: impl AsRef<str>,
+        new_dir: impl AsRef<str>,
+    ) -> Result<usize, UpdateError> {
+        self.check_writable()?;
+        let old_dir = old_dir.as_ref();
+        let new_dir = new_dir.as_ref();
+        // compare prefixes with substr instead of LIKE, since LIKE treats '%' and '_' in folder
+        // names as wildcards
+        let sql = format!(
+            "UPDATE items
+             SET path = ?2 || substr(path, length(?1) + 1),
+                 meta_tags = meta_tags(?2 || substr(path, length(?1) + 1))
+             WHERE substr(path, 1, length(?1) + 1) = ?1 || '/' COLLATE {}",
+            self.path_collation()
+        );
+        let count = self.conn.execute(&sql, params![old_dir, new_dir])?;
+        Ok(count)
+    }
+
+    pub fn insert_tags(&self, item_id: i64, tags: impl IntoTags) -> Result<(), InsertTagsError> {
+        self.check_writable()?;
+        let tags = self.prepare_new_tags(tags)?;
         if tags.len() == 0 {
             return Ok(());
         }
@@ -404,10 +1699,11 @@ impl Repo {
         item_ids: &Vec<i64>,
         tags: impl IntoTags,
     ) -> Result<(), InsertTagsError> {
+        self.check_writable()?;
         if item_ids.len() == 0 {
             return Ok(());
         }
-        let tags = tags.into_tags();
+        let tags = self.prepare_new_tags(tags)?;
         if tags.len() == 0 {
             return Ok(());
         }
@@ -427,12 +1723,9 @@ impl Repo {
         Ok(())
     }
 
-    pub(crate) fn remove_tags(
-        &self,
-        item_id: i64,
-        tags: impl IntoTags,
-    ) -> Result<(), RemoveTagsError> {
-        let tags = tags.into_tags();
+    pub fn remove_tags(&self, item_id: i64, tags: impl IntoTags) -> Result<(), RemoveTagsError> {
+        self.check_writable()?;
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
         if tags.len() == 0 {
             return Ok(());
         }
@@ -450,15 +1743,29 @@ impl Repo {
         Ok(())
     }
 
+    /// Add the tags of a template in `RepoConfig::tag_templates` to the given items.
+    pub fn apply_tag_template(
+        &self,
+        item_ids: &[i64],
+        name: &str,
+    ) -> Result<(), ApplyTemplateError> {
+        let Some(tags) = self.config.tag_templates.get(name) else {
+            return Err(ApplyTemplateError::NotFound(name.to_string()));
+        };
+        self.batch_insert_tags(&item_ids.to_vec(), tags.as_str())?;
+        Ok(())
+    }
+
     pub(crate) fn batch_remove_tags(
         &self,
         item_ids: &Vec<i64>,
         tags: impl IntoTags,
     ) -> Result<(), RemoveTagsError> {
+        self.check_writable()?;
         if item_ids.len() == 0 {
             return Ok(());
         }
-        let tags = tags.into_tags();
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
         if tags.len() == 0 {
             return Ok(());
         }
@@ -478,26 +1785,350 @@ impl Repo {
         Ok(())
     }
 
+    /// Add tags to a folder, which every item in the folder and its subfolders implicitly has.
+    /// Searches match these tags, but they aren't added to the items' own tags.
+    pub fn insert_folder_tags(
+        &self,
+        folder: &str,
+        tags: impl IntoTags,
+    ) -> Result<(), InsertTagsError> {
+        self.check_writable()?;
+        let tags = self.prepare_new_tags(tags)?;
+        let folder = folder_prefix(folder);
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO folder_tags (path, tag) VALUES (?, ?)")?;
+        for tag in tags {
+            stmt.execute(params![folder, tag])?;
+        }
+        Ok(())
+    }
+
+    /// Remove tags that were added to a folder with `insert_folder_tags()`.
+    pub fn remove_folder_tags(
+        &self,
+        folder: &str,
+        tags: impl IntoTags,
+    ) -> Result<(), RemoveTagsError> {
+        self.check_writable()?;
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
+        let folder = folder_prefix(folder);
+        let mut stmt = self
+            .conn
+            .prepare_cached("DELETE FROM folder_tags WHERE path = ? AND tag = ?")?;
+        for tag in tags {
+            stmt.execute(params![folder, tag])?;
+        }
+        Ok(())
+    }
+
+    /// Tags of a folder and the folders it is in, i.e. the tags that items in the folder inherit.
+    /// Sorted by folder from the outermost folder, then by tag.
+    pub fn folder_tags(&self, folder: &str) -> Result<Vec<FolderTag>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(indoc! {"
+            SELECT path, tag
+            FROM folder_tags
+            WHERE substr(?, 1, length(path)) = path
+            ORDER BY length(path), tag
+        "})?;
+        let rows = stmt.query_map([folder_prefix(folder)], |row| {
+            Ok(FolderTag { path: row.get(0)?, tag: row.get(1)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Create an empty collection. Items are added with `add_to_collection()`, and the items in a
+    /// collection are searched with `collection:name`.
+    pub fn create_collection(&self, name: &str) -> Result<(), CollectionError> {
+        self.check_writable()?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(CollectionError::EmptyName);
+        }
+        let count = self.conn.execute(
+            "INSERT OR IGNORE INTO collections (name) VALUES (?)",
+            [name],
+        )?;
+        if count == 0 {
+            return Err(CollectionError::AlreadyExists(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Delete a collection, the items in it aren't changed.
+    pub fn delete_collection(&self, name: &str) -> Result<(), CollectionError> {
+        self.check_writable()?;
+        let id = self.collection_id(name)?;
+        self.conn
+            .execute("DELETE FROM collections WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    fn collection_id(&self, name: &str) -> Result<i64, CollectionError> {
+        let name = name.trim();
+        self.conn
+            .query_row("SELECT id FROM collections WHERE name = ?", [name], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| CollectionError::NotFound(name.to_string()))
+    }
+
+    /// Add items to a collection, returns the number of items that weren't in it yet. Items are
+    /// removed from collections when they are removed from the repo.
+    pub fn add_to_collection(
+        &self,
+        name: &str,
+        item_ids: &[i64],
+    ) -> Result<usize, CollectionError> {
+        self.check_writable()?;
+        let id = self.collection_id(name)?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare_cached(indoc! {"
+                INSERT OR IGNORE INTO collection_items (collection_id, item_id)
+                SELECT ?1, id FROM items WHERE id = ?2
+            "})?;
+            for item_id in item_ids {
+                count += stmt.execute(params![id, item_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Remove items from a collection, returns the number of items that were in it.
+    pub fn remove_from_collection(
+        &self,
+        name: &str,
+        item_ids: &[i64],
+    ) -> Result<usize, CollectionError> {
+        self.check_writable()?;
+        let id = self.collection_id(name)?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "DELETE FROM collection_items WHERE collection_id = ? AND item_id = ?",
+            )?;
+            for item_id in item_ids {
+                count += stmt.execute(params![id, item_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Every collection and the number of items in it, sorted by name.
+    pub fn collections(&self) -> Result<Vec<Collection>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(indoc! {"
+            SELECT c.name, count(ci.item_id)
+            FROM collections c
+            LEFT JOIN collection_items ci ON ci.collection_id = c.id
+            GROUP BY c.id
+            ORDER BY c.name
+        "})?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Collection { name: row.get(0)?, count: row.get(1)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Every tag used in the repo and the number of items that have it, sorted by name.
+    pub fn tag_counts(&self) -> Result<Vec<TagCount>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(indoc! {"
+            SELECT t.name, count(*)
+            FROM tags t
+            JOIN item_tags it ON it.tag_id = t.id
+            GROUP BY t.id
+            ORDER BY t.name
+        "})?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TagCount { name: row.get(0)?, count: row.get(1)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Usage statistics of every tag used in the repo, sorted by name.
+    pub fn tag_stats(&self) -> Result<Vec<TagStats>, rusqlite::Error> {
+        let mut related: HashMap<String, Vec<TagCount>> = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare(indoc! {"
+                SELECT name, other, count FROM (
+                    SELECT
+                        t1.name AS name,
+                        t2.name AS other,
+                        count(*) AS count,
+                        row_number() OVER (
+                            PARTITION BY a.tag_id ORDER BY count(*) DESC, t2.name
+                        ) AS rank
+                    FROM item_tags a
+                    JOIN item_tags b ON b.item_id = a.item_id AND b.tag_id != a.tag_id
+                    JOIN tags t1 ON t1.id = a.tag_id
+                    JOIN tags t2 ON t2.id = b.tag_id
+                    GROUP BY a.tag_id, b.tag_id
+                )
+                WHERE rank <= ?
+                ORDER BY name, rank
+            "})?;
+            let mut rows = stmt.query([MAX_RELATED_TAGS])?;
+            while let Some(row) = rows.next()? {
+                let other = TagCount { name: row.get(1)?, count: row.get(2)? };
+                related.entry(row.get(0)?).or_default().push(other);
+            }
+        }
+
+        let mut stmt = self.conn.prepare(indoc! {"
+            SELECT
+                t.name,
+                count(*),
+                (SELECT min(time) FROM tag_history h WHERE h.tag = t.name AND h.added),
+                (SELECT max(time) FROM tag_history h WHERE h.tag = t.name AND h.added),
+                (
+                    SELECT count(*) FROM tag_history h
+                    WHERE h.tag = t.name AND h.added AND h.time >= unixepoch() - ?1
+                ),
+                (
+                    SELECT count(*) FROM tag_history h
+                    WHERE h.tag = t.name AND NOT h.added AND h.time >= unixepoch() - ?1
+                )
+            FROM tags t
+            JOIN item_tags it ON it.tag_id = t.id
+            GROUP BY t.id
+            ORDER BY t.name
+        "})?;
+        let rows = stmt.query_map([TAG_TREND_DAYS * 24 * 60 * 60], |row| {
+            let name: String = row.get(0)?;
+            Ok(TagStats {
+                related: related.remove(&name).unwrap_or_default(),
+                name,
+                count: row.get(1)?,
+                first_used: row.get(2)?,
+                last_used: row.get(3)?,
+                added_recently: row.get(4)?,
+                removed_recently: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// The tags of the given items, how many of them have each tag, and which tags all of them
+    /// have. Ids of items that don't exist are ignored.
+    pub fn selection_summary(&self, item_ids: &[i64]) -> Result<SelectionSummary, rusqlite::Error> {
+        let item_ids: Vec<_> = item_ids.iter().unique().copied().collect();
+        let mut items = 0;
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for chunk in item_ids.chunks(SYNC_CHUNK_SIZE) {
+            let vars = repeat_vars(chunk.len());
+            let sql = format!("SELECT count(*) FROM items WHERE id IN ({vars})");
+            let count: i64 =
+                self.conn
+                    .query_row(&sql, rusqlite::params_from_iter(chunk), |row| row.get(0))?;
+            items += count;
+
+            let sql = format!(
+                indoc! {"
+                    SELECT t.name, count(*)
+                    FROM tags t
+                    JOIN item_tags it ON it.tag_id = t.id
+                    WHERE it.item_id IN ({})
+                    GROUP BY t.id
+                "},
+                vars,
+            );
+            let mut stmt = self.conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(chunk))?;
+            while let Some(row) = rows.next()? {
+                *counts.entry(row.get(0)?).or_default() += row.get::<_, i64>(1)?;
+            }
+        }
+
+        let tags: Vec<_> = counts
+            .into_iter()
+            .map(|(name, count)| TagCount { name, count })
+            .sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)))
+            .collect();
+        let common = tags
+            .iter()
+            .filter(|x| x.count == items)
+            .map(|x| x.name.clone())
+            .sorted()
+            .collect();
+        Ok(SelectionSummary { items, tags, common })
+    }
+
+    /// Rename a tag on every item that has it, returns the number of items changed. Items that
+    /// already have the new tag just lose the old tag.
+    pub fn rename_tag(&self, old: &str, new: &str) -> Result<usize, UpdateError> {
+        self.check_writable()?;
+        let config = &self.config;
+        let case = |tag: &str| {
+            let tag = normalize_tag(tag).into_owned();
+            if config.case_insensitive_tags {
+                tag.to_lowercase()
+            } else {
+                tag
+            }
+        };
+        let old = case(old);
+        let new = Tag::parse(&case(new), config.tag_policy, &config.invalid_tag_chars)?;
+        let count = self.conn.execute(
+            indoc! {"
+                UPDATE items SET tags = insert_tags(remove_tags(tags, ?1), ?2)
+                WHERE id IN (
+                    SELECT it.item_id
+                    FROM item_tags it
+                    JOIN tags t ON t.id = it.tag_id
+                    WHERE t.name = ?1
+                )
+            "},
+            params![old, new.into_string()],
+        )?;
+        Ok(count)
+    }
+
     pub fn query_items<'a>(&'a self, query: &'a str) -> Result<Vec<Item>, QueryError> {
-        let where_clause = to_sql(query).map_err(|_x| QueryError::InvalidQuery)?;
+        let where_clause = {
+            let _perf = perf::measure("query.parse");
+            to_sql(query)?
+        };
+        let _perf = perf::measure("query.sql");
         let sql = format!(
             indoc! {"
-                SELECT i.id, i.path, i.tags, i.meta_tags
+                SELECT i.id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid
                 FROM items i
                 INNER JOIN
                     tag_query tq ON tq.id = i.id
                 WHERE {}
             "},
-            where_clause
+            where_clause.sql
         );
         let mut stmt = self.conn.prepare_cached(sql.as_str())?;
-        let mapped_rows = stmt.query_map([], Self::row_to_item)?;
+        let params = rusqlite::params_from_iter(&where_clause.params);
+        let mapped_rows = stmt.query_map(params, Self::row_to_item)?;
         let items: Result<Vec<_>, _> = mapped_rows.collect();
         Ok(items?)
     }
 
+    /// Ids of items matching a query, ordered by path. If the query contains `sort:relevance`,
+    /// items that match its tags best are listed first instead.
     pub fn query_ids<'a>(&'a self, query: &'a str) -> Result<Vec<i64>, QueryError> {
-        let where_clause = to_sql(query).map_err(|_x| QueryError::InvalidQuery)?;
+        self.query_ids_in_dir(query, None)
+    }
+
+    /// Like `query_ids()`, but only items inside the folder `scope_dir` are returned, see
+    /// `to_scoped_sql()`.
+    pub fn query_ids_in_dir(
+        &self,
+        query: &str,
+        scope_dir: Option<&str>,
+    ) -> Result<Vec<i64>, QueryError> {
+        let (where_clause, order) = {
+            let _perf = perf::measure("query.parse");
+            to_scoped_sql(query, scope_dir)?
+        };
+        let _perf = perf::measure("query.sql");
         let sql = format!(
             indoc! {"
                 SELECT i.id
@@ -505,55 +2136,572 @@ impl Repo {
                 INNER JOIN
                     tag_query tq ON tq.id = i.id
                 WHERE {}
-                ORDER BY i.path
+                ORDER BY {}
             "},
-            where_clause
+            where_clause.sql,
+            order.to_sql()
         );
         let mut stmt = self.conn.prepare_cached(sql.as_str())?;
-        let mapped_rows = stmt.query_map([], Self::row_to_id)?;
+        let params = rusqlite::params_from_iter(&where_clause.params);
+        let mapped_rows = stmt.query_map(params, Self::row_to_id)?;
         let items: Result<Vec<_>, _> = mapped_rows.collect();
         Ok(items?)
     }
 
+    /// Like `query_ids_in_dir()`, but ids are passed to `on_chunk` in chunks of `chunk_size` as
+    /// they are read, instead of being collected first. Stops early if `on_chunk` returns false.
+    /// Returns the number of ids read.
+    pub fn query_ids_chunked(
+        &self,
+        query: &str,
+        scope_dir: Option<&str>,
+        chunk_size: usize,
+        mut on_chunk: impl FnMut(Vec<i64>) -> bool,
+    ) -> Result<usize, QueryError> {
+        let (where_clause, order) = {
+            let _perf = perf::measure("query.parse");
+            to_scoped_sql(query, scope_dir)?
+        };
+        let _perf = perf::measure("query.sql");
+        let sql = format!(
+            indoc! {"
+                SELECT i.id
+                FROM items i
+                INNER JOIN
+                    tag_query tq ON tq.id = i.id
+                WHERE {}
+                ORDER BY {}
+            "},
+            where_clause.sql,
+            order.to_sql()
+        );
+        let chunk_size = chunk_size.max(1);
+        let mut stmt = self.conn.prepare_cached(sql.as_str())?;
+        let params = rusqlite::params_from_iter(&where_clause.params);
+        let mut rows = stmt.query(params)?;
+        let mut total = 0;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        while let Some(row) = rows.next()? {
+            chunk.push(Self::row_to_id(row)?);
+            total += 1;
+            if chunk.len() == chunk_size {
+                let chunk = std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+                if !on_chunk(chunk) {
+                    return Ok(total);
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            on_chunk(chunk);
+        }
+        Ok(total)
+    }
+
+    /// Count the number of items matching a query, without fetching the items themselves.
+    pub fn count(&self, query: &str) -> Result<i64, QueryError> {
+        let where_clause = {
+            let _perf = perf::measure("query.parse");
+            to_sql(query)?
+        };
+        let _perf = perf::measure("query.sql");
+        let sql = format!(
+            indoc! {"
+                SELECT COUNT(*)
+                FROM items i
+                INNER JOIN
+                    tag_query tq ON tq.id = i.id
+                WHERE {}
+            "},
+            where_clause.sql
+        );
+        let mut stmt = self.conn.prepare_cached(sql.as_str())?;
+        let params = rusqlite::params_from_iter(&where_clause.params);
+        let count = stmt.query_row(params, |row| row.get::<_, i64>(0))?;
+        Ok(count)
+    }
+
     pub(crate) fn all_items(&self) -> Result<Vec<Item>, rusqlite::Error> {
-        let sql = "SELECT i.id, i.path, i.tags, i.meta_tags FROM items i";
+        let sql = "SELECT i.id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid FROM items i";
         let mut stmt = self.conn.prepare_cached(sql)?;
         let mapped_rows = stmt.query_map([], Self::row_to_item)?;
         let items: Result<Vec<_>, _> = mapped_rows.collect();
         Ok(items?)
     }
 
-    pub fn all_folders(&self) -> Result<Vec<String>, rusqlite::Error> {
-        let sql = "SELECT DISTINCT dirname(i.path) FROM items i ORDER BY dirname(i.path)";
+    /// Items inside the folder `dir` or its subfolders, sorted by path. If `dir` is empty, this
+    /// returns all items.
+    pub(crate) fn items_in_dir(&self, dir: &RelativePath) -> Result<Vec<Item>, rusqlite::Error> {
+        let prefix = if dir.as_str().is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+        let sql = format!(
+            "SELECT i.id, i.path, i.tags, i.meta_tags, i.updated_at, i.uid FROM items i
+             WHERE substr(i.path, 1, length(?1)) = ?1 COLLATE {}
+             ORDER BY i.path",
+            self.path_collation()
+        );
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let mapped_rows = stmt.query_map([prefix], Self::row_to_item)?;
+        let items: Result<Vec<_>, _> = mapped_rows.collect();
+        Ok(items?)
+    }
+
+    /// Add the files copied by `import_subtree()` as items with their tags, in a single
+    /// transaction. Files that are already in the repo are skipped. Returns the number of items
+    /// added.
+    pub(crate) fn insert_imported_files(
+        &self,
+        files: &[ImportedFile],
+    ) -> Result<usize, SubtreeError> {
+        self.check_writable()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO items (path, tags, meta_tags, updated_at)
+                 VALUES (?1, ?2, meta_tags(?1), ?3)",
+            )?;
+            for file in files {
+                let tags = prepare_tags(file.tags.as_str(), self.config.case_insensitive_tags);
+                count += stmt.execute(params![
+                    file.path.as_str(),
+                    tags.join(" "),
+                    unix_timestamp_ms()
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Items whose files no longer exist, sorted by path. Unlike syncing, this only checks the
+    /// stored paths and doesn't remove anything, so the user can decide what to do with them.
+    pub fn missing_items(&self) -> Result<Vec<Item>, rusqlite::Error> {
+        let mut items: Vec<_> = self
+            .all_items()?
+            .into_iter()
+            .filter(|item| {
+                let path = RelativePath::new(&item.path).to_path(&self.path);
+                !path.exists()
+            })
+            .collect();
+        items.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        Ok(items)
+    }
+
+    /// Move an item to the file at `new_path`, e.g. after its file was moved outside of the app.
+    /// The item keeps its tags. If `new_path` already has an item without tags, e.g. because the
+    /// moved file was found by a sync, that item is replaced.
+    pub fn relocate_item(&self, id: i64, new_path: impl AsRef<str>) -> Result<(), RelocateError> {
+        self.check_writable()?;
+        let new_path = new_path.as_ref();
+        let full_path = RelativePath::new(new_path).to_path(&self.path);
+        if !full_path.is_file() {
+            return Err(RelocateError::FileNotFound(new_path.to_string()));
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let existing: Option<(i64, String)> = tx
+            .query_row(
+                &format!(
+                    "SELECT id, tags FROM items WHERE path = ?1 COLLATE {}",
+                    self.path_collation()
+                ),
+                [new_path],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match existing {
+            Some((existing_id, _)) if existing_id == id => return Ok(()),
+            Some((_, tags)) if !tags.is_empty() => {
+                return Err(RelocateError::PathTaken(new_path.to_string()))
+            }
+            Some((existing_id, _)) => {
+                tx.execute("DELETE FROM items WHERE id = ?1", [existing_id])?;
+            }
+            None => {}
+        }
+        // the file may not have the same contents, so it will be hashed again
+        let count = tx.execute(
+            "UPDATE items SET path = ?2, meta_tags = meta_tags(?2), mtime = ?3, size = NULL, hash = NULL
+             WHERE id = ?1",
+            params![id, new_path, file_mtime(&full_path)],
+        )?;
+        if count == 0 {
+            return Err(RelocateError::ItemNotFound(id));
+        }
+        clear_file_data(&tx, id)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Match items whose files are missing to the files they were probably moved to. `paths`
+    /// should be every file in the repo, e.g. from `scan_dir()`, files of items with tags are
+    /// never suggested. Nothing is changed, use `relocate_item()` to apply a suggestion.
+    ///
+    /// Items that have been hashed are matched to files with the same contents, and files are only
+    /// hashed if they have the same size. Other items are matched by file name, as long as the name
+    /// is unique among both the missing items and the files. Each file is suggested at most once.
+    pub fn suggest_relocations(
+        &self,
+        paths: impl IntoIterator<Item = RelativePathBuf>,
+    ) -> Result<Vec<Relocation>, rusqlite::Error> {
+        let missing = self.missing_items()?;
+        if missing.is_empty() {
+            return Ok(vec![]);
+        }
+        let case_insensitive = self.config.case_insensitive_paths;
+        let key = |path: &str| {
+            if case_insensitive {
+                path.to_lowercase()
+            } else {
+                path.to_string()
+            }
+        };
+        let name_of = |path: &str| key(RelativePath::new(path).file_name().unwrap_or(path));
+
+        let tagged: HashSet<String> = {
+            let mut stmt = self
+                .conn
+                .prepare_cached("SELECT path FROM items WHERE tags != ''")?;
+            let rows: Result<Vec<_>, _> =
+                stmt.query_map([], |row| row.get::<_, String>(0))?.collect();
+            rows?.iter().map(|path| key(path)).collect()
+        };
+        let candidates: Vec<RelativePathBuf> = paths
+            .into_iter()
+            .filter(|path| !tagged.contains(&key(path.as_str())))
+            .collect();
+
+        let mut hashed = vec![];
+        {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT size, hash FROM items WHERE id = ?1 AND size IS NOT NULL AND hash IS NOT NULL",
+            )?;
+            for item in &missing {
+                let row = stmt
+                    .query_row([item.id], |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                    })
+                    .optional()?;
+                if let Some((size, hash)) = row {
+                    hashed.push((item, size, hash));
+                }
+            }
+        }
+
+        let mut used = vec![false; candidates.len()];
+        let mut relocations = vec![];
+        let mut matched = HashSet::new();
+        if !hashed.is_empty() {
+            let mut by_size: HashMap<i64, Vec<usize>> = HashMap::new();
+            for (i, path) in candidates.iter().enumerate() {
+                if let Ok(metadata) = fs::metadata(path.to_path(&self.path)) {
+                    by_size.entry(metadata.len() as i64).or_default().push(i);
+                }
+            }
+            let mut hashes: HashMap<usize, Option<String>> = HashMap::new();
+            for (item, size, hash) in hashed {
+                let Some(indexes) = by_size.get(&size) else {
+                    continue;
+                };
+                for &i in indexes {
+                    if used[i] {
+                        continue;
+                    }
+                    let new_hash = hashes
+                        .entry(i)
+                        .or_insert_with(|| hash_file(candidates[i].to_path(&self.path)).ok());
+                    if new_hash.as_ref() == Some(&hash) {
+                        used[i] = true;
+                        matched.insert(item.id);
+                        relocations.push(Relocation {
+                            id: item.id,
+                            old_path: item.path.clone(),
+                            new_path: candidates[i].to_string(),
+                            matched_by: RelocationMatch::Hash,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, path) in candidates.iter().enumerate() {
+            if !used[i] {
+                by_name.entry(name_of(path.as_str())).or_default().push(i);
+            }
+        }
+        let mut missing_names: HashMap<String, usize> = HashMap::new();
+        for item in missing.iter().filter(|x| !matched.contains(&x.id)) {
+            *missing_names.entry(name_of(&item.path)).or_default() += 1;
+        }
+        for item in missing.iter().filter(|x| !matched.contains(&x.id)) {
+            let name = name_of(&item.path);
+            if missing_names[&name] != 1 {
+                continue;
+            }
+            if let Some([i]) = by_name.get(&name).map(Vec::as_slice) {
+                relocations.push(Relocation {
+                    id: item.id,
+                    old_path: item.path.clone(),
+                    new_path: candidates[*i].to_string(),
+                    matched_by: RelocationMatch::Name,
+                });
+            }
+        }
+
+        relocations.sort_unstable_by(|a, b| a.old_path.cmp(&b.old_path));
+        Ok(relocations)
+    }
+
+    pub fn all_folders(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let sql = "SELECT DISTINCT i.dir FROM items i ORDER BY i.dir";
         let mut stmt = self.conn.prepare_cached(sql)?;
         let mapped_rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
         let items: Result<Vec<_>, _> = mapped_rows.collect();
         Ok(items?)
     }
 
+    /// Return the names of the folders directly inside the repo root that contain items.
+    pub(crate) fn top_level_folders(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let folders = self
+            .all_folders()?
+            .into_iter()
+            .filter_map(|folder| {
+                let name = folder.split('/').next()?;
+                (!name.is_empty()).then(|| name.to_string())
+            })
+            .unique()
+            .collect();
+        Ok(folders)
+    }
+
+    /// The folders containing items, with the number of items inside each folder.
     pub fn dir_structure(&self) -> Result<FolderBuf, DirStructureError> {
-        let paths = self.all_folders()?;
-        let dirs = from_ordered_paths(&paths).map_err(|x| match x {
+        let sql = "SELECT i.dir, COUNT(*) FROM items i GROUP BY i.dir";
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let mapped_rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+        let counts: Vec<(String, usize)> = mapped_rows.collect::<Result<_, _>>()?;
+        let dirs = from_ordered_paths(&counts).map_err(|x| match x {
             PathTreeError::MalformedPath(path) => DirStructureError::MalformedPath(path),
         })?;
         Ok(dirs)
     }
 
+    /// The folders directly inside the folder at `path`, sorted by name. Only folders that contain
+    /// items are listed, an empty path lists the folders in the repo root.
+    pub fn dir_children(&self, path: &str) -> Result<Vec<DirChild>, rusqlite::Error> {
+        let path = path.trim_matches('/');
+        // every folder beneath `path` is in the range [prefix, upper)
+        let (prefix, upper) = match path.is_empty() {
+            true => (String::new(), None),
+            false => (format!("{}/", path), Some(format!("{}0", path))),
+        };
+        let sql = match upper {
+            Some(_) => indoc! {"
+                SELECT dir, COUNT(*) FROM items
+                WHERE dir >= ?1 AND dir < ?2
+                GROUP BY dir
+            "},
+            None => indoc! {"
+                SELECT dir, COUNT(*) FROM items
+                WHERE dir > ?1
+                GROUP BY dir
+            "},
+        };
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let params = std::iter::once(prefix.clone()).chain(upper);
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+        let mut children: BTreeMap<String, DirChild> = BTreeMap::new();
+        let mut subfolders = HashSet::new();
+        while let Some(row) = rows.next()? {
+            let dir: String = row.get(0)?;
+            let count: usize = row.get(1)?;
+            let rest = &dir[prefix.len()..];
+            let (name, subfolder) = match rest.split_once('/') {
+                Some((name, rest)) => (name, rest.split('/').next()),
+                None => (rest, None),
+            };
+            let child = children
+                .entry(name.to_string())
+                .or_insert_with(|| DirChild {
+                    name: name.to_string(),
+                    direct: 0,
+                    recursive: 0,
+                    subfolders: 0,
+                });
+            child.recursive += count;
+            match subfolder {
+                Some(subfolder) => {
+                    if subfolders.insert((name.to_string(), subfolder.to_string())) {
+                        child.subfolders += 1;
+                    }
+                }
+                None => child.direct += count,
+            }
+        }
+        Ok(children.into_values().collect())
+    }
+
+    /// Same as `dir_structure()`, but the tree can be updated as items are added and removed.
+    pub fn dir_tree(&self) -> Result<DirTree, DirStructureError> {
+        Ok(DirTree::new(self.dir_structure()?))
+    }
+
     #[tracing::instrument(skip(new_paths))]
     pub fn sync(
         &mut self,
         new_paths: impl IntoIterator<Item = RelativePathBuf>,
-    ) -> Result<(), SyncError> {
+    ) -> Result<SyncReport, SyncError> {
+        self.sync_with_progress(new_paths, |_| {})
+    }
+
+    /// Same as `sync()`, but calls `on_progress` after each batch of changes is committed.
+    pub(crate) fn sync_with_progress(
+        &mut self,
+        new_paths: impl IntoIterator<Item = RelativePathBuf>,
+        on_progress: impl FnMut(SyncProgress),
+    ) -> Result<SyncReport, SyncError> {
+        let delta = self.plan_sync(new_paths)?;
+        self.apply_sync(&delta, on_progress)?;
+        Ok(delta.report())
+    }
+
+    /// Compute the changes that `sync()` would make, without modifying the database.
+    pub(crate) fn plan_sync(
+        &self,
+        new_paths: impl IntoIterator<Item = RelativePathBuf>,
+    ) -> Result<SyncDelta, SyncError> {
+        let _perf = perf::measure("sync.plan");
         let old_paths: HashSet<RelativePathBuf> = self
             .all_items()?
             .into_iter()
             .map(|x| RelativePathBuf::from(x.path))
             .collect();
         let new_paths: HashSet<RelativePathBuf> = new_paths.into_iter().collect();
+        self.diff_paths(&old_paths, &new_paths)
+    }
+
+    /// Start staging the scanned paths of a sync, see `stage_sync_paths()`. Paths staged by an
+    /// earlier sync are discarded.
+    pub(crate) fn begin_staged_sync(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(indoc! {"
+            CREATE TEMP TABLE IF NOT EXISTS staged_paths (path TEXT PRIMARY KEY) WITHOUT ROWID;
+            DELETE FROM temp.staged_paths;
+        "})
+    }
+
+    /// Store a batch of scanned paths in a temporary table, so a sync can be staged while the
+    /// folder is still being scanned. The staged paths are synced with `plan_staged_sync()`.
+    pub(crate) fn stage_sync_paths(
+        &mut self,
+        paths: impl IntoIterator<Item = RelativePathBuf>,
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt =
+                tx.prepare_cached("INSERT OR IGNORE INTO temp.staged_paths (path) VALUES (?1)")?;
+            for path in paths {
+                stmt.execute([path.as_str()])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Compute the changes that syncing the staged paths would make, like `plan_sync()`. Only the
+    /// paths that are in either the repo or the staged paths are read, renames are only detected
+    /// between those.
+    pub(crate) fn plan_staged_sync(&self) -> Result<SyncDelta, SyncError> {
+        let _perf = perf::measure("sync.plan");
+        let read_paths = |sql: &str| -> rusqlite::Result<HashSet<RelativePathBuf>> {
+            let mut stmt = self.conn.prepare(sql)?;
+            let paths = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .map(|path| path.map(RelativePathBuf::from))
+                .collect();
+            paths
+        };
+        // paths are compared exactly here, case-only renames are detected by `diff_paths()`
+        let old_paths = read_paths(indoc! {"
+            SELECT i.path FROM items i
+            WHERE NOT EXISTS (
+                SELECT 1 FROM temp.staged_paths s WHERE s.path = i.path COLLATE BINARY
+            )
+        "})?;
+        let new_paths = read_paths(indoc! {"
+            SELECT s.path FROM temp.staged_paths s
+            WHERE NOT EXISTS (SELECT 1 FROM items i WHERE i.path = s.path COLLATE BINARY)
+        "})?;
+        self.diff_paths(&old_paths, &new_paths)
+    }
+
+    /// Discard the staged paths of a sync, once it has been applied or rejected.
+    pub(crate) fn clear_staged_sync(&self) -> rusqlite::Result<()> {
+        self.conn
+            .execute_batch("DROP TABLE IF EXISTS temp.staged_paths")
+    }
+
+    /// Sync only the items in the given subfolder. `new_paths` must be the paths currently in the
+    /// subfolder, e.g. from `scan_subdir()`. If `recursive` is false, only items directly inside
+    /// the subfolder are synced.
+    ///
+    /// Renames are only detected within the subfolder, a file moved to another subfolder is
+    /// treated as a deletion.
+    #[tracing::instrument(skip(new_paths))]
+    pub fn sync_subdir(
+        &mut self,
+        subdir: &RelativePath,
+        recursive: bool,
+        new_paths: impl IntoIterator<Item = RelativePathBuf>,
+    ) -> Result<SyncDelta, SyncError> {
+        let delta = self.plan_sync_subdir(subdir, recursive, new_paths)?;
+        self.apply_sync(&delta, |_| {})?;
+        Ok(delta)
+    }
+
+    /// Compute the changes that `sync_subdir()` would make, without modifying the database.
+    pub(crate) fn plan_sync_subdir(
+        &self,
+        subdir: &RelativePath,
+        recursive: bool,
+        new_paths: impl IntoIterator<Item = RelativePathBuf>,
+    ) -> Result<SyncDelta, SyncError> {
+        let prefix = if subdir.as_str().is_empty() {
+            String::new()
+        } else {
+            format!("{}/", subdir)
+        };
+        let old_paths: HashSet<RelativePathBuf> = {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT i.path FROM items i WHERE substr(i.path, 1, length(?1)) = ?1",
+            )?;
+            let rows: Result<Vec<_>, _> = stmt
+                .query_map(params![prefix], |row| row.get::<_, String>(0))?
+                .collect();
+            rows?
+                .into_iter()
+                .filter(|path| recursive || !path[prefix.len()..].contains('/'))
+                .map(RelativePathBuf::from)
+                .collect()
+        };
+        let new_paths: HashSet<RelativePathBuf> = new_paths.into_iter().collect();
+        self.diff_paths(&old_paths, &new_paths)
+    }
+
+    /// Diff the old and new paths, and count the deleted items that have tags.
+    fn diff_paths(
+        &self,
+        old_paths: &HashSet<RelativePathBuf>,
+        new_paths: &HashSet<RelativePathBuf>,
+    ) -> Result<SyncDelta, SyncError> {
         debug!("unique old paths: {}", old_paths.len());
         debug!("unique new paths: {}", new_paths.len());
 
-        let path_diff = diff_path_list(&old_paths, &new_paths)?;
+        let path_diff = diff_path_list(old_paths, new_paths, self.config.case_insensitive_paths)?;
         debug!(
             "diff: created={}, deleted={}, renamed={}",
             path_diff.created.len(),
@@ -561,32 +2709,403 @@ impl Repo {
             path_diff.renamed.len(),
         );
 
-        let tx = self.conn.transaction()?;
-        {
-            // delete old paths
-            let mut stmt = tx.prepare_cached("DELETE FROM items WHERE path = :path")?;
-            for path in &path_diff.deleted {
-                stmt.execute(params![path.as_str()])?;
-            }
-            // create new paths
-            let mut stmt = tx.prepare_cached("INSERT INTO items (path, tags) VALUES (?1, ?2)")?;
-            for path in &path_diff.created {
-                stmt.execute(params![path.as_str(), ""])?;
+        let mut retagged_lost = 0;
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT i.tags != '' FROM items i WHERE i.path = ?1")?;
+        for path in &path_diff.deleted {
+            let has_tags = stmt
+                .query_row(params![path.as_str()], |row| row.get::<_, bool>(0))
+                .optional()?;
+            if has_tags == Some(true) {
+                retagged_lost += 1;
             }
-            // rename existing paths
-            let mut stmt = tx.prepare_cached("UPDATE items SET path = ?2 WHERE path = ?1")?;
-            for (from, to) in &path_diff.renamed {
-                stmt.execute(params![from.as_str(), to.as_str()])?;
+        }
+
+        Ok(SyncDelta {
+            created: path_diff.created.iter().map(|x| x.to_string()).collect(),
+            deleted: path_diff.deleted.iter().map(|x| x.to_string()).collect(),
+            renamed: path_diff
+                .renamed
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            retagged_lost,
+        })
+    }
+
+    /// Update the database with the changes from `plan_sync()`.
+    ///
+    /// Changes are committed in chunks of `SYNC_CHUNK_SIZE`, so large syncs don't hold a single
+    /// huge transaction. If a sync fails partway, the committed chunks are kept and the next sync
+    /// picks up the remaining changes.
+    pub(crate) fn apply_sync(
+        &mut self,
+        delta: &SyncDelta,
+        mut on_progress: impl FnMut(SyncProgress),
+    ) -> Result<(), SyncError> {
+        self.check_writable()?;
+        let _perf = perf::measure("sync.apply");
+        let rule_set = self.rule_set()?;
+        let tag_memory = self.tag_memory_enabled();
+        let collation = self.path_collation();
+        let case_insensitive = self.config.case_insensitive_tags;
+        let now = unix_timestamp_ms();
+        self.prune_tombstones()?;
+        // only look for tombstones if there are any, since matching them requires reading the
+        // metadata of every created file
+        let has_tombstones = tag_memory
+            && (delta.retagged_lost > 0
+                || self
+                    .conn
+                    .query_row("SELECT EXISTS (SELECT 1 FROM tombstones)", [], |row| {
+                        row.get::<_, bool>(0)
+                    })?);
+
+        // old paths must be deleted first, before new paths are created
+        let ops: Vec<_> = delta
+            .deleted
+            .iter()
+            .map(|path| SyncOp::Delete(path))
+            .chain(delta.created.iter().map(|path| SyncOp::Create(path)))
+            .chain(
+                delta
+                    .renamed
+                    .iter()
+                    .map(|(from, to)| SyncOp::Rename(from, to)),
+            )
+            .collect();
+
+        let mut done = 0;
+        for chunk in ops.chunks(SYNC_CHUNK_SIZE) {
+            let tx = self.conn.transaction()?;
+            {
+                let mut delete_stmt = tx.prepare_cached("DELETE FROM items WHERE path = ?1")?;
+                // a `NULL` uid is replaced with a new one by the `items_uid_ai` trigger
+                let mut create_stmt = tx.prepare_cached(
+                    "INSERT INTO items (path, tags, meta_tags, size, hash, updated_at, uid)
+                     VALUES (?1, ?2, meta_tags(?1), ?3, ?4, ?5, ?6)",
+                )?;
+                let mut rename_stmt = tx.prepare_cached(
+                    "UPDATE items SET path = ?2, meta_tags = meta_tags(?2) WHERE path = ?1",
+                )?;
+                for op in chunk {
+                    match *op {
+                        SyncOp::Delete(path) => {
+                            if tag_memory {
+                                write_tombstone(&tx, path)?;
+                            }
+                            delete_stmt.execute(params![path])?;
+                        }
+                        SyncOp::Create(path) => {
+                            let mut tags = rule_set.tags_for(RelativePath::new(path));
+                            let mut hash = None;
+                            let mut uid = None;
+                            if has_tombstones {
+                                if let Some(restored) =
+                                    take_tombstone(&tx, &self.path, path, collation)?
+                                {
+                                    tags.extend(restored.tags);
+                                    hash = restored.hash;
+                                    uid = restored.uid;
+                                }
+                            }
+                            let tags = prepare_tags(tags, case_insensitive)
+                                .into_iter()
+                                .dedup()
+                                .join(" ");
+                            let (size, hash) = hash.unzip();
+                            create_stmt.execute(params![path, tags, size, hash, now, uid])?;
+                        }
+                        SyncOp::Rename(from, to) => {
+                            rename_stmt.execute(params![from, to])?;
+                        }
+                    }
+                }
             }
+            tx.commit()?;
+            done += chunk.len();
+            on_progress(SyncProgress { done, total: ops.len() });
         }
-        tx.commit()?;
+
         Ok(())
     }
 
     pub fn sync_all(&mut self) -> Result<(), SyncError> {
-        self.sync(scan_dir(&self.path, Options::default()).unwrap())?;
+        self.sync(scan_dir(&self.path, self.config.scan_options())?)?;
+        Ok(())
+    }
+
+    /// Rescan a folder and its subfolders, then sync only the items inside it. Much cheaper than
+    /// `sync_all()` when only one folder may have changed. If the folder no longer exists, all its
+    /// items are removed. Modified files are also detected, see `refresh_mtimes()`.
+    pub fn sync_subtree(&mut self, subdir: &RelativePath) -> Result<SyncDelta, SyncError> {
+        let new_paths = scan_subdir(&self.path, subdir, true, self.config.scan_options())?;
+        let delta = self.sync_subdir(subdir, true, new_paths)?;
+        self.refresh_mtimes(subdir, true)?;
+        Ok(delta)
+    }
+
+    /// Checkpoint the WAL file, rebuild the database file to reclaim unused space, then update
+    /// the statistics used by the query planner. This may take a while on large repos.
+    pub(crate) fn maintenance(&self) -> Result<(), MaintenanceError> {
+        self.check_writable()?;
+        // this pragma returns a row, so it can't be run with `execute`
+        self.prune_tombstones()?;
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        self.conn.execute_batch("VACUUM; ANALYZE;")?;
+        Ok(())
+    }
+
+    /// Common function used to convert a query row into a rule.
+    ///
+    /// Queried columns must be:
+    ///
+    /// ```sql
+    /// SELECT r.id, r.conditions, r.tags, r.enabled
+    /// ```
+    fn row_to_rule(row: &Row) -> Result<(i64, String, String, bool), rusqlite::Error> {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, bool>(3)?,
+        ))
+    }
+
+    pub(crate) fn rules(&self) -> Result<Vec<Rule>, RuleError> {
+        let sql = "SELECT r.id, r.conditions, r.tags, r.enabled FROM rules r ORDER BY r.id";
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows: Result<Vec<_>, _> = stmt.query_map([], Self::row_to_rule)?.collect();
+        let mut rules = vec![];
+        for (id, conditions, tags, enabled) in rows? {
+            let conditions = serde_json::from_str(&conditions)?;
+            let tags = Self::convert_raw_tags(tags);
+            rules.push(Rule { id, conditions, tags, enabled });
+        }
+        Ok(rules)
+    }
+
+    pub(crate) fn get_rule(&self, id: i64) -> Result<Rule, RuleError> {
+        self.rules()?
+            .into_iter()
+            .find(|rule| rule.id == id)
+            .ok_or(RuleError::RuleNotFound)
+    }
+
+    /// Compile all enabled rules in the repo. If auto-tagging is disabled in the repo config,
+    /// this returns an empty rule set.
+    pub(crate) fn rule_set(&self) -> Result<RuleSet, RuleError> {
+        if !self.config.auto_tag {
+            return Ok(RuleSet::empty());
+        }
+        RuleSet::new(&self.rules()?)
+    }
+
+    pub(crate) fn insert_rule(
+        &self,
+        conditions: Vec<Condition>,
+        tags: impl IntoTags,
+    ) -> Result<Rule, RuleError> {
+        self.check_writable()?;
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
+        validate_rule(&conditions, &tags)?;
+        self.conn.execute(
+            "INSERT INTO rules (conditions, tags) VALUES (?1, ?2)",
+            params![serde_json::to_string(&conditions)?, tags.join(" ")],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.get_rule(id)
+    }
+
+    pub(crate) fn update_rule(
+        &self,
+        id: i64,
+        conditions: Vec<Condition>,
+        tags: impl IntoTags,
+        enabled: bool,
+    ) -> Result<Rule, RuleError> {
+        self.check_writable()?;
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
+        validate_rule(&conditions, &tags)?;
+        let updated = self.conn.execute(
+            "UPDATE rules SET conditions = ?2, tags = ?3, enabled = ?4 WHERE id = ?1",
+            params![
+                id,
+                serde_json::to_string(&conditions)?,
+                tags.join(" "),
+                enabled
+            ],
+        )?;
+        if updated == 0 {
+            return Err(RuleError::RuleNotFound);
+        }
+        self.get_rule(id)
+    }
+
+    pub(crate) fn remove_rule(&self, id: i64) -> Result<(), RuleError> {
+        self.check_writable()?;
+        let removed = self
+            .conn
+            .execute("DELETE FROM rules WHERE id = :id", [id])?;
+        if removed == 0 {
+            return Err(RuleError::RuleNotFound);
+        }
         Ok(())
     }
+
+    /// Dry-run a rule against all items in the repo. Returns the items that would receive new
+    /// tags from this rule, without modifying the database.
+    pub(crate) fn preview_rule(
+        &self,
+        conditions: &Vec<Condition>,
+        tags: impl IntoTags,
+    ) -> Result<Vec<RuleMatch>, RuleError> {
+        let tags = prepare_tags(tags, self.config.case_insensitive_tags);
+        validate_rule(conditions, &tags)?;
+        let rule_set = RuleSet::single(conditions, &tags)?;
+        let mut matches = vec![];
+        for item in self.all_items()? {
+            let new_tags: Vec<_> = rule_set
+                .tags_for(RelativePath::new(&item.path))
+                .into_iter()
+                .filter(|tag| !item.tags.contains(tag))
+                .collect();
+            if !new_tags.is_empty() {
+                matches.push(RuleMatch { id: item.id, path: item.path, new_tags });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Dry-run a tag pattern, returning the items that would be tagged by it. See `TagPattern`
+    /// for the pattern syntax.
+    pub(crate) fn preview_tag_pattern(
+        &self,
+        pattern: &str,
+    ) -> Result<Vec<PatternMatch>, TagPatternError> {
+        let pattern = TagPattern::new(pattern)?;
+        let mut matches = vec![];
+        for item in self.all_items()? {
+            let Some(tags) = pattern.tags_for(RelativePath::new(&item.path)) else {
+                continue;
+            };
+            let new_tags: Vec<_> = prepare_tags(tags, self.config.case_insensitive_tags)
+                .into_iter()
+                .filter(|tag| !item.tags.contains(tag))
+                .collect();
+            if !new_tags.is_empty() {
+                matches.push(PatternMatch { id: item.id, path: item.path, new_tags });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Add the tags extracted by a tag pattern to all matching items, returning the items that
+    /// were tagged.
+    pub(crate) fn import_tags_from_pattern(
+        &mut self,
+        pattern: &str,
+    ) -> Result<Vec<PatternMatch>, TagPatternError> {
+        self.check_writable()?;
+        let matches = self.preview_tag_pattern(pattern)?;
+        let tx = self.conn.transaction()?;
+        for m in &matches {
+            let sql = format!(
+                "UPDATE items SET tags = insert_tags(tags, {}) WHERE id = ?",
+                repeat_vars(m.new_tags.len()),
+            );
+            // converting item_id to a string is fine, sqlite converts types dynamically
+            let item_id = m.id.to_string();
+            tx.execute(
+                &sql,
+                rusqlite::params_from_iter(m.new_tags.iter().chain(Some(&item_id))),
+            )?;
+        }
+        tx.commit()?;
+        Ok(matches)
+    }
+
+    /// Apply tags read from a CSV file with rows like `path,tag1 tag2`, see `parse_csv()`. All rows
+    /// are applied in a single transaction. Rows that can't be applied, e.g. because the item
+    /// doesn't exist, are skipped and listed in the report.
+    pub(crate) fn import_tags_csv(
+        &self,
+        rows: &[CsvRow],
+        options: CsvImportOptions,
+    ) -> Result<CsvImportReport, CsvImportError> {
+        self.check_writable()?;
+        let mut report = CsvImportReport::default();
+        let tx = self.conn.unchecked_transaction()?;
+        for (i, row) in rows.iter().enumerate() {
+            if i == 0 && is_header(row) {
+                continue;
+            }
+            match self.import_csv_row(row, options.replace)? {
+                Ok(true) => report.imported += 1,
+                Ok(false) => report.unchanged += 1,
+                Err(error) => report.errors.push(CsvRowError {
+                    row: row.row,
+                    path: row.fields[0].clone(),
+                    error,
+                }),
+            }
+        }
+        // a dry run is rolled back, so the report is the same as a real import
+        if !options.dry_run {
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    /// Apply the tags in a single CSV row. Returns whether the item's tags changed, or a
+    /// description of why the row can't be applied.
+    fn import_csv_row(
+        &self,
+        row: &CsvRow,
+        replace: bool,
+    ) -> Result<Result<bool, String>, rusqlite::Error> {
+        let path = match resolve_path(row.fields[0].trim(), &self.path) {
+            Ok(path) => path,
+            Err(err) => return Ok(Err(err.to_string())),
+        };
+        // extra columns are treated as more tags, in case each tag was put in its own cell
+        let tags = match self.prepare_new_tags(row.fields[1..].join(" ")) {
+            Ok(tags) => tags,
+            Err(err) => return Ok(Err(err.to_string())),
+        };
+        let sql = format!(
+            "SELECT id FROM items WHERE path = ? COLLATE {}",
+            self.path_collation()
+        );
+        let Some(item_id) = self
+            .conn
+            .query_row(&sql, [path.as_str()], |row| row.get::<_, i64>(0))
+            .optional()?
+        else {
+            return Ok(Err(String::from("item not found")));
+        };
+        let count = if replace {
+            self.conn.execute(
+                "UPDATE items SET tags = ?2 WHERE id = ?1 AND tags != ?2",
+                params![item_id, tags.join(" ")],
+            )?
+        } else if tags.is_empty() {
+            0
+        } else {
+            let sql = format!(
+                "UPDATE items SET tags = insert_tags(tags, {0}) WHERE id = ? AND tags != insert_tags(tags, {0})",
+                repeat_vars(tags.len()),
+            );
+            let item_id = item_id.to_string();
+            self.conn.execute(
+                &sql,
+                rusqlite::params_from_iter(tags.iter().chain(Some(&item_id)).chain(tags.iter())),
+            )?
+        };
+        Ok(Ok(count > 0))
+    }
 }
 
 lazy_static! {
@@ -595,6 +3114,44 @@ lazy_static! {
         Migrations::new(vec![
             M::up(include_str!("migrations/01u_initial.sql"))
             .down(include_str!("migrations/01d_initial.sql")),
+            M::up(include_str!("migrations/02u_rules.sql"))
+            .down(include_str!("migrations/02d_rules.sql")),
+            M::up(include_str!("migrations/03u_tombstones.sql"))
+            .down(include_str!("migrations/03d_tombstones.sql")),
+            M::up(include_str!("migrations/04u_unicode_tags.sql"))
+            .down(include_str!("migrations/04d_unicode_tags.sql")),
+            M::up(include_str!("migrations/05u_tag_trigrams.sql"))
+            .down(include_str!("migrations/05d_tag_trigrams.sql")),
+            M::up(include_str!("migrations/06u_change_feed.sql"))
+            .down(include_str!("migrations/06d_change_feed.sql")),
+            M::up(include_str!("migrations/07u_mtime.sql"))
+            .down(include_str!("migrations/07d_mtime.sql")),
+            M::up(include_str!("migrations/08u_tag_tables.sql"))
+            .down(include_str!("migrations/08d_tag_tables.sql")),
+            M::up(include_str!("migrations/09u_folder_tags.sql"))
+            .down(include_str!("migrations/09d_folder_tags.sql")),
+            M::up(include_str!("migrations/10u_collections.sql"))
+            .down(include_str!("migrations/10d_collections.sql")),
+            M::up(include_str!("migrations/11u_tag_history.sql"))
+            .down(include_str!("migrations/11d_tag_history.sql")),
+            M::up(include_str!("migrations/12u_fingerprints.sql"))
+            .down(include_str!("migrations/12d_fingerprints.sql")),
+            M::up(include_str!("migrations/13u_item_content.sql"))
+            .down(include_str!("migrations/13d_item_content.sql")),
+            M::up(include_str!("migrations/14u_item_updated_at.sql"))
+            .down(include_str!("migrations/14d_item_updated_at.sql")),
+            M::up(include_str!("migrations/15u_meta_tags.sql"))
+            .down(include_str!("migrations/15d_meta_tags.sql")),
+            M::up(include_str!("migrations/16u_activity_log.sql"))
+            .down(include_str!("migrations/16d_activity_log.sql")),
+            M::up(include_str!("migrations/17u_dirname_index.sql"))
+            .down(include_str!("migrations/17d_dirname_index.sql")),
+            M::up(include_str!("migrations/18u_dir_ext_columns.sql"))
+            .down(include_str!("migrations/18d_dir_ext_columns.sql")),
+            M::up(include_str!("migrations/19u_item_uid.sql"))
+            .down(include_str!("migrations/19d_item_uid.sql")),
+            M::up(include_str!("migrations/20u_loudness.sql"))
+            .down(include_str!("migrations/20d_loudness.sql")),
         ]);
 }
 
@@ -611,6 +3168,18 @@ fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
             Ok(result)
         },
     )?;
+    conn.create_scalar_function(
+        "normalize_tags",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+
+            let tags = ctx.get::<String>(0)?;
+            let case_insensitive = ctx.get::<bool>(1)?;
+            Ok(prepare_tags(tags, case_insensitive).join(" "))
+        },
+    )?;
     conn.create_scalar_function(
         "insert_tags",
         -1,
@@ -659,6 +3228,31 @@ fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
             Ok(old_tags.join(" "))
         },
     )?;
+    conn.create_scalar_function(
+        "split_tags",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
+
+            // convert to a JSON array, so the tags can be read with `json_each()`
+            let tags = ctx.get::<String>(0)?;
+            let tags: Vec<_> = tags.split_whitespace().collect();
+            serde_json::to_string(&tags)
+                .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))
+        },
+    )?;
+    conn.create_scalar_function(
+        "meta_tags",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
+
+            let path = ctx.get::<String>(0)?;
+            Ok(meta_tags(&path))
+        },
+    )?;
     conn.create_scalar_function(
         "dirname",
         1,
@@ -673,6 +3267,21 @@ fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
             Ok(parent.to_str().unwrap().to_string())
         },
     )?;
+    conn.create_scalar_function(
+        "basename",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
+
+            let fullpath = ctx.get::<String>(0)?;
+            let fullpath: &Path = fullpath.as_ref();
+            match fullpath.file_name() {
+                None => Ok(String::from("")),
+                Some(name) => Ok(name.to_str().unwrap().to_string()),
+            }
+        },
+    )?;
     conn.create_scalar_function(
         "extname",
         1,
@@ -691,22 +3300,246 @@ fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
     Ok(())
 }
 
-pub(crate) fn open_database(db_path: impl AsRef<Path>) -> Result<Connection, OpenError> {
-    let db_path = db_path.as_ref();
-    let mut conn = Connection::open(db_path).map_err(OpenError::FailedToCreateDatabase)?;
+impl Drop for Repo {
+    fn drop(&mut self) {
+        if let Some(lock) = &self.lock {
+            lock.release(self.path.join(".tagrepo"));
+        }
+    }
+}
 
-    // https://www.sqlite.org/pragma.html
-    // WAL is somehow slower. Play around with the benchmark test at the bottom of this file.
-    conn.pragma_update(None, "journal_mode", "WAL").unwrap();
-    conn.pragma_update(None, "foreign_keys", "ON").unwrap();
-    conn.pragma_update(None, "synchronous", "FULL").unwrap();
-    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")
-        .unwrap();
-    conn.pragma_update(None, "case_sensitive_like", false)
-        .unwrap();
+/// Last modification time of a file in milliseconds since the unix epoch.
+fn file_mtime(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let millis = modified.duration_since(UNIX_EPOCH).ok()?.as_millis();
+    i64::try_from(millis).ok()
+}
+
+/// Store the modification time of an item's file, see `Repo::refresh_mtime()`. Returns whether the
+/// file was modified.
+fn update_mtime(
+    conn: &Connection,
+    id: i64,
+    old_mtime: Option<i64>,
+    new_mtime: i64,
+) -> rusqlite::Result<bool> {
+    match old_mtime {
+        Some(old_mtime) if new_mtime > old_mtime => {
+            conn.execute(
+                "UPDATE items SET mtime = ?2, size = NULL, hash = NULL WHERE id = ?1",
+                params![id, new_mtime],
+            )?;
+            clear_file_data(conn, id)?;
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        None => {
+            conn.execute(
+                "UPDATE items SET mtime = ?2 WHERE id = ?1",
+                params![id, new_mtime],
+            )?;
+            Ok(false)
+        }
+    }
+}
+
+/// Remove the data computed from an item's file, after the file is modified or replaced. It is
+/// computed again in the background.
+fn clear_file_data(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM fingerprints WHERE item_id = ?1", [id])?;
+    conn.execute("DELETE FROM loudness WHERE item_id = ?1", [id])?;
+    conn.execute("DELETE FROM item_content WHERE rowid = ?1", [id])?;
+    Ok(())
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/// The current time in milliseconds since the unix epoch, like `items.updated_at`.
+fn unix_timestamp_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Remember the tags of an item that is about to be deleted, so they can be restored if the file
+/// re-appears later, see `take_tombstone()`. Items without tags are skipped.
+fn write_tombstone(conn: &Connection, path: &str) -> rusqlite::Result<()> {
+    let name = RelativePath::new(path).file_name().unwrap_or(path);
+    let mut stmt = conn.prepare_cached(indoc! {"
+        INSERT INTO tombstones (name, size, hash, tags, deleted_at, uid)
+        SELECT ?2, i.size, i.hash, i.tags, ?3, i.uid FROM items i
+        WHERE i.path = ?1 AND i.tags != ''
+    "})?;
+    stmt.execute(params![path, name, unix_timestamp()])?;
+    Ok(())
+}
+
+/// Find the tombstone of a deleted item that matches the file at `path`, then remove the
+/// tombstone and return its tags and uid.
+///
+/// Tombstones are first matched by content hash, the new file is only hashed if a tombstone has
+/// the same file size. Otherwise, the most recent tombstone with the same file name is used, as
+/// long as the file size hasn't changed.
+fn take_tombstone(
+    conn: &Connection,
+    repo_path: &Path,
+    path: &str,
+    collation: &str,
+) -> rusqlite::Result<Option<RestoredTags>> {
+    let full_path = repo_path.join(path);
+    let size = fs::metadata(&full_path).ok().map(|x| x.len() as i64);
+
+    let mut found = None;
+    let mut hash = None;
+    if let Some(size) = size {
+        let mut stmt = conn.prepare_cached(indoc! {"
+            SELECT t.id, t.hash FROM tombstones t
+            WHERE t.size = ?1 AND t.hash IS NOT NULL
+            ORDER BY t.deleted_at DESC
+        "})?;
+        let candidates: Vec<(i64, String)> = stmt
+            .query_map([size], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        if !candidates.is_empty() {
+            if let Ok(new_hash) = hash_file(&full_path) {
+                found = candidates
+                    .iter()
+                    .find(|(_, old_hash)| *old_hash == new_hash)
+                    .map(|(id, _)| *id);
+                hash = Some((size, new_hash));
+            }
+        }
+    }
+    if found.is_none() {
+        // if both files were hashed, the hashes must match too
+        let name = RelativePath::new(path).file_name().unwrap_or(path);
+        let new_hash = hash.as_ref().map(|(_, hash)| hash);
+        found = conn
+            .query_row(
+                &format!(
+                    "SELECT t.id FROM tombstones t
+                    WHERE t.name = ?1 COLLATE {}
+                    AND (t.size IS NULL OR ?2 IS NULL OR t.size = ?2)
+                    AND (t.hash IS NULL OR ?3 IS NULL OR t.hash = ?3)
+                    ORDER BY t.deleted_at DESC
+                    LIMIT 1",
+                    collation
+                ),
+                params![name, size, new_hash],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?;
+    }
+    let Some(id) = found else {
+        return Ok(None);
+    };
+
+    let (tags, uid): (String, Option<String>) = conn.query_row(
+        "DELETE FROM tombstones WHERE id = ?1 RETURNING tags, uid",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    debug!("restored tags of {} from tombstone: {}", path, tags);
+    Ok(Some(RestoredTags { tags: tags.into_tags(), hash, uid }))
+}
+
+/// Add `has_path_prefix(path, prefix)`, used by `in:` and `leading:` queries, and
+/// `is_same_path(a, b)`, used by `children:` queries, which match the case sensitivity of paths.
+/// Other `LIKE` clauses, e.g. `ext:` and `name:`, are always case-insensitive.
+fn set_path_case(conn: &Connection, case_insensitive: bool) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "is_same_path",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+
+            let a = ctx.get::<String>(0)?;
+            let b = ctx.get::<String>(1)?;
+            Ok(match case_insensitive {
+                true => a.eq_ignore_ascii_case(&b),
+                false => a == b,
+            })
+        },
+    )?;
+    conn.create_scalar_function(
+        "has_path_prefix",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+
+            let path = ctx.get::<String>(0)?;
+            let prefix = ctx.get::<String>(1)?;
+            // compare like the NOCASE and BINARY collations, see `Repo::path_collation()`
+            Ok(match path.as_bytes().get(..prefix.len()) {
+                Some(start) if case_insensitive => start.eq_ignore_ascii_case(prefix.as_bytes()),
+                Some(start) => start == prefix.as_bytes(),
+                None => false,
+            })
+        },
+    )
+}
+
+/// Whether the error is caused by another connection holding a lock on the database.
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+pub(crate) fn open_database(
+    db_path: impl AsRef<Path>,
+    mode: OpenMode,
+    locking_mode: LockingMode,
+) -> Result<Connection, OpenError> {
+    let db_path = db_path.as_ref();
+    let mut conn = match mode {
+        OpenMode::ReadWrite => Connection::open(db_path),
+        OpenMode::ReadOnly => Connection::open_with_flags(
+            db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        ),
+    }
+    .map_err(OpenError::FailedToCreateDatabase)?;
+
+    // https://www.sqlite.org/pragma.html
+    // WAL is somehow slower. Play around with the benchmark test at the bottom of this file.
+    if mode == OpenMode::ReadWrite {
+        // these pragmas write to the database file, skip them in read-only mode
+        match locking_mode {
+            LockingMode::Exclusive => {
+                // other programs are kept out by `LockGuard`, the database isn't locked so the
+                // repo's own readers can read it while it is written to
+                conn.pragma_update(None, "journal_mode", "WAL")?;
+                conn.pragma_update(None, "locking_mode", "NORMAL")?;
+                // the repo's readers may briefly hold locks, e.g. while the WAL is recovered
+                conn.busy_timeout(Duration::from_secs(5))?;
+            }
+            LockingMode::Shared => {
+                conn.pragma_update(None, "journal_mode", "DELETE")?;
+                conn.pragma_update(None, "locking_mode", "NORMAL")?;
+                // wait for other programs to finish writing instead of failing immediately
+                conn.busy_timeout(Duration::from_secs(5))?;
+            }
+        }
+        conn.pragma_update(None, "synchronous", "FULL")?;
+    }
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    conn.pragma_update(None, "case_sensitive_like", false)?;
 
     add_functions(&conn).unwrap();
 
+    // in read-only mode, this only succeeds if the database is already up-to-date
     MIGRATIONS
         .to_latest(&mut conn)
         .map_err(OpenError::FailedToMigrateDatabase)?;
@@ -726,204 +3559,1992 @@ pub(crate) struct TestRepo {
     tempdir: TempDir,
 }
 
-#[cfg(test)]
-impl TestRepo {
-    pub(crate) fn new() -> Self {
+#[cfg(test)]
+impl TestRepo {
+    pub(crate) fn new() -> Self {
+        let dir = tempdir().unwrap();
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        Self { repo, tempdir: dir }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use crate::tag_import::parse_csv;
+    use crate::tests::utils::assert_unordered_eq;
+
+    use super::*;
+
+    fn empty_testrepo() -> TestRepo {
+        TestRepo::new()
+    }
+
+    /// Simple repo with 5 items in ascending alphabetical order
+    fn testrepo_1() -> TestRepo {
+        let tr = empty_testrepo();
+        tr.repo.insert_item("apple", "food red").unwrap();
+        tr.repo.insert_item("bee", "animal yellow").unwrap();
+        tr.repo.insert_item("cat", "animal yellow").unwrap();
+        tr.repo.insert_item("dog", "animal orange").unwrap();
+        tr.repo.insert_item("egg", "food orange").unwrap();
+        tr
+    }
+
+    /// Repo with all possible combinations of letters "a", "b", "c", "d", "e"
+    fn testrepo_2() -> TestRepo {
+        let tr = empty_testrepo();
+
+        let possible_tags: Vec<_> = "a b c d e".split_whitespace().collect();
+
+        let mut counter = 0;
+
+        for i in 1..=possible_tags.len() {
+            for x in possible_tags.iter().combinations(i) {
+                let name = format!("item {}", counter);
+                let tags = x.iter().join(" ");
+                tr.repo.insert_item(name, tags).unwrap();
+                counter += 1;
+            }
+        }
+
+        tr
+    }
+
+    #[test]
+    fn check_tables_of_newly_created_database() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+
+        let mut stmt = repo
+            .conn
+            .prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")
+            .unwrap();
+        let table_names = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+        let table_names: Vec<_> = table_names.flatten().collect();
+
+        assert_unordered_eq(
+            table_names.iter().map(String::as_str),
+            [
+                "activity_log",
+                "item_changes",
+                "item_tags",
+                "collection_items",
+                "collections",
+                "fingerprints",
+                "folder_tags",
+                "item_content",
+                "item_content_config",
+                "item_content_content",
+                "item_content_data",
+                "item_content_docsize",
+                "item_content_idx",
+                "items",
+                "loudness",
+                "rules",
+                "sqlite_sequence",
+                "tag_history",
+                "tag_query",
+                "tag_query_data",
+                "tag_query_idx",
+                "tag_query_docsize",
+                "tag_query_config",
+                "tag_trigram",
+                "tag_trigram_data",
+                "tag_trigram_idx",
+                "tag_trigram_docsize",
+                "tag_trigram_config",
+                "tags",
+                "tombstones",
+            ],
+        );
+    }
+
+    #[test]
+    fn can_get_changes_since() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        assert_eq!(repo.change_seq().unwrap(), 0);
+
+        let a = repo.insert_item("a", "kick").unwrap();
+        let b = repo.insert_item("b", "snare").unwrap();
+        let seq = repo.change_seq().unwrap();
+        assert!(seq > 0);
+
+        repo.insert_tags(a.id, "hard").unwrap();
+        repo.insert_tags(a.id, "loud").unwrap();
+        let c = repo.insert_item("c", "hat").unwrap();
+        repo.remove_item_by_path("b").unwrap();
+
+        let changes = repo.changes_since(seq).unwrap();
+        assert_eq!(changes.to, repo.change_seq().unwrap());
+        let ids: Vec<_> = changes.items.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![a.id, c.id]);
+        assert_eq!(changes.items[0].tags, vec!["hard", "kick", "loud"]);
+        assert_eq!(changes.removed, vec![b.id]);
+
+        let changes = repo.changes_since(changes.to).unwrap();
+        assert!(changes.items.is_empty() && changes.removed.is_empty());
+    }
+
+    #[test]
+    fn stale_items_are_detected() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let a = repo.insert_item("a", "kick").unwrap();
+        let b = repo.insert_item("b", "snare").unwrap();
+        assert!(a.updated_at > 0);
+        let last_seen = HashMap::from([(a.id, a.updated_at), (b.id, b.updated_at)]);
+        assert!(repo.stale_items(&last_seen).unwrap().is_empty());
+
+        // changes that don't change anything don't count
+        repo.insert_tags(a.id, "kick").unwrap();
+        assert!(repo.stale_items(&last_seen).unwrap().is_empty());
+
+        repo.insert_tags(a.id, "hard").unwrap();
+        repo.remove_item_by_path("b").unwrap();
+        assert_eq!(repo.stale_items(&last_seen).unwrap(), vec![a.id, b.id]);
+        let a = repo.get_item_by_id(a.id).unwrap();
+        assert!(a.updated_at > last_seen[&a.id]);
+        assert!(repo
+            .stale_items(&HashMap::from([(a.id, a.updated_at)]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn can_insert_items() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+
+        repo.insert_item("hello", "text root").unwrap();
+        repo.insert_item("world", "video root").unwrap();
+
+        let mut stmt = repo.conn.prepare("SELECT path FROM items").unwrap();
+        let item_names: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .flatten()
+            .collect();
+
+        assert_unordered_eq(item_names.iter().map(String::as_str), ["hello", "world"]);
+    }
+
+    #[test]
+    fn cant_insert_duplicate_items() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+
+        repo.insert_item("hello", "text root").unwrap();
+        let rv = repo.insert_item("hello", "video root");
+
+        assert!(matches!(rv, Err(InsertError::DuplicatePathError(_))));
+    }
+
+    #[test]
+    fn can_query_items() {
+        fn expect_query(repo: &Repo, query: &str, expected: Vec<&str>) {
+            let items = repo.query_items(query).unwrap();
+
+            assert_unordered_eq(items.iter().map(|x| x.path.as_str()), expected);
+        }
+
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+
+        expect_query(&repo, "animal", vec!["bee", "cat", "dog"]);
+        expect_query(&repo, "food", vec!["apple", "egg"]);
+        expect_query(&repo, "yellow", vec!["bee", "cat"]);
+    }
+
+    #[test]
+    fn can_search_collections() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+        let id = |path| repo.get_item_by_path(path).unwrap().id;
+        let (apple, bee, cat) = (id("apple"), id("bee"), id("cat"));
+
+        repo.create_collection("picks").unwrap();
+        repo.create_collection("favourite things").unwrap();
+        assert!(matches!(
+            repo.create_collection(" picks "),
+            Err(CollectionError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            repo.create_collection(" "),
+            Err(CollectionError::EmptyName)
+        ));
+
+        assert_eq!(repo.add_to_collection("picks", &[apple, bee]).unwrap(), 2);
+        assert_eq!(repo.add_to_collection("picks", &[bee, cat]).unwrap(), 1);
+        assert_eq!(repo.add_to_collection("picks", &[9999]).unwrap(), 0);
+        repo.add_to_collection("favourite things", &[cat]).unwrap();
+        assert!(matches!(
+            repo.add_to_collection("missing", &[cat]),
+            Err(CollectionError::NotFound(_))
+        ));
+
+        assert_eq!(
+            repo.query_ids("collection:picks").unwrap(),
+            vec![apple, bee, cat]
+        );
+        assert_eq!(
+            repo.query_ids("collection:picks -yellow").unwrap(),
+            vec![apple]
+        );
+        assert_eq!(
+            repo.query_ids("collection:'favourite things'").unwrap(),
+            vec![cat]
+        );
+        assert!(repo.query_ids("collection:missing").unwrap().is_empty());
+
+        assert_eq!(repo.remove_from_collection("picks", &[bee]).unwrap(), 1);
+        repo.remove_item_by_path("cat").unwrap();
+        assert_eq!(
+            repo.collections().unwrap(),
+            vec![
+                Collection { name: "favourite things".to_string(), count: 0 },
+                Collection { name: "picks".to_string(), count: 1 },
+            ]
+        );
+
+        repo.delete_collection("picks").unwrap();
+        assert!(repo.query_ids("collection:picks").unwrap().is_empty());
+        assert_eq!(repo.collections().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn folder_tags_are_searchable() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let a = repo.insert_item("Vinyl/kick.wav", "drum").unwrap().id;
+        let b = repo.insert_item("Vinyl/Loops/break.wav", "").unwrap().id;
+        let c = repo.insert_item("Vinyl.wav", "").unwrap().id;
+
+        repo.insert_folder_tags("Vinyl/", "vinyl").unwrap();
+        repo.insert_folder_tags("Vinyl/Loops", "loop").unwrap();
+        let d = repo.insert_item("Vinyl/Loops/groove.wav", "").unwrap().id;
+        assert_eq!(repo.query_ids("vinyl").unwrap(), vec![b, d, a]);
+        assert_eq!(repo.query_ids("vinyl -loop").unwrap(), vec![a]);
+        assert_eq!(repo.query_ids("drum vinyl").unwrap(), vec![a]);
+        // CJK tags are searched with the trigram index instead
+        repo.insert_folder_tags("Vinyl/Loops", "ループ").unwrap();
+        assert_eq!(repo.query_ids("ループ").unwrap(), vec![b, d]);
+        // folder tags aren't added to the items' own tags
+        assert!(repo.get_item_by_id(b).unwrap().tags.is_empty());
+
+        repo.update_path(c, "Vinyl/Loops/moved.wav").unwrap();
+        assert_eq!(repo.query_ids("loop").unwrap(), vec![b, d, c]);
+
+        repo.remove_folder_tags("Vinyl", "vinyl").unwrap();
+        assert!(repo.query_ids("vinyl").unwrap().is_empty());
+        assert_eq!(repo.query_ids("loop").unwrap(), vec![b, d, c]);
+
+        // removing entries that don't match what was indexed corrupts an FTS index
+        repo.remove_item_by_path("Vinyl/kick.wav").unwrap();
+        for table in ["tag_query", "tag_trigram"] {
+            let sql = format!("INSERT INTO {0}({0}) VALUES ('integrity-check')", table);
+            repo.conn.execute(&sql, []).unwrap();
+        }
+    }
+
+    #[test]
+    fn folder_tags_are_inherited() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_folder_tags("Vinyl", "vinyl crackle").unwrap();
+        repo.insert_folder_tags("Vinyl/Loops/", "loop").unwrap();
+        repo.insert_folder_tags("Vinyl2", "other").unwrap();
+
+        let tags = |folder| {
+            repo.folder_tags(folder)
+                .unwrap()
+                .into_iter()
+                .map(|x| (x.path, x.tag))
+                .collect::<Vec<_>>()
+        };
+        let tag = |path: &str, tag: &str| (path.to_string(), tag.to_string());
+        assert_eq!(
+            tags("Vinyl/Loops/Drums"),
+            vec![
+                tag("Vinyl/", "crackle"),
+                tag("Vinyl/", "vinyl"),
+                tag("Vinyl/Loops/", "loop"),
+            ]
+        );
+        assert_eq!(
+            tags("Vinyl"),
+            vec![tag("Vinyl/", "crackle"), tag("Vinyl/", "vinyl")]
+        );
+        assert!(tags("").is_empty());
+    }
+
+    #[test]
+    fn can_sort_by_relevance() {
+        let dir = tempdir().unwrap();
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        // FTS5 ranks items with more occurrences of rare terms higher, relative to their length
+        let a = repo
+            .insert_item("a", "kick other1 other2 other3 other4")
+            .unwrap()
+            .id;
+        let b = repo.insert_item("b", "kick snare").unwrap().id;
+        let c = repo.insert_item("c", "kick").unwrap().id;
+        let d = repo.insert_item("d", "snare").unwrap().id;
+
+        assert_eq!(repo.query_ids("kick").unwrap(), vec![a, b, c]);
+        assert_eq!(
+            repo.query_ids("kick sort:relevance").unwrap(),
+            vec![c, b, a]
+        );
+        assert_eq!(
+            repo.query_ids("kick | snare sort:relevance").unwrap(),
+            vec![b, c, d, a]
+        );
+        // without tags to rank by, items are ordered by path
+        assert_eq!(repo.query_ids("sort:relevance").unwrap(), vec![a, b, c, d]);
+    }
+
+    #[test]
+    fn can_query_ids_in_chunks() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+        let all_ids = repo.query_ids("").unwrap();
+
+        let mut chunks = vec![];
+        let total = repo
+            .query_ids_chunked("", None, 2, |chunk| {
+                chunks.push(chunk);
+                true
+            })
+            .unwrap();
+        assert_eq!(total, all_ids.len());
+        assert!(chunks.iter().all(|x| x.len() <= 2));
+        assert_eq!(chunks.concat(), all_ids);
+
+        let mut chunks = vec![];
+        let total = repo
+            .query_ids_chunked("", None, 2, |chunk| {
+                chunks.push(chunk);
+                false
+            })
+            .unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(chunks, vec![all_ids[..2].to_vec()]);
+    }
+
+    #[test]
+    fn can_query_ids_in_dir() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("it's/kick.wav", "drum").unwrap();
+        repo.insert_item("it's/snare.wav", "drum").unwrap();
+        repo.insert_item("it's/sub/pad.wav", "").unwrap();
+        repo.insert_item("other/kick.wav", "drum").unwrap();
+
+        let paths = |query, dir| {
+            let ids = repo.query_ids_in_dir(query, dir).unwrap();
+            let items: Vec<_> = ids
+                .iter()
+                .map(|&id| repo.get_item_by_id(id).unwrap())
+                .collect();
+            items.into_iter().map(|x| x.path).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths("drum", Some("it's")),
+            vec!["it's/kick.wav", "it's/snare.wav"]
+        );
+        assert_eq!(
+            paths("-drum | name:kick", Some("it's/")),
+            vec!["it's/kick.wav", "it's/sub/pad.wav"]
+        );
+        assert_eq!(paths("", Some("it's/sub")), vec!["it's/sub/pad.wav"]);
+        assert_eq!(
+            paths("name:kick", None),
+            vec!["it's/kick.wav", "other/kick.wav"]
+        );
+    }
+
+    #[test]
+    fn can_query_by_tag_count() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+        repo.insert_item("fig", "").unwrap();
+        repo.insert_item("grape", "food").unwrap();
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths("is:untagged"), vec!["fig"]);
+        assert_eq!(paths("is:tagged food"), vec!["apple", "egg", "grape"]);
+        assert_eq!(paths("tagcount:0"), vec!["fig"]);
+        assert_eq!(paths("tagcount:1"), vec!["grape"]);
+        assert_eq!(paths("food -tagcount:2"), vec!["grape"]);
+    }
+
+    #[test]
+    fn can_query_by_file_name() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("snare/kick 1.wav", "").unwrap();
+        repo.insert_item("drums/snare 1.wav", "").unwrap();
+        repo.insert_item("drums/snare 2.wav", "").unwrap();
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths("name:snare"),
+            vec!["drums/snare 1.wav", "drums/snare 2.wav"]
+        );
+        assert_eq!(
+            paths("inpath:snare"),
+            vec!["drums/snare 1.wav", "drums/snare 2.wav", "snare/kick 1.wav"]
+        );
+        assert_eq!(paths("name:'e 2'"), vec!["drums/snare 2.wav"]);
+    }
+
+    #[test]
+    fn can_query_by_file_type() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("kick.wav", "drum").unwrap();
+        repo.insert_item("pad.FLAC", "").unwrap();
+        repo.insert_item("cover.png", "").unwrap();
+        let item = repo.get_item_by_path("pad.FLAC").unwrap();
+        assert_eq!(item.meta_tags, "all type:audio ext:flac");
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths("type:audio"), vec!["kick.wav", "pad.FLAC"]);
+        assert_eq!(paths("type:audio -drum"), vec!["pad.FLAC"]);
+        assert_eq!(paths("-type:audio"), vec!["cover.png"]);
+
+        // meta tags are updated when an item is moved
+        repo.rename_path("cover.png", "cover.txt").unwrap();
+        assert_eq!(paths("type:image"), Vec::<String>::new());
+        assert_eq!(paths("type:document"), vec!["cover.txt"]);
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let item = repo.insert_item("a", "cafe\u{301} Kick").unwrap();
+        assert_eq!(item.tags, vec!["Kick", "caf\u{e9}"]);
+
+        // searching is case-insensitive even for non-ASCII letters
+        repo.insert_item("b", "\u{c9}t\u{e9}").unwrap();
+        assert_eq!(repo.count("kick").unwrap(), 1);
+        assert_eq!(repo.count("\u{e9}t\u{e9}").unwrap(), 1);
+        assert_eq!(repo.count("ete").unwrap(), 0);
+    }
+
+    #[test]
+    fn can_query_with_raw_fts() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("a", "kick loud snare").unwrap();
+        repo.insert_item("b", "kick snare").unwrap();
+        repo.insert_item("c", "kick").unwrap();
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths("fts:'NEAR(kick snare, 0)'"), vec!["b"]);
+        assert_eq!(paths("fts:'kick NOT snare'"), vec!["c"]);
+        // the query can't match the `meta_tags` column
+        assert!(paths("fts:all").is_empty());
+        assert!(repo.query_items("fts:'kick AND'").is_err());
+    }
+
+    #[test]
+    fn can_query_cjk_tags() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("a", "ドラムキック 打击乐").unwrap();
+        repo.insert_item("b", "ドラム").unwrap();
+        repo.insert_item("c", "kick").unwrap();
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths("ドラム"), vec!["a", "b"]);
+        assert_eq!(paths("キック"), vec!["a"]);
+        assert_eq!(paths("打击"), vec!["a"]);
+        assert_eq!(paths("kick | 打击"), vec!["a", "c"]);
+        assert_eq!(paths("-キック"), vec!["b", "c"]);
+
+        // the trigram index is kept up to date
+        let item = repo.get_item_by_path("c").unwrap();
+        repo.update_tags(item.id, "キック").unwrap();
+        assert_eq!(paths("キック"), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn can_make_tags_case_insensitive() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let item = repo.insert_item("a", "Kick kick Snare").unwrap();
+        assert_eq!(item.tags, vec!["Kick", "Snare", "kick"]);
+
+        let mut config = repo.config().clone();
+        config.case_insensitive_tags = true;
+        repo.set_config(config).unwrap();
+        let item = repo.get_item_by_id(item.id).unwrap();
+        assert_eq!(item.tags, vec!["kick", "snare"]);
+
+        repo.insert_tags(item.id, vec!["HARD"]).unwrap();
+        repo.remove_tags(item.id, vec!["SNARE"]).unwrap();
+        let item = repo.get_item_by_id(item.id).unwrap();
+        assert_eq!(item.tags, vec!["hard", "kick"]);
+    }
+
+    #[test]
+    fn tags_are_parsed_with_policy() {
+        let parse = |text, policy| Tag::parse(text, policy, ":/").map(Tag::into_string);
+        assert_eq!(parse("in:x", TagPolicy::Allow), Ok(String::from("in:x")));
+        assert_eq!(
+            parse("in:x", TagPolicy::Reject),
+            Err(InvalidTagError::InvalidChar { tag: String::from("in:x"), char: ':' })
+        );
+        assert_eq!(
+            parse("a/b:c", TagPolicy::Replace),
+            Ok(String::from("a_b_c"))
+        );
+        assert_eq!(parse("", TagPolicy::Allow), Err(InvalidTagError::Empty));
+        assert_eq!(
+            parse("a b", TagPolicy::Allow),
+            Err(InvalidTagError::Whitespace(String::from("a b")))
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let item = repo.insert_item("a", "kick").unwrap();
+
+        let rv = repo.insert_tags(item.id, vec!["in:drums"]);
+        assert!(matches!(rv, Err(InsertTagsError::InvalidTag(_))));
+        let rv = repo.batch_insert_tags(&vec![item.id], vec!["(hard)"]);
+        assert!(matches!(rv, Err(InsertTagsError::InvalidTag(_))));
+        let rv = repo.update_tags(item.id, vec!["kick", "a b"]);
+        assert!(matches!(rv, Err(UpdateError::InvalidTag(_))));
+        assert_eq!(repo.get_item_by_id(item.id).unwrap().tags, vec!["kick"]);
+
+        let mut config = repo.config().clone();
+        config.tag_policy = TagPolicy::Replace;
+        repo.set_config(config).unwrap();
+        repo.insert_tags(item.id, vec!["in:drums", "in/drums"])
+            .unwrap();
+        assert_eq!(
+            repo.get_item_by_id(item.id).unwrap().tags,
+            vec!["in_drums", "kick"]
+        );
+    }
+
+    #[test]
+    fn can_query_by_tag_prefix() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("a", "kick").unwrap();
+        repo.insert_item("b", "kick-hard").unwrap();
+        repo.insert_item("c", "kickdrum").unwrap();
+        repo.insert_item("d", "snare").unwrap();
+
+        let paths = |query| {
+            let items = repo.query_items(query).unwrap();
+            items
+                .into_iter()
+                .map(|x| x.path)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths("kick*"), vec!["a", "b", "c"]);
+        assert_eq!(paths("-kick*"), vec!["d"]);
+        assert_eq!(paths("kickd* | sn*"), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn can_get_all_items() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let items = repo.query_items("").unwrap();
+        assert_unordered_eq(
+            items.iter().map(|x| x.path.as_str()),
+            ["apple", "bee", "cat", "dog", "egg"],
+        )
+    }
+
+    #[test]
+    fn can_count_items() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+        assert_eq!(repo.count("").unwrap(), 5);
+        assert_eq!(repo.count("animal").unwrap(), 3);
+        assert_eq!(repo.count("animal -yellow").unwrap(), 1);
+        assert_eq!(repo.count("pizza").unwrap(), 0);
+        assert!(matches!(
+            repo.count("(animal"),
+            Err(QueryError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn can_get_item_by_path() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let item = repo.get_item_by_path("apple").unwrap();
+        assert_eq!(item.id, 1);
+        assert_eq!(item.path, "apple");
+        assert_eq!(item.tags, vec!["food", "red"]);
+    }
+
+    #[test]
+    fn can_get_item_by_id() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let item = repo.get_item_by_id(1).unwrap();
+        assert_eq!(item.id, 1);
+        assert_eq!(item.path, "apple");
+        assert_eq!(item.tags, vec!["food", "red"]);
+    }
+
+    #[test]
+    fn can_remove_item_by_path() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        repo.remove_item_by_path("apple").unwrap();
+        let rv = repo.get_item_by_path("apple");
+        assert!(matches!(rv, Err(SearchError::ItemNotFound)))
+    }
+
+    #[test]
+    fn can_remove_item_by_id() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        repo.remove_item_by_id(1).unwrap();
+        let rv = repo.get_item_by_id(1);
+        assert!(matches!(rv, Err(SearchError::ItemNotFound)))
+    }
+
+    #[test]
+    fn can_update_item_tags() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+
+        let item = repo.get_item_by_path("apple").unwrap();
+        let new_tags = "computer laptop";
+        repo.update_tags(item.id, new_tags).unwrap();
+
+        // fetch item again
+        let item = repo.get_item_by_path("apple").unwrap();
+        let new_tags: Vec<_> = new_tags.split(" ").map(String::from).collect();
+        assert_eq!(item.tags, new_tags);
+    }
+
+    #[test]
+    fn can_apply_tag_templates() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let mut config = repo.config().clone();
+        config
+            .tag_templates
+            .insert(String::from("pets"), String::from("pet cute"));
+        repo.set_config(config).unwrap();
+
+        let ids = repo.query_ids("animal").unwrap();
+        repo.apply_tag_template(&ids, "pets").unwrap();
+        assert_eq!(repo.query_ids("pet cute").unwrap(), ids);
+        assert_eq!(
+            repo.get_item_by_path("bee").unwrap().tags(),
+            ["animal", "cute", "pet", "yellow"]
+        );
+
+        assert!(matches!(
+            repo.apply_tag_template(&ids, "missing"),
+            Err(ApplyTemplateError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn can_set_tags_of_many_items() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+        let ids = repo.query_ids("animal").unwrap();
+        let seq = repo.change_seq().unwrap();
+
+        // tags are sorted and deduplicated, and unchanged items are skipped
+        repo.update_tags(ids[0], "pet animal").unwrap();
+        assert_eq!(repo.set_tags(&ids, "pet animal pet").unwrap(), 2);
+        for id in &ids {
+            assert_eq!(repo.get_item_by_id(*id).unwrap().tags(), ["animal", "pet"]);
+        }
+        assert_eq!(repo.changes_since(seq).unwrap().items.len(), 3);
+
+        assert!(matches!(
+            repo.set_tags(&ids, "in:x"),
+            Err(UpdateError::InvalidTag(_))
+        ));
+        assert_eq!(repo.query_ids("pet").unwrap(), ids);
+    }
+
+    #[test]
+    fn can_summarize_selection() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let count = |name: &str, count: i64| TagCount { name: name.to_string(), count };
+        let id = |path: &str| repo.get_item_by_path(path).unwrap().id;
+        let (bee, cat, dog) = (id("bee"), id("cat"), id("dog"));
+
+        // duplicate and missing ids are ignored
+        let summary = repo
+            .selection_summary(&[bee, cat, dog, dog, 12345])
+            .unwrap();
+        assert_eq!(
+            summary,
+            SelectionSummary {
+                items: 3,
+                tags: vec![count("animal", 3), count("yellow", 2), count("orange", 1)],
+                common: vec!["animal".to_string()],
+            }
+        );
+
+        let summary = repo.selection_summary(&[]).unwrap();
+        assert_eq!(summary.items, 0);
+        assert!(summary.tags.is_empty());
+        assert!(summary.common.is_empty());
+    }
+
+    #[test]
+    fn tags_are_counted() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let count = |name: &str, count: i64| TagCount { name: name.to_string(), count };
+        assert_eq!(
+            repo.tag_counts().unwrap(),
+            vec![
+                count("animal", 3),
+                count("food", 2),
+                count("orange", 2),
+                count("red", 1),
+                count("yellow", 2),
+            ]
+        );
+
+        // counts follow changes to items, and unused tags are removed
+        let apple = repo.get_item_by_path("apple").unwrap();
+        repo.update_tags(apple.id, "food green").unwrap();
+        repo.insert_tags(2, "insect").unwrap();
+        repo.remove_item_by_path("egg").unwrap();
+        assert_eq!(
+            repo.tag_counts().unwrap(),
+            vec![
+                count("animal", 3),
+                count("food", 1),
+                count("green", 1),
+                count("insect", 1),
+                count("orange", 1),
+                count("yellow", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_stats_are_calculated() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let kick = repo.insert_item("kick", "drum one-shot").unwrap().id;
+        let snare = repo.insert_item("snare", "drum").unwrap().id;
+        repo.insert_item("loop", "drum loop one-shot").unwrap();
+        repo.insert_tags(snare, "one-shot").unwrap();
+        repo.remove_tags(kick, "one-shot").unwrap();
+
+        let stats = repo.tag_stats().unwrap();
+        let names: Vec<_> = stats.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["drum", "loop", "one-shot"]);
+
+        let drum = &stats[0];
+        assert_eq!(drum.count, 3);
+        assert_eq!(drum.added_recently, 3);
+        assert_eq!(drum.removed_recently, 0);
+        assert!(drum.first_used.is_some());
+        assert!(drum.first_used <= drum.last_used);
+        assert_eq!(
+            drum.related,
+            vec![
+                TagCount { name: "one-shot".to_string(), count: 2 },
+                TagCount { name: "loop".to_string(), count: 1 },
+            ]
+        );
+
+        let one_shot = &stats[2];
+        assert_eq!(one_shot.count, 2);
+        assert_eq!(one_shot.added_recently, 3);
+        assert_eq!(one_shot.removed_recently, 1);
+    }
+
+    #[test]
+    fn unchanged_tags_arent_recorded_in_history() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let id = repo.insert_item("kick", "drum one-shot").unwrap().id;
+        repo.insert_tags(id, "drum kick").unwrap();
+        repo.remove_tags(id, "one-shot").unwrap();
+
+        let history: Vec<(String, bool)> = repo
+            .conn
+            .prepare("SELECT tag, added FROM tag_history ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        let event = |tag: &str, added| (tag.to_string(), added);
+        assert_eq!(
+            history,
+            vec![
+                event("drum", true),
+                event("one-shot", true),
+                event("kick", true),
+                event("one-shot", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_rename_tag() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        assert_eq!(repo.rename_tag("yellow", "gold").unwrap(), 2);
+        assert_eq!(repo.query_ids("gold").unwrap(), vec![2, 3]);
+        assert!(repo.query_ids("yellow").unwrap().is_empty());
+
+        // renaming to an existing tag merges them
+        assert_eq!(repo.rename_tag("orange", "food").unwrap(), 2);
+        assert_eq!(repo.get_item_by_path("egg").unwrap().tags, vec!["food"]);
+        assert_eq!(
+            repo.get_item_by_path("dog").unwrap().tags,
+            vec!["animal", "food"]
+        );
+
+        assert_eq!(repo.rename_tag("missing", "other").unwrap(), 0);
+        assert!(matches!(
+            repo.rename_tag("food", "two words"),
+            Err(UpdateError::InvalidTag(InvalidTagError::Whitespace(_)))
+        ));
+    }
+
+    #[test]
+    fn search_index_survives_folder_tags_migration() {
+        let dir = tempdir().unwrap();
+        let mut conn = Connection::open(dir.path().join("tags.db")).unwrap();
+        add_functions(&conn).unwrap();
+        MIGRATIONS.to_version(&mut conn, 8).unwrap();
+        conn.execute_batch(indoc! {"
+            INSERT INTO items (path, tags) VALUES ('kick', 'drum one-shot');
+            INSERT INTO items (path, tags) VALUES ('snare', 'drum');
+        "})
+            .unwrap();
+
+        let search = |conn: &Connection, query: &str| -> Vec<i64> {
+            conn.prepare("SELECT rowid FROM tag_query WHERE tag_query MATCH ? ORDER BY rowid")
+                .unwrap()
+                .query_map([query], |row| row.get(0))
+                .unwrap()
+                .try_collect()
+                .unwrap()
+        };
+        MIGRATIONS.to_latest(&mut conn).unwrap();
+        assert_eq!(search(&conn, "drum"), vec![1, 2]);
+        MIGRATIONS.to_version(&mut conn, 8).unwrap();
+        assert_eq!(search(&conn, "\"one-shot\""), vec![1]);
+        conn.execute("UPDATE items SET tags = 'snare' WHERE id = 2", [])
+            .unwrap();
+        assert_eq!(search(&conn, "drum"), vec![1]);
+    }
+
+    #[test]
+    fn tag_tables_are_filled_by_migration() {
+        let dir = tempdir().unwrap();
+        let mut conn = Connection::open(dir.path().join("tags.db")).unwrap();
+        add_functions(&conn).unwrap();
+        MIGRATIONS.to_version(&mut conn, 7).unwrap();
+        conn.execute_batch(indoc! {"
+            INSERT INTO items (path, tags) VALUES ('kick', 'drum one-shot');
+            INSERT INTO items (path, tags) VALUES ('snare', 'drum');
+        "})
+            .unwrap();
+        MIGRATIONS.to_latest(&mut conn).unwrap();
+
+        let counts: Vec<(String, i64)> = conn
+            .prepare(indoc! {"
+                SELECT t.name, count(*) FROM tags t
+                JOIN item_tags it ON it.tag_id = t.id
+                GROUP BY t.id ORDER BY t.name
+            "})
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(
+            counts,
+            vec![("drum".to_string(), 2), ("one-shot".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn can_update_item_path() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+
+        let item = repo.get_item_by_id(1).unwrap();
+        let new_path = "pizza";
+        repo.update_path(item.id, new_path).unwrap();
+
+        // fetch item again
+        let item = repo.get_item_by_id(1).unwrap();
+        assert_eq!(item.path, new_path);
+    }
+
+    #[test]
+    fn can_rename_dir() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("Drums/kick.wav", "").unwrap();
+        repo.insert_item("Drums/Snares/snare.wav", "").unwrap();
+        repo.insert_item("Drums.wav", "").unwrap();
+        repo.insert_item("drums/hat.wav", "").unwrap();
+        repo.insert_item("Drums2/clap.wav", "").unwrap();
+
+        let count = repo.rename_dir("Drums", "Percussion/Drums").unwrap();
+        assert_eq!(count, 2);
+
+        let paths: Vec<_> = repo
+            .all_items()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "Percussion/Drums/kick.wav",
+                "Percussion/Drums/Snares/snare.wav",
+                "Drums.wav",
+                "drums/hat.wav",
+                "Drums2/clap.wav",
+            ]
+        );
+    }
+
+    #[test]
+    fn can_sync_subdir() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        repo.insert_item("apple", "food").unwrap();
+        repo.insert_item("drums/kick", "kick").unwrap();
+        repo.insert_item("drums/snares/snare", "snare").unwrap();
+        repo.insert_item("drumsets/kit", "").unwrap();
+
+        // shallow sync of the root folder only touches items directly in the root
+        let delta = repo
+            .sync_subdir(
+                RelativePath::new(""),
+                false,
+                vec![RelativePathBuf::from("bee")],
+            )
+            .unwrap();
+        assert_eq!(delta.created, vec!["bee"]);
+        assert_eq!(delta.deleted, vec!["apple"]);
+
+        // recursive sync of a subfolder detects renames within it
+        let new_paths = vec![
+            RelativePathBuf::from("drums/kick"),
+            RelativePathBuf::from("drums/snare"),
+        ];
+        let delta = repo
+            .sync_subdir(RelativePath::new("drums"), true, new_paths)
+            .unwrap();
+        assert!(delta.created.is_empty());
+        assert!(delta.deleted.is_empty());
+        assert_eq!(
+            delta.renamed,
+            vec![("drums/snares/snare".to_string(), "drums/snare".to_string())]
+        );
+        assert_eq!(
+            repo.get_item_by_path("drums/snare").unwrap().tags,
+            vec!["snare"]
+        );
+        assert!(repo.get_item_by_path("drumsets/kit").is_ok());
+
+        // nothing changed
+        let new_paths = vec![RelativePathBuf::from("drumsets/kit")];
+        let delta = repo
+            .sync_subdir(RelativePath::new("drumsets"), true, new_paths)
+            .unwrap();
+        assert!(delta.is_empty());
+
+        assert_eq!(repo.top_level_folders().unwrap(), vec!["drums", "drumsets"]);
+    }
+
+    #[test]
+    fn planning_subdir_sync_keeps_items() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        repo.insert_item("drums/kick", "kick").unwrap();
+        repo.insert_item("drums/snare", "").unwrap();
+        repo.insert_item("keys/piano", "piano").unwrap();
+
+        // the folder is gone, e.g. its drive was unmounted
+        let delta = repo
+            .plan_sync_subdir(RelativePath::new("drums"), true, vec![])
+            .unwrap();
+        assert_eq!(delta.deleted.len(), 2);
+        assert_eq!(delta.retagged_lost, 1);
+        assert!(repo.get_item_by_path("drums/kick").is_ok());
+        assert!(repo.get_item_by_path("drums/snare").is_ok());
+    }
+
+    #[test]
+    fn can_sync_subtree() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let root = repo.path().to_path_buf();
+        fs::create_dir_all(root.join("drums/snares")).unwrap();
+        fs::write(root.join("drums/kick"), "").unwrap();
+        fs::write(root.join("drums/snares/snare"), "").unwrap();
+        fs::write(root.join("apple"), "").unwrap();
+        repo.insert_item("drums/kick", "kick").unwrap();
+        repo.insert_item("drums/hat", "hat").unwrap();
+        repo.insert_item("banana", "").unwrap();
+
+        let delta = repo.sync_subtree(RelativePath::new("drums")).unwrap();
+        assert_eq!(delta.created, vec!["drums/snares/snare"]);
+        assert_eq!(delta.deleted, vec!["drums/hat"]);
+        // items outside the folder are left alone
+        assert!(repo.get_item_by_path("apple").is_err());
+        assert!(repo.get_item_by_path("banana").is_ok());
+
+        // a folder that was removed from disk
+        fs::remove_dir_all(root.join("drums")).unwrap();
+        let delta = repo.sync_subtree(RelativePath::new("drums")).unwrap();
+        assert_unordered_eq(
+            delta.deleted.iter().map(String::as_str),
+            vec!["drums/kick", "drums/snares/snare"],
+        );
+        assert_eq!(repo.count("").unwrap(), 1);
+    }
+
+    #[test]
+    fn can_find_missing_items() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let root = repo.path().to_path_buf();
+        fs::create_dir_all(root.join("drums")).unwrap();
+        fs::write(root.join("drums/kick"), "").unwrap();
+        repo.insert_item("drums/kick", "kick").unwrap();
+        repo.insert_item("drums/snare", "snare drum").unwrap();
+        repo.insert_item("apple", "food").unwrap();
+
+        let missing = repo.missing_items().unwrap();
+        let paths: Vec<_> = missing.iter().map(Item::path).collect();
+        assert_eq!(paths, vec!["apple", "drums/snare"]);
+        assert_eq!(missing[1].tags(), ["drum", "snare"]);
+        // nothing is removed
+        assert_eq!(repo.count("").unwrap(), 3);
+    }
+
+    #[test]
+    fn can_relocate_missing_items() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let root = repo.path().to_path_buf();
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::create_dir_all(root.join("new/a")).unwrap();
+        fs::create_dir_all(root.join("new/b")).unwrap();
+        fs::write(root.join("old/kick.wav"), "kick").unwrap();
+        let kick = repo.insert_item("old/kick.wav", "kick").unwrap().id;
+        repo.set_hash(kick, 4, &hash_file(root.join("old/kick.wav")).unwrap())
+            .unwrap();
+        let snare = repo.insert_item("old/snare.wav", "snare").unwrap().id;
+        repo.insert_item("old/hat.wav", "hat").unwrap();
+        repo.insert_item("tagged.wav", "drum").unwrap();
+
+        // kick was renamed, hat is ambiguous
+        fs::remove_dir_all(root.join("old")).unwrap();
+        fs::write(root.join("new/renamed kick.wav"), "kick").unwrap();
+        fs::write(root.join("new/snare.wav"), "snare").unwrap();
+        fs::write(root.join("new/a/hat.wav"), "").unwrap();
+        fs::write(root.join("new/b/hat.wav"), "").unwrap();
+        fs::write(root.join("tagged.wav"), "kick").unwrap();
+        let paths = scan_dir(&root, Default::default()).unwrap();
+
+        let suggestions = repo.suggest_relocations(paths).unwrap();
+        assert_eq!(
+            suggestions,
+            vec![
+                Relocation {
+                    id: kick,
+                    old_path: "old/kick.wav".to_string(),
+                    new_path: "new/renamed kick.wav".to_string(),
+                    matched_by: RelocationMatch::Hash,
+                },
+                Relocation {
+                    id: snare,
+                    old_path: "old/snare.wav".to_string(),
+                    new_path: "new/snare.wav".to_string(),
+                    matched_by: RelocationMatch::Name,
+                },
+            ]
+        );
+
+        // a sync may have already added the file as an item without tags
+        repo.insert_item("new/snare.wav", "").unwrap();
+        for relocation in &suggestions {
+            repo.relocate_item(relocation.id(), relocation.new_path())
+                .unwrap();
+        }
+        assert_eq!(repo.get_item_by_id(snare).unwrap().path(), "new/snare.wav");
+        assert_eq!(repo.get_item_by_path("new/snare.wav").unwrap().id(), snare);
+        assert_eq!(repo.query_ids("kick").unwrap(), vec![kick]);
+        assert_eq!(repo.missing_items().unwrap().len(), 1);
+
+        assert!(matches!(
+            repo.relocate_item(kick, "tagged.wav"),
+            Err(RelocateError::PathTaken(_))
+        ));
+        assert!(matches!(
+            repo.relocate_item(kick, "missing.wav"),
+            Err(RelocateError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            repo.relocate_item(1000, "new/a/hat.wav"),
+            Err(RelocateError::ItemNotFound(1000))
+        ));
+    }
+
+    #[test]
+    fn can_search_document_contents() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let manual = repo.insert_item("docs/Manual.HTML", "docs").unwrap().id;
+        let notes = repo.insert_item("docs/notes.txt", "").unwrap().id;
+        let empty = repo.insert_item("docs/empty.txt", "docs").unwrap().id;
+        repo.insert_item("kick.wav", "").unwrap();
+        let unextracted: Vec<_> = repo.unextracted_items(0, 10).unwrap();
+        assert_eq!(
+            unextracted.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![manual, notes, empty]
+        );
+
+        repo.set_content(manual, "Synth manual: tuning the oscillators")
+            .unwrap();
+        repo.set_content(notes, "remember to tune the snare")
+            .unwrap();
+        repo.set_content(empty, "").unwrap();
+        // items that were removed while being extracted are ignored
+        repo.set_content(1000, "gone").unwrap();
+        assert_eq!(repo.unextracted_items(0, 10).unwrap(), vec![]);
+
+        assert_eq!(repo.query_ids("content:oscillators").unwrap(), vec![manual]);
+        assert_eq!(repo.query_ids("content:'the snare'").unwrap(), vec![notes]);
+        assert_eq!(repo.query_ids("content:the docs").unwrap(), vec![manual]);
+        assert_eq!(repo.query_ids("-content:tuning docs").unwrap(), vec![empty]);
+        assert!(repo.query_ids("content:'snare the'").unwrap().is_empty());
+
+        // replacing the text removes the old text from the index
+        repo.set_content(notes, "nothing here").unwrap();
+        assert!(repo.query_ids("content:snare").unwrap().is_empty());
+        repo.remove_item_by_id(manual).unwrap();
+        assert!(repo.query_ids("content:oscillators").unwrap().is_empty());
+    }
+
+    #[test]
+    fn can_find_similar_items() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let kick = repo.insert_item("kick.wav", "").unwrap().id;
+        let kick2 = repo.insert_item("kick 2.wav", "").unwrap().id;
+        let snare = repo.insert_item("snare.wav", "").unwrap().id;
+        let notes = repo.insert_item("notes.txt", "").unwrap().id;
+        assert_eq!(repo.unfingerprinted_items(0, 10).unwrap().len(), 4);
+
+        repo.set_fingerprint(kick, Some(&[1.0, 0.0])).unwrap();
+        repo.set_fingerprint(kick2, Some(&[0.8, 0.6])).unwrap();
+        repo.set_fingerprint(snare, Some(&[0.0, 1.0])).unwrap();
+        repo.set_fingerprint(notes, None).unwrap();
+        // items that were removed while being fingerprinted are ignored
+        repo.set_fingerprint(1000, Some(&[1.0, 0.0])).unwrap();
+        assert_eq!(repo.unfingerprinted_items(0, 10).unwrap(), vec![]);
+
+        let similar = repo.find_similar(kick, 10).unwrap();
+        let ids: Vec<_> = similar.iter().map(|x| x.item().id()).collect();
+        assert_eq!(ids, vec![kick2, snare]);
+        assert!((similar[0].similarity() - 0.8).abs() < 1e-6);
+        assert_eq!(repo.find_similar(kick, 1).unwrap().len(), 1);
+        assert!(matches!(
+            repo.find_similar(notes, 10),
+            Err(SimilarError::NoFingerprint(_))
+        ));
+
+        repo.remove_item_by_id(kick2).unwrap();
+        let similar = repo.find_similar(kick, 10).unwrap();
+        assert_eq!(similar.len(), 1);
+    }
+
+    #[test]
+    fn can_store_loudness() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        let kick = repo.insert_item("kick.wav", "").unwrap().id;
+        let notes = repo.insert_item("notes.txt", "").unwrap().id;
+        assert_eq!(repo.loudness(kick).unwrap(), None);
+
+        let loudness = Loudness { lufs: -12.5, peak: 0.75 };
+        repo.set_loudness(kick, Some(loudness)).unwrap();
+        repo.set_loudness(notes, None).unwrap();
+        repo.set_loudness(1000, Some(loudness)).unwrap();
+        assert_eq!(repo.loudness(kick).unwrap(), Some(Some(loudness)));
+        assert_eq!(repo.loudness(notes).unwrap(), Some(None));
+        assert_eq!(repo.loudness(1000).unwrap(), None);
+
+        repo.remove_item_by_id(kick).unwrap();
+        assert_eq!(repo.loudness(kick).unwrap(), None);
+    }
+
+    #[test]
+    fn modified_files_are_detected() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let root = repo.path().to_path_buf();
+        let set_mtime = |path: &str, secs: u64| {
+            let file = fs::File::options()
+                .write(true)
+                .open(root.join(path))
+                .unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        fs::create_dir(root.join("drums")).unwrap();
+        fs::write(root.join("drums/kick"), "kick").unwrap();
+        fs::write(root.join("snare"), "snare").unwrap();
+        set_mtime("drums/kick", 1000);
+        set_mtime("snare", 1000);
+        let kick = repo
+            .insert_new_item(RelativePath::new("drums/kick"))
+            .unwrap();
+        // items inserted without checking the file
+        let snare = repo.insert_item("snare", "snare").unwrap();
+        repo.set_hash(snare.id, 5, "abc").unwrap();
+
+        // the first check only stores the modification time
+        assert!(repo
+            .refresh_mtimes(RelativePath::new(""), true)
+            .unwrap()
+            .is_empty());
+        assert!(!repo.refresh_mtime(RelativePath::new("snare")).unwrap());
+
+        set_mtime("snare", 2000);
+        assert!(repo.refresh_mtime(RelativePath::new("snare")).unwrap());
+        assert!(!repo.refresh_mtime(RelativePath::new("snare")).unwrap());
+        // the file will be hashed again
+        assert_eq!(repo.get_hash_by_path("snare").unwrap(), None);
+
+        set_mtime("drums/kick", 2000);
+        let seq = repo.change_seq().unwrap();
+        assert!(repo
+            .refresh_mtimes(RelativePath::new(""), false)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            repo.refresh_mtimes(RelativePath::new("drums"), true)
+                .unwrap(),
+            vec![kick.id]
+        );
+        let changes = repo.changes_since(seq).unwrap();
+        assert_eq!(changes.items.len(), 1);
+        assert_eq!(changes.items[0].id, kick.id);
+
+        // paths that aren't in the repo
+        assert!(!repo.refresh_mtime(RelativePath::new("hat")).unwrap());
+    }
+
+    #[test]
+    fn can_run_maintenance() {
+        let tr = testrepo_2();
+        let repo = &tr.repo;
+        for item in repo.all_items().unwrap() {
+            repo.remove_item_by_id(item.id).unwrap();
+        }
+        repo.maintenance().unwrap();
+        assert_eq!(repo.count("").unwrap(), 0);
+    }
+
+    #[test]
+    fn can_open_read_only() {
+        let dir = tempdir().unwrap();
+
+        // read-only mode can't create a new repo
+        assert!(matches!(
+            Repo::open(&dir, OpenMode::ReadOnly),
+            Err(OpenError::RepoNotCreated)
+        ));
+
+        {
+            let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+            repo.insert_item("apple", "food red").unwrap();
+        }
+
+        let repo = Repo::open(&dir, OpenMode::ReadOnly).unwrap();
+        assert!(repo.read_only());
+        assert_eq!(repo.query_ids("food").unwrap(), vec![1]);
+        assert!(matches!(
+            repo.insert_item("bee", "animal"),
+            Err(InsertError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            repo.insert_tags(1, "fruit"),
+            Err(InsertTagsError::ReadOnly(_))
+        ));
+        assert_eq!(repo.get_item_by_id(1).unwrap().tags, vec!["food", "red"]);
+    }
+
+    #[test]
+    fn can_create_repo() {
         let dir = tempdir().unwrap();
-        let repo = Repo::open(&dir).unwrap();
-        Self { repo, tempdir: dir }
+        let config = RepoConfig { auto_tag: false, ..Default::default() };
+        {
+            let repo = Repo::create(&dir, &config).unwrap();
+            assert_eq!(repo.config(), &config);
+            assert_eq!(repo.count("").unwrap(), 0);
+        }
+
+        assert!(matches!(
+            Repo::create(&dir, &config),
+            Err(CreateError::AlreadyExists)
+        ));
+        assert!(matches!(
+            Repo::create(dir.path().join("missing"), &config),
+            Err(CreateError::PathDoesNotExist)
+        ));
+        // the config is kept when the repo is opened again
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        assert!(!repo.config().auto_tag);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use itertools::Itertools;
+    #[test]
+    fn exclusive_repo_is_locked() {
+        let dir = tempdir().unwrap();
+        let repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        repo.insert_item("apple", "").unwrap();
+        let lock = RepoLock::read(dir.path().join(".tagrepo")).unwrap();
+        assert_eq!(lock.pid, std::process::id());
+
+        assert!(matches!(
+            Repo::open(&dir, OpenMode::ReadWrite),
+            Err(OpenError::RepoLocked(_))
+        ));
+
+        // lock file is removed when the repo is closed
+        drop(repo);
+        assert!(RepoLock::read(dir.path().join(".tagrepo")).is_none());
+    }
 
-    use crate::tests::utils::assert_unordered_eq;
+    #[test]
+    fn stale_lock_is_replaced() {
+        let dir = tempdir().unwrap();
+        drop(Repo::open(&dir, OpenMode::ReadWrite).unwrap());
+
+        let stale_lock = RepoLock {
+            host: String::from("other-pc"),
+            pid: 1,
+            opened_at: 0,
+        };
+        stale_lock.write(dir.path().join(".tagrepo")).unwrap();
+
+        let _repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        let lock = RepoLock::read(dir.path().join(".tagrepo")).unwrap();
+        assert_eq!(lock.pid, std::process::id());
+    }
 
-    use super::*;
+    #[test]
+    fn shared_repo_can_be_opened_twice() {
+        let dir = tempdir().unwrap();
+        {
+            let mut repo = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+            let mut config = repo.config().clone();
+            config.locking_mode = LockingMode::Shared;
+            repo.set_config(config).unwrap();
+        }
 
-    fn empty_testrepo() -> TestRepo {
-        TestRepo::new()
+        let repo_1 = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        let repo_2 = Repo::open(&dir, OpenMode::ReadWrite).unwrap();
+        repo_1.insert_item("apple", "food").unwrap();
+        assert_eq!(repo_2.query_ids("food").unwrap(), vec![1]);
+        assert!(RepoLock::read(dir.path().join(".tagrepo")).is_none());
     }
 
-    /// Simple repo with 5 items in ascending alphabetical order
-    fn testrepo_1() -> TestRepo {
-        let tr = empty_testrepo();
-        tr.repo.insert_item("apple", "food red").unwrap();
-        tr.repo.insert_item("bee", "animal yellow").unwrap();
-        tr.repo.insert_item("cat", "animal yellow").unwrap();
-        tr.repo.insert_item("dog", "animal orange").unwrap();
-        tr.repo.insert_item("egg", "food orange").unwrap();
-        tr
+    #[test]
+    fn case_insensitive_paths() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let mut config = repo.config().clone();
+        config.case_insensitive_paths = false;
+        repo.set_config(config.clone()).unwrap();
+
+        repo.insert_item("Drums/Kick.wav", "kick").unwrap();
+        assert!(repo.query_ids("in:drums").unwrap().is_empty());
+        assert!(repo.query_ids("leading:drums/k").unwrap().is_empty());
+        assert!(repo.query_ids("children:drums").unwrap().is_empty());
+        assert_eq!(repo.query_ids("in:Drums").unwrap(), vec![1]);
+        assert_eq!(repo.query_ids("children:Drums").unwrap(), vec![1]);
+        // only path prefixes are case-sensitive
+        assert_eq!(repo.query_ids("ext:WAV").unwrap(), vec![1]);
+        assert_eq!(repo.query_ids("name:kick").unwrap(), vec![1]);
+        assert_eq!(repo.query_ids("inpath:drums").unwrap(), vec![1]);
+
+        config.case_insensitive_paths = true;
+        repo.set_config(config).unwrap();
+        assert_eq!(repo.query_ids("in:drums").unwrap(), vec![1]);
+        assert_eq!(repo.query_ids("leading:drums/k").unwrap(), vec![1]);
+        assert_eq!(repo.query_ids("children:drums").unwrap(), vec![1]);
+
+        // a case-only rename keeps the item's tags
+        repo.sync(vec![RelativePathBuf::from("Drums/kick.wav")])
+            .unwrap();
+        let item = repo.get_item_by_id(1).unwrap();
+        assert_eq!(item.path, "Drums/kick.wav");
+        assert_eq!(item.tags, vec!["kick"]);
+
+        repo.rename_path("drums/KICK.wav", "Drums/Kick 1.wav")
+            .unwrap();
+        assert_eq!(repo.get_item_by_id(1).unwrap().path, "Drums/Kick 1.wav");
+        repo.rename_dir("DRUMS", "Percussion").unwrap();
+        assert_eq!(
+            repo.get_item_by_id(1).unwrap().path,
+            "Percussion/Kick 1.wav"
+        );
     }
 
-    /// Repo with all possible combinations of letters "a", "b", "c", "d", "e"
-    fn testrepo_2() -> TestRepo {
-        let tr = empty_testrepo();
+    #[test]
+    fn can_plan_sync() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        let new_paths = vec![RelativePathBuf::from("apple"), RelativePathBuf::from("fig")];
 
-        let possible_tags: Vec<_> = "a b c d e".split_whitespace().collect();
+        let delta = repo.plan_sync(new_paths.clone()).unwrap();
+        assert_eq!(delta.created, vec!["fig"]);
+        assert_eq!(delta.retagged_lost, 4);
+        // planning doesn't modify the repo
+        assert_eq!(repo.count("").unwrap(), 5);
 
-        let mut counter = 0;
+        repo.apply_sync(&delta, |_| {}).unwrap();
+        assert_eq!(repo.count("").unwrap(), 2);
+    }
 
-        for i in 1..=possible_tags.len() {
-            for x in possible_tags.iter().combinations(i) {
-                let name = format!("item {}", counter);
-                let tags = x.iter().join(" ");
-                tr.repo.insert_item(name, tags).unwrap();
-                counter += 1;
-            }
-        }
+    #[test]
+    fn can_plan_staged_sync() {
+        let mut tr = testrepo_1();
+        let repo = &mut tr.repo;
+        repo.insert_item("drums/kick", "kick").unwrap();
 
-        tr
+        repo.begin_staged_sync().unwrap();
+        repo.stage_sync_paths(vec![RelativePathBuf::from("apple")])
+            .unwrap();
+        repo.stage_sync_paths(vec![
+            RelativePathBuf::from("fig"),
+            RelativePathBuf::from("percussion/kick"),
+        ])
+        .unwrap();
+        let delta = repo.plan_staged_sync().unwrap();
+        assert_eq!(delta.created, vec!["fig"]);
+        assert_eq!(
+            delta.renamed,
+            vec![("drums/kick".to_string(), "percussion/kick".to_string())]
+        );
+        assert_eq!(delta.retagged_lost, 4);
+        // staging and planning don't modify the repo
+        assert_eq!(repo.count("").unwrap(), 6);
+
+        // an earlier sync's paths are discarded
+        repo.begin_staged_sync().unwrap();
+        let delta = repo.plan_staged_sync().unwrap();
+        assert_eq!(delta.deleted.len(), 6);
+
+        repo.clear_staged_sync().unwrap();
+        assert!(repo.plan_staged_sync().is_err());
     }
 
     #[test]
-    fn check_tables_of_newly_created_database() {
+    fn tags_are_restored_by_file_name() {
         let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
+        repo.insert_item("a/photo.jpg", "cat cute").unwrap();
+        repo.insert_item("a/other.jpg", "").unwrap();
 
-        let mut stmt = repo
-            .conn
-            .prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")
+        repo.remove_item_by_path("a/photo.jpg").unwrap();
+        repo.remove_item_by_path("a/other.jpg").unwrap();
+        let item = repo
+            .insert_new_item(RelativePath::new("b/photo.jpg"))
             .unwrap();
-        let table_names = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
-        let table_names: Vec<_> = table_names.flatten().collect();
+        assert_eq!(item.tags, vec!["cat", "cute"]);
 
-        assert_unordered_eq(
-            table_names.iter().map(String::as_str),
-            [
-                "items",
-                "tag_query",
-                "tag_query_data",
-                "tag_query_idx",
-                "tag_query_docsize",
-                "tag_query_config",
-            ],
-        );
+        // the tombstone is removed once its tags are restored
+        let item = repo
+            .insert_new_item(RelativePath::new("c/photo.jpg"))
+            .unwrap();
+        assert!(item.tags.is_empty());
+        let item = repo
+            .insert_new_item(RelativePath::new("c/other.jpg"))
+            .unwrap();
+        assert!(item.tags.is_empty());
     }
 
     #[test]
-    fn can_insert_items() {
+    fn uid_is_kept_when_item_moves() {
         let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
+        let kick = repo.insert_item("a/kick.wav", "kick").unwrap();
+        let snare = repo.insert_item("a/snare.wav", "").unwrap();
+        assert_ne!(kick.uid, snare.uid);
 
-        repo.insert_item("hello", "text root").unwrap();
-        repo.insert_item("world", "video root").unwrap();
+        repo.rename_path("a/kick.wav", "b/kick.wav").unwrap();
+        assert_eq!(repo.get_item_by_id(kick.id).unwrap().uid, kick.uid);
 
-        let mut stmt = repo.conn.prepare("SELECT path FROM items").unwrap();
-        let item_names: Vec<String> = stmt
-            .query_map([], |row| row.get::<_, String>(0))
-            .unwrap()
-            .flatten()
-            .collect();
+        // the tombstone keeps the uid of the deleted item
+        repo.remove_item_by_path("b/kick.wav").unwrap();
+        let restored = repo
+            .insert_new_item(RelativePath::new("c/kick.wav"))
+            .unwrap();
+        assert_eq!(restored.uid, kick.uid);
+        assert_eq!(repo.get_item_by_path("c/kick.wav").unwrap().uid, kick.uid);
 
-        assert_unordered_eq(item_names.iter().map(String::as_str), ["hello", "world"]);
+        // untagged items don't have tombstones, so they get a new uid
+        repo.remove_item_by_path("a/snare.wav").unwrap();
+        let new = repo
+            .insert_new_item(RelativePath::new("a/snare.wav"))
+            .unwrap();
+        assert_ne!(new.uid, snare.uid);
     }
 
     #[test]
-    fn cant_insert_duplicate_items() {
+    fn uid_is_kept_when_sync_restores_item() {
         let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
+        let kick = repo.insert_item("a/kick.wav", "kick").unwrap();
+        let snare = repo.insert_item("a/snare.wav", "").unwrap();
+
+        let delta = repo.plan_sync(vec![]).unwrap();
+        repo.apply_sync(&delta, |_| {}).unwrap();
+
+        // the deleted items re-appear in another folder
+        let new_paths = vec![
+            RelativePathBuf::from("b/kick.wav"),
+            RelativePathBuf::from("b/snare.wav"),
+        ];
+        let delta = repo.plan_sync(new_paths).unwrap();
+        assert_eq!(delta.created.len(), 2);
+        repo.apply_sync(&delta, |_| {}).unwrap();
+
+        let restored = repo.get_item_by_path("b/kick.wav").unwrap();
+        assert_eq!(restored.tags, vec!["kick"]);
+        assert_eq!(restored.uid, kick.uid);
+        // untagged items don't have tombstones, so they get a new uid
+        let new = repo.get_item_by_path("b/snare.wav").unwrap();
+        assert!(!new.uid.is_empty());
+        assert_ne!(new.uid, snare.uid);
+    }
 
-        repo.insert_item("hello", "text root").unwrap();
-        let rv = repo.insert_item("hello", "video root");
+    #[test]
+    fn failed_restore_does_not_insert_item() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let kick = repo.insert_item("a/kick.wav", "kick").unwrap();
+        let snare = repo.insert_item("a/snare.wav", "snare").unwrap();
+        repo.remove_item_by_path("a/kick.wav").unwrap();
+
+        // restoring the uid fails, since another item has it now
+        repo.conn
+            .execute(
+                "UPDATE items SET uid = ?2 WHERE id = ?1",
+                params![snare.id, kick.uid],
+            )
+            .unwrap();
+        assert!(repo
+            .insert_new_item(RelativePath::new("b/kick.wav"))
+            .is_err());
+        assert!(repo.get_item_by_path("b/kick.wav").is_err());
+
+        // the tombstone is kept, so the tags are restored once the insert succeeds
+        repo.conn
+            .execute("UPDATE items SET uid = 'other' WHERE id = ?1", [snare.id])
+            .unwrap();
+        let item = repo
+            .insert_new_item(RelativePath::new("b/kick.wav"))
+            .unwrap();
+        assert_eq!(item.tags, vec!["kick"]);
+        assert_eq!(item.uid, kick.uid);
+    }
 
-        assert!(matches!(rv, Err(InsertError::DuplicatePathError(_))));
+    #[test]
+    fn tags_are_restored_by_hash() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        let root = repo.path().to_path_buf();
+        fs::create_dir(root.join("b")).unwrap();
+        fs::write(root.join("a.txt"), "some text").unwrap();
+        fs::write(root.join("same size.txt"), "some tex_").unwrap();
+        let item = repo.insert_item("a.txt", "text").unwrap();
+        let unhashed = repo.unhashed_items(0, 10).unwrap();
+        assert_eq!(unhashed, vec![(item.id, String::from("a.txt"))]);
+        assert_eq!(repo.get_hash_by_path("a.txt").unwrap(), None);
+        let hash = hash_file(root.join("a.txt")).unwrap();
+        repo.set_hash(item.id, 9, &hash).unwrap();
+        assert!(repo.unhashed_items(0, 10).unwrap().is_empty());
+        assert_eq!(repo.get_hash_by_path("a.txt").unwrap(), Some((9, hash)));
+
+        // move and rename the file, so it isn't detected as a rename
+        fs::rename(root.join("a.txt"), root.join("b/renamed.txt")).unwrap();
+        let delta = repo
+            .plan_sync(vec![
+                RelativePathBuf::from("b/renamed.txt"),
+                RelativePathBuf::from("same size.txt"),
+            ])
+            .unwrap();
+        assert_eq!(delta.retagged_lost, 1);
+        repo.apply_sync(&delta, |_| {}).unwrap();
+
+        let item = repo.get_item_by_path("b/renamed.txt").unwrap();
+        assert_eq!(item.tags, vec!["text"]);
+        let item = repo.get_item_by_path("same size.txt").unwrap();
+        assert!(item.tags.is_empty());
+        // the hash of the restored item is kept
+        assert!(repo.unhashed_items(0, 10).unwrap().is_empty());
     }
 
     #[test]
-    fn can_query_items() {
-        fn expect_query(repo: &Repo, query: &str, expected: Vec<&str>) {
-            let items = repo.query_items(query).unwrap();
+    fn activity_is_logged() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        repo.log_activity(ActivityKind::Created, "a.wav", None)
+            .unwrap();
+        repo.log_activity(ActivityKind::Renamed, "b.wav", Some("a.wav"))
+            .unwrap();
+        repo.log_activity(ActivityKind::Deleted, "b.wav", None)
+            .unwrap();
 
-            assert_unordered_eq(items.iter().map(|x| x.path.as_str()), expected);
-        }
+        let activity = repo.activity(10, &ActivityFilter::default()).unwrap();
+        let changes: Vec<_> = activity
+            .iter()
+            .map(|x| (x.kind, x.path.as_str(), x.old_path.as_deref()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (ActivityKind::Deleted, "b.wav", None),
+                (ActivityKind::Renamed, "b.wav", Some("a.wav")),
+                (ActivityKind::Created, "a.wav", None),
+            ]
+        );
+        assert_eq!(
+            repo.activity(1, &ActivityFilter::default()).unwrap().len(),
+            1
+        );
 
-        let mut tr = testrepo_1();
-        let repo = &mut tr.repo;
+        let filter = ActivityFilter { kind: Some(ActivityKind::Renamed), since: None };
+        let activity = repo.activity(10, &filter).unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(activity[0].path, "b.wav");
 
-        expect_query(&repo, "animal", vec!["bee", "cat", "dog"]);
-        expect_query(&repo, "food", vec!["apple", "egg"]);
-        expect_query(&repo, "yellow", vec!["bee", "cat"]);
+        let filter = ActivityFilter {
+            kind: None,
+            since: Some(unix_timestamp() as i64 + 60),
+        };
+        assert!(repo.activity(10, &filter).unwrap().is_empty());
     }
 
     #[test]
-    fn can_get_all_items() {
-        let mut tr = testrepo_1();
+    fn activity_log_is_capped() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
-        let items = repo.query_items("").unwrap();
-        assert_unordered_eq(
-            items.iter().map(|x| x.path.as_str()),
-            ["apple", "bee", "cat", "dog", "egg"],
-        )
+        for i in 0..ACTIVITY_LOG_SIZE + 5 {
+            repo.log_activity(ActivityKind::Created, &format!("{i}.wav"), None)
+                .unwrap();
+        }
+        let count: i64 = repo
+            .conn
+            .query_row("SELECT count(*) FROM activity_log", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, ACTIVITY_LOG_SIZE);
+        let latest = repo.activity(1, &ActivityFilter::default()).unwrap();
+        assert_eq!(latest[0].path, format!("{}.wav", ACTIVITY_LOG_SIZE + 4));
     }
 
     #[test]
-    fn can_get_item_by_path() {
-        let mut tr = testrepo_1();
+    fn can_get_item_sizes() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
-        let item = repo.get_item_by_path("apple").unwrap();
-        assert_eq!(item.id, 1);
-        assert_eq!(item.path, "apple");
-        assert_eq!(item.tags, vec!["food", "red"]);
+        let a = repo.insert_item("a.txt", "").unwrap();
+        let b = repo.insert_item("b.txt", "").unwrap();
+        repo.set_hash(a.id, 9, "abc").unwrap();
+
+        let sizes = repo.item_sizes(&[a.id, b.id, 12345]).unwrap();
+        assert_eq!(sizes, HashMap::from([(a.id, 9)]));
     }
 
     #[test]
-    fn can_get_item_by_id() {
-        let mut tr = testrepo_1();
+    fn can_query_folder_and_extension() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
-        let item = repo.get_item_by_id(1).unwrap();
-        assert_eq!(item.id, 1);
-        assert_eq!(item.path, "apple");
-        assert_eq!(item.tags, vec!["food", "red"]);
+        for path in [
+            "a/kick.WAV",
+            "a/snare.wav",
+            "a/b/hat.wav",
+            "a/b/notes.txt",
+            "c.wav",
+        ] {
+            repo.insert_item(path, "").unwrap();
+        }
+        assert_eq!(repo.count("ext:wav").unwrap(), 4);
+        // extensions are matched exactly, not as a pattern
+        assert_eq!(repo.count("ext:w_v").unwrap(), 0);
+        assert_eq!(repo.count("children:a").unwrap(), 2);
+        assert_eq!(repo.count("children:a/b ext:txt").unwrap(), 1);
+        assert_eq!(repo.all_folders().unwrap(), vec!["", "a", "a/b"]);
     }
 
     #[test]
-    fn can_remove_item_by_path() {
-        let mut tr = testrepo_1();
+    fn can_get_dir_children() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
-        repo.remove_item_by_path("apple").unwrap();
-        let rv = repo.get_item_by_path("apple");
-        assert!(matches!(rv, Err(SearchError::ItemNotFound)))
+        for path in [
+            "root.wav",
+            "drums/kick.wav",
+            "drums/snare.wav",
+            "drums/acoustic/kick.wav",
+            "drums/acoustic/old/kick.wav",
+            "drums/electronic/808.wav",
+            "drums 2/kick.wav",
+            "keys/piano/c.wav",
+        ] {
+            repo.insert_item(path, "").unwrap();
+        }
+
+        let names = |children: &[DirChild]| children.iter().map(|x| x.name()).collect_vec();
+        let counts = |x: &DirChild| (x.direct(), x.recursive(), x.subfolders());
+        let root = repo.dir_children("").unwrap();
+        assert_eq!(names(&root), vec!["drums", "drums 2", "keys"]);
+        assert_eq!(counts(&root[0]), (2, 5, 2));
+        assert_eq!(counts(&root[2]), (0, 1, 1));
+
+        // "drums 2" isn't inside "drums"
+        let drums = repo.dir_children("drums/").unwrap();
+        assert_eq!(names(&drums), vec!["acoustic", "electronic"]);
+        assert_eq!(counts(&drums[0]), (1, 2, 1));
+        assert!(repo.dir_children("keys/piano").unwrap().is_empty());
     }
 
     #[test]
-    fn can_remove_item_by_id() {
-        let mut tr = testrepo_1();
+    fn tag_memory_can_be_disabled() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
-        repo.remove_item_by_id(1).unwrap();
-        let rv = repo.get_item_by_id(1);
-        assert!(matches!(rv, Err(SearchError::ItemNotFound)))
+        let mut config = repo.config().clone();
+        config.tombstone_retention_days = 0;
+        repo.set_config(config).unwrap();
+
+        repo.insert_item("a/photo.jpg", "cat").unwrap();
+        repo.remove_item_by_path("a/photo.jpg").unwrap();
+        let item = repo
+            .insert_new_item(RelativePath::new("b/photo.jpg"))
+            .unwrap();
+        assert!(item.tags.is_empty());
     }
 
     #[test]
-    fn can_update_item_tags() {
-        let mut tr = testrepo_1();
+    fn can_import_tags_from_pattern() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
+        repo.insert_item("Foo - 120bpm - Kick.wav", "drums")
+            .unwrap();
+        repo.insert_item("loops/Bar - 90bpm - Pad.wav", "bpm=90")
+            .unwrap();
+        repo.insert_item("Kick.wav", "").unwrap();
+        let pattern = "{artist} - {bpm}bpm - {_}.wav";
 
-        let item = repo.get_item_by_path("apple").unwrap();
-        let new_tags = "computer laptop";
-        repo.update_tags(item.id, new_tags).unwrap();
+        let preview = repo.preview_tag_pattern(pattern).unwrap();
+        let preview: Vec<_> = preview
+            .iter()
+            .map(|x| (x.path.as_str(), x.new_tags.join(" ")))
+            .collect();
+        assert_eq!(
+            preview,
+            vec![
+                (
+                    "Foo - 120bpm - Kick.wav",
+                    String::from("artist=Foo bpm=120")
+                ),
+                ("loops/Bar - 90bpm - Pad.wav", String::from("artist=Bar")),
+            ]
+        );
+        // previewing doesn't modify the repo
+        assert_eq!(repo.count("artist=Foo").unwrap(), 0);
+
+        let matches = repo.import_tags_from_pattern(pattern).unwrap();
+        assert_eq!(matches.len(), 2);
+        let item = repo.get_item_by_path("Foo - 120bpm - Kick.wav").unwrap();
+        assert_eq!(item.tags, vec!["artist=Foo", "bpm=120", "drums"]);
+        let item = repo
+            .get_item_by_path("loops/Bar - 90bpm - Pad.wav")
+            .unwrap();
+        assert_eq!(item.tags, vec!["artist=Bar", "bpm=90"]);
+        assert_eq!(repo.count("bpm=120").unwrap(), 1);
 
-        // fetch item again
-        let item = repo.get_item_by_path("apple").unwrap();
-        let new_tags: Vec<_> = new_tags.split(" ").map(String::from).collect();
-        assert_eq!(item.tags, new_tags);
+        assert!(matches!(
+            repo.preview_tag_pattern("{artist"),
+            Err(TagPatternError::UnclosedField)
+        ));
     }
 
     #[test]
-    fn can_update_item_path() {
-        let mut tr = testrepo_1();
+    fn can_import_tags_csv() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("a.wav", "drums").unwrap();
+        repo.insert_item("loops/b.wav", "loop").unwrap();
+        repo.insert_item("c.wav", "pad").unwrap();
+        let absolute = repo.path.join("loops").join("b.wav");
+        let text = format!(
+            "path,tags\r\na.wav,kick 808\r\n\"{}\",loop\r\nmissing.wav,x\r\nc.wav,bad(tag)\r\n../d.wav,y\r\n",
+            absolute.display()
+        );
+        let rows = parse_csv(&text).unwrap();
+
+        let options = CsvImportOptions { replace: false, dry_run: true };
+        let report = repo.import_tags_csv(&rows, options).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.unchanged, 1);
+        let errors: Vec<_> = report
+            .errors
+            .iter()
+            .map(|x| (x.row, x.path.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![(4, "missing.wav"), (5, "c.wav"), (6, "../d.wav")]
+        );
+        // a dry run doesn't modify the repo
+        assert_eq!(repo.count("kick").unwrap(), 0);
+
+        let options = CsvImportOptions { replace: false, dry_run: false };
+        assert_eq!(repo.import_tags_csv(&rows, options).unwrap(), report);
+        let item = repo.get_item_by_path("a.wav").unwrap();
+        assert_eq!(item.tags, vec!["808", "drums", "kick"]);
+
+        let rows = parse_csv("a.wav,snare\nloops/b.wav,").unwrap();
+        let options = CsvImportOptions { replace: true, dry_run: false };
+        let report = repo.import_tags_csv(&rows, options).unwrap();
+        assert_eq!(report.imported, 2);
+        let item = repo.get_item_by_path("a.wav").unwrap();
+        assert_eq!(item.tags, vec!["snare"]);
+        let item = repo.get_item_by_path("loops/b.wav").unwrap();
+        assert!(item.tags.is_empty());
+    }
+
+    #[test]
+    fn can_insert_imported_files() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+        repo.insert_item("a/kick.wav", "drums").unwrap();
+        repo.insert_item("ab/pad.wav", "").unwrap();
+        let files = vec![
+            ImportedFile {
+                path: RelativePathBuf::from("a/b/snare.wav"),
+                tags: String::from("snare drums"),
+            },
+            ImportedFile {
+                path: RelativePathBuf::from("a/kick.wav"),
+                tags: String::from("kick"),
+            },
+        ];
+        assert_eq!(repo.insert_imported_files(&files).unwrap(), 1);
+
+        let items = repo.items_in_dir(RelativePath::new("a")).unwrap();
+        let items: Vec<_> = items.iter().map(|x| (x.path(), x.tags.join(" "))).collect();
+        assert_eq!(
+            items,
+            vec![
+                ("a/b/snare.wav", String::from("drums snare")),
+                // existing items aren't changed
+                ("a/kick.wav", String::from("drums")),
+            ]
+        );
+        assert_eq!(repo.items_in_dir(RelativePath::new("")).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn sync_reports_changes_in_chunks() {
+        let mut tr = empty_testrepo();
         let repo = &mut tr.repo;
+        repo.insert_item("tagged", "a").unwrap();
+        repo.insert_item("untagged", "").unwrap();
+        repo.insert_item("moved", "b").unwrap();
 
-        let item = repo.get_item_by_id(1).unwrap();
-        let new_path = "pizza";
-        repo.update_path(item.id, new_path).unwrap();
+        let mut new_paths: Vec<_> = (0..SYNC_CHUNK_SIZE)
+            .map(|i| RelativePathBuf::from(format!("new {}", i)))
+            .collect();
+        new_paths.push(RelativePathBuf::from("folder/moved"));
 
-        // fetch item again
-        let item = repo.get_item_by_id(1).unwrap();
-        assert_eq!(item.path, new_path);
+        let mut progress = vec![];
+        let report = repo
+            .sync_with_progress(new_paths, |x| progress.push((x.done, x.total)))
+            .unwrap();
+
+        assert_eq!(
+            report,
+            SyncReport {
+                created: SYNC_CHUNK_SIZE,
+                deleted: 2,
+                renamed: 1,
+                retagged_lost: 1,
+            }
+        );
+        let total = SYNC_CHUNK_SIZE + 3;
+        assert_eq!(progress, vec![(SYNC_CHUNK_SIZE, total), (total, total)]);
+        assert_eq!(
+            repo.get_item_by_path("folder/moved").unwrap().tags,
+            vec!["b"]
+        );
+        assert_eq!(repo.count("").unwrap(), SYNC_CHUNK_SIZE as i64 + 1);
     }
 
     #[test]
@@ -946,7 +5567,7 @@ mod tests {
                 AND i.path LIKE '%0%'
         "#};
 
-        let conn = tr.repo.conn;
+        let conn = &tr.repo.conn;
 
         let mut stmt = conn.prepare(&sql).unwrap();
         let out = stmt
@@ -1243,6 +5864,96 @@ mod tests {
         assert_eq!(item.tags, new_tags);
     }
 
+    #[test]
+    fn can_crud_rules() {
+        let tr = empty_testrepo();
+        let repo = &tr.repo;
+
+        let rule = repo
+            .insert_rule(vec![Condition::ExtensionIs("mid".into())], "midi")
+            .unwrap();
+        assert_eq!(rule.tags, vec!["midi"]);
+        assert!(rule.enabled);
+
+        let rule = repo
+            .update_rule(rule.id, rule.conditions, "midi song", false)
+            .unwrap();
+        assert_eq!(rule.tags, vec!["midi", "song"]);
+        assert!(!rule.enabled);
+        assert_eq!(repo.rules().unwrap().len(), 1);
+
+        repo.remove_rule(rule.id).unwrap();
+        assert!(repo.rules().unwrap().is_empty());
+        assert!(matches!(
+            repo.remove_rule(rule.id),
+            Err(RuleError::RuleNotFound)
+        ));
+    }
+
+    #[test]
+    fn rules_are_applied_on_insert_and_sync() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        repo.insert_rule(vec![Condition::FolderContains("loops".into())], "loop")
+            .unwrap();
+
+        let item = repo
+            .insert_new_item(RelativePath::new("Loops/a.wav"))
+            .unwrap();
+        assert_eq!(item.tags, vec!["loop"]);
+
+        repo.sync(vec![
+            RelativePathBuf::from("Loops/a.wav"),
+            RelativePathBuf::from("Loops/b.wav"),
+            RelativePathBuf::from("c.wav"),
+        ])
+        .unwrap();
+        assert_eq!(
+            repo.get_item_by_path("Loops/b.wav").unwrap().tags,
+            vec!["loop"]
+        );
+        assert!(repo.get_item_by_path("c.wav").unwrap().tags.is_empty());
+    }
+
+    #[test]
+    fn rules_are_skipped_if_auto_tag_is_disabled() {
+        let mut tr = empty_testrepo();
+        let repo = &mut tr.repo;
+        repo.insert_rule(vec![Condition::ExtensionIs("wav".into())], "audio")
+            .unwrap();
+        let mut config = repo.config().clone();
+        config.auto_tag = false;
+        repo.set_config(config).unwrap();
+
+        let item = repo.insert_new_item(RelativePath::new("a.wav")).unwrap();
+        assert!(item.tags.is_empty());
+    }
+
+    #[test]
+    fn can_preview_rule() {
+        let tr = testrepo_1();
+        let repo = &tr.repo;
+
+        let matches = repo
+            .preview_rule(&vec![Condition::PathMatches("*e*".into())], "e red")
+            .unwrap();
+        let matches: Vec<_> = matches
+            .iter()
+            .map(|m| (m.path.clone(), m.new_tags.join(" ")))
+            .collect();
+        // "apple" already has the "red" tag
+        let expected: Vec<_> = [("apple", "e"), ("bee", "e red"), ("egg", "e red")]
+            .iter()
+            .map(|(path, tags)| (path.to_string(), tags.to_string()))
+            .collect();
+        assert_eq!(matches, expected);
+        // previewing doesn't modify any items
+        assert_eq!(
+            repo.get_item_by_path("bee").unwrap().tags,
+            vec!["animal", "yellow"]
+        );
+    }
+
     // #[test]
     // fn print_sqlite_version() {
     //   let repo = new_repo();
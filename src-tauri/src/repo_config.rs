@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::helpers::fs::write_atomic;
+use crate::repo::RepoReadOnly;
+use crate::scan::Options;
+
+/// Name of the config file, stored in the `.tagrepo` folder.
+const CONFIG_FILE_NAME: &str = "config.json";
+
+#[derive(Error, Debug)]
+pub enum RepoConfigError {
+    #[error("failed to read or write repo config, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("malformed repo config, {0}")]
+    MalformedConfig(#[from] serde_json::Error),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+    #[error("failed to apply repo config, {0}")]
+    BackendError(#[from] rusqlite::Error),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortColumn {
+    Path,
+    Name,
+    Extension,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortOrder {
+    pub(crate) column: SortColumn,
+    pub(crate) descending: bool,
+}
+
+/// How the database is locked while the repo is open.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockingMode {
+    /// Only one program can open the repo at a time, see `LockGuard`. The database uses a WAL
+    /// journal, so the repo can be read while it is written to.
+    #[default]
+    Exclusive,
+    /// Multiple programs can open the repo at the same time, e.g. from different computers on a
+    /// network share. The database uses a rollback journal, since WAL doesn't work over a
+    /// network.
+    Shared,
+}
+
+/// How the repo folder is watched for changes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatcherStrategy {
+    /// Poll the folder if it's on a network drive, otherwise use the OS's change notifications.
+    #[default]
+    Auto,
+    /// Always use the OS's change notifications.
+    Native,
+    /// Always poll the folder, e.g. for filesystems that don't report changes reliably but
+    /// aren't detected as network drives.
+    Polling,
+}
+
+/// What happens when a tag containing one of `RepoConfig::invalid_tag_chars` is added to an item.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagPolicy {
+    /// Tags are added as-is, even if they can't be searched for without quotes.
+    Allow,
+    /// Adding the tag is an error.
+    #[default]
+    Reject,
+    /// Invalid characters are replaced with underscores.
+    Replace,
+}
+
+/// A column displayed in the item list. This mirrors `ListViewColumn` in the frontend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnConfig {
+    #[serde(rename = "type")]
+    pub(crate) column_type: String,
+    pub(crate) width: u32,
+}
+
+/// Settings for a single repo, stored in `.tagrepo/config.json`.
+///
+/// Missing fields are filled in with their defaults, so older config files can still be loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// Ignored paths, relative to the repo root.
+    pub(crate) excluded_paths: Vec<String>,
+    /// Ignored file and folder names, these are checked in all subfolders.
+    pub(crate) excluded_names: Vec<String>,
+    /// Whether auto-tagging rules are applied to newly discovered items.
+    pub(crate) auto_tag: bool,
+    pub(crate) default_sort: SortOrder,
+    pub(crate) columns: Vec<ColumnConfig>,
+    /// Minutes between background consistency checks, or 0 to disable them.
+    pub(crate) consistency_check_interval: u64,
+    /// Minutes between full resyncs while the repo isn't being watched, e.g. on network drives
+    /// where the watcher can't start, or 0 to disable them.
+    pub(crate) auto_resync_interval: u64,
+    /// Changes only take effect after the repo is reopened.
+    pub(crate) locking_mode: LockingMode,
+    /// Whether paths are compared case-insensitively, e.g. when syncing or searching for paths.
+    /// This should match the filesystem, so it defaults to true on Windows and macOS.
+    pub(crate) case_insensitive_paths: bool,
+    /// Whether tags are lowercased when they are added, so `Kick` and `kick` are the same tag.
+    /// Existing tags are lowercased when this is turned on.
+    pub(crate) case_insensitive_tags: bool,
+    /// Characters that aren't allowed in tags added by the user, since they conflict with the
+    /// query syntax, e.g. `in:x` is parsed as a folder search.
+    pub(crate) invalid_tag_chars: String,
+    pub(crate) tag_policy: TagPolicy,
+    /// Ask for confirmation before a resync deletes more than this many items with tags, e.g.
+    /// when a drive is briefly unplugged. Set to 0 to never ask.
+    pub(crate) sync_confirmation_threshold: usize,
+    /// Days to remember the tags of deleted items, so they can be restored if the file appears
+    /// again, e.g. after being moved out of the repo and back. Set to 0 to disable this.
+    pub(crate) tombstone_retention_days: u64,
+    /// Milliseconds to wait for a deleted file to appear elsewhere, so it is treated as a move.
+    /// Only used on Windows, where moves are reported as a deletion then a creation. Changes
+    /// only take effect after the repo is reopened.
+    pub(crate) rename_pairing_window_ms: u64,
+    /// Milliseconds to keep skipping watcher events after the app has finished changing files,
+    /// e.g. writing tags to them. Events can arrive late, and the app has already updated the
+    /// repo for its own changes.
+    pub(crate) watcher_resume_delay_ms: u64,
+    /// Changes only take effect after the repo is reopened.
+    pub(crate) watcher_strategy: WatcherStrategy,
+    /// Seconds between scans of the repo folder when it is polled, see `watcher_strategy`.
+    pub(crate) watcher_poll_interval: u64,
+    /// Named sets of tags that can be added to items in one action, e.g. `drum-kit` could be
+    /// `drum kick acoustic`. Tags are separated by spaces.
+    pub(crate) tag_templates: BTreeMap<String, String>,
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        fn column(column_type: &str, width: u32) -> ColumnConfig {
+            ColumnConfig { column_type: column_type.to_string(), width }
+        }
+
+        Self {
+            excluded_paths: vec![String::from(".tagrepo")],
+            excluded_names: vec![String::from(".git"), String::from(".tagrepo")],
+            auto_tag: true,
+            default_sort: SortOrder { column: SortColumn::Path, descending: false },
+            columns: vec![
+                column("name", 300),
+                column("tags", 160),
+                column("extension", 60),
+                column("path", 500),
+            ],
+            consistency_check_interval: 10,
+            auto_resync_interval: 0,
+            locking_mode: LockingMode::Exclusive,
+            case_insensitive_paths: cfg!(any(windows, target_os = "macos")),
+            case_insensitive_tags: false,
+            invalid_tag_chars: String::from(":/()"),
+            tag_policy: TagPolicy::Reject,
+            sync_confirmation_threshold: 20,
+            tombstone_retention_days: 30,
+            rename_pairing_window_ms: 10,
+            watcher_resume_delay_ms: 500,
+            watcher_strategy: WatcherStrategy::Auto,
+            watcher_poll_interval: 30,
+            tag_templates: BTreeMap::new(),
+        }
+    }
+}
+
+impl RepoConfig {
+    /// Load the config from the given `.tagrepo` folder. If no config file exists, the default
+    /// config is returned.
+    pub(crate) fn load(data_path: impl AsRef<Path>) -> Result<Self, RepoConfigError> {
+        let config_path = data_path.as_ref().join(CONFIG_FILE_NAME);
+        match fs::read_to_string(config_path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Save the config to the given `.tagrepo` folder.
+    pub(crate) fn save(&self, data_path: impl AsRef<Path>) -> Result<(), RepoConfigError> {
+        let config_path = data_path.as_ref().join(CONFIG_FILE_NAME);
+        let text = serde_json::to_string_pretty(self)?;
+        write_atomic(config_path, text)?;
+        Ok(())
+    }
+
+    /// The scan options used when scanning this repo. The `.tagrepo` folder is always excluded,
+    /// even if it has been removed from the config.
+    pub(crate) fn scan_options(&self) -> Options {
+        let mut excluded_paths: Vec<_> = self
+            .excluded_paths
+            .iter()
+            .map(RelativePathBuf::from)
+            .collect();
+        let data_path = RelativePathBuf::from(".tagrepo");
+        if !excluded_paths.contains(&data_path) {
+            excluded_paths.push(data_path);
+        }
+        Options::new(excluded_paths, self.excluded_names.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn missing_config_is_default() {
+        let dir = tempdir().unwrap();
+        assert_eq!(RepoConfig::load(&dir).unwrap(), RepoConfig::default());
+    }
+
+    #[test]
+    fn can_save_and_load_config() {
+        let dir = tempdir().unwrap();
+        let mut config = RepoConfig::default();
+        config.excluded_names.push(String::from("node_modules"));
+        config.auto_tag = false;
+        config.default_sort = SortOrder { column: SortColumn::Name, descending: true };
+        config
+            .tag_templates
+            .insert(String::from("drum-kit"), String::from("drum kick acoustic"));
+        config.save(&dir).unwrap();
+
+        assert_eq!(RepoConfig::load(&dir).unwrap(), config);
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            r#"{ "auto_tag": false }"#,
+        )
+        .unwrap();
+
+        let config = RepoConfig::load(&dir).unwrap();
+        assert!(!config.auto_tag);
+        assert_eq!(config.excluded_names, RepoConfig::default().excluded_names);
+    }
+
+    #[test]
+    fn malformed_config_is_error() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "{ not json").unwrap();
+
+        let rv = RepoConfig::load(&dir);
+        assert!(matches!(rv, Err(RepoConfigError::MalformedConfig(_))));
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Name of the lock file, stored in the `.tagrepo` folder.
+const LOCK_FILE_NAME: &str = "lock";
+
+/// Name of the file locked by `LockGuard`, stored in the `.tagrepo` folder. The lock file itself
+/// isn't locked, since other programs couldn't read it on Windows.
+const GUARD_FILE_NAME: &str = "lock.guard";
+
+/// Contents of the advisory lock file, which records who currently has the repo open.
+///
+/// The lock file is only used to give a useful error message when the repo is already open. The
+/// `LockGuard` is the source of truth: if it can be acquired, the lock file is stale and gets
+/// replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoLock {
+    pub(crate) host: String,
+    pub(crate) pid: u32,
+    /// Unix timestamp in seconds
+    pub(crate) opened_at: u64,
+}
+
+impl Display for RepoLock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "opened by process {} on {}", self.pid, self.host)
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("unknown host"))
+}
+
+fn lock_path(data_path: &Path) -> PathBuf {
+    data_path.join(LOCK_FILE_NAME)
+}
+
+impl RepoLock {
+    /// Create a lock for the current process.
+    pub(crate) fn current() -> Self {
+        let opened_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        Self {
+            host: hostname(),
+            pid: std::process::id(),
+            opened_at,
+        }
+    }
+
+    /// Read the lock file in the given `.tagrepo` folder. Returns `None` if there is no lock file,
+    /// or if it can't be read.
+    pub(crate) fn read(data_path: impl AsRef<Path>) -> Option<Self> {
+        let text = fs::read_to_string(lock_path(data_path.as_ref())).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Write this lock to the given `.tagrepo` folder, replacing any existing lock.
+    pub(crate) fn write(&self, data_path: impl AsRef<Path>) -> std::io::Result<()> {
+        let text = serde_json::to_string(self).expect("failed to serialize repo lock");
+        fs::write(lock_path(data_path.as_ref()), text)
+    }
+
+    /// Remove the lock file in the given `.tagrepo` folder, if it belongs to this lock.
+    pub(crate) fn release(&self, data_path: impl AsRef<Path>) {
+        let data_path = data_path.as_ref();
+        if Self::read(data_path).as_ref() != Some(self) {
+            // another program has taken over the lock
+            return;
+        }
+        if let Err(err) = fs::remove_file(lock_path(data_path)) {
+            warn!("Failed to remove repo lock file: {:?}", err);
+        }
+    }
+}
+
+/// An OS lock on a file in the `.tagrepo` folder, held while the repo is open in
+/// `LockingMode::Exclusive`, so only one program can open the repo at a time. The database itself
+/// isn't locked, so other connections from the same program can read it while it is written to,
+/// see `ReadPool`.
+///
+/// The OS releases the lock when the file is closed, or when the program exits or crashes.
+#[derive(Debug)]
+pub(crate) struct LockGuard {
+    #[allow(dead_code)]
+    file: File,
+}
+
+impl LockGuard {
+    /// Lock the guard file in the given `.tagrepo` folder. Returns `None` if another program, or
+    /// another repo in this program, holds the lock.
+    pub(crate) fn acquire(data_path: impl AsRef<Path>) -> std::io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(data_path.as_ref().join(GUARD_FILE_NAME))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err),
+        }
+    }
+}
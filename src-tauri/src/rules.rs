@@ -0,0 +1,251 @@
+use glob::{MatchOptions, Pattern, PatternError};
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::repo::RepoReadOnly;
+
+#[derive(Error, Debug)]
+pub enum RuleError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("invalid path pattern, {0}")]
+    InvalidPattern(#[from] PatternError),
+    #[error("failed to read rule conditions, {0}")]
+    MalformedConditions(#[from] serde_json::Error),
+    #[error("rule must have at least one condition")]
+    NoConditions,
+    #[error("rule must add at least one tag")]
+    NoTags,
+    #[error("failed to find rule")]
+    RuleNotFound,
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+/// A single condition of a rule. A rule applies only if all of its conditions match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum Condition {
+    /// The path matches a glob pattern, e.g. `*.mid` or `Drums/**/kick*`
+    PathMatches(String),
+    /// The file extension is equal to this value, e.g. `wav`
+    ExtensionIs(String),
+    /// Any of the parent folders contain this text, e.g. `loops`
+    FolderContains(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub(crate) id: i64,
+    pub(crate) conditions: Vec<Condition>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) enabled: bool,
+}
+
+/// An item that a rule would add tags to. Used to preview the effect of a rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleMatch {
+    pub(crate) id: i64,
+    pub(crate) path: String,
+    /// Tags that would be added to the item, excluding tags it already has
+    pub(crate) new_tags: Vec<String>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Check that the given rule definition can be stored and compiled.
+pub(crate) fn validate_rule(
+    conditions: &Vec<Condition>,
+    tags: &Vec<String>,
+) -> Result<(), RuleError> {
+    if conditions.is_empty() {
+        return Err(RuleError::NoConditions);
+    }
+    if tags.is_empty() {
+        return Err(RuleError::NoTags);
+    }
+    for condition in conditions {
+        CompiledCondition::new(condition)?;
+    }
+    Ok(())
+}
+
+enum CompiledCondition {
+    PathMatches(Pattern),
+    ExtensionIs(String),
+    FolderContains(String),
+}
+
+impl CompiledCondition {
+    fn new(condition: &Condition) -> Result<Self, RuleError> {
+        Ok(match condition {
+            Condition::PathMatches(pattern) => Self::PathMatches(Pattern::new(pattern)?),
+            Condition::ExtensionIs(ext) => {
+                Self::ExtensionIs(ext.trim_start_matches('.').to_lowercase())
+            }
+            Condition::FolderContains(text) => Self::FolderContains(text.to_lowercase()),
+        })
+    }
+
+    fn matches(&self, path: &RelativePath) -> bool {
+        match self {
+            Self::PathMatches(pattern) => pattern.matches_with(path.as_str(), MATCH_OPTIONS),
+            Self::ExtensionIs(ext) => match path.extension() {
+                Some(path_ext) => path_ext.to_lowercase() == *ext,
+                None => ext.is_empty(),
+            },
+            Self::FolderContains(text) => match path.parent() {
+                Some(parent) => parent
+                    .components()
+                    .any(|dirname| dirname.as_str().to_lowercase().contains(text.as_str())),
+                None => false,
+            },
+        }
+    }
+}
+
+struct CompiledRule {
+    conditions: Vec<CompiledCondition>,
+    tags: Vec<String>,
+}
+
+impl CompiledRule {
+    fn new(conditions: &Vec<Condition>, tags: &Vec<String>) -> Result<Self, RuleError> {
+        let conditions: Result<Vec<_>, _> = conditions.iter().map(CompiledCondition::new).collect();
+        Ok(Self { conditions: conditions?, tags: tags.clone() })
+    }
+
+    fn matches(&self, path: &RelativePath) -> bool {
+        // a rule without conditions should never be stored, but don't tag everything if it is
+        !self.conditions.is_empty() && self.conditions.iter().all(|c| c.matches(path))
+    }
+}
+
+/// A list of compiled rules, used to determine the tags of newly discovered items.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    /// Compile the given rules, skipping any rules that are disabled.
+    pub(crate) fn new<'a>(rules: impl IntoIterator<Item = &'a Rule>) -> Result<Self, RuleError> {
+        let rules: Result<Vec<_>, _> = rules
+            .into_iter()
+            .filter(|rule| rule.enabled)
+            .map(|rule| CompiledRule::new(&rule.conditions, &rule.tags))
+            .collect();
+        Ok(Self { rules: rules? })
+    }
+
+    /// Create a rule set containing a single rule, regardless of whether it is enabled.
+    pub(crate) fn single(
+        conditions: &Vec<Condition>,
+        tags: &Vec<String>,
+    ) -> Result<Self, RuleError> {
+        Ok(Self { rules: vec![CompiledRule::new(conditions, tags)?] })
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self { rules: vec![] }
+    }
+
+    /// Return the tags that should be given to a path, according to the rules in this set.
+    /// The tags are sorted and deduplicated.
+    pub(crate) fn tags_for(&self, path: impl AsRef<RelativePath>) -> Vec<String> {
+        let path = path.as_ref();
+        let mut tags: Vec<String> = vec![];
+        for rule in self.rules.iter().filter(|rule| rule.matches(path)) {
+            for tag in &rule.tags {
+                if let Err(pos) = tags.binary_search(tag) {
+                    tags.insert(pos, tag.clone());
+                }
+            }
+        }
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(conditions: Vec<Condition>, tags: &str) -> Rule {
+        Rule {
+            id: 0,
+            conditions,
+            tags: tags.split_whitespace().map(String::from).collect(),
+            enabled: true,
+        }
+    }
+
+    fn assert_tags(rules: &Vec<Rule>, path: &str, expected: Vec<&str>) {
+        let rule_set = RuleSet::new(rules).unwrap();
+        assert_eq!(rule_set.tags_for(RelativePath::new(path)), expected);
+    }
+
+    #[test]
+    fn path_matches() {
+        let rules = vec![rule(vec![Condition::PathMatches("*.mid".into())], "midi")];
+        assert_tags(&rules, "song.mid", vec!["midi"]);
+        assert_tags(&rules, "Songs/Chorus/song.MID", vec!["midi"]);
+        assert_tags(&rules, "song.wav", vec![]);
+    }
+
+    #[test]
+    fn extension_and_folder() {
+        let rules = vec![rule(
+            vec![
+                Condition::ExtensionIs("wav".into()),
+                Condition::FolderContains("loops".into()),
+            ],
+            "loop",
+        )];
+        assert_tags(&rules, "Drum Loops/120 Funk.wav", vec!["loop"]);
+        assert_tags(&rules, "Drum Loops/120 Funk.mp3", vec![]);
+        assert_tags(&rules, "Drums/loops.wav", vec![]);
+    }
+
+    #[test]
+    fn multiple_rules_are_merged() {
+        let rules = vec![
+            rule(vec![Condition::ExtensionIs(".wav".into())], "audio wav"),
+            rule(vec![Condition::FolderContains("kick".into())], "kick drum"),
+        ];
+        assert_tags(
+            &rules,
+            "Kicks/Kick 1.wav",
+            vec!["audio", "drum", "kick", "wav"],
+        );
+    }
+
+    #[test]
+    fn disabled_rules_are_skipped() {
+        let mut disabled = rule(vec![Condition::ExtensionIs("wav".into())], "wav");
+        disabled.enabled = false;
+        assert_tags(&vec![disabled], "a.wav", vec![]);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(matches!(
+            validate_rule(&vec![], &vec!["a".into()]),
+            Err(RuleError::NoConditions)
+        ));
+        assert!(matches!(
+            validate_rule(&vec![Condition::ExtensionIs("wav".into())], &vec![]),
+            Err(RuleError::NoTags)
+        ));
+        assert!(matches!(
+            validate_rule(
+                &vec![Condition::PathMatches("[a".into())],
+                &vec!["a".into()]
+            ),
+            Err(RuleError::InvalidPattern(_))
+        ));
+    }
+}
@@ -1,8 +1,12 @@
-use relative_path::RelativePathBuf;
+use relative_path::{RelativePath, RelativePathBuf};
 use std::fs;
 use std::fs::DirEntry;
 use std::io::Error;
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use jwalk::WalkDir;
 use thiserror::Error;
 use tracing::{debug, warn};
 
@@ -22,6 +26,12 @@ pub struct Options {
     excluded_names: Vec<String>,
 }
 
+impl Options {
+    pub fn new(excluded_paths: Vec<RelativePathBuf>, excluded_names: Vec<String>) -> Self {
+        Options { excluded_paths, excluded_names }
+    }
+}
+
 impl Default for Options {
     fn default() -> Self {
         Options {
@@ -31,33 +41,128 @@ impl Default for Options {
     }
 }
 
+/// Number of paths sent in each batch by `scan_dir_batched()`.
+const SCAN_BATCH_SIZE: usize = 1000;
+
 /// Scan a given folder, return a vector of paths `Vec<PathBuf>`
 #[tracing::instrument(skip(path), fields(path = path.as_ref().to_string_lossy().to_string()))]
 pub fn scan_dir(
     path: impl AsRef<Path>,
     options: Options,
 ) -> Result<Vec<RelativePathBuf>, ScanError> {
-    let path = path.as_ref();
+    let mut items = vec![];
+    scan_dir_batched(path, options, |batch| items.extend(batch))?;
+    Ok(items)
+}
+
+/// Scan a given folder, passing the found paths to `on_batch` in batches while the scan is still
+/// running. Folders are read in parallel. Returns the total number of paths found.
+pub(crate) fn scan_dir_batched(
+    path: impl AsRef<Path>,
+    options: Options,
+    mut on_batch: impl FnMut(Vec<RelativePathBuf>),
+) -> Result<usize, ScanError> {
+    let root_path = path.as_ref().to_path_buf();
 
     // make sure path is a directory
-    let metadata = path.metadata().map_err(ScanError::IOError)?;
+    let metadata = root_path.metadata().map_err(ScanError::IOError)?;
     if !metadata.is_dir() {
         return Err(ScanError::NotADirectory);
     }
 
+    let walker = WalkDir::new(&root_path)
+        .skip_hidden(false)
+        .follow_links(true)
+        .process_read_dir({
+            let root_path = root_path.clone();
+            let options = Arc::new(options);
+            // remove ignored entries here, so ignored folders are never read
+            move |depth, _path, _state, children| {
+                // this is also called with the root folder as the only child, with no depth
+                if depth.is_none() {
+                    return;
+                }
+                children.retain(|entry| match entry {
+                    Ok(entry) => {
+                        let relpath = to_relative_path(entry.path().as_path(), &root_path);
+                        !is_excluded(&relpath, &options)
+                    }
+                    Err(_) => true,
+                });
+            }
+        });
+
+    let mut total = 0;
+    let mut batch = Vec::with_capacity(SCAN_BATCH_SIZE);
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Failed to scan entry: {}", err);
+                continue;
+            }
+        };
+        // the root folder itself is also yielded by the walker
+        if entry.depth() == 0 || entry.file_type().is_dir() {
+            continue;
+        }
+        batch.push(to_relative_path(entry.path().as_path(), &root_path));
+        if batch.len() >= SCAN_BATCH_SIZE {
+            total += batch.len();
+            on_batch(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(SCAN_BATCH_SIZE),
+            ));
+        }
+    }
+    if !batch.is_empty() {
+        total += batch.len();
+        on_batch(batch);
+    }
+
+    Ok(total)
+}
+
+/// Scan a subfolder of the given root folder. The returned paths are relative to the root folder,
+/// not the subfolder. If `recursive` is false, only files directly inside the subfolder are
+/// returned. A subfolder that doesn't exist is treated as an empty folder.
+pub(crate) fn scan_subdir(
+    root_path: impl AsRef<Path>,
+    subdir: impl AsRef<RelativePath>,
+    recursive: bool,
+    options: Options,
+) -> Result<Vec<RelativePathBuf>, ScanError> {
+    let root_path = root_path.as_ref();
+    let path = subdir.as_ref().to_path(root_path);
+
+    let dir_iter = match fs::read_dir(&path) {
+        Ok(dir_iter) => dir_iter,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(ScanError::IOError(err)),
+    };
+
     let mut items = vec![];
     let mut unscanned_dirs = vec![];
+    classify_dir_items(
+        dir_iter,
+        &mut items,
+        &mut unscanned_dirs,
+        root_path,
+        &options,
+    );
+    if !recursive {
+        return Ok(items);
+    }
 
-    // scan the path for initial list of folders
-    let dir_iter = fs::read_dir(path).map_err(ScanError::IOError)?;
-    classify_dir_items(dir_iter, &mut items, &mut unscanned_dirs, &path, &options);
-
-    // scan remaining folders
     while !unscanned_dirs.is_empty() {
         match fs::read_dir(unscanned_dirs.pop().unwrap()) {
-            Ok(dir_iter) => {
-                classify_dir_items(dir_iter, &mut items, &mut unscanned_dirs, &path, &options)
-            }
+            Ok(dir_iter) => classify_dir_items(
+                dir_iter,
+                &mut items,
+                &mut unscanned_dirs,
+                root_path,
+                &options,
+            ),
             Err(err) => warn!("Failed to scan folder: {}", err),
         }
     }
@@ -65,6 +170,25 @@ pub fn scan_dir(
     Ok(items)
 }
 
+/// List the folders directly inside the given root folder, excluding ignored folders.
+pub(crate) fn top_level_dirs(
+    root_path: impl AsRef<Path>,
+    options: &Options,
+) -> Result<Vec<RelativePathBuf>, ScanError> {
+    let root_path = root_path.as_ref();
+    let mut dirs = vec![];
+    for entry in fs::read_dir(root_path).map_err(ScanError::IOError)? {
+        let Ok(entry) = entry else {
+            warn!("Failed to scan entry: {:?}", entry);
+            continue;
+        };
+        if let PathType::Directory(path) = classify_path(entry.path(), root_path, options) {
+            dirs.push(to_relative_path(path.as_path(), root_path));
+        }
+    }
+    Ok(dirs)
+}
+
 pub(crate) enum PathType {
     Item(RelativePathBuf),
     Directory(PathBuf),
@@ -79,6 +203,30 @@ pub(crate) fn to_relative_path(path: &Path, root_path: &Path) -> RelativePathBuf
     RelativePathBuf::from_path(relpath).expect("failed to convert to RelativePathBuf")
 }
 
+/// Whether the path should be skipped when scanning, according to the given options.
+fn is_excluded(relpath: &RelativePath, options: &Options) -> bool {
+    if options
+        .excluded_paths
+        .iter()
+        .any(|path| path.as_relative_path() == relpath)
+    {
+        debug!("Skipping excluded path: {}", relpath);
+        return true;
+    }
+
+    let file_name = relpath.file_name().expect("path doesn't have file name");
+    if options
+        .excluded_names
+        .iter()
+        .any(|name| name.as_str() == file_name)
+    {
+        debug!("Skipping excluded file name: {}", relpath);
+        return true;
+    }
+
+    false
+}
+
 pub(crate) fn classify_path(path: PathBuf, root_path: &Path, options: &Options) -> PathType {
     let is_dir = match fs::metadata(&path) {
         Ok(metadata) => metadata.is_dir(),
@@ -91,18 +239,7 @@ pub(crate) fn classify_path(path: PathBuf, root_path: &Path, options: &Options)
     // convert to relative path
     let relpath = to_relative_path(path.as_path(), root_path);
 
-    if options.excluded_paths.contains(&relpath) {
-        debug!("Skipping excluded path: {}", relpath);
-        return PathType::Ignored;
-    }
-
-    let file_name = relpath.file_name().expect("path doesn't have file name");
-    if options
-        .excluded_names
-        .iter()
-        .any(|name| name.as_str() == file_name)
-    {
-        debug!("Skipping excluded file name: {}", relpath);
+    if is_excluded(&relpath, options) {
         return PathType::Ignored;
     }
 
@@ -192,6 +329,60 @@ mod tests {
         assert_unordered_eq(scanned_paths.iter().map(|x| x.as_str()), expected)
     }
 
+    #[test]
+    fn scans_nested_folders_in_batches() {
+        let dir = test_folder_1();
+        fs::create_dir_all(dir.path().join("drums/kicks")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        File::create(dir.path().join(".git/HEAD")).unwrap();
+        for i in 0..SCAN_BATCH_SIZE {
+            File::create(dir.path().join(format!("drums/kicks/kick {}", i))).unwrap();
+        }
+
+        let mut batches = vec![];
+        let total =
+            scan_dir_batched(&dir, Options::default(), |batch| batches.push(batch)).unwrap();
+
+        assert_eq!(total, SCAN_BATCH_SIZE + 3);
+        assert_eq!(batches.len(), 2);
+        let paths: Vec<_> = batches.into_iter().flatten().collect();
+        assert_eq!(paths.len(), total);
+        assert!(paths.contains(&RelativePathBuf::from("drums/kicks/kick 0")));
+        assert!(!paths.iter().any(|x| x.starts_with(".git")));
+    }
+
+    #[test]
+    fn scans_subfolder() {
+        let dir = test_folder_1();
+        fs::create_dir_all(dir.path().join("drums/kicks")).unwrap();
+        File::create(dir.path().join("drums/snare")).unwrap();
+        File::create(dir.path().join("drums/kicks/kick")).unwrap();
+
+        let scanned_paths = scan_subdir(&dir, "drums", true, Options::default()).unwrap();
+        assert_unordered_eq(
+            scanned_paths.iter().map(|x| x.as_str()),
+            vec!["drums/snare", "drums/kicks/kick"],
+        );
+
+        let scanned_paths = scan_subdir(&dir, "drums", false, Options::default()).unwrap();
+        assert_unordered_eq(
+            scanned_paths.iter().map(|x| x.as_str()),
+            vec!["drums/snare"],
+        );
+
+        let scanned_paths = scan_subdir(&dir, "", false, Options::default()).unwrap();
+        assert_unordered_eq(
+            scanned_paths.iter().map(|x| x.as_str()),
+            vec!["apple", "bee", "cat"],
+        );
+
+        let scanned_paths = scan_subdir(&dir, "missing", true, Options::default()).unwrap();
+        assert!(scanned_paths.is_empty());
+
+        let dirs = top_level_dirs(&dir, &Options::default()).unwrap();
+        assert_eq!(dirs, vec![RelativePathBuf::from("drums")]);
+    }
+
     // #[test]
     // fn set_benchmark() -> () {
     //     let path = PathBuf::from(r#"D:\Audio Samples\"#);
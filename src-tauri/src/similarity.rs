@@ -0,0 +1,200 @@
+//! Fingerprints of audio files, used to find similar sounding samples.
+//!
+//! A fingerprint describes the spectrum of a sound: the file is split into frames, and the power
+//! of each frame is measured at a fixed set of frequencies. The fingerprint is the mean and
+//! standard deviation of each frequency's power across frames, so it doesn't depend on the length
+//! of the sound. The power is relative to the frame's average, so loudness doesn't matter either.
+
+use std::f32::consts::PI;
+#[cfg(feature = "audio")]
+use std::path::Path;
+#[cfg(feature = "audio")]
+use std::time::Duration;
+
+/// Number of frequencies measured in each frame.
+const BANDS: usize = 24;
+
+/// Frequencies are spaced logarithmically between these, in Hz.
+const MIN_FREQ: f32 = 50.0;
+const MAX_FREQ: f32 = 10000.0;
+
+/// Number of samples in each frame.
+const FRAME_LEN: usize = 2048;
+
+/// Frames quieter than this are skipped, so silence at the start or end of a file is ignored.
+/// This is the mean squared amplitude, about -60 dB.
+const SILENCE: f32 = 1e-6;
+
+/// Added to the power of each frequency relative to the frame's energy before taking its log, so
+/// the log is finite.
+const POWER_FLOOR: f32 = 1e-9;
+
+/// Only the start of a file is decoded, long files like songs are usually identified by it.
+#[cfg(feature = "audio")]
+const MAX_DURATION: Duration = Duration::from_secs(30);
+
+/// Number of values in a fingerprint.
+pub(crate) const FINGERPRINT_LEN: usize = BANDS * 2;
+
+/// Compute the fingerprint of mono samples between -1 and 1. Returns `None` if the sound is
+/// shorter than a frame or is silent.
+pub(crate) fn fingerprint(samples: &[f32], sample_rate: u32) -> Option<Vec<f32>> {
+    let max_freq = MAX_FREQ.min(sample_rate as f32 / 2.0);
+    let freqs: Vec<f32> = (0..BANDS)
+        .map(|i| MIN_FREQ * (max_freq / MIN_FREQ).powf(i as f32 / (BANDS - 1) as f32))
+        .collect();
+    let window: Vec<f32> = (0..FRAME_LEN)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FRAME_LEN - 1) as f32).cos())
+        .collect();
+
+    let mut sum = [0.0; BANDS];
+    let mut sum_sq = [0.0; BANDS];
+    let mut frames = 0;
+    let mut frame = vec![0.0; FRAME_LEN];
+    for chunk in samples.chunks_exact(FRAME_LEN) {
+        let energy = chunk.iter().map(|x| x * x).sum::<f32>() / FRAME_LEN as f32;
+        if energy < SILENCE {
+            continue;
+        }
+        for ((x, sample), weight) in frame.iter_mut().zip(chunk).zip(&window) {
+            *x = sample * weight;
+        }
+        let powers: Vec<f32> = freqs
+            .iter()
+            .map(|&freq| {
+                let power = goertzel(&frame, freq / sample_rate as f32) / energy;
+                (power + POWER_FLOOR).ln()
+            })
+            .collect();
+        let mean = powers.iter().sum::<f32>() / BANDS as f32;
+        for (i, power) in powers.into_iter().enumerate() {
+            let power = power - mean;
+            sum[i] += power;
+            sum_sq[i] += power * power;
+        }
+        frames += 1;
+    }
+    if frames == 0 {
+        return None;
+    }
+
+    let frames = frames as f32;
+    let mut rv = Vec::with_capacity(FINGERPRINT_LEN);
+    rv.extend(sum.iter().map(|sum| sum / frames));
+    rv.extend(sum.iter().zip(&sum_sq).map(|(sum, sum_sq)| {
+        let mean = sum / frames;
+        (sum_sq / frames - mean * mean).max(0.0).sqrt()
+    }));
+    let norm = rv.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        rv.iter_mut().for_each(|x| *x /= norm);
+    }
+    Some(rv)
+}
+
+/// Power of a frame at a single frequency, given in cycles per sample.
+fn goertzel(frame: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * freq).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in frame {
+        let s0 = x + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2) / frame.len() as f32
+}
+
+/// How similar two fingerprints are, from -1 to 1 where 1 is identical.
+pub(crate) fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Decode an audio file and compute its fingerprint. Returns `None` if the file can't be decoded,
+/// e.g. if it isn't an audio file.
+#[cfg(feature = "audio")]
+pub(crate) fn fingerprint_file(path: impl AsRef<Path>) -> std::io::Result<Option<Vec<f32>>> {
+    use rodio::{Decoder, Source};
+
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let Ok(source) = Decoder::new(file) else {
+        return Ok(None);
+    };
+    let channels = source.channels().max(1) as usize;
+    let sample_rate = source.sample_rate();
+    let samples: Vec<i16> = source.take_duration(MAX_DURATION).collect();
+    // mix down to mono
+    let samples: Vec<f32> = samples
+        .chunks(channels)
+        .map(|frame| {
+            frame
+                .iter()
+                .map(|&x| x as f32 / i16::MAX as f32)
+                .sum::<f32>()
+                / frame.len() as f32
+        })
+        .collect();
+    Ok(fingerprint(&samples, sample_rate))
+}
+
+/// Store a fingerprint as little-endian floats.
+pub(crate) fn to_bytes(fingerprint: &[f32]) -> Vec<u8> {
+    fingerprint.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub(crate) fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn sine(freq: f32, amplitude: f32) -> Vec<f32> {
+        (0..SAMPLE_RATE)
+            .map(|i| amplitude * (2.0 * PI * freq * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    fn noise() -> Vec<f32> {
+        // a simple LCG, so the test is deterministic
+        let mut state: u32 = 1;
+        (0..SAMPLE_RATE)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn similar_sounds_have_similar_fingerprints() {
+        let a = fingerprint(&sine(440.0, 0.8), SAMPLE_RATE).unwrap();
+        let quiet = fingerprint(&sine(440.0, 0.1), SAMPLE_RATE).unwrap();
+        let close = fingerprint(&sine(450.0, 0.8), SAMPLE_RATE).unwrap();
+        let high = fingerprint(&sine(3000.0, 0.8), SAMPLE_RATE).unwrap();
+        let noise = fingerprint(&noise(), SAMPLE_RATE).unwrap();
+        assert_eq!(a.len(), FINGERPRINT_LEN);
+
+        assert!((similarity(&a, &a) - 1.0).abs() < 1e-4);
+        assert!(similarity(&a, &quiet) > 0.99);
+        assert!(similarity(&a, &close) > similarity(&a, &high));
+        assert!(similarity(&a, &close) > similarity(&a, &noise));
+    }
+
+    #[test]
+    fn silence_has_no_fingerprint() {
+        assert_eq!(fingerprint(&[0.0; FRAME_LEN * 4], SAMPLE_RATE), None);
+        assert_eq!(fingerprint(&sine(440.0, 0.8)[..100], SAMPLE_RATE), None);
+    }
+
+    #[test]
+    fn fingerprints_are_stored_as_bytes() {
+        let fingerprint = vec![0.5, -1.25, 3.0];
+        assert_eq!(from_bytes(&to_bytes(&fingerprint)), fingerprint);
+    }
+}
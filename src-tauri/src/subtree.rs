@@ -0,0 +1,243 @@
+//! Copying a folder of a repo to another location along with its tags, and merging such a copy
+//! into another repo. This is used to split a large repo into smaller ones, or to merge repos.
+//!
+//! The tags are stored in a manifest in the root of the copy, which is a CSV file in the same
+//! format as `ExportFormat::Csv`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::export::write_csv;
+use crate::repo::{Item, RepoReadOnly};
+use crate::tag_import::{is_header, parse_csv, resolve_path, CsvImportError};
+
+/// File name of the tag manifest in an exported folder.
+pub(crate) const MANIFEST_NAME: &str = "tags.csv";
+
+#[derive(Error, Debug)]
+pub enum SubtreeError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to copy files, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("folder {0:?} isn't empty")]
+    DestinationNotEmpty(PathBuf),
+    #[error("invalid folder {0:?}, {1}")]
+    InvalidFolder(String, &'static str),
+    #[error("no tag manifest found in {0:?}")]
+    MissingManifest(PathBuf),
+    #[error("failed to read tag manifest, {0}")]
+    InvalidManifest(#[from] CsvImportError),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+/// A file that couldn't be copied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubtreeFileError {
+    pub(crate) path: String,
+    pub(crate) error: String,
+}
+
+/// Result of exporting or importing a folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SubtreeReport {
+    /// Number of files copied
+    pub(crate) copied: usize,
+    /// Files that weren't copied, e.g. because a file already exists at the destination
+    pub(crate) errors: Vec<SubtreeFileError>,
+}
+
+impl SubtreeReport {
+    fn push_error(&mut self, path: impl ToString, error: impl ToString) {
+        self.errors
+            .push(SubtreeFileError { path: path.to_string(), error: error.to_string() });
+    }
+}
+
+/// A file copied into a repo by `import_subtree()`, which should be added as an item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ImportedFile {
+    pub(crate) path: RelativePathBuf,
+    pub(crate) tags: String,
+}
+
+/// Check a folder given by the user, and convert it to a path relative to the repo root. An
+/// empty string is the repo root.
+pub(crate) fn parse_folder(dir: &str) -> Result<RelativePathBuf, SubtreeError> {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        return Ok(RelativePathBuf::new());
+    }
+    resolve_path(dir, Path::new("")).map_err(|err| SubtreeError::InvalidFolder(dir.into(), err))
+}
+
+/// Copy the files of the given items to `dest`, keeping their paths relative to `dir`, and write a
+/// manifest with their tags. `dest` must be empty or not exist yet.
+pub(crate) fn export_subtree(
+    items: &[Item],
+    dir: &RelativePath,
+    repo_path: &Path,
+    dest: &Path,
+) -> Result<SubtreeReport, SubtreeError> {
+    if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+        return Err(SubtreeError::DestinationNotEmpty(dest.to_path_buf()));
+    }
+    fs::create_dir_all(dest)?;
+
+    let mut report = SubtreeReport::default();
+    let mut copied = vec![];
+    for item in items {
+        let relpath = match RelativePath::new(&item.path).strip_prefix(dir) {
+            Ok(relpath) => relpath,
+            Err(_) => {
+                report.push_error(&item.path, "item is outside the folder");
+                continue;
+            }
+        };
+        let target = relpath.to_path(dest);
+        let result = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::copy(RelativePath::new(&item.path).to_path(repo_path), &target));
+        match result {
+            Ok(_) => copied.push(Item { path: relpath.to_string(), ..item.clone() }),
+            Err(err) => report.push_error(&item.path, err),
+        }
+    }
+    report.copied = copied.len();
+
+    let mut writer = fs::File::create(dest.join(MANIFEST_NAME))?;
+    write_csv(&mut writer, &copied)?;
+    Ok(report)
+}
+
+/// Copy a folder written by `export_subtree()` into the folder `dir` of a repo. Files that
+/// already exist in the repo are skipped. Returns the copied files with their tags from the
+/// manifest, which should be added to the repo.
+pub(crate) fn import_subtree(
+    src: &Path,
+    dir: &RelativePath,
+    repo_path: &Path,
+) -> Result<(SubtreeReport, Vec<ImportedFile>), SubtreeError> {
+    let manifest_path = src.join(MANIFEST_NAME);
+    if !manifest_path.is_file() {
+        return Err(SubtreeError::MissingManifest(src.to_path_buf()));
+    }
+    let text = String::from_utf8(fs::read(manifest_path)?).map_err(|_| CsvImportError::NotText)?;
+    let rows = parse_csv(&text)?;
+
+    let mut report = SubtreeReport::default();
+    let mut files = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        if i == 0 && is_header(row) {
+            continue;
+        }
+        let relpath = match resolve_path(&row.fields[0], Path::new("")) {
+            Ok(relpath) => relpath,
+            Err(err) => {
+                report.push_error(&row.fields[0], err);
+                continue;
+            }
+        };
+        let path = dir.join(&relpath);
+        let target = path.to_path(repo_path);
+        if target.exists() {
+            report.push_error(&path, "file already exists");
+            continue;
+        }
+        let result = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::copy(relpath.to_path(src), &target));
+        match result {
+            Ok(_) => files.push(ImportedFile { path, tags: row.fields[1..].join(" ") }),
+            Err(err) => report.push_error(&path, err),
+        }
+    }
+    report.copied = files.len();
+    Ok((report, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn item(id: i64, path: &str, tags: &[&str]) -> Item {
+        Item {
+            id,
+            path: path.to_string(),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            meta_tags: String::new(),
+            updated_at: 0,
+            uid: String::new(),
+        }
+    }
+
+    #[test]
+    fn subtree_can_be_exported_and_imported() {
+        let repo = tempdir().unwrap();
+        fs::create_dir_all(repo.path().join("a/b")).unwrap();
+        fs::write(repo.path().join("a/kick.wav"), "kick").unwrap();
+        fs::write(repo.path().join("a/b/pad, soft.wav"), "pad").unwrap();
+        let items = vec![
+            item(1, "a/kick.wav", &["drums", "kick"]),
+            item(2, "a/b/pad, soft.wav", &[]),
+            item(3, "a/missing.wav", &["x"]),
+        ];
+
+        let copy = tempdir().unwrap();
+        let dest = copy.path().join("split");
+        let dir = RelativePath::new("a");
+        let report = export_subtree(&items, dir, repo.path(), &dest).unwrap();
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].path, "a/missing.wav");
+        assert_eq!(
+            fs::read_to_string(dest.join("b/pad, soft.wav")).unwrap(),
+            "pad"
+        );
+        assert!(matches!(
+            export_subtree(&items, dir, repo.path(), &dest),
+            Err(SubtreeError::DestinationNotEmpty(_))
+        ));
+
+        // the first file already exists in the repo
+        let (report, files) = import_subtree(&dest, dir, repo.path()).unwrap();
+        assert_eq!(report.copied, 0);
+        assert_eq!(report.errors.len(), 2);
+        assert!(files.is_empty());
+
+        let (report, files) =
+            import_subtree(&dest, RelativePath::new("merged"), repo.path()).unwrap();
+        assert_eq!(report, SubtreeReport { copied: 2, errors: vec![] });
+        assert_eq!(
+            files,
+            vec![
+                ImportedFile {
+                    path: RelativePathBuf::from("merged/kick.wav"),
+                    tags: String::from("drums kick"),
+                },
+                ImportedFile {
+                    path: RelativePathBuf::from("merged/b/pad, soft.wav"),
+                    tags: String::new(),
+                },
+            ]
+        );
+        let kick = repo.path().join("merged/kick.wav");
+        assert_eq!(fs::read_to_string(kick).unwrap(), "kick");
+    }
+
+    #[test]
+    fn folders_are_checked() {
+        assert_eq!(parse_folder("").unwrap(), "");
+        assert_eq!(parse_folder("/a/b/").unwrap(), "a/b");
+        assert!(parse_folder("../a").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::Path;
+
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::repo::RepoReadOnly;
+
+#[derive(Error, Debug)]
+pub enum CsvImportError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("failed to read CSV file, {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("CSV file isn't valid UTF-8 text")]
+    NotText,
+    #[error("row {0} has an unclosed quote")]
+    UnclosedQuote(usize),
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+/// Options for `Repo::import_tags_csv()`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvImportOptions {
+    /// Replace the tags of each item, instead of adding to them
+    pub(crate) replace: bool,
+    /// Don't modify the repo, only report what would be imported
+    pub(crate) dry_run: bool,
+}
+
+/// A row of a CSV file, as a list of fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CsvRow {
+    /// Row number in the file, starting from 1
+    pub(crate) row: usize,
+    pub(crate) fields: Vec<String>,
+}
+
+/// A row that couldn't be imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CsvRowError {
+    pub(crate) row: usize,
+    pub(crate) path: String,
+    pub(crate) error: String,
+}
+
+/// Result of importing tags from a CSV file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CsvImportReport {
+    /// Number of items whose tags were changed
+    pub(crate) imported: usize,
+    /// Number of rows that matched an item, but didn't change its tags
+    pub(crate) unchanged: usize,
+    pub(crate) errors: Vec<CsvRowError>,
+}
+
+/// Parse CSV text as described in RFC 4180, the format written by `ExportFormat::Csv`. Both CRLF
+/// and LF line endings are accepted, and empty lines are skipped.
+pub(crate) fn parse_csv(text: &str) -> Result<Vec<CsvRow>, CsvImportError> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut rows = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut row = 1;
+    // line that the current row starts on, rows may span multiple lines if a field is quoted
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    let mut in_quotes = false;
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.len() == 1 && fields[0].is_empty() {
+                    fields.clear();
+                } else {
+                    rows.push(CsvRow { row, fields: std::mem::take(&mut fields) });
+                }
+                line += 1;
+                row = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(CsvImportError::UnclosedQuote(row));
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        rows.push(CsvRow { row, fields });
+    }
+    Ok(rows)
+}
+
+/// Whether a row is the header written by `ExportFormat::Csv`.
+pub(crate) fn is_header(row: &CsvRow) -> bool {
+    matches!(&row.fields[..], [path, tags]
+        if path.trim().eq_ignore_ascii_case("path") && tags.trim().eq_ignore_ascii_case("tags"))
+}
+
+/// Convert a path from a CSV file to a path relative to the repo root. Absolute paths must be
+/// inside the repo, and relative paths are resolved against the repo root.
+pub(crate) fn resolve_path(path: &str, repo_path: &Path) -> Result<RelativePathBuf, &'static str> {
+    let path = Path::new(path);
+    let relpath = if path.is_absolute() {
+        let relpath = path
+            .strip_prefix(repo_path)
+            .map_err(|_| "path is outside the repo")?;
+        RelativePathBuf::from_path(relpath).map_err(|_| "malformed path")?
+    } else {
+        RelativePathBuf::from_path(path).map_err(|_| "malformed path")?
+    };
+    let relpath = relpath.normalize();
+    if relpath.as_str().is_empty() {
+        return Err("path is empty");
+    }
+    if relpath.starts_with("..") {
+        return Err("path is outside the repo");
+    }
+    Ok(relpath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(rows: &[CsvRow]) -> Vec<(usize, Vec<&str>)> {
+        rows.iter()
+            .map(|r| (r.row, r.fields.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_csv() {
+        let text =
+            "path,tags\r\na.wav,kick 808\r\n\r\n\"b, \"\"c\"\".wav\",\"multi\nline\"\nd.wav,snare";
+        let rows = parse_csv(text).unwrap();
+        assert_eq!(
+            fields(&rows),
+            vec![
+                (1, vec!["path", "tags"]),
+                (2, vec!["a.wav", "kick 808"]),
+                (4, vec!["b, \"c\".wav", "multi\nline"]),
+                (6, vec!["d.wav", "snare"]),
+            ]
+        );
+        assert!(is_header(&rows[0]));
+        assert!(!is_header(&rows[1]));
+        assert!(matches!(
+            parse_csv("a.wav,\"kick\nb.wav,snare"),
+            Err(CsvImportError::UnclosedQuote(1))
+        ));
+    }
+
+    #[test]
+    fn resolves_paths() {
+        let root = if cfg!(windows) {
+            Path::new("C:\\repo")
+        } else {
+            Path::new("/repo")
+        };
+        assert_eq!(resolve_path("a/./b.wav", root).unwrap(), "a/b.wav");
+        assert_eq!(resolve_path("a/../b.wav", root).unwrap(), "b.wav");
+        let absolute = root.join("a").join("b.wav");
+        assert_eq!(
+            resolve_path(absolute.to_str().unwrap(), root).unwrap(),
+            "a/b.wav"
+        );
+        assert!(resolve_path("../b.wav", root).is_err());
+        assert!(resolve_path(".", root).is_err());
+        let outside = root.parent().unwrap().join("b.wav");
+        assert!(resolve_path(outside.to_str().unwrap(), root).is_err());
+    }
+}
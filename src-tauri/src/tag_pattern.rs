@@ -0,0 +1,242 @@
+use relative_path::RelativePath;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::repo::RepoReadOnly;
+
+#[derive(Error, Debug)]
+pub enum TagPatternError {
+    #[error("an error occurred in rusqlite, {0}")]
+    BackendError(#[from] rusqlite::Error),
+    #[error("pattern has an unclosed field, a `{{` must be followed by a `}}`")]
+    UnclosedField,
+    #[error("pattern has an unexpected `}}`")]
+    UnexpectedBrace,
+    #[error("invalid field name {0:?}, names can't contain spaces or `=`")]
+    InvalidFieldName(String),
+    #[error("pattern must have at least one field, e.g. `{{artist}} - {{_}}.wav`")]
+    NoFields,
+    #[error("fields must be separated by some text, e.g. `{{a}} - {{b}}` instead of `{{a}}{{b}}`")]
+    AdjacentFields,
+    #[error("{0}")]
+    ReadOnly(#[from] RepoReadOnly),
+}
+
+/// An item that a tag pattern would add tags to. Used to preview the effect of a pattern.
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternMatch {
+    pub(crate) id: i64,
+    pub(crate) path: String,
+    /// Tags that would be added to the item, excluding tags it already has
+    pub(crate) new_tags: Vec<String>,
+}
+
+/// Name of the field that matches any text without creating a tag.
+const IGNORED_FIELD: &str = "_";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+/// A pattern used to extract tags from file names, e.g. `{artist} - {bpm}bpm - {_}.wav`.
+///
+/// Each `{field}` matches some text, which becomes the tag `field=text`. Spaces in the text are
+/// replaced with underscores, since tags can't contain spaces. The field `{_}` matches text
+/// without creating a tag.
+///
+/// If the pattern contains a `/`, it is matched against the whole path of an item. Otherwise, it
+/// is matched against the file name only.
+#[derive(Debug)]
+pub struct TagPattern {
+    segments: Vec<Segment>,
+    match_path: bool,
+}
+
+impl TagPattern {
+    pub(crate) fn new(pattern: &str) -> Result<Self, TagPatternError> {
+        let mut segments = vec![];
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(pos) if rest[pos..].starts_with('}') => {
+                    return Err(TagPatternError::UnexpectedBrace);
+                }
+                Some(pos) => {
+                    if pos > 0 {
+                        segments.push(Segment::Literal(rest[..pos].to_string()));
+                    }
+                    let Some(len) = rest[pos..].find('}') else {
+                        return Err(TagPatternError::UnclosedField);
+                    };
+                    let name = &rest[pos + 1..pos + len];
+                    if name.is_empty()
+                        || name.contains(|c: char| c.is_whitespace() || c == '=' || c == '{')
+                    {
+                        return Err(TagPatternError::InvalidFieldName(name.to_string()));
+                    }
+                    if let Some(Segment::Field(_)) = segments.last() {
+                        return Err(TagPatternError::AdjacentFields);
+                    }
+                    segments.push(Segment::Field(name.to_string()));
+                    rest = &rest[pos + len + 1..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+        if !segments.iter().any(|x| matches!(x, Segment::Field(_))) {
+            return Err(TagPatternError::NoFields);
+        }
+        Ok(Self { segments, match_path: pattern.contains('/') })
+    }
+
+    /// Return the tags extracted from the given path, or `None` if the path doesn't match the
+    /// pattern. The tags are sorted and deduplicated.
+    pub(crate) fn tags_for(&self, path: impl AsRef<RelativePath>) -> Option<Vec<String>> {
+        let path = path.as_ref();
+        let text = match self.match_path {
+            true => path.as_str(),
+            false => path.file_name()?,
+        };
+        let mut values = vec![];
+        if !match_segments(&self.segments, text, &mut values) {
+            return None;
+        }
+
+        let mut tags: Vec<String> = vec![];
+        for (name, value) in values {
+            if name == IGNORED_FIELD {
+                continue;
+            }
+            let value = value.split_whitespace().collect::<Vec<_>>().join("_");
+            let tag = format!("{}={}", name, value);
+            if let Err(pos) = tags.binary_search(&tag) {
+                tags.insert(pos, tag);
+            }
+        }
+        Some(tags)
+    }
+}
+
+/// Match the segments against the whole of `text`, pushing the value of each field to `values`.
+/// Fields match as little text as possible, must match at least one character, and can't contain
+/// a `/`.
+fn match_segments<'a>(
+    segments: &'a [Segment],
+    text: &'a str,
+    values: &mut Vec<(&'a str, &'a str)>,
+) -> bool {
+    let Some((first, rest)) = segments.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        Segment::Literal(literal) => match text.strip_prefix(literal.as_str()) {
+            Some(text) => match_segments(rest, text, values),
+            None => false,
+        },
+        Segment::Field(name) => {
+            for (end, _) in text.char_indices().skip(1).chain([(text.len(), ' ')]) {
+                let value = &text[..end];
+                if value.ends_with('/') {
+                    break;
+                }
+                if value.trim().is_empty() {
+                    continue;
+                }
+                values.push((name, value.trim()));
+                if match_segments(rest, &text[end..], values) {
+                    return true;
+                }
+                values.pop();
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_tags(pattern: &str, path: &str, expected: Option<Vec<&str>>) {
+        let pattern = TagPattern::new(pattern).unwrap();
+        let expected = expected.map(|tags| tags.into_iter().map(String::from).collect());
+        assert_eq!(pattern.tags_for(RelativePath::new(path)), expected);
+    }
+
+    #[test]
+    fn matches_file_name() {
+        let pattern = "{artist} - {bpm}bpm - {name}.wav";
+        assert_tags(
+            pattern,
+            "Samples/DJ Foo - 120bpm - Kick.wav",
+            Some(vec!["artist=DJ_Foo", "bpm=120", "name=Kick"]),
+        );
+        // the last field takes the remaining text
+        assert_tags(
+            pattern,
+            "DJ Foo - 120bpm - Kick - Hard.wav",
+            Some(vec!["artist=DJ_Foo", "bpm=120", "name=Kick_-_Hard"]),
+        );
+        assert_tags(pattern, "DJ Foo - 120bpm - Kick.mp3", None);
+        assert_tags(pattern, "DJ Foo - Kick.wav", None);
+    }
+
+    #[test]
+    fn ignored_fields() {
+        assert_tags("{_} ({key}).{_}", "Pad (Am).wav", Some(vec!["key=Am"]));
+        assert_tags("{_} ({key}).{_}", "Pad.wav", None);
+    }
+
+    #[test]
+    fn matches_whole_path() {
+        let pattern = "{pack}/{_}/{_}.wav";
+        assert_tags(
+            pattern,
+            "Cool Drums/Kicks/kick 1.wav",
+            Some(vec!["pack=Cool_Drums"]),
+        );
+        // fields don't match across folders
+        assert_tags(pattern, "Cool Drums/kick 1.wav", None);
+        assert_tags(pattern, "Cool Drums/Kicks/Hard/kick 1.wav", None);
+    }
+
+    #[test]
+    fn fields_must_not_be_empty() {
+        assert_tags("{a}-{b}", "-b", None);
+        assert_tags("{a}-{b}", "a-", None);
+        assert_tags("{a}-{b}", "a-b", Some(vec!["a=a", "b=b"]));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(matches!(
+            TagPattern::new("{artist - x"),
+            Err(TagPatternError::UnclosedField)
+        ));
+        assert!(matches!(
+            TagPattern::new("artist} - x"),
+            Err(TagPatternError::UnexpectedBrace)
+        ));
+        assert!(matches!(
+            TagPattern::new("{my artist} - x"),
+            Err(TagPatternError::InvalidFieldName(_))
+        ));
+        assert!(matches!(
+            TagPattern::new("{}"),
+            Err(TagPatternError::InvalidFieldName(_))
+        ));
+        assert!(matches!(
+            TagPattern::new("{a}{b}"),
+            Err(TagPatternError::AdjacentFields)
+        ));
+        assert!(matches!(
+            TagPattern::new("kick.wav"),
+            Err(TagPatternError::NoFields)
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+//! Feeds random queries and tags through the query converter and runs them against a repo, to
+//! check that user input can't cause SQL errors or change the database.
+//!
+//! The inputs are generated from a fixed seed, so failures can be reproduced. Set the environment
+//! variable `FUZZ_ITERATIONS` to run more iterations than the default.
+
+use crate::repo::{QueryError, Repo, TestRepo};
+
+const DEFAULT_ITERATIONS: usize = 300;
+
+/// Fragments that are likely to break SQL or FTS5 queries if they aren't escaped properly.
+#[rustfmt::skip]
+const FRAGMENTS: &[&str] = &[
+    "'", "''", "\"", "\"\"", "%", "_", "\\", "*", "-", "|", "(", ")", ":", ";", "^", "+", "NEAR",
+    "AND", "OR", "NOT", "in:", "ext:", "inpath:", "children:", "leading:", "name:", "is:",
+    "tagged", "untagged", "tagcount:", "fts:", "3", "kick", "Snare", "a/b", "C:\\", "é",
+    "e\u{301}", "ドラム", "打击乐", "\u{a0}", "\u{3000}", "'; DROP TABLE items; --",
+    "\" OR 1=1 --", "') OR ('1'='1", "tags:", "meta_tags:", "all",
+];
+
+/// A small xorshift random number generator, so the tests don't need extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn fragment(&mut self) -> &'static str {
+        FRAGMENTS[self.below(FRAGMENTS.len())]
+    }
+}
+
+fn iterations() -> usize {
+    match std::env::var("FUZZ_ITERATIONS") {
+        Ok(text) => text.parse().expect("FUZZ_ITERATIONS must be a number"),
+        Err(_) => DEFAULT_ITERATIONS,
+    }
+}
+
+fn random_query(rng: &mut Rng) -> String {
+    let mut query = String::new();
+    for _ in 0..rng.below(8) + 1 {
+        query.push_str(rng.fragment());
+        if rng.below(3) == 0 {
+            query.push(' ');
+        }
+    }
+    query
+}
+
+/// A random tag, tags can't contain whitespace.
+fn random_tag(rng: &mut Rng) -> String {
+    let mut tag = String::new();
+    while tag.is_empty() {
+        for _ in 0..rng.below(3) + 1 {
+            tag.push_str(rng.fragment());
+        }
+        tag.retain(|c| !c.is_whitespace());
+    }
+    tag
+}
+
+fn table_count(repo: &Repo) -> i64 {
+    repo.count("").unwrap()
+}
+
+#[test]
+fn random_queries_are_valid_sql() {
+    let mut tr = TestRepo::new();
+    let repo = &mut tr.repo;
+    repo.insert_item("a.wav", "kick 'quoted' 100% ドラム")
+        .unwrap();
+    repo.insert_item("b/c.wav", "snare").unwrap();
+    repo.insert_item("d.wav", "").unwrap();
+
+    let mut rng = Rng(0x5eed);
+    for _ in 0..iterations() {
+        let query = random_query(&mut rng);
+        // raw FTS queries are passed to FTS5 as-is, so they may have syntax errors
+        let allow_fts_errors = query.contains("fts:");
+        match repo.query_items(&query) {
+            Ok(_) | Err(QueryError::InvalidQuery(_)) => {}
+            Err(QueryError::BackendError(_)) if allow_fts_errors => {}
+            Err(err) => panic!("query {:?} failed: {}", query, err),
+        }
+        assert_eq!(
+            table_count(repo),
+            3,
+            "query {:?} changed the database",
+            query
+        );
+    }
+}
+
+#[test]
+fn random_tags_can_be_found() {
+    let mut tr = TestRepo::new();
+    let repo = &mut tr.repo;
+
+    let mut rng = Rng(0xfeed);
+    for i in 0..iterations() {
+        let tag = random_tag(&mut rng);
+        let path = format!("{}.wav", i);
+        repo.insert_item(&path, vec![tag.as_str()]).unwrap();
+
+        // search for the tag as a quoted string, so it is never parsed as syntax
+        let query = format!("\"{}\"", tag.replace('"', "\"\""));
+        let items = match repo.query_items(&query) {
+            Ok(items) => items,
+            Err(err) => panic!("query {:?} for tag {:?} failed: {}", query, tag, err),
+        };
+        // FTS ignores punctuation, so tags without any letters or numbers can't be searched
+        if tag.chars().any(char::is_alphanumeric) {
+            assert!(
+                items.iter().any(|x| x.path == path),
+                "tag {:?} wasn't found by query {:?}",
+                tag,
+                query
+            );
+        }
+        assert_eq!(table_count(repo), i as i64 + 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! Runs a `RepoManager` on a temporary folder with a mocked Tauri app, so the watcher, syncing
+//! and queries can be tested together. Files are changed with `FsOperator`, then the test waits
+//! for the repo to catch up with `Harness::wait_for()`.
+//!
+//! To test how events are handled without depending on the OS's watcher, use
+//! `Harness::watch_injected()` and send the events yourself.
+//!
+//! These tests need mocked Tauri apps, run them with `cargo test --features gui-test`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{App, Manager};
+use tempfile::{tempdir, TempDir};
+
+use crate::manager::RepoManager;
+use crate::repo::{OpenMode, Repo};
+use crate::repo_config::RepoConfig;
+use crate::watch::EventInjector;
+
+/// How long `wait_for()` waits for the watcher before failing the test.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time between checks in `wait_for()`.
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Changes files in a folder, panicking if a change fails.
+pub(crate) struct FsOperator {
+    base_path: PathBuf,
+}
+
+impl FsOperator {
+    pub(crate) fn new(base_path: impl AsRef<Path>) -> Self {
+        Self { base_path: base_path.as_ref().to_path_buf() }
+    }
+
+    /// The absolute path of a file in the folder.
+    pub(crate) fn path(&self, path: &str) -> PathBuf {
+        self.base_path.join(path)
+    }
+
+    pub(crate) fn create(&self, path: &str) {
+        fs::write(self.base_path.join(path), path).unwrap();
+    }
+
+    pub(crate) fn create_dir(&self, path: &str) {
+        fs::create_dir_all(self.base_path.join(path)).unwrap();
+    }
+
+    pub(crate) fn remove(&self, path: &str) {
+        fs::remove_file(self.base_path.join(path)).unwrap();
+    }
+
+    pub(crate) fn remove_dir(&self, path: &str) {
+        fs::remove_dir_all(self.base_path.join(path)).unwrap();
+    }
+
+    /// Rename a file or folder, failing if the destination exists.
+    pub(crate) fn rename(&self, a: &str, b: &str) {
+        let b = self.base_path.join(b);
+        assert!(!b.exists(), "rename destination exists: {}", b.display());
+        fs::rename(self.base_path.join(a), b).unwrap();
+    }
+}
+
+/// A repo in a temporary folder, opened by a `RepoManager`.
+pub(crate) struct Harness {
+    pub(crate) manager: RepoManager<MockRuntime>,
+    pub(crate) fs: FsOperator,
+    // the app must outlive the manager's app handle
+    _app: App<MockRuntime>,
+    // dropped last, so the folder is deleted after the manager is closed
+    _dir: TempDir,
+}
+
+impl Harness {
+    /// Create an empty repo. Files created with `setup` are added before the repo is opened, so
+    /// they are found by the first `resync()`.
+    pub(crate) fn new(setup: impl FnOnce(&FsOperator)) -> Self {
+        let dir = tempdir().unwrap();
+        let fs = FsOperator::new(dir.path());
+        setup(&fs);
+        drop(Repo::create(dir.path(), &RepoConfig::default()).unwrap());
+        let app = mock_app();
+        let manager = RepoManager::new(dir.path(), OpenMode::ReadWrite, app.app_handle()).unwrap();
+        Self { manager, fs, _app: app, _dir: dir }
+    }
+
+    /// Watch the repo with an `InjectableNormWatcher`, so it only receives the events sent with
+    /// the returned injector.
+    pub(crate) async fn watch_injected(&self) -> EventInjector {
+        let injector = EventInjector::default();
+        self.manager
+            .watch_with(Some(injector.factory()))
+            .await
+            .unwrap();
+        injector
+    }
+
+    /// Paths of the items matching a query, sorted.
+    pub(crate) async fn paths(&self, query: &str) -> Vec<String> {
+        let ids = self.manager.query(query, None).await.unwrap();
+        let items = self.manager.get_items(ids).await.unwrap();
+        let mut paths: Vec<_> = items.into_iter().map(|x| x.path).collect();
+        paths.sort();
+        paths
+    }
+
+    /// The id of the item at a path.
+    pub(crate) async fn id(&self, path: &str) -> i64 {
+        let path = path.to_string();
+        let ids = self.manager.query("", None).await.unwrap();
+        let items = self.manager.get_items(ids).await.unwrap();
+        match items.into_iter().find(|x| x.path == path) {
+            Some(item) => item.id,
+            None => panic!("no item at {}", path),
+        }
+    }
+
+    /// Wait until the items matching a query are at exactly the given paths, e.g. after the
+    /// watcher has handled a change. Panics if this takes too long.
+    pub(crate) async fn wait_for(&self, query: &str, expected: &[&str]) {
+        let mut expected: Vec<_> = expected.iter().map(|x| x.to_string()).collect();
+        expected.sort();
+        let start = Instant::now();
+        loop {
+            let paths = self.paths(query).await;
+            if paths == expected {
+                return;
+            }
+            if start.elapsed() > WAIT_TIMEOUT {
+                panic!(
+                    "query {:?} returned {:?}, expected {:?}",
+                    query, paths, expected
+                );
+            }
+            tokio::time::sleep(WAIT_INTERVAL).await;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resync_finds_existing_files() {
+    let harness = Harness::new(|fs| {
+        fs.create_dir("drums");
+        fs.create("drums/kick.wav");
+        fs.create("snare.wav");
+    });
+    harness.manager.resync().await.unwrap();
+    harness.wait_for("", &["drums/kick.wav", "snare.wav"]).await;
+    harness.wait_for("in:drums", &["drums/kick.wav"]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watcher_keeps_tags_of_renamed_files() {
+    let harness = Harness::new(|fs| {
+        fs.create("kick.wav");
+        fs.create("snare.wav");
+    });
+    harness.manager.resync().await.unwrap();
+    harness.manager.watch().await.unwrap();
+    let kick = harness.id("kick.wav").await;
+    harness
+        .manager
+        .insert_tags(vec![kick], vec![String::from("drum")], None)
+        .await
+        .unwrap();
+
+    harness.fs.create_dir("drums");
+    harness.fs.rename("kick.wav", "drums/kick.wav");
+    harness.fs.create("hat.wav");
+    harness.wait_for("drum", &["drums/kick.wav"]).await;
+    harness
+        .wait_for("", &["drums/kick.wav", "hat.wav", "snare.wav"])
+        .await;
+
+    harness.fs.remove("snare.wav");
+    harness.fs.remove_dir("drums");
+    harness.wait_for("", &["hat.wav"]).await;
+    harness.wait_for("drum", &[]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_renames_move_items() {
+    let harness = Harness::new(|fs| {
+        fs.create_dir("drums");
+        fs.create("drums/kick.wav");
+        fs.create("snare.wav");
+    });
+    harness.manager.resync().await.unwrap();
+    let injector = harness.watch_injected().await;
+    let snare = harness.id("snare.wav").await;
+    harness
+        .manager
+        .insert_tags(vec![snare], vec![String::from("drum")], None)
+        .await
+        .unwrap();
+
+    // a file is moved into a folder
+    let fs = &harness.fs;
+    fs.rename("snare.wav", "drums/snare.wav");
+    assert!(injector.rename(fs.path("snare.wav"), fs.path("drums/snare.wav")));
+    harness.wait_for("drum", &["drums/snare.wav"]).await;
+
+    // the folder is renamed, only the folder's event is sent
+    fs.rename("drums", "perc");
+    assert!(injector.rename(fs.path("drums"), fs.path("perc")));
+    harness
+        .wait_for("", &["perc/kick.wav", "perc/snare.wav"])
+        .await;
+    harness.wait_for("drum", &["perc/snare.wav"]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_removes_delete_items() {
+    let harness = Harness::new(|fs| {
+        fs.create_dir("drums");
+        fs.create("drums/kick.wav");
+        fs.create("drums/snare.wav");
+        fs.create("hat.wav");
+    });
+    harness.manager.resync().await.unwrap();
+    let injector = harness.watch_injected().await;
+
+    // removing a folder reports its files, then the folder itself
+    let fs = &harness.fs;
+    fs.remove_dir("drums");
+    assert!(injector.remove(fs.path("drums/kick.wav")));
+    assert!(injector.remove(fs.path("drums/snare.wav")));
+    assert!(injector.remove(fs.path("drums")));
+    harness.wait_for("", &["hat.wav"]).await;
+
+    // nothing is sent once the repo is unwatched
+    harness.manager.unwatch().await.unwrap();
+    fs.create("ride.wav");
+    assert!(!injector.create(fs.path("ride.wav")));
+}
@@ -1,2 +1,5 @@
+mod fuzz_query;
+#[cfg(feature = "gui-test")]
+mod harness;
 mod query_repo;
 pub mod utils;
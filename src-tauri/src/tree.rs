@@ -1,54 +1,261 @@
+use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
-use std::collections::HashMap;
-use std::fmt::{Debug, Formatter};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
+use itertools::Itertools;
 use thiserror::Error;
 
 struct Folder<'a> {
-    children: HashMap<&'a str, Folder<'a>>,
-}
-
-impl<'a> Serialize for Folder<'a> {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.children.serialize(serializer)
-    }
-}
-
-impl<'a> Debug for Folder<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.children.fmt(f)
-    }
+    children: BTreeMap<&'a str, Folder<'a>>,
+    direct: usize,
 }
 
 impl<'a> Folder<'a> {
     fn new() -> Self {
-        Self { children: HashMap::new() }
+        Self { children: BTreeMap::new(), direct: 0 }
     }
 
     fn to_folder_buf(&self) -> FolderBuf {
-        let mut map = HashMap::with_capacity(self.children.len());
+        let mut map = BTreeMap::new();
+        let mut recursive = self.direct;
         for (dirname, folder) in &self.children {
-            map.insert(dirname.to_string(), folder.to_folder_buf());
+            let folder = folder.to_folder_buf();
+            recursive += folder.recursive;
+            map.insert(dirname.to_string(), folder);
         }
-        FolderBuf { children: map }
+        FolderBuf { children: map, direct: self.direct, recursive }
     }
 }
 
+/// A folder and its subfolders, ordered alphabetically by name.
+#[derive(Clone, Debug, Default)]
 pub struct FolderBuf {
-    children: HashMap<String, FolderBuf>,
+    children: BTreeMap<String, FolderBuf>,
+    /// Number of items directly inside this folder
+    direct: usize,
+    /// Number of items inside this folder and its subfolders
+    recursive: usize,
+}
+
+impl FolderBuf {
+    pub fn direct_count(&self) -> usize {
+        self.direct
+    }
+
+    pub fn recursive_count(&self) -> usize {
+        self.recursive
+    }
+
+    fn get(&self, names: &[&str]) -> Option<&FolderBuf> {
+        let mut folder = self;
+        for name in names {
+            folder = folder.children.get(*name)?;
+        }
+        Some(folder)
+    }
+
+    fn get_mut(&mut self, names: &[&str]) -> Option<&mut FolderBuf> {
+        let mut folder = self;
+        for name in names {
+            folder = folder.children.get_mut(*name)?;
+        }
+        Some(folder)
+    }
+
+    /// Add the item counts of this folder and its descendants to `counts`, `path` is the path of
+    /// this folder.
+    fn collect_counts(&self, path: &str, counts: &mut HashMap<String, (usize, usize)>) {
+        counts.insert(path.to_string(), (self.direct, self.recursive));
+        for (name, child) in &self.children {
+            child.collect_counts(&join(path, name), counts);
+        }
+    }
+}
+
+/// A folder serialized with its name. Subfolders are serialized as a list instead of a map, so
+/// they keep their order.
+struct NamedFolder<'a>(Option<&'a str>, &'a FolderBuf);
+
+impl<'a> Serialize for NamedFolder<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let NamedFolder(name, folder) = self;
+        let mut state = serializer.serialize_struct("Folder", 4)?;
+        match name {
+            Some(name) => state.serialize_field("name", name)?,
+            None => state.skip_field("name")?,
+        }
+        state.serialize_field("direct", &folder.direct)?;
+        state.serialize_field("recursive", &folder.recursive)?;
+        let children: Vec<_> = folder
+            .children
+            .iter()
+            .map(|(name, child)| NamedFolder(Some(name), child))
+            .collect();
+        state.serialize_field("children", &children)?;
+        state.end()
+    }
 }
 
 impl Serialize for FolderBuf {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.children.serialize(serializer)
+        NamedFolder(None, self).serialize(serializer)
+    }
+}
+
+/// Folders that were added to or removed from a `DirTree`, and the new item counts of folders
+/// whose counts changed.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirTreeDelta {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// `(direct, recursive)` item counts by folder path, the repo root is an empty path
+    pub counts: BTreeMap<String, (usize, usize)>,
+}
+
+impl DirTreeDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.counts.is_empty()
+    }
+
+    /// Record an added folder. A folder that was removed and added back again is unchanged.
+    fn add(&mut self, path: String) {
+        if let Some(i) = self.removed.iter().position(|x| *x == path) {
+            self.removed.remove(i);
+        } else {
+            self.added.push(path);
+        }
+    }
+
+    fn remove(&mut self, path: String) {
+        self.counts.remove(&path);
+        if let Some(i) = self.added.iter().position(|x| *x == path) {
+            self.added.remove(i);
+        } else {
+            self.removed.push(path);
+        }
+    }
+}
+
+/// The folders containing items, which can be updated as items are added and removed without
+/// rebuilding the whole tree.
+#[derive(Clone, Debug)]
+pub struct DirTree {
+    root: FolderBuf,
+}
+
+impl DirTree {
+    pub fn new(root: FolderBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn structure(&self) -> &FolderBuf {
+        &self.root
+    }
+
+    /// Update the tree after an item was added at `path`.
+    pub fn add_item(&mut self, path: &str, delta: &mut DirTreeDelta) {
+        let names: Vec<_> = components(dirname(path)).collect();
+        let mut folder = &mut self.root;
+        folder.recursive += 1;
+        for (depth, name) in names.iter().enumerate() {
+            if !folder.children.contains_key(*name) {
+                delta.add(names[..=depth].join("/"));
+            }
+            folder = folder.children.entry(name.to_string()).or_default();
+            folder.recursive += 1;
+        }
+        folder.direct += 1;
+        self.record_counts(&names, delta);
+    }
+
+    /// Update the tree after the item at `path` was removed. Folders are removed once they
+    /// don't contain any items.
+    pub fn remove_item(&mut self, path: &str, delta: &mut DirTreeDelta) {
+        let names: Vec<_> = components(dirname(path)).collect();
+        // the item may not have been counted, e.g. if it was already removed
+        if self.root.get(&names).map_or(true, |x| x.direct == 0) {
+            return;
+        }
+        let mut folder = &mut self.root;
+        folder.recursive -= 1;
+        for name in &names {
+            folder = folder
+                .children
+                .get_mut(*name)
+                .expect("folder isn't in the tree");
+            folder.recursive -= 1;
+        }
+        folder.direct -= 1;
+        // remove empty folders, from the innermost one outwards
+        for depth in (1..=names.len()).rev() {
+            let parent = self
+                .root
+                .get_mut(&names[..depth - 1])
+                .expect("folder isn't in the tree");
+            let name = names[depth - 1];
+            if parent.children[name].recursive > 0 {
+                break;
+            }
+            parent.children.remove(name);
+            delta.remove(names[..depth].join("/"));
+        }
+        self.record_counts(&names, delta);
     }
+
+    /// Record the counts of the folder at `names` and its parents.
+    fn record_counts(&self, names: &[&str], delta: &mut DirTreeDelta) {
+        for depth in 0..=names.len() {
+            let Some(folder) = self.root.get(&names[..depth]) else {
+                break;
+            };
+            let counts = (folder.direct, folder.recursive);
+            delta.counts.insert(names[..depth].join("/"), counts);
+        }
+    }
+
+    /// The folders that are in `other` but not in this tree, the other way round, and the
+    /// folders whose counts are different in `other`.
+    pub fn diff(&self, other: &DirTree) -> DirTreeDelta {
+        let (mut old, mut new) = (HashMap::new(), HashMap::new());
+        self.root.collect_counts("", &mut old);
+        other.root.collect_counts("", &mut new);
+        DirTreeDelta {
+            added: new
+                .keys()
+                .filter(|x| !old.contains_key(*x))
+                .cloned()
+                .sorted()
+                .collect(),
+            removed: old
+                .keys()
+                .filter(|x| !new.contains_key(*x))
+                .cloned()
+                .sorted()
+                .collect(),
+            counts: new
+                .into_iter()
+                .filter(|(path, counts)| old.get(path) != Some(counts))
+                .collect(),
+        }
+    }
+}
+
+/// The folder that the item at `path` is in, this is empty for items in the repo root.
+fn dirname(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn components(dir: &str) -> impl Iterator<Item = &str> {
+    dir.split('/').filter(|x| !x.is_empty())
 }
 
-impl Debug for FolderBuf {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.children.fmt(f)
+fn join(dir: &str, name: &str) -> String {
+    match dir.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", dir, name),
     }
 }
 
@@ -58,11 +265,11 @@ pub enum PathTreeError {
     MalformedPath(PathBuf),
 }
 
-/// Construct a tree from a given list of paths.
-/// The paths must already be sorted alphabetically.
-pub fn from_ordered_paths(paths: &Vec<impl AsRef<Path>>) -> Result<FolderBuf, PathTreeError> {
+/// Construct a tree from a given list of folder paths, and the number of items directly inside
+/// each folder. The folders are sorted in the tree, so the paths can be in any order.
+pub fn from_ordered_paths(paths: &[(impl AsRef<Path>, usize)]) -> Result<FolderBuf, PathTreeError> {
     let mut root = Folder::new();
-    for path in paths.iter() {
+    for (path, count) in paths.iter() {
         let path = path.as_ref();
         let mut current_folder = &mut root;
 
@@ -78,6 +285,7 @@ pub fn from_ordered_paths(paths: &Vec<impl AsRef<Path>>) -> Result<FolderBuf, Pa
                 .entry(component)
                 .or_insert_with(|| Folder::new());
         }
+        current_folder.direct += count;
     }
 
     Ok(root.to_folder_buf())
@@ -90,16 +298,88 @@ mod tests {
     #[test]
     fn paths_test() {
         let paths = vec![
-            "",
-            "Band",
-            "Band/ready",
-            "Guitar IRs",
-            "Guitar IRs/Aurora DSP/FREE PACK",
-            "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 0cm",
-            "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 2cm",
-            "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 4cm",
+            ("", 1),
+            ("Band", 2),
+            ("Band/ready", 3),
+            ("Guitar IRs", 1),
+            ("Guitar IRs/Aurora DSP/FREE PACK", 1),
+            (
+                "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 0cm",
+                4,
+            ),
+            (
+                "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 2cm",
+                4,
+            ),
+            (
+                "Guitar IRs/Aurora DSP/FREE PACK/WAVE/GOVERNOR/LEWITT 4cm",
+                4,
+            ),
         ];
         let dirs = from_ordered_paths(&paths).unwrap();
         dbg!(&dirs);
     }
+
+    #[test]
+    fn folders_are_counted_and_ordered() {
+        let paths = vec![("b", 1), ("a/y", 2), ("a/x", 3), ("a", 1), ("", 1)];
+        let root = from_ordered_paths(&paths).unwrap();
+        assert_eq!(root.recursive_count(), 8);
+        let a = root.get(&["a"]).unwrap();
+        assert_eq!((a.direct_count(), a.recursive_count()), (1, 6));
+        assert_eq!(a.children.keys().collect_vec(), vec!["x", "y"]);
+
+        let json = serde_json::to_value(&root).unwrap();
+        assert_eq!(json["children"][0]["name"], "a");
+        assert_eq!(json["children"][0]["children"][0]["name"], "x");
+        assert_eq!(json["children"][0]["children"][0]["recursive"], 3);
+        assert_eq!(json["children"][1]["name"], "b");
+    }
+
+    #[test]
+    fn dir_tree_is_updated() {
+        let root = from_ordered_paths(&[("", 1), ("Band/ready", 2)]).unwrap();
+        let mut tree = DirTree::new(root);
+
+        let mut delta = DirTreeDelta::default();
+        tree.add_item("Band/ready/kick.wav", &mut delta);
+        tree.add_item("Guitar IRs/Aurora DSP/a.wav", &mut delta);
+        assert_eq!(delta.added, vec!["Guitar IRs", "Guitar IRs/Aurora DSP"]);
+        assert!(delta.removed.is_empty());
+        assert_eq!(delta.counts[""], (1, 5));
+        assert_eq!(delta.counts["Band/ready"], (3, 3));
+        assert_eq!(delta.counts["Guitar IRs"], (0, 1));
+
+        // folders are only removed once they're empty
+        let mut delta = DirTreeDelta::default();
+        tree.remove_item("Band/ready/snare.wav", &mut delta);
+        tree.remove_item("Band/ready/hat.wav", &mut delta);
+        assert!(delta.removed.is_empty());
+        assert_eq!(delta.counts["Band"], (0, 1));
+        tree.remove_item("Band/ready/kick.wav", &mut delta);
+        assert_eq!(delta.removed, vec!["Band/ready", "Band"]);
+        assert!(!delta.counts.contains_key("Band"));
+
+        // moving the only item in a folder within the folder doesn't change the folders
+        let mut delta = DirTreeDelta::default();
+        tree.add_item("Guitar IRs/Aurora DSP/b.wav", &mut delta);
+        tree.remove_item("Guitar IRs/Aurora DSP/a.wav", &mut delta);
+        assert!(delta.added.is_empty());
+        assert!(delta.removed.is_empty());
+
+        let rebuilt = from_ordered_paths(&[("", 1), ("Guitar IRs/Aurora DSP", 1)]).unwrap();
+        assert!(tree.diff(&DirTree::new(rebuilt)).is_empty());
+    }
+
+    #[test]
+    fn dir_tree_diff() {
+        let old = DirTree::new(from_ordered_paths(&[("a/b", 1), ("c", 1)]).unwrap());
+        let new = DirTree::new(from_ordered_paths(&[("a/c", 1), ("c", 2)]).unwrap());
+        let delta = old.diff(&new);
+        assert_eq!(delta.added, vec!["a/c"]);
+        assert_eq!(delta.removed, vec!["a/b"]);
+        assert_eq!(delta.counts["c"], (2, 2));
+        assert_eq!(delta.counts[""], (0, 3));
+        assert!(!delta.counts.contains_key("a"));
+    }
 }
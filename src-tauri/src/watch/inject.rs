@@ -0,0 +1,225 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use notify::event::ModifyKind::Name;
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+use notify::EventKind::{Create, Modify, Remove};
+use notify::{Config, Event, EventHandler, RecursiveMode, Watcher, WatcherKind};
+
+use crate::watch::{RepoWatcher, WatcherFactory};
+
+/// A watcher that doesn't look at the filesystem at all, its events are sent with an
+/// `EventInjector` instead. This is for tests of the code that handles events, so they decide
+/// exactly which events arrive and in what order, instead of racing the OS's watcher.
+///
+/// Like a real watcher, events are only sent if one of their paths is in a watched folder, and
+/// events sent after the watcher is dropped are ignored.
+pub struct InjectableNormWatcher {
+    shared: Arc<Mutex<Shared>>,
+    /// Compared with `Shared::generation` to tell if this watcher has been replaced
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// The event handler of the watcher that is alive, if any
+    handler: Option<Box<dyn EventHandler>>,
+    /// Incremented for each watcher created with the injector
+    generation: u64,
+    roots: Vec<(PathBuf, RecursiveMode)>,
+}
+
+impl Shared {
+    fn is_watched(&self, path: &Path) -> bool {
+        self.roots.iter().any(|(root, mode)| match mode {
+            RecursiveMode::Recursive => path.starts_with(root),
+            RecursiveMode::NonRecursive => path == root || path.parent() == Some(root),
+        })
+    }
+}
+
+/// Sends events to the `InjectableNormWatcher` created with it. If the watcher is replaced, e.g.
+/// when the manager restarts a failed watcher, events go to the new watcher.
+#[derive(Clone, Default)]
+pub struct EventInjector {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl InjectableNormWatcher {
+    /// Create a watcher whose events are sent with `injector`. This replaces any watcher that was
+    /// previously created with the same injector.
+    pub fn with_injector<F: EventHandler>(event_handler: F, injector: &EventInjector) -> Self {
+        let shared = injector.shared.clone();
+        let generation = {
+            let mut shared = shared.lock().unwrap();
+            shared.handler = Some(Box::new(event_handler));
+            shared.generation += 1;
+            shared.roots.clear();
+            shared.generation
+        };
+        Self { shared, generation }
+    }
+
+    pub fn injector(&self) -> EventInjector {
+        EventInjector { shared: self.shared.clone() }
+    }
+}
+
+impl Debug for InjectableNormWatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InjectableNormWatcher")
+            .field("roots", &self.shared.lock().unwrap().roots)
+            .finish()
+    }
+}
+
+impl Drop for InjectableNormWatcher {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        // a newer watcher may have taken over the injector
+        if shared.generation == self.generation {
+            shared.handler = None;
+            shared.roots.clear();
+        }
+    }
+}
+
+impl Watcher for InjectableNormWatcher {
+    fn new<F: EventHandler>(event_handler: F, _config: Config) -> notify::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self::with_injector(
+            event_handler,
+            &EventInjector::default(),
+        ))
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        if !path.is_dir() {
+            return Err(notify::Error::path_not_found().add_path(path.to_path_buf()));
+        }
+        let mut shared = self.shared.lock().unwrap();
+        shared.roots.retain(|(x, _)| x != path);
+        shared.roots.push((path.to_path_buf(), recursive_mode));
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let mut shared = self.shared.lock().unwrap();
+        let Some(index) = shared.roots.iter().position(|(x, _)| x == path) else {
+            return Err(notify::Error::watch_not_found().add_path(path.to_path_buf()));
+        };
+        shared.roots.remove(index);
+        Ok(())
+    }
+
+    fn kind() -> WatcherKind
+    where
+        Self: Sized,
+    {
+        WatcherKind::NullWatcher
+    }
+}
+
+impl EventInjector {
+    /// A factory for `RepoManager::watch_with()` that creates watchers for this injector.
+    pub fn factory(&self) -> WatcherFactory {
+        let injector = self.clone();
+        WatcherFactory::new(move |mut event_handler, paths| {
+            let mut watcher = InjectableNormWatcher::with_injector(
+                move |evt: notify::Result<Event>| event_handler.handle_event(evt),
+                &injector,
+            );
+            for (path, mode) in paths {
+                watcher.watch(path, *mode)?;
+            }
+            Ok(RepoWatcher::Injected(watcher))
+        })
+    }
+
+    /// Send an event to the watcher. Returns whether it was sent, i.e. the watcher is alive and
+    /// one of the event's paths is watched. Events without paths are always sent.
+    pub fn send(&self, event: Event) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        let watched = event.paths.is_empty() || event.paths.iter().any(|x| shared.is_watched(x));
+        match &mut shared.handler {
+            Some(handler) if watched => {
+                handler.handle_event(Ok(event));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Send an error to the watcher, as if it had stopped working.
+    pub fn send_error(&self, error: notify::Error) -> bool {
+        match &mut self.shared.lock().unwrap().handler {
+            Some(handler) => {
+                handler.handle_event(Err(error));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn create(&self, path: impl Into<PathBuf>) -> bool {
+        self.send(Event::new(Create(CreateKind::Any)).add_path(path.into()))
+    }
+
+    pub fn modify(&self, path: impl Into<PathBuf>) -> bool {
+        let kind = Modify(ModifyKind::Data(DataChange::Any));
+        self.send(Event::new(kind).add_path(path.into()))
+    }
+
+    pub fn remove(&self, path: impl Into<PathBuf>) -> bool {
+        self.send(Event::new(Remove(RemoveKind::Any)).add_path(path.into()))
+    }
+
+    /// Send a rename with both paths in one event, like the watchers on Linux and macOS do.
+    pub fn rename(&self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> bool {
+        let event = Event::new(Modify(Name(RenameMode::Both)))
+            .add_path(from.into())
+            .add_path(to.into());
+        self.send(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn only_watched_paths_are_sent() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let (tx, rx) = channel();
+        let injector = EventInjector::default();
+        let mut watcher = InjectableNormWatcher::with_injector(tx, &injector);
+
+        // nothing is watched yet
+        assert!(!injector.create(root.join("a.wav")));
+        watcher.watch(&root, RecursiveMode::NonRecursive).unwrap();
+        assert!(injector.create(root.join("a.wav")));
+        assert!(!injector.create(root.join("sub/b.wav")));
+        assert!(injector.rename(root.join("a.wav"), root.join("c.wav")));
+
+        let events: Vec<Event> = rx.try_iter().map(|x| x.unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].paths, vec![root.join("a.wav")]);
+        assert_eq!(
+            events[1].paths,
+            vec![root.join("a.wav"), root.join("c.wav")]
+        );
+
+        // events are dropped once the watcher is gone
+        drop(watcher);
+        assert!(!injector.remove(root.join("c.wav")));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,9 +1,323 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{Config, EventHandler, RecursiveMode, Watcher, WatcherKind};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::repo_config::WatcherStrategy;
+
+mod inject;
+mod poll;
+mod volume;
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "windows")]
 pub type BestWatcher = windows::WindowsNormWatcher;
 #[cfg(not(target_os = "windows"))]
 pub type BestWatcher = notify::RecommendedWatcher;
+pub use inject::{EventInjector, InjectableNormWatcher};
+pub use poll::PollNormWatcher;
+
+/// Looks up the size and content hash of a deleted file, e.g. from the repo. Returns `None` if
+/// they aren't known.
+pub(crate) type HashLookup = Arc<dyn Fn(&Path) -> Option<(u64, String)> + Send + Sync>;
+
+/// Settings for pairing deletions with creations into renames. Only Windows reports moves as a
+/// deletion followed by a creation, so these are ignored on other platforms, except by
+/// `PollNormWatcher`, which uses the lookup and metrics.
+#[derive(Clone)]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub struct PairingOptions {
+    /// How long a deletion waits for a creation with the same file name.
+    pub(crate) window: Duration,
+    /// Used to pick between several deleted files with the same name as a created file.
+    pub(crate) lookup: Option<HashLookup>,
+    pub(crate) metrics: Arc<PairingMetrics>,
+}
+
+impl Default for PairingOptions {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(10),
+            lookup: None,
+            metrics: Default::default(),
+        }
+    }
+}
+
+impl Debug for PairingOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairingOptions")
+            .field("window", &self.window)
+            .field("lookup", &self.lookup.is_some())
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+/// Counters of how deletions and creations were paired, to tell whether the pairing window
+/// is too short or too long for a repo.
+#[derive(Debug, Default)]
+pub(crate) struct PairingMetrics {
+    paired: AtomicU64,
+    ambiguous: AtomicU64,
+    matched_by_size: AtomicU64,
+    matched_by_hash: AtomicU64,
+    unresolved: AtomicU64,
+    unpaired_removes: AtomicU64,
+    unpaired_creates: AtomicU64,
+    lost_events: AtomicU64,
+}
+
+/// A snapshot of `PairingMetrics`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PairingStats {
+    /// Renames reported by the watcher, including deletions paired with creations
+    pub(crate) paired: u64,
+    /// Creations with more than one deleted file of the same name
+    pub(crate) ambiguous: u64,
+    /// Ambiguous creations where only one deleted file had the same size
+    pub(crate) matched_by_size: u64,
+    /// Ambiguous creations where only one deleted file had the same content hash
+    pub(crate) matched_by_hash: u64,
+    /// Ambiguous creations that were paired with the oldest deletion, these may be mispaired
+    pub(crate) unresolved: u64,
+    /// Deletions that expired without a matching creation
+    pub(crate) unpaired_removes: u64,
+    /// Creations without a matching deletion
+    pub(crate) unpaired_creates: u64,
+    /// Renames that were only half reported, e.g. when the event buffer overflowed
+    pub(crate) lost_events: u64,
+}
+
+/// A counter in `PairingMetrics`.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub(crate) enum PairingCounter {
+    Paired,
+    Ambiguous,
+    MatchedBySize,
+    MatchedByHash,
+    Unresolved,
+    UnpairedRemove,
+    UnpairedCreate,
+    LostEvent,
+}
+
+impl PairingMetrics {
+    pub(crate) fn record(&self, counter: PairingCounter) {
+        let counter = match counter {
+            PairingCounter::Paired => &self.paired,
+            PairingCounter::Ambiguous => &self.ambiguous,
+            PairingCounter::MatchedBySize => &self.matched_by_size,
+            PairingCounter::MatchedByHash => &self.matched_by_hash,
+            PairingCounter::Unresolved => &self.unresolved,
+            PairingCounter::UnpairedRemove => &self.unpaired_removes,
+            PairingCounter::UnpairedCreate => &self.unpaired_creates,
+            PairingCounter::LostEvent => &self.lost_events,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> PairingStats {
+        PairingStats {
+            paired: self.paired.load(Ordering::Relaxed),
+            ambiguous: self.ambiguous.load(Ordering::Relaxed),
+            matched_by_size: self.matched_by_size.load(Ordering::Relaxed),
+            matched_by_hash: self.matched_by_hash.load(Ordering::Relaxed),
+            unresolved: self.unresolved.load(Ordering::Relaxed),
+            unpaired_removes: self.unpaired_removes.load(Ordering::Relaxed),
+            unpaired_creates: self.unpaired_creates.load(Ordering::Relaxed),
+            lost_events: self.lost_events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Create the best watcher for this platform.
+pub fn new_watcher<F: EventHandler>(
+    event_handler: F,
+    options: PairingOptions,
+) -> notify::Result<BestWatcher> {
+    #[cfg(target_os = "windows")]
+    {
+        BestWatcher::with_options(event_handler, Config::default(), options)
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = options;
+        <BestWatcher as notify::Watcher>::new(event_handler, Config::default())
+    }
+}
+
+/// How a repo folder is watched, see `WatchMethod::choose()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchMethod {
+    /// The OS's change notifications, with `BestWatcher`
+    Native,
+    /// Scan the folder for changes every given interval, with `PollNormWatcher`
+    Poll(Duration),
+}
+
+impl WatchMethod {
+    /// Pick how to watch the repo at `path`. With `WatcherStrategy::Auto`, folders on network
+    /// drives are polled, since their changes often aren't reported.
+    pub(crate) fn choose(strategy: WatcherStrategy, poll_interval: Duration, path: &Path) -> Self {
+        match strategy {
+            WatcherStrategy::Native => WatchMethod::Native,
+            WatcherStrategy::Polling => WatchMethod::Poll(poll_interval),
+            WatcherStrategy::Auto if volume::is_remote_volume(path) => {
+                WatchMethod::Poll(poll_interval)
+            }
+            WatcherStrategy::Auto => WatchMethod::Native,
+        }
+    }
+}
+
+/// The kind of watcher used for a repo, reported for diagnostics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum WatcherBackend {
+    ReadDirectoryChanges,
+    Inotify,
+    FsEvents,
+    Kqueue,
+    Polling,
+    /// Events are sent by `EventInjector`, only used in tests
+    Injected,
+}
+
+/// A watcher created with `new_repo_watcher()` or `best_for()`.
+#[derive(Debug)]
+pub enum RepoWatcher {
+    Native(BestWatcher),
+    Poll(PollNormWatcher),
+    Injected(InjectableNormWatcher),
+}
+
+impl RepoWatcher {
+    pub fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        match self {
+            RepoWatcher::Native(watcher) => watcher.watch(path, recursive_mode),
+            RepoWatcher::Poll(watcher) => watcher.watch(path, recursive_mode),
+            RepoWatcher::Injected(watcher) => watcher.watch(path, recursive_mode),
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        match self {
+            RepoWatcher::Native(watcher) => watcher.unwatch(path),
+            RepoWatcher::Poll(watcher) => watcher.unwatch(path),
+            RepoWatcher::Injected(watcher) => watcher.unwatch(path),
+        }
+    }
+
+    pub fn backend(&self) -> WatcherBackend {
+        match self {
+            RepoWatcher::Native(_) => match <BestWatcher as Watcher>::kind() {
+                WatcherKind::ReadDirectoryChangesWatcher => WatcherBackend::ReadDirectoryChanges,
+                WatcherKind::Inotify => WatcherBackend::Inotify,
+                WatcherKind::Fsevent => WatcherBackend::FsEvents,
+                WatcherKind::Kqueue => WatcherBackend::Kqueue,
+                _ => WatcherBackend::Polling,
+            },
+            RepoWatcher::Poll(_) => WatcherBackend::Polling,
+            RepoWatcher::Injected(_) => WatcherBackend::Injected,
+        }
+    }
+}
+
+/// Creates a repo's watcher instead of `best_for()`, given the event handler and the folders to
+/// watch. Tests use this to control which events the watcher sends, see `EventInjector::factory()`.
+#[derive(Clone)]
+pub struct WatcherFactory(
+    Arc<
+        dyn Fn(Box<dyn EventHandler>, &[(PathBuf, RecursiveMode)]) -> notify::Result<RepoWatcher>
+            + Send
+            + Sync,
+    >,
+);
+
+impl WatcherFactory {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(Box<dyn EventHandler>, &[(PathBuf, RecursiveMode)]) -> notify::Result<RepoWatcher>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    pub fn create<F: EventHandler>(
+        &self,
+        event_handler: F,
+        paths: &[(PathBuf, RecursiveMode)],
+    ) -> notify::Result<RepoWatcher> {
+        (self.0)(Box::new(event_handler), paths)
+    }
+}
+
+impl Debug for WatcherFactory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WatcherFactory")
+    }
+}
+
+/// Create a watcher that watches folders with the given method.
+pub fn new_repo_watcher<F: EventHandler>(
+    event_handler: F,
+    method: WatchMethod,
+    options: PairingOptions,
+) -> notify::Result<RepoWatcher> {
+    match method {
+        WatchMethod::Native => Ok(RepoWatcher::Native(new_watcher(event_handler, options)?)),
+        WatchMethod::Poll(interval) => {
+            let config = Config::default().with_poll_interval(interval);
+            let watcher = PollNormWatcher::with_options(event_handler, config, options)?;
+            Ok(RepoWatcher::Poll(watcher))
+        }
+    }
+}
+
+/// Create the best watcher for the repo at `root` and watch `paths` with it. The strategy and the
+/// filesystem decide whether the OS's change notifications are used, see `WatchMethod::choose()`.
+/// With `WatcherStrategy::Auto`, the folders are polled if the native watcher can't be created or
+/// can't watch them, e.g. when the OS limit of watched folders is reached.
+pub fn best_for<F: EventHandler + Clone>(
+    event_handler: F,
+    root: &Path,
+    paths: &[(PathBuf, RecursiveMode)],
+    strategy: WatcherStrategy,
+    poll_interval: Duration,
+    options: PairingOptions,
+) -> notify::Result<RepoWatcher> {
+    let watch_all = |mut watcher: RepoWatcher| -> notify::Result<RepoWatcher> {
+        for (path, mode) in paths {
+            watcher.watch(path, *mode)?;
+        }
+        Ok(watcher)
+    };
+    let method = WatchMethod::choose(strategy, poll_interval, root);
+    if method == WatchMethod::Native {
+        let native =
+            new_repo_watcher(event_handler.clone(), method, options.clone()).and_then(watch_all);
+        match native {
+            Ok(watcher) => return Ok(watcher),
+            Err(err) if strategy == WatcherStrategy::Auto => {
+                warn!(
+                    "can't watch {:?} natively, polling it instead: {}",
+                    root, err
+                )
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let watcher = new_repo_watcher(event_handler, WatchMethod::Poll(poll_interval), options)?;
+    watch_all(watcher)
+}
 
 // #[cfg(test)]
 // mod tests {
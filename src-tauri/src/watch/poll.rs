@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use jwalk::WalkDir;
+use notify::event::ModifyKind::Name;
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+use notify::EventKind::{Create, Modify, Remove};
+use notify::{Config, Event, EventHandler, RecursiveMode, Watcher, WatcherKind};
+use tracing::debug;
+
+use crate::hash::hash_file;
+use crate::watch::{PairingCounter, PairingOptions};
+
+/// A watcher that scans the watched folders for changes every `Config::poll_interval()`, for
+/// filesystems where the OS doesn't report changes reliably, e.g. NFS, FUSE or WSL mounts.
+///
+/// Each scan is compared with the previous one. Polling can't see a file being moved, only that
+/// one path disappeared and another appeared, so renames are inferred instead:
+///
+/// - Folders: A deleted folder is paired with a created folder that has exactly the same
+///   contents, i.e. the same relative paths with the same sizes and modification times.
+/// - Files: A deleted file is paired with a created file with the same size and modification
+///   time, since moving a file keeps both. If several created files match, the one with the same
+///   name is picked, then the one with the same content hash. The hash of the deleted file comes
+///   from `PairingOptions::lookup`. If that doesn't help, the files aren't paired.
+///
+/// The outcome of each pairing is counted in `PairingOptions::metrics`.
+///
+/// If a watched folder can't be read, e.g. because the network drive was disconnected, the scan
+/// is skipped instead of reporting every file as deleted.
+///
+/// ## How to stop watching
+///
+/// Just drop this struct. The polling thread stops once it notices the watcher is gone.
+#[derive(Debug)]
+pub struct PollNormWatcher {
+    roots: Arc<Mutex<Vec<(PathBuf, RecursiveMode)>>>,
+    /// Wakes the polling thread, so newly watched folders are scanned straight away. The thread
+    /// stops when this is dropped.
+    wake: Sender<()>,
+}
+
+impl PollNormWatcher {
+    pub(crate) fn with_options<F: EventHandler>(
+        event_handler: F,
+        config: Config,
+        options: PairingOptions,
+    ) -> notify::Result<Self> {
+        let roots: Arc<Mutex<Vec<(PathBuf, RecursiveMode)>>> = Default::default();
+        let (wake, wake_rx) = channel();
+        let interval = config.poll_interval();
+
+        let thread_roots = roots.clone();
+        std::thread::spawn(move || {
+            let mut event_handler = event_handler;
+            let mut snapshots: HashMap<PathBuf, Snapshot> = HashMap::new();
+            loop {
+                let roots = thread_roots.lock().unwrap().clone();
+                snapshots.retain(|root, _| roots.iter().any(|(x, _)| x == root));
+                for (root, mode) in roots {
+                    let Some(new) = snapshot(&root, mode) else {
+                        debug!("can't read watched folder {:?}, skipping scan", root);
+                        continue;
+                    };
+                    // the first scan of a folder is only used for comparison
+                    if let Some(old) = snapshots.get(&root) {
+                        for evt in diff(old, &new, &options) {
+                            event_handler.handle_event(Ok(evt));
+                        }
+                    }
+                    snapshots.insert(root, new);
+                }
+                match wake_rx.recv_timeout(interval) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            debug!("poll watcher stopped");
+        });
+
+        Ok(Self { roots, wake })
+    }
+}
+
+impl Watcher for PollNormWatcher {
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::with_options(event_handler, config, PairingOptions::default())
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        if !path.is_dir() {
+            return Err(notify::Error::path_not_found().add_path(path.to_path_buf()));
+        }
+        let mut roots = self.roots.lock().unwrap();
+        roots.retain(|(x, _)| x != path);
+        roots.push((path.to_path_buf(), recursive_mode));
+        drop(roots);
+        let _ = self.wake.send(());
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let mut roots = self.roots.lock().unwrap();
+        let Some(index) = roots.iter().position(|(x, _)| x == path) else {
+            return Err(notify::Error::watch_not_found().add_path(path.to_path_buf()));
+        };
+        roots.remove(index);
+        Ok(())
+    }
+
+    fn kind() -> WatcherKind
+    where
+        Self: Sized,
+    {
+        WatcherKind::PollWatcher
+    }
+}
+
+/// What a scan knows about a path.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct FileState {
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+type Snapshot = HashMap<PathBuf, FileState>;
+
+/// Scan a watched folder, or return `None` if it can't be read.
+fn snapshot(root: &Path, mode: RecursiveMode) -> Option<Snapshot> {
+    // reading the folder also checks that it's still there
+    std::fs::read_dir(root).ok()?;
+    let max_depth = match mode {
+        RecursiveMode::Recursive => usize::MAX,
+        RecursiveMode::NonRecursive => 1,
+    };
+    let walker = WalkDir::new(root)
+        .skip_hidden(false)
+        .follow_links(false)
+        .max_depth(max_depth);
+    let mut snapshot = HashMap::new();
+    for entry in walker {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        // the root folder itself is also yielded by the walker
+        if entry.depth() == 0 {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let state = FileState {
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        };
+        snapshot.insert(entry.path(), state);
+    }
+    Some(snapshot)
+}
+
+/// The contents of a folder in a snapshot, relative to the folder and sorted by path. Two
+/// folders with the same contents are assumed to be the same folder.
+fn folder_contents(
+    folder: &Path,
+    paths: &[&PathBuf],
+    snapshot: &Snapshot,
+) -> Vec<(PathBuf, FileState)> {
+    let mut contents: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            let relpath = path.strip_prefix(folder).ok()?;
+            if relpath.as_os_str().is_empty() {
+                return None;
+            }
+            Some((relpath.to_path_buf(), snapshot[*path]))
+        })
+        .collect();
+    contents.sort_by(|a, b| a.0.cmp(&b.0));
+    contents
+}
+
+/// Pick which of the created files in `candidates` the deleted file was moved to, or `None` if
+/// it can't be told.
+fn pick_created_path<'a>(
+    deleted_path: &Path,
+    candidates: &[&'a PathBuf],
+    options: &PairingOptions,
+) -> Option<&'a PathBuf> {
+    let metrics = &options.metrics;
+    metrics.record(PairingCounter::Ambiguous);
+    let name = deleted_path.file_name();
+    let mut same_name = candidates.iter().filter(|x| x.file_name() == name);
+    if let (Some(path), None) = (same_name.next(), same_name.next()) {
+        return Some(*path);
+    }
+    if let Some(lookup) = &options.lookup {
+        if let Some((_, hash)) = lookup(deleted_path) {
+            let mut same_hash = candidates
+                .iter()
+                .filter(|x| hash_file(x).is_ok_and(|x| x == hash));
+            if let (Some(path), None) = (same_hash.next(), same_hash.next()) {
+                metrics.record(PairingCounter::MatchedByHash);
+                return Some(*path);
+            }
+        }
+    }
+    debug!("can't tell where {:?} was moved to", deleted_path);
+    metrics.record(PairingCounter::Unresolved);
+    None
+}
+
+/// The events that turn the `old` snapshot into the `new` one.
+fn diff(old: &Snapshot, new: &Snapshot, options: &PairingOptions) -> Vec<Event> {
+    let metrics = &options.metrics;
+    let changed_type = |path: &PathBuf| new.get(path).is_some_and(|x| x.is_dir != old[path].is_dir);
+    let mut removed: Vec<&PathBuf> = old
+        .keys()
+        .filter(|x| !new.contains_key(*x) || changed_type(*x))
+        .collect();
+    let mut created: Vec<&PathBuf> = new
+        .keys()
+        .filter(|x| !old.contains_key(*x) || changed_type(*x))
+        .collect();
+    // outer folders are paired before the folders inside them
+    removed.sort_by_key(|x| (x.components().count(), x.to_path_buf()));
+    created.sort_by_key(|x| (x.components().count(), x.to_path_buf()));
+
+    let mut events = vec![];
+    let mut paired_removed: HashSet<&PathBuf> = HashSet::new();
+    let mut paired_created: HashSet<&PathBuf> = HashSet::new();
+    let rename = |from: &PathBuf, to: &PathBuf| {
+        Event::new(Modify(Name(RenameMode::Both)))
+            .add_path(from.clone())
+            .add_path(to.clone())
+    };
+
+    // folders
+    let created_folders: Vec<_> = created
+        .iter()
+        .filter(|x| new[**x].is_dir)
+        .map(|x| (*x, folder_contents(x, &created, new)))
+        .collect();
+    for from in removed.iter().filter(|x| old[**x].is_dir) {
+        if paired_removed.contains(from) {
+            continue;
+        }
+        let contents = folder_contents(from, &removed, old);
+        let mut matches = created_folders.iter().filter(|(to, to_contents)| {
+            !paired_created.contains(to)
+                && to_contents == &contents
+                // empty folders can only be told apart by name
+                && (!contents.is_empty() || to.file_name() == from.file_name())
+        });
+        let (Some((to, _)), None) = (matches.next(), matches.next()) else {
+            continue;
+        };
+        metrics.record(PairingCounter::Paired);
+        events.push(rename(*from, *to));
+        paired_removed.extend(removed.iter().filter(|x| x.starts_with(from)));
+        paired_created.extend(created.iter().filter(|x| x.starts_with(to)));
+    }
+
+    // files
+    for from in removed.iter().filter(|x| !old[**x].is_dir) {
+        if paired_removed.contains(from) {
+            continue;
+        }
+        let state = old[*from];
+        let candidates: Vec<&PathBuf> = created
+            .iter()
+            .filter(|x| !paired_created.contains(*x) && new[**x] == state)
+            .copied()
+            .collect();
+        let to = match candidates[..] {
+            [] => None,
+            [to] => Some(to),
+            _ => pick_created_path(from, &candidates, options),
+        };
+        if let Some(to) = to {
+            metrics.record(PairingCounter::Paired);
+            events.push(rename(*from, to));
+            paired_removed.insert(*from);
+            paired_created.insert(to);
+        }
+    }
+
+    for path in removed.iter().filter(|x| !paired_removed.contains(*x)) {
+        if !old[*path].is_dir {
+            metrics.record(PairingCounter::UnpairedRemove);
+        }
+        events.push(Event::new(Remove(RemoveKind::Any)).add_path(path.to_path_buf()));
+    }
+    for path in created.iter().filter(|x| !paired_created.contains(*x)) {
+        if !new[*path].is_dir {
+            metrics.record(PairingCounter::UnpairedCreate);
+        }
+        events.push(Event::new(Create(CreateKind::Any)).add_path(path.to_path_buf()));
+    }
+    for (path, state) in new {
+        match old.get(path) {
+            Some(old_state) if !state.is_dir && !old_state.is_dir && old_state != state => {
+                let kind = Modify(ModifyKind::Data(DataChange::Any));
+                events.push(Event::new(kind).add_path(path.clone()));
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use notify::EventKind;
+
+    use super::*;
+
+    fn file(size: u64, secs: u64) -> FileState {
+        FileState {
+            is_dir: false,
+            size,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    fn folder() -> FileState {
+        FileState { is_dir: true, size: 0, modified: None }
+    }
+
+    fn snapshot_of(entries: &[(&str, FileState)]) -> Snapshot {
+        entries
+            .iter()
+            .map(|(path, state)| (PathBuf::from(path), *state))
+            .collect()
+    }
+
+    /// The events as `(kind, paths)` pairs, sorted so they can be compared.
+    fn summarize(events: Vec<Event>) -> Vec<(&'static str, Vec<String>)> {
+        let mut events: Vec<_> = events
+            .into_iter()
+            .map(|evt| {
+                let kind = match evt.kind {
+                    EventKind::Create(_) => "create",
+                    EventKind::Remove(_) => "remove",
+                    EventKind::Modify(Name(_)) => "rename",
+                    EventKind::Modify(_) => "modify",
+                    _ => "other",
+                };
+                let paths = evt
+                    .paths
+                    .iter()
+                    .map(|x| x.to_string_lossy().replace('\\', "/"))
+                    .collect();
+                (kind, paths)
+            })
+            .collect();
+        events.sort();
+        events
+    }
+
+    #[test]
+    fn files_are_paired_by_size_and_mtime() {
+        let old = snapshot_of(&[("a/kick.wav", file(10, 1)), ("a/snare.wav", file(20, 1))]);
+        let new = snapshot_of(&[("b/kick.wav", file(10, 1)), ("b/other.wav", file(30, 1))]);
+        let events = diff(&old, &new, &PairingOptions::default());
+        assert_eq!(
+            summarize(events),
+            vec![
+                ("create", vec!["b/other.wav".to_string()]),
+                ("remove", vec!["a/snare.wav".to_string()]),
+                (
+                    "rename",
+                    vec!["a/kick.wav".to_string(), "b/kick.wav".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ambiguous_files_are_paired_by_name() {
+        let old = snapshot_of(&[("a/kick.wav", file(10, 1))]);
+        let new = snapshot_of(&[("b/kick.wav", file(10, 1)), ("b/copy.wav", file(10, 1))]);
+        let options = PairingOptions::default();
+        let events = diff(&old, &new, &options);
+        assert_eq!(
+            summarize(events),
+            vec![
+                ("create", vec!["b/copy.wav".to_string()]),
+                (
+                    "rename",
+                    vec!["a/kick.wav".to_string(), "b/kick.wav".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(options.metrics.stats().ambiguous, 1);
+
+        // without a way to tell them apart, the files aren't paired
+        let new = snapshot_of(&[("b/one.wav", file(10, 1)), ("b/two.wav", file(10, 1))]);
+        let events = diff(&old, &new, &options);
+        assert_eq!(
+            summarize(events),
+            vec![
+                ("create", vec!["b/one.wav".to_string()]),
+                ("create", vec!["b/two.wav".to_string()]),
+                ("remove", vec!["a/kick.wav".to_string()]),
+            ]
+        );
+        assert_eq!(options.metrics.stats().unresolved, 1);
+    }
+
+    #[test]
+    fn folders_are_paired_by_contents() {
+        let old = snapshot_of(&[
+            ("drums", folder()),
+            ("drums/kick.wav", file(10, 1)),
+            ("drums/snare.wav", file(20, 1)),
+        ]);
+        let new = snapshot_of(&[
+            ("percussion", folder()),
+            ("percussion/kick.wav", file(10, 1)),
+            ("percussion/snare.wav", file(20, 1)),
+        ]);
+        let events = diff(&old, &new, &PairingOptions::default());
+        assert_eq!(
+            summarize(events),
+            vec![(
+                "rename",
+                vec!["drums".to_string(), "percussion".to_string()]
+            )]
+        );
+    }
+
+    #[test]
+    fn modified_files_are_reported() {
+        let old = snapshot_of(&[("kick.wav", file(10, 1)), ("snare.wav", file(20, 1))]);
+        let new = snapshot_of(&[("kick.wav", file(10, 2)), ("snare.wav", file(20, 1))]);
+        let events = diff(&old, &new, &PairingOptions::default());
+        assert_eq!(
+            summarize(events),
+            vec![("modify", vec!["kick.wav".to_string()])]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+/// Whether native change notifications don't work on a filesystem, or only report changes made on
+/// this computer.
+#[cfg(target_os = "linux")]
+fn is_remote_fs(fs_type: &str) -> bool {
+    matches!(
+        fs_type,
+        "nfs" | "nfs4" | "cifs" | "smb3" | "smbfs" | "afs" | "9p" | "v9fs" | "drvfs" | "ceph"
+    ) || fs_type.starts_with("fuse.")
+}
+
+/// Whether the path is on a network drive or another filesystem where the OS's change
+/// notifications are unreliable, so the folder should be polled instead.
+pub(crate) fn is_remote_volume(path: &Path) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+            return false;
+        };
+        mount_fs_type(&mounts, &path).is_some_and(is_remote_fs)
+    }
+    #[cfg(target_os = "windows")]
+    {
+        // UNC paths like `\\server\share`, drive letters mapped to a share aren't detected
+        let path = path.to_string_lossy();
+        match path.strip_prefix(r"\\?\") {
+            Some(path) => path.starts_with(r"UNC\"),
+            None => path.starts_with(r"\\"),
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// The filesystem type of the mount containing `path`, given the contents of `/proc/mounts`.
+#[cfg(target_os = "linux")]
+fn mount_fs_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fs_type = fields.next()?;
+            // spaces in mount points are escaped
+            let mount_point = mount_point.replace(r"\040", " ");
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.len(), fs_type))
+        })
+        // the innermost mount is the one with the longest path
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn innermost_mount_is_used() {
+        let mounts = "\
+            /dev/sda1 / ext4 rw,relatime 0 0\n\
+            //nas/samples /mnt/nas cifs rw 0 0\n\
+            /dev/sdb1 /mnt/nas/local\\040disk ext4 rw 0 0\n";
+        let fs_type = |path: &str| mount_fs_type(mounts, Path::new(path));
+        assert_eq!(fs_type("/home/user/samples"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/nas/drums"), Some("cifs"));
+        assert_eq!(fs_type("/mnt/nas/local disk/drums"), Some("ext4"));
+        // only whole path components are matched
+        assert_eq!(fs_type("/mnt/nasty"), Some("ext4"));
+    }
+}
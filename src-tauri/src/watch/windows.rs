@@ -1,14 +1,18 @@
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
 
 use notify::event::ModifyKind::Name;
-use notify::event::{CreateKind, EventAttributes, RemoveKind, RenameMode};
-use notify::EventKind::{Create, Modify, Remove};
+use notify::event::{CreateKind, EventAttributes, Flag, RemoveKind, RenameMode};
+use notify::EventKind::{Create, Modify, Other, Remove};
 use notify::{
     Config, Event, EventHandler, ReadDirectoryChangesWatcher, RecursiveMode, Watcher, WatcherKind,
 };
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::time::{timeout_at, Instant};
+use tracing::debug;
+
+use crate::hash::hash_file;
+use crate::watch::{PairingCounter, PairingMetrics, PairingOptions};
 
 /// A wrapper for `ReadDirectoryChangesWatcher`.
 ///
@@ -33,10 +37,23 @@ use tokio::time::{timeout_at, Instant};
 /// - Delete events: It defers these events for later processing. This is because both file
 ///   deletions and file moves are returned as deletions on Windows. This watcher delays any delete
 ///   events to see if any create events with the same name are created later, then treats the event
-///   as a rename if so.
-/// - Create events: It either returns a create event or a rename event. See the above point.
+///   as a rename if so. How long deletions are delayed is set by `PairingOptions::window`.
+/// - Create events: It either returns a create event or a rename event. See the above point. If
+///   several delayed deletions have the same name as the created file, the deletion with the same
+///   size is picked, then the one with the same content hash. The size and hash of deleted files
+///   come from `PairingOptions::lookup`. If that doesn't help, the oldest deletion is picked.
+///
+/// The outcome of each pairing is counted in `PairingOptions::metrics`.
 /// - Other events: It returns the events as-is.
 ///
+/// ## Lost events
+///
+/// When the buffer of `ReadDirectoryChangesW` overflows, events are dropped silently, so a rename
+/// may arrive with only one of its halves. When this happens, the handler returns the half it
+/// received as a create or remove event, followed by an event with the `Rescan` flag for the
+/// parent folder, so the receiver can rescan it to pick up the changes it missed. Errors from the
+/// watcher are returned as-is.
+///
 /// ## How to stop watching
 ///
 /// Just drop this struct. It should automatically clean up everything. When this struct drops, it
@@ -48,11 +65,12 @@ pub struct WindowsNormWatcher {
     watcher: ReadDirectoryChangesWatcher,
 }
 
-impl Watcher for WindowsNormWatcher {
-    fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self>
-    where
-        Self: Sized,
-    {
+impl WindowsNormWatcher {
+    pub(crate) fn with_options<F: EventHandler>(
+        event_handler: F,
+        config: Config,
+        options: PairingOptions,
+    ) -> notify::Result<Self> {
         // Spawn the watcher
         let (watcher_tx, watcher_rx) = unbounded_channel();
 
@@ -62,11 +80,20 @@ impl Watcher for WindowsNormWatcher {
         // Spawn the event handler
         // Don't need to store the JoinHandle, it should naturally terminate once the watcher drops
         tokio::spawn(async move {
-            event_handler_loop(watcher_rx, event_handler).await;
+            event_handler_loop(watcher_rx, event_handler, options).await;
         });
 
         Ok(Self { watcher })
     }
+}
+
+impl Watcher for WindowsNormWatcher {
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::with_options(event_handler, config, PairingOptions::default())
+    }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
         self.watcher.watch(path, recursive_mode)
@@ -84,13 +111,67 @@ impl Watcher for WindowsNormWatcher {
     }
 }
 
+/// Pick which of the deleted paths in `candidates` was moved to `created_path`. `candidates` are
+/// indexes into `recent_deleted_paths`, ordered from oldest to newest.
+fn pick_deleted_path(
+    created_path: &Path,
+    recent_deleted_paths: &[(Instant, PathBuf, EventAttributes)],
+    candidates: &[usize],
+    options: &PairingOptions,
+) -> usize {
+    let metrics = &options.metrics;
+    metrics.record(PairingCounter::Ambiguous);
+    let (Some(lookup), Ok(metadata)) = (&options.lookup, fs::metadata(created_path)) else {
+        metrics.record(PairingCounter::Unresolved);
+        return candidates[0];
+    };
+    let size = metadata.len();
+    let same_size: Vec<(usize, String)> = candidates
+        .iter()
+        .filter_map(|&i| match lookup(&recent_deleted_paths[i].1) {
+            Some((deleted_size, hash)) if deleted_size == size => Some((i, hash)),
+            _ => None,
+        })
+        .collect();
+    if let [(i, _)] = same_size[..] {
+        metrics.record(PairingCounter::MatchedBySize);
+        return i;
+    }
+    if same_size.len() > 1 {
+        // only hash the file when the size isn't enough, since this blocks the event loop
+        if let Ok(hash) = hash_file(created_path) {
+            let mut same_hash = same_size.iter().filter(|(_, x)| x == &hash);
+            if let (Some((i, _)), None) = (same_hash.next(), same_hash.next()) {
+                metrics.record(PairingCounter::MatchedByHash);
+                return *i;
+            }
+        }
+    }
+    debug!(
+        "can't tell which deleted file was moved to {:?}",
+        created_path
+    );
+    metrics.record(PairingCounter::Unresolved);
+    candidates[0]
+}
+
 async fn event_handler_loop(
     mut watcher_rx: UnboundedReceiver<notify::Result<Event>>,
     mut event_handler: impl EventHandler,
+    options: PairingOptions,
 ) {
+    /// An event telling the receiver that changes in the given folder may have been missed.
+    fn rescan_event(path: &Path) -> Event {
+        let dir = path.parent().unwrap_or(path);
+        Event::new(Other)
+            .add_path(dir.to_path_buf())
+            .set_flag(Flag::Rescan)
+    }
+
     fn clear_expired_records(
         recent_deleted_paths: &mut Vec<(Instant, PathBuf, EventAttributes)>,
         event_handler: &mut impl EventHandler,
+        metrics: &PairingMetrics,
     ) {
         let now = Instant::now();
         let mut i = 0;
@@ -102,6 +183,7 @@ async fn event_handler_loop(
                 let (expires_at, _, _) = recent_deleted_paths.get(i).unwrap();
                 if expires_at <= &now {
                     let (_, path, attrs) = recent_deleted_paths.remove(i);
+                    metrics.record(PairingCounter::UnpairedRemove);
                     let evt = Event {
                         kind: Remove(RemoveKind::Any),
                         paths: vec![path],
@@ -129,7 +211,11 @@ async fn event_handler_loop(
                 }
                 Err(_) => {
                     // Timeout occurred, clear expired records from database and wait again
-                    clear_expired_records(&mut recent_deleted_paths, &mut event_handler);
+                    clear_expired_records(
+                        &mut recent_deleted_paths,
+                        &mut event_handler,
+                        &options.metrics,
+                    );
                     continue;
                 }
             }
@@ -148,8 +234,18 @@ async fn event_handler_loop(
                     Event {
                         kind: Modify(Name(RenameMode::From)), mut paths, ..
                     } => {
-                        if let Some(_) = last_rename_from {
-                            panic!("Got multiple 'Rename From' events in a row!")
+                        if let Some(prev_path) = last_rename_from.take() {
+                            // the 'Rename To' event of the previous rename was lost, treat it as
+                            // a deletion and rescan its folder in case it was moved elsewhere
+                            options.metrics.record(PairingCounter::LostEvent);
+                            let rescan = rescan_event(&prev_path);
+                            let evt = Event {
+                                kind: Remove(RemoveKind::Any),
+                                paths: vec![prev_path],
+                                attrs: Default::default(),
+                            };
+                            event_handler.handle_event(Ok(evt));
+                            event_handler.handle_event(Ok(rescan));
                         }
                         let path = paths.pop().unwrap();
                         last_rename_from = Some(path);
@@ -160,10 +256,22 @@ async fn event_handler_loop(
                         mut paths,
                         attrs,
                     } => {
-                        let from_path = last_rename_from.take().expect(
-                        "Got 'Rename To' event, but no 'Rename From' event happened before this!",
-                    );
                         let to_path = paths.pop().unwrap();
+                        let Some(from_path) = last_rename_from.take() else {
+                            // the 'Rename From' event was lost, treat this as a new file and
+                            // rescan its folder in case the original file is still listed
+                            options.metrics.record(PairingCounter::LostEvent);
+                            let rescan = rescan_event(&to_path);
+                            let evt = Event {
+                                kind: Create(CreateKind::Any),
+                                paths: vec![to_path],
+                                attrs,
+                            };
+                            event_handler.handle_event(Ok(evt));
+                            event_handler.handle_event(Ok(rescan));
+                            continue;
+                        };
+                        options.metrics.record(PairingCounter::Paired);
                         let evt = Event {
                             kind: Modify(Name(RenameMode::Both)),
                             paths: vec![from_path, to_path],
@@ -179,7 +287,7 @@ async fn event_handler_loop(
                             paths.len()
                         );
                         let removed_path = paths.pop().unwrap();
-                        let expires_at = Instant::now() + Duration::from_millis(10);
+                        let expires_at = Instant::now() + options.window;
                         recent_deleted_paths.push((expires_at, removed_path, attrs));
                     }
                     Event { kind: Create(CreateKind::Any), mut paths, attrs } => {
@@ -190,22 +298,31 @@ async fn event_handler_loop(
                             paths.len()
                         );
                         let created_path = paths.pop().unwrap();
-                        let mut deleted_path_match_id: Option<usize> = None;
-                        for i in 0..recent_deleted_paths.len() {
-                            let deleted_path = &recent_deleted_paths.get(i).unwrap().1;
-                            let created_name = created_path
-                                .file_name()
-                                .expect("Path doesn't have file name");
-                            let deleted_name = deleted_path
-                                .file_name()
-                                .expect("Path doesn't have file name");
-                            if created_name == deleted_name {
-                                deleted_path_match_id = Some(i);
-                                break;
-                            }
-                        }
+                        let created_name = created_path
+                            .file_name()
+                            .expect("Path doesn't have file name");
+                        let candidates: Vec<usize> = (0..recent_deleted_paths.len())
+                            .filter(|&i| {
+                                let deleted_name = recent_deleted_paths[i]
+                                    .1
+                                    .file_name()
+                                    .expect("Path doesn't have file name");
+                                created_name == deleted_name
+                            })
+                            .collect();
+                        let deleted_path_match_id = match candidates[..] {
+                            [] => None,
+                            [i] => Some(i),
+                            _ => Some(pick_deleted_path(
+                                &created_path,
+                                &recent_deleted_paths,
+                                &candidates,
+                                &options,
+                            )),
+                        };
                         match deleted_path_match_id {
                             Some(i) => {
+                                options.metrics.record(PairingCounter::Paired);
                                 let deleted_path_match = recent_deleted_paths.remove(i).1;
                                 let evt = Event {
                                     kind: Modify(Name(RenameMode::Both)),
@@ -215,6 +332,7 @@ async fn event_handler_loop(
                                 event_handler.handle_event(Ok(evt));
                             }
                             None => {
+                                options.metrics.record(PairingCounter::UnpairedCreate);
                                 let evt = Event {
                                     kind: Create(CreateKind::Any),
                                     paths: vec![created_path],
@@ -230,6 +348,7 @@ async fn event_handler_loop(
             None => {
                 // send remaining deleted paths to output
                 for (_, path, attrs) in recent_deleted_paths {
+                    options.metrics.record(PairingCounter::UnpairedRemove);
                     let evt = Event {
                         kind: Remove(RemoveKind::Any),
                         paths: vec![path],
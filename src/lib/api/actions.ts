@@ -1,9 +1,13 @@
-import { launchFile, revealFile } from "@/lib/ffi";
+import {
+  copyPathsToClipboard,
+  copyTagsToClipboard,
+  launchFile,
+  revealFile,
+} from "@/lib/ffi";
 import { state } from "@/lib/api/state";
 import { selection } from "@/lib/api/selection";
 import { requestItemToBeFetched } from "@/lib/api/items";
 import path from "path-browserify";
-import { clipboard } from "@tauri-apps/api";
 import { normalize } from "@tauri-apps/api/path";
 
 /**
@@ -54,11 +58,17 @@ export async function revealSelectedItems() {
   }
 }
 
-export async function copySelectedItemPaths() {
-  let itemPaths = await getSelectedItemFullPaths();
-  if (itemPaths === null) return;
+function getSelectedItemIds(): number[] {
+  return [...selection.selected.value].map((index) => state.itemIds[index]);
+}
+
+export async function copySelectedItemPaths(absolute: boolean = true) {
+  // the backend looks up the paths, so items don't need to be loaded first
+  await copyPathsToClipboard(getSelectedItemIds(), absolute);
+}
 
-  await clipboard.writeText(itemPaths.join("\n"));
+export async function copySelectedItemTags() {
+  await copyTagsToClipboard(getSelectedItemIds());
 }
 
 export function shuffleList() {
@@ -4,12 +4,19 @@ import {
   insertTags,
   removeTags,
   stopAudio,
-  previewAudio, setAudioVolume,
+  getPreview, setAudioVolume,
+  type SyncReport,
+  type AppError,
+  type SyncDelta,
+  type AppConfig,
+  confirmSync,
+  getChangesSince,
 } from '@/lib/ffi';
+import { ask } from "@tauri-apps/api/dialog";
 import { Event, listen } from "@tauri-apps/api/event";
 import { appWindow } from "@tauri-apps/api/window";
 import { refreshAll, state } from "./state";
-import { closeRepo, openRepo, promptOpenRepo } from "./repo";
+import { closeRepo, openRepo, promptOpenRepo, promptRelocateRepo } from "./repo";
 import { setQuery } from "./query";
 import { type ListViewColumn } from "@/lib/api/view-columns";
 import {
@@ -21,7 +28,7 @@ import {
 } from "./items";
 import { selection } from "./selection";
 import * as actions from "./actions";
-import path from "path-browserify";
+import { applyTheme } from "@/lib/theme";
 
 export {
   revealFile,
@@ -29,13 +36,21 @@ export {
   determineFileType,
   FileType,
   getFolders,
+  resyncFolder,
   type Folder,
+  type NamedFolder,
+  type DirStructureDelta,
   supportsAudioPlayback,
   previewAudio,
   stopAudio,
   getAudioVolume,
   setAudioVolume,
   openManual,
+  newWindow,
+  getRecentRepos,
+  pinRepo,
+  removeRecentRepo,
+  type RecentRepo,
 } from "@/lib/ffi";
 export {
   type ItemDetails,
@@ -54,32 +69,109 @@ export {
   actions,
 };
 
+interface ChangeRange {
+  from: number;
+  to: number;
+}
+
+let catchingUp: Promise<void> | null = null;
+
+// apply changes made to the repo since the last catch-up, only one catch-up runs at a time
+async function catchUpChanges() {
+  while (catchingUp !== null) {
+    await catchingUp;
+  }
+  catchingUp = (async () => {
+    if (state.changeSeq === null) return;
+    const changes = await getChangesSince(state.changeSeq);
+    state.changeSeq = changes.to;
+    let hasNewItems = false;
+    for (const itemDetail of changes.items) {
+      if (state.itemIds.indexOf(itemDetail.item.id) === -1) {
+        hasNewItems = true;
+      }
+      setCachedItem(itemDetail.item.id, itemDetail);
+    }
+    for (const id of changes.removed) {
+      delete state.itemCache[id];
+      // remove the item from the item list, if it exists
+      const index = state.itemIds.indexOf(id);
+      if (index !== -1) {
+        // remove from selection if it's in it
+        try {
+          selection.remove(index);
+        } catch (e) {
+          // nothing
+        }
+        state.itemIds.splice(index, 1);
+      }
+    }
+    if (hasNewItems) {
+      // update item list without discarding cache
+      state.itemIds = await queryItemIds(state.query);
+    }
+  })();
+  try {
+    await catchingUp;
+  } finally {
+    catchingUp = null;
+  }
+}
+
 // listen to change events from the backend
 (async () => {
   await Promise.all([
-    listen("item-added", async (evt: Event<ItemDetails>) => {
-      console.log("item-added", evt);
-      // update item list without discarding cache
-      state.itemIds = await queryItemIds(state.query);
+    listen("repo-changed", async (evt: Event<ChangeRange>) => {
+      console.log("repo-changed", evt);
+      await catchUpChanges();
     }),
-    listen("item-removed", async (evt: Event<ItemDetails>) => {
-      console.log("item-removed", evt);
-      // remove the item from the item list, if it exists
-      const index = state.itemIds.indexOf(evt.payload.item.id);
-      if (index !== -1) {
-        state.itemIds.splice(index, 1);
+    listen("consistency-repaired", async (evt: Event<unknown>) => {
+      // the repaired items are applied when "repo-changed" is received
+      console.log("consistency-repaired", evt);
+    }),
+    listen("sync-report", (evt: Event<SyncReport>) => {
+      console.log("sync-report", evt);
+      if (evt.payload.retagged_lost > 0) {
+        console.warn(
+          `sync deleted ${evt.payload.retagged_lost} items that had tags`
+        );
+      }
+    }),
+    listen("sync-confirmation-required", async (evt: Event<SyncDelta>) => {
+      console.log("sync-confirmation-required", evt);
+      const accept = await ask(
+        `Syncing will remove ${evt.payload.retagged_lost} tagged items that ` +
+          `can no longer be found, and their tags will be lost. This can ` +
+          `happen if a drive was unplugged.\n\nRemove these items?`,
+        { title: "Confirm sync", type: "warning" }
+      );
+      await confirmSync(accept);
+    }),
+    listen("app-error", (evt: Event<AppError>) => {
+      const { source, message, severity } = evt.payload;
+      if (severity === "Error") {
+        console.error(`${source}: ${message}`);
+      } else {
+        console.warn(`${source}: ${message}`);
       }
-      // remove from selection if it's in it
-      try {
-        selection.remove(evt.id);
-      } catch (e) {
-        // nothing
+    }),
+    listen("media-key-pressed", (evt: Event<"Next" | "Previous">) => {
+      console.log("media-key-pressed", evt);
+      // the selection watcher previews the newly selected item
+      if (evt.payload === "Next") {
+        selection.isolateDown();
+      } else {
+        selection.isolateUp();
       }
     }),
-    listen("item-renamed", async (evt: Event<ItemDetails>) => {
-      console.log("item-renamed", evt);
-      // put item into cache, replacing if it already exists
-      setCachedItem(evt.payload.item.id, evt.payload);
+    listen("preview-volume-changed", (evt: Event<number>) => {
+      // changed by a global shortcut
+      state.audioVolume = evt.payload;
+    }),
+    listen("config-changed", (evt: Event<AppConfig>) => {
+      // changed by this or another window
+      state.appConfig = evt.payload;
+      applyTheme(evt.payload.theme);
     }),
     listen("status-changed", (evt: Event<ManagerStatus | null>) => {
       console.log("Status changed to:", evt.payload);
@@ -89,7 +181,12 @@ export {
       state.path = evt.payload;
       selection.clear();
     }),
+    listen("repo-missing", async (evt: Event<string>) => {
+      console.warn("repo-missing", evt);
+      await promptRelocateRepo(evt.payload);
+    }),
     listen("repo-resynced", async (evt: Event<string>) => {
+      state.changeSeq = (await getChangesSince(null)).to;
       const newItems = await queryItemIds(state.query);
       clearItemCache();
       state.itemIds = newItems;
@@ -98,26 +195,6 @@ export {
       //  you can remove them from the selection
       selection.clear();
     }),
-    listen("item-tags-added", async (evt: Event<ItemDetails>) => {
-      console.log("item-tags-added", evt);
-      setCachedItem(evt.payload.item.id, evt.payload);
-    }),
-    listen("batch-item-tags-added", async (evt: Event<ItemDetails[]>) => {
-      console.log("batch-item-tags-added", evt);
-      for (const itemDetail of evt.payload) {
-        setCachedItem(itemDetail.item.id, itemDetail);
-      }
-    }),
-    listen("item-tags-removed", async (evt: Event<ItemDetails>) => {
-      console.log("item-tags-removed", evt);
-      setCachedItem(evt.payload.item.id, evt.payload);
-    }),
-    listen("batch-item-tags-removed", async (evt: Event<ItemDetails[]>) => {
-      console.log("batch-item-tags-removed", evt);
-      for (const itemDetail of evt.payload) {
-        setCachedItem(itemDetail.item.id, itemDetail);
-      }
-    }),
   ]);
 })();
 
@@ -147,8 +224,10 @@ watch(
       // re-query for new items
       (async () => {
         if (newPath === null) {
+          state.changeSeq = null;
           state.itemIds = [];
         } else {
+          state.changeSeq = (await getChangesSince(null)).to;
           const newItems = await queryItemIds(state.query);
           clearItemCache();
           state.itemIds = newItems;
@@ -171,25 +250,30 @@ watch(
     state.itemIds = newItems;
   }
 );
+
+function clearPreview() {
+  state.preview = null;
+  stopAudio().then();
+}
+
 // when the selection changes...
 watch(
   () => selection.selected.value,
   (selectedIndexes) => {
     if (!state.audioPreview) {
-      stopAudio().then();
+      clearPreview();
       return;
     }
 
     // if repo isn't loaded, do nothing
-    const repoPath = state.path;
-    if (repoPath === null) {
-      stopAudio().then();
+    if (state.path === null) {
+      clearPreview();
       return;
     }
 
-    // play back audio only if 1 item selected
+    // preview only if 1 item selected
     if (selectedIndexes.length !== 1) {
-      stopAudio().then();
+      clearPreview();
       return;
     }
 
@@ -198,19 +282,17 @@ watch(
     const details = state.itemCache[itemId];
     if (details === undefined) {
       // item not loaded yet, do nothing
-      stopAudio().then();
+      clearPreview();
       return;
     }
-    const relPath = details.item.path;
-    const extension = path.extname(relPath).toLowerCase();
-    const ALLOWED_EXTENSIONS = [".mp3", ".wav", ".ewav", ".flac", ".ogg"];
-    if (ALLOWED_EXTENSIONS.indexOf(extension) !== -1) {
-      console.log("extension:", extension);
-      const fullPath = path.join(repoPath, relPath);
-      previewAudio(fullPath).then();
-    } else {
-      stopAudio().then();
-    }
+    // the backend plays audio files, and stops the audio for other files
+    getPreview(itemId).then(
+      (preview) => (state.preview = preview),
+      (err) => {
+        console.error("failed to preview item:", err);
+        state.preview = null;
+      }
+    );
   }
 );
 // when the audio preview setting changes...
@@ -218,7 +300,7 @@ watch(
   () => state.audioPreview,
   (audioPreview) => {
     if (!audioPreview) {
-      stopAudio().then();
+      clearPreview();
     }
   }
 )
@@ -1,9 +1,9 @@
 import * as ffi from "@/lib/ffi";
-import { open } from "@tauri-apps/api/dialog";
+import { ask, open } from "@tauri-apps/api/dialog";
 import { state } from "./state";
 
-export async function openRepo(path: string) {
-  await ffi.openRepo(path);
+export async function openRepo(path: string, readOnly: boolean = false) {
+  await ffi.openRepo(path, readOnly);
 }
 
 export async function promptOpenRepo() {
@@ -15,6 +15,23 @@ export async function promptOpenRepo() {
   }
 }
 
+// ask the user where a recent repo was moved to, then open it there
+export async function promptRelocateRepo(oldPath: string) {
+  const locate = await ask(
+    `The repo at ${oldPath} can't be found, it may have been moved or renamed.\n\n` +
+      `Locate the repo?`,
+    { title: "Repo not found", type: "warning" }
+  );
+  if (!locate) return;
+
+  const newPath = await open({ directory: true, multiple: false });
+  if (Array.isArray(newPath)) throw "cannot open multiple directories";
+
+  if (newPath !== null) {
+    await ffi.relocateRepo(oldPath, newPath);
+  }
+}
+
 export async function closeRepo() {
   await ffi.closeRepo();
   state.path = null;
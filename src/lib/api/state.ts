@@ -1,6 +1,6 @@
 import { reactive } from "vue";
 import * as ffi from "@/lib/ffi";
-import { ItemDetails, ManagerStatus, supportsAudioPlayback } from '@/lib/ffi';
+import { AppConfig, ItemDetails, ManagerStatus, Preview, supportsAudioPlayback } from '@/lib/ffi';
 import { Selection } from "./selection";
 import { ListViewColumn } from "./view-columns";
 
@@ -17,6 +17,9 @@ export interface AppState {
   itemIds: number[];
   // the item cache, this will be changed regularly
   itemCache: Record<number, ItemDetails | undefined>;
+  // the latest change to the repo that has been applied to the item list and cache, will be null
+  // if no repo loaded
+  changeSeq: number | null;
   // the headers/columns displayed in the list view
   listViewColumns: ListViewColumn[];
   // the selection in the list view
@@ -25,6 +28,10 @@ export interface AppState {
   audioPreview: boolean;
   // playback volume
   audioVolume: number;
+  // preview of the selected item, for the preview panel
+  preview: Preview | null;
+  // the app config, kept in sync with other windows by the `config-changed` event
+  appConfig: AppConfig | null;
   // app panels
   panelSizes: {
     bottomPanel: number;
@@ -47,6 +54,7 @@ export const state: AppState = reactive({
   queryIsInvalid: false,
   itemIds: [],
   itemCache: {},
+  changeSeq: null,
   listViewColumns: [
     { type: "name", width: 300 },
     { type: "tags", width: 160 },
@@ -56,6 +64,8 @@ export const state: AppState = reactive({
   itemIdSelection: null,
   audioPreview: false,
   audioVolume: 0.5,
+  preview: null,
+  appConfig: null,
   // size of various panels
   panelSizes: {
     bottomPanel: 160,
@@ -97,6 +107,11 @@ export async function refreshAudioVolume() {
   }
 }
 
+refreshFuncs.push(refreshAppConfig);
+export async function refreshAppConfig() {
+  state.appConfig = await ffi.getAppConfig();
+}
+
 export async function refreshAll() {
   for (const refreshFunc of refreshFuncs) {
     await refreshFunc();
@@ -9,25 +9,85 @@ export interface Item {
   path: string;
   tags: string[];
   meta_tags: string;
+  /** When the item's path or tags last changed, in milliseconds since the unix epoch */
+  updated_at: number;
+  /**
+   * Kept when the item is renamed, relocated or restored after being deleted. Unlike `id`, this
+   * is never reused by another item.
+   */
+  uid: string;
 }
 
 export interface ItemDetails {
   item: Item;
   filetype: FileType;
+  // file name, e.g. "kick.wav"
+  name: string;
+  // extension without the leading dot, empty if there is none
+  ext: string;
+  // folder containing the file, empty for the repo root
+  dirname: string;
+  // size in bytes, null if it hasn't been read yet
+  size: number | null;
 }
 
-export async function openRepo(path: string) {
-  await invoke("open_repo", { path: path });
+export async function openRepo(path: string, readOnly: boolean = false) {
+  await invoke("open_repo", { path: path, readOnly: readOnly });
+}
+
+/**
+ * Open a recent repo that was moved or renamed, after the `repo-missing` event. The new path
+ * replaces the old one in the recent repos list.
+ */
+export async function relocateRepo(oldPath: string, newPath: string) {
+  await invoke("relocate_repo", { oldPath, newPath });
+}
+
+/**
+ * Create a repo of silent audio files and notes that are already tagged, then open it. The folder
+ * must be empty, it is created if it doesn't exist.
+ */
+export async function createDemoRepo(path: string) {
+  await invoke("create_demo_repo", { path });
 }
 
 export async function closeRepo() {
   await invoke("close_repo");
 }
 
+export interface RecentRepo {
+  path: string;
+  pinned: boolean;
+}
+
+/** Recently opened repos, pinned repos first. */
+export async function getRecentRepos(): Promise<RecentRepo[]> {
+  return await invoke("get_recent_repos");
+}
+
+export async function pinRepo(path: string, pinned: boolean) {
+  await invoke("pin_repo", { path, pinned });
+}
+
+export async function removeRecentRepo(path: string) {
+  await invoke("remove_recent_repo", { path });
+}
+
+/** Assign space-separated tags to a quick tag slot, empty tags clear the slot. */
+export async function setQuickTag(slot: number, tags: string) {
+  await invoke("set_quick_tag", { slot, tags });
+}
+
+/** Add the tags of a quick tag slot to the given items. */
+export async function applyQuickTag(ids: number[], slot: number) {
+  await invoke("apply_quick_tag", { ids, slot });
+}
+
 export enum ManagerStatus {
   IDLE = "Idle",
   SCANNING_DIRECTORY = "ScanningDirectory",
   UPDATING_REPO = "UpdatingRepo",
+  AWAITING_SYNC_CONFIRMATION = "AwaitingSyncConfirmation",
   // QUERYING = "Querying",
 }
 
@@ -39,20 +99,381 @@ export async function getRepoPath(): Promise<string | null> {
   return await invoke("current_path");
 }
 
-export async function queryItemIds(query: string): Promise<number[]> {
-  return await invoke("query_item_ids", { query: query });
+/** Counters of how the watcher paired deleted and created files into renames. */
+export interface WatcherStats {
+  paired: number;
+  ambiguous: number;
+  matched_by_size: number;
+  matched_by_hash: number;
+  // ambiguous creations paired with the oldest deletion, these may be mispaired
+  unresolved: number;
+  unpaired_removes: number;
+  unpaired_creates: number;
+  lost_events: number;
+}
+
+export async function getWatcherStats(): Promise<WatcherStats | null> {
+  return await invoke("get_watcher_stats");
+}
+
+/** The kind of watcher used for the open repo, `null` if it isn't being watched. */
+export type WatcherBackend =
+  | "ReadDirectoryChanges"
+  | "Inotify"
+  | "FsEvents"
+  | "Kqueue"
+  | "Polling"
+  | "Injected";
+
+export async function getWatcherBackend(): Promise<WatcherBackend | null> {
+  return await invoke("get_watcher_backend");
+}
+
+/** Timings of one kind of operation, in milliseconds. */
+export interface OperationStats {
+  op: string;
+  count: number;
+  p50: number;
+  p95: number;
+  max: number;
+}
+
+export interface PerfReport {
+  // false if the app wasn't built with the `profiling` feature
+  enabled: boolean;
+  operations: OperationStats[];
+}
+
+export async function getPerfReport(): Promise<PerfReport> {
+  return await invoke("get_perf_report");
+}
+
+/** A line of the app's log files. */
+export interface LogEntry {
+  timestamp: string;
+  level: string;
+  target: string;
+  // the event's message is stored under `message`
+  fields: Record<string, unknown>;
+}
+
+/** The last `n` lines of the log files, from oldest to newest. */
+export async function getRecentLogs(n: number): Promise<LogEntry[]> {
+  return await invoke("get_recent_logs", { n });
+}
+
+/**
+ * Error thrown by `queryItemIds` and `countItems` when the query is invalid. Other errors are
+ * thrown as strings. `offset` and `end` are byte offsets into the query.
+ */
+export interface QueryParseError {
+  message: string;
+  offset: number;
+  end: number;
+  expected: string[];
+}
+
+/**
+ * Ids of items matching the query. If `scopeDir` is given, e.g. the folder selected in the
+ * folder tree, only items inside it are returned.
+ */
+export async function queryItemIds(
+  query: string,
+  scopeDir: string | null = null
+): Promise<number[]> {
+  return await invoke("query_item_ids", { query: query, scopeDir: scopeDir });
+}
+
+/**
+ * Check the syntax of a query without running it, returns the query in a normalized form. Throws
+ * a `QueryParseError` if the query is invalid.
+ */
+export async function validateQuery(query: string): Promise<string> {
+  return await invoke("validate_query", { query });
+}
+
+/** How a query is converted to SQL, see `explainQuery`. */
+export interface QueryExplanation {
+  // the parsed query, null if the query is empty
+  parsed: unknown;
+  // the query without `sort:` terms after simplifying, null if it matches every item
+  simplified: unknown;
+  sql: string;
+  params: unknown[];
+  order_by: string;
+}
+
+/** Explain how a query is converted to SQL. Throws a `QueryParseError` if it's invalid. */
+export async function explainQuery(query: string): Promise<QueryExplanation> {
+  return await invoke("explain_query", { query });
+}
+
+/** Payload of the `query-results-chunk` event. */
+export interface QueryResultsChunk {
+  stream_id: number;
+  ids: number[];
+  // the last event of a stream, `error` is set if the query failed
+  done: boolean;
+  error: string | null;
+}
+
+/**
+ * Like `queryItemIds`, but ids are emitted in `query-results-chunk` events as they are read.
+ * Returns the stream id, which can be passed to `cancelQueryStream`.
+ */
+export async function queryItemIdsStreamed(
+  query: string,
+  chunkSize: number,
+  scopeDir: string | null = null
+): Promise<number> {
+  return await invoke("query_item_ids_streamed", { query, scopeDir, chunkSize });
+}
+
+/** Stop a streamed query, returns false if it has already finished. */
+export async function cancelQueryStream(streamId: number): Promise<boolean> {
+  return await invoke("cancel_query_stream", { streamId });
+}
+
+/**
+ * Copy the paths of the given items to the clipboard, one per line. Paths are relative to the
+ * repo root unless `absolute` is true.
+ */
+export async function copyPathsToClipboard(ids: number[], absolute: boolean) {
+  await invoke("copy_paths_to_clipboard", { ids: ids, absolute: absolute });
+}
+
+/** Copy the tags of the given items to the clipboard, one item per line. */
+export async function copyTagsToClipboard(ids: number[]) {
+  await invoke("copy_tags_to_clipboard", { ids: ids });
+}
+
+export async function countItems(query: string): Promise<number> {
+  return await invoke("count_items", { query: query });
+}
+
+export enum ExportFormat {
+  M3U = "M3u",
+  CSV = "Csv",
+}
+
+/**
+ * Write the items matching the query to a file, either as an M3U playlist or a CSV file with
+ * tags. Returns the number of items written.
+ */
+export async function exportQueryResults(
+  query: string,
+  format: ExportFormat,
+  path: string
+): Promise<number> {
+  return await invoke("export_query_results", {
+    query: query,
+    format: format,
+    path: path,
+  });
+}
+
+/**
+ * Start an OS drag of all items matching the query. The paths are resolved in the backend, so
+ * this works for any number of items. Returns the number of items being dragged.
+ */
+export async function startDragForQuery(query: string): Promise<number> {
+  return await invoke("start_drag_for_query", { query: query });
+}
+
+/** An error in a background task, e.g. the watcher, emitted in the `app-error` event. */
+export interface AppError {
+  source: string;
+  message: string;
+  severity: "Warning" | "Error";
+}
+
+/** Number of items changed by a resync, emitted in the `sync-report` event. */
+export interface SyncReport {
+  created: number;
+  deleted: number;
+  renamed: number;
+  // number of deleted items that had tags
+  retagged_lost: number;
+}
+
+/** Changes that a resync would make, emitted in the `sync-confirmation-required` event. */
+export interface SyncDelta {
+  created: string[];
+  deleted: string[];
+  renamed: [string, string][];
+  // number of deleted items that had tags
+  retagged_lost: number;
+}
+
+/** Apply or discard a resync that is waiting for confirmation. */
+export async function confirmSync(accept: boolean) {
+  await invoke("confirm_sync", { accept: accept });
+}
+
+/** Compact the repo database and update its statistics. This may take a while. */
+export async function optimizeRepo() {
+  await invoke("optimize_repo");
 }
 
 export async function getItemDetails(id: number): Promise<ItemDetails> {
   return await invoke("get_item_details", { id: id });
 }
 
-export interface Folder extends Record<string, Folder> {}
+/** Details of multiple items at once, in the same order as the given ids. */
+export async function getItemsDetails(ids: number[]): Promise<ItemDetails[]> {
+  return await invoke("get_items_details", { ids: ids });
+}
+
+export enum ConflictStrategy {
+  // leave files whose comment has other text unchanged
+  SKIP = "Skip",
+  OVERWRITE = "Overwrite",
+  // add missing tags to the end of the comment
+  MERGE = "Merge",
+}
+
+export type WriteOutcome =
+  | { status: "Written"; old: string | null; new: string }
+  | { status: "Unchanged" }
+  | { status: "Conflict"; existing: string }
+  | { status: "Failed"; error: string };
+
+export interface WriteTagsReport {
+  id: number;
+  path: string;
+  outcome: WriteOutcome;
+}
+
+/**
+ * Write the tags of items into the comments of their audio files, so the tags travel with the
+ * files. With `dryRun`, files aren't changed but the report shows what would be written.
+ */
+export async function writeTagsToFiles(
+  ids: number[],
+  strategy: ConflictStrategy,
+  dryRun: boolean
+): Promise<WriteTagsReport[]> {
+  return await invoke("write_tags_to_files", { ids, strategy, dryRun });
+}
+
+export interface SimilarItem {
+  item: Item;
+  // from -1 to 1, where 1 is identical
+  similarity: number;
+}
+
+/**
+ * Items whose files sound most like an item's file, most similar first. Audio files are
+ * fingerprinted in the background, so recently added items may not be found yet.
+ */
+export async function findSimilar(
+  id: number,
+  limit: number
+): Promise<SimilarItem[]> {
+  return await invoke("find_similar", { id, limit });
+}
+
+// items that have changed after a change sequence number
+export interface ChangeDetails {
+  // the latest change sequence number, pass this to the next call
+  to: number;
+  items: ItemDetails[];
+  // ids of removed items
+  removed: number[];
+}
+
+// pass null to only get the current change sequence number
+export async function getChangesSince(
+  seq: number | null
+): Promise<ChangeDetails> {
+  return await invoke("get_changes_since", { seq: seq });
+}
+
+export type ActivityKind = "Created" | "Deleted" | "Renamed";
+
+// a file created, deleted or renamed while the repo was being watched
+export interface Activity {
+  id: number;
+  kind: ActivityKind;
+  path: string;
+  // the previous path of a renamed file or folder
+  old_path: string | null;
+  // seconds since the unix epoch
+  time: number;
+}
+
+export interface ActivityFilter {
+  kind?: ActivityKind;
+  // only changes made at or after this time, in seconds since the unix epoch
+  since?: number;
+}
+
+/** The latest changes noticed by the watcher, newest first. */
+export async function getActivity(
+  limit: number,
+  filter: ActivityFilter | null = null
+): Promise<Activity[]> {
+  return await invoke("get_activity", { limit, filter });
+}
+
+// watch an additional folder inside the repo for changes
+export async function watchPath(path: string, recursive: boolean) {
+  await invoke("watch_path", { path: path, recursive: recursive });
+}
+
+export async function unwatchPath(path: string) {
+  await invoke("unwatch_path", { path: path });
+}
+
+/** Rescan a folder of the repo and its subfolders, `path` is relative to the repo root. */
+export async function resyncFolder(path: string): Promise<SyncDelta> {
+  return await invoke("resync_folder", { path: path });
+}
+
+/** A folder and its subfolders, which are sorted by name. */
+export interface Folder {
+  // number of items directly inside the folder
+  direct: number;
+  // number of items inside the folder and its subfolders
+  recursive: number;
+  children: NamedFolder[];
+}
+
+export interface NamedFolder extends Folder {
+  name: string;
+}
 
 export async function getFolders(): Promise<Folder> {
   return await invoke("get_dir_structure");
 }
 
+/** A folder directly inside another folder, see `getDirChildren()`. */
+export interface DirChild {
+  name: string;
+  // number of items directly inside the folder
+  direct: number;
+  // number of items inside the folder and its subfolders
+  recursive: number;
+  // number of folders directly inside the folder
+  subfolders: number;
+}
+
+/**
+ * The folders directly inside a folder, sorted by name. Use this instead of `getFolders()` to
+ * expand a large folder tree one level at a time. `path` is empty for the repo root.
+ */
+export async function getDirChildren(path: string): Promise<DirChild[]> {
+  return await invoke("get_dir_children", { path: path });
+}
+
+/** Folders that were added or removed, sent with the `dir-structure-changed` event. */
+export interface DirStructureDelta {
+  added: string[];
+  removed: string[];
+  // [direct, recursive] item counts of folders whose counts changed, the root is ""
+  counts: Record<string, [number, number]>;
+}
+
 export async function revealFile(path: string) {
   return await invoke("reveal_file", { path: path });
 }
@@ -61,6 +482,93 @@ export async function launchFile(path: string) {
   return await invoke("launch_file", { path: path });
 }
 
+/** An application that can open a file, returned by `listOpenWith`. */
+export interface OpenWithApp {
+  id: string;
+  name: string;
+}
+
+/** List the applications that can open the file, for an "open with" menu. */
+export async function listOpenWith(path: string): Promise<OpenWithApp[]> {
+  return await invoke("list_open_with", { path: path });
+}
+
+export async function launchFileWith(path: string, appId: string) {
+  return await invoke("launch_file_with", { path: path, appId: appId });
+}
+
+/** An application added to the "open with" menu in the app config. */
+export interface CustomApp {
+  name: string;
+  command: string;
+  // `{path}` is replaced with the file path, otherwise the path is appended
+  args: string[];
+}
+
+export interface AppConfig {
+  // keys are lowercase extensions without the leading dot, e.g. "wav"
+  open_with: Record<string, CustomApp[]>;
+  // HTTP server on localhost for external tools, requests must send the token as
+  // `Authorization: Bearer <token>`
+  api_server: {
+    enabled: boolean;
+    port: number;
+    token: string;
+  };
+  // tags of each quick tag slot, change these with `setQuickTag()`
+  quick_tags: Record<string, string>;
+  // global shortcuts for audio previews, e.g. "CmdOrCtrl+Alt+Space", null if disabled
+  preview_shortcuts: {
+    stop: string | null;
+    replay: string | null;
+    volume_up: string | null;
+    volume_down: string | null;
+  };
+  audio_preview: {
+    // play audio files when they are selected
+    auto_preview: boolean;
+    // where previews start, unless `previewAudio()` is given an offset
+    skip_milliseconds: number;
+    // previews are stopped after this long, null to play the whole file
+    max_duration_milliseconds: number | null;
+    // play items at a similar loudness, each item is measured the first time it is previewed
+    normalize_loudness: boolean;
+  };
+  theme: ThemeConfig;
+}
+
+export interface ThemeConfig {
+  mode: "dark" | "light" | "system";
+  // CSS hex color like "#3b82f6", null for the default color
+  accent_color: string | null;
+  // base font size in pixels, only applied to windows opened afterwards
+  font_size: number;
+}
+
+/** Every window is sent the new config in the `config-changed` event when it changes. */
+export async function getAppConfig(): Promise<AppConfig> {
+  return await invoke("get_app_config");
+}
+
+export async function setAppConfig(config: AppConfig) {
+  await invoke("set_app_config", { config: config });
+}
+
+/** Write the app config to a file, so it can be imported on another computer. */
+export async function exportSettings(path: string) {
+  await invoke("export_settings", { path: path });
+}
+
+/** Replace the app config with an exported one, window sizes are kept. */
+export async function importSettings(path: string) {
+  await invoke("import_settings", { path: path });
+}
+
+/** Restore the default app config, except for the recent repos list. */
+export async function resetSettings() {
+  await invoke("reset_settings");
+}
+
 export enum FileType {
   AUDIO = "Audio",
   DOCUMENT = "Document",
@@ -73,29 +581,211 @@ export async function determineFileType(path: string): Promise<FileType> {
   return await invoke("determine_filetype", { path: path });
 }
 
-export async function insertTags(itemIds: number[], tags: string) {
-  await invoke("insert_tags", { ids: itemIds, tags: tags });
+/**
+ * The `updated_at` of items when they were last loaded, by item id. Tag commands given this fail
+ * without changing anything if any of the items have changed since, e.g. in another window.
+ */
+export type LastSeen = Record<number, number>;
+
+export async function insertTags(itemIds: number[], tags: string, lastSeen?: LastSeen) {
+  await invoke("insert_tags", { ids: itemIds, tags: tags, lastSeen: lastSeen ?? null });
+}
+
+export async function removeTags(itemIds: number[], tags: string, lastSeen?: LastSeen) {
+  await invoke("remove_tags", { ids: itemIds, tags: tags, lastSeen: lastSeen ?? null });
+}
+
+/** Replace the tags of the given items, returns the number of items changed. */
+export async function setTags(
+  itemIds: number[],
+  tags: string,
+  lastSeen?: LastSeen
+): Promise<number> {
+  return await invoke("set_tags", { ids: itemIds, tags: tags, lastSeen: lastSeen ?? null });
+}
+
+/** Add the tags of a tag template from the repo config to the given items. */
+export async function applyTemplate(itemIds: number[], template: string) {
+  await invoke("apply_template", { ids: itemIds, template: template });
+}
+
+/** Items whose files no longer exist. They stay in the repo until they are purged. */
+export async function verifyItems(): Promise<Item[]> {
+  return await invoke("verify_items");
+}
+
+export async function purgeItems(itemIds: number[]) {
+  await invoke("purge_items", { ids: itemIds });
+}
+
+export interface Relocation {
+  id: number;
+  old_path: string;
+  new_path: string;
+  matched_by: "Hash" | "Name";
+}
+
+/** Files that missing items were probably moved to. This scans the repo folder. */
+export async function suggestRelocations(): Promise<Relocation[]> {
+  return await invoke("suggest_relocations");
+}
+
+/** Move a missing item to a new file, keeping its tags. */
+export async function relocateItem(itemId: number, newPath: string) {
+  await invoke("relocate_item", { id: itemId, newPath: newPath });
+}
+
+export interface TagCount {
+  name: string;
+  count: number;
+}
+
+/** Every tag used in the repo and the number of items that have it. */
+export async function getTagCounts(): Promise<TagCount[]> {
+  return await invoke("get_tag_counts");
 }
 
-export async function removeTags(itemIds: number[], tags: string) {
-  await invoke("remove_tags", { ids: itemIds, tags: tags });
+/** Usage statistics of a tag, timestamps are in seconds since the unix epoch. */
+export interface TagStats {
+  name: string;
+  count: number;
+  // null if the tag was only added before tag history was recorded
+  first_used: number | null;
+  last_used: number | null;
+  // number of times the tag was added to and removed from items in the last 30 days
+  added_recently: number;
+  removed_recently: number;
+  // tags that are most often on the same items, at most 5
+  related: TagCount[];
+}
+
+/** Usage statistics of every tag used in the repo, sorted by name. */
+export async function getTagStats(): Promise<TagStats[]> {
+  return await invoke("get_tag_stats");
+}
+
+export interface SelectionSummary {
+  // number of selected items that exist
+  items: number;
+  // tags on at least one item, sorted by descending count
+  tags: TagCount[];
+  // tags on every item, sorted by name
+  common: string[];
+}
+
+/** The tags of the selected items, and which tags all of them have. */
+export async function getSelectionSummary(ids: number[]): Promise<SelectionSummary> {
+  return await invoke("get_selection_summary", { ids: ids });
+}
+
+/** Rename a tag on every item that has it, returns the number of items changed. */
+export async function renameTag(oldTag: string, newTag: string): Promise<number> {
+  return await invoke("rename_tag", { old: oldTag, new: newTag });
+}
+
+/** A tag that every item in a folder and its subfolders implicitly has. */
+export interface FolderTag {
+  // relative to the repo root, ending with a slash
+  path: string;
+  tag: string;
+}
+
+/**
+ * Tags that items in the folder inherit from it and the folders it is in. Search results should
+ * be refreshed on the `folder-tags-changed` event, since folder tags don't change items.
+ */
+export async function getFolderTags(path: string): Promise<FolderTag[]> {
+  return await invoke("get_folder_tags", { path });
+}
+
+export async function insertFolderTags(path: string, tags: string) {
+  await invoke("insert_folder_tags", { path, tags });
+}
+
+export async function removeFolderTags(path: string, tags: string) {
+  await invoke("remove_folder_tags", { path, tags });
+}
+
+/** A hand-picked set of items, searched with `collection:name`. */
+export interface Collection {
+  name: string;
+  count: number;
+}
+
+/**
+ * Every collection and the number of items in it. Search results should be refreshed on the
+ * `collections-changed` event.
+ */
+export async function getCollections(): Promise<Collection[]> {
+  return await invoke("get_collections");
+}
+
+export async function createCollection(name: string) {
+  await invoke("create_collection", { name });
+}
+
+export async function deleteCollection(name: string) {
+  await invoke("delete_collection", { name });
+}
+
+/** Add items to a collection, returns the number of items that weren't in it yet. */
+export async function addToCollection(
+  name: string,
+  ids: number[]
+): Promise<number> {
+  return await invoke("add_to_collection", { name, ids });
+}
+
+/** Remove items from a collection, returns the number of items that were in it. */
+export async function removeFromCollection(
+  name: string,
+  ids: number[]
+): Promise<number> {
+  return await invoke("remove_from_collection", { name, ids });
 }
 
 export async function supportsAudioPlayback(): Promise<boolean> {
   return await invoke("supports_audio_playback");
 }
 
-export async function previewAudio(path: string, skipMilliseconds: number = 0) {
+/** Play an audio file, from the start offset in the app config unless `skipMilliseconds` is given. */
+export async function previewAudio(path: string, skipMilliseconds: number | null = null) {
   await invoke("preview_audio", {
     path: path,
     skipMilliseconds: skipMilliseconds,
   });
 }
 
+export type Preview =
+  | { type: "Audio"; path: string }
+  | { type: "Image"; mime: string; bytes: number[] }
+  // the start of a text document, `truncated` if the document is longer
+  | { type: "Text"; text: string; truncated: boolean }
+  | { type: "Unsupported" };
+
+/**
+ * Preview an item, audio files are played if auto-preview is enabled in the app config and any
+ * other audio is stopped.
+ */
+export async function getPreview(id: number): Promise<Preview> {
+  return await invoke("get_preview", { id });
+}
+
 export async function stopAudio() {
   await invoke("stop_audio");
 }
 
+/** RMS and peak of the audio preview before the volume is applied, between 0 and 1. */
+export interface PreviewLevels {
+  rms: number;
+  peak: number;
+}
+
+/** Levels of the playing audio preview for a level meter, 0 if nothing is playing. */
+export async function getPreviewLevels(): Promise<PreviewLevels> {
+  return await invoke("get_preview_levels");
+}
+
 export async function getAudioVolume(): Promise<number> {
   return await invoke("get_audio_volume");
 }
@@ -108,3 +798,209 @@ export async function setAudioVolume(volume: number) {
 export async function openManual() {
   await invoke("launch_manual");
 }
+
+/** Open another window on the current repo, returns the new window's label. */
+export async function newWindow(): Promise<string> {
+  return await invoke("new_window");
+}
+
+export type RuleCondition =
+  | { type: "PathMatches"; value: string }
+  | { type: "ExtensionIs"; value: string }
+  | { type: "FolderContains"; value: string };
+
+export interface Rule {
+  id: number;
+  conditions: RuleCondition[];
+  tags: string[];
+  enabled: boolean;
+}
+
+export interface RuleMatch {
+  id: number;
+  path: string;
+  new_tags: string[];
+}
+
+export async function getRules(): Promise<Rule[]> {
+  return await invoke("get_rules");
+}
+
+export async function addRule(
+  conditions: RuleCondition[],
+  tags: string
+): Promise<Rule> {
+  return await invoke("add_rule", { conditions: conditions, tags: tags });
+}
+
+export async function updateRule(
+  id: number,
+  conditions: RuleCondition[],
+  tags: string,
+  enabled: boolean
+): Promise<Rule> {
+  return await invoke("update_rule", {
+    id: id,
+    conditions: conditions,
+    tags: tags,
+    enabled: enabled,
+  });
+}
+
+export async function removeRule(id: number) {
+  await invoke("remove_rule", { id: id });
+}
+
+export async function previewRule(
+  conditions: RuleCondition[],
+  tags: string
+): Promise<RuleMatch[]> {
+  return await invoke("preview_rule", { conditions: conditions, tags: tags });
+}
+
+/** An item that would be tagged by `importTagsFromPattern`. */
+export interface PatternMatch {
+  id: number;
+  path: string;
+  new_tags: string[];
+}
+
+/**
+ * Add tags extracted from file names to all items matching the pattern, e.g.
+ * `{artist} - {bpm}bpm - {_}.wav`. If `dryRun` is true, the repo isn't modified and the items
+ * that would be tagged are returned instead.
+ */
+export async function importTagsFromPattern(
+  pattern: string,
+  dryRun: boolean
+): Promise<PatternMatch[]> {
+  return await invoke("import_tags_from_pattern", {
+    pattern: pattern,
+    dryRun: dryRun,
+  });
+}
+
+export interface CsvImportOptions {
+  /** Replace the tags of each item, instead of adding to them */
+  replace: boolean;
+  /** Don't modify the repo, only report what would be imported */
+  dry_run: boolean;
+}
+
+/** A row of a CSV file that couldn't be imported by `importTagsCsv`. */
+export interface CsvRowError {
+  row: number;
+  path: string;
+  error: string;
+}
+
+export interface CsvImportReport {
+  imported: number;
+  unchanged: number;
+  errors: CsvRowError[];
+}
+
+/**
+ * Add tags to items from a CSV file with rows like `path,tag1 tag2`. Relative paths are resolved
+ * against the repo root. Rows that can't be imported are skipped and listed in the report.
+ */
+export async function importTagsCsv(
+  path: string,
+  options: CsvImportOptions
+): Promise<CsvImportReport> {
+  return await invoke("import_tags_csv", { path: path, options: options });
+}
+
+/** A file that couldn't be copied by `exportSubtree` or `importSubtree`. */
+export interface SubtreeFileError {
+  path: string;
+  error: string;
+}
+
+export interface SubtreeReport {
+  copied: number;
+  errors: SubtreeFileError[];
+}
+
+/**
+ * Copy the items in the folder `dir` to the empty folder `dest`, along with a `tags.csv`
+ * manifest of their tags. Use `importSubtree` to merge the copy into another repo.
+ */
+export async function exportSubtree(
+  dir: string,
+  dest: string
+): Promise<SubtreeReport> {
+  return await invoke("export_subtree", { dir: dir, dest: dest });
+}
+
+/**
+ * Copy a folder written by `exportSubtree` into the folder `dir` of the repo, and add its files
+ * with their tags. Files that already exist in the repo are skipped.
+ */
+export async function importSubtree(
+  src: string,
+  dir: string
+): Promise<SubtreeReport> {
+  return await invoke("import_subtree", { src: src, dir: dir });
+}
+
+export interface RepoConfig {
+  excluded_paths: string[];
+  excluded_names: string[];
+  auto_tag: boolean;
+  default_sort: {
+    column: "Path" | "Name" | "Extension";
+    descending: boolean;
+  };
+  columns: { type: string; width: number }[];
+  consistency_check_interval: number;
+  // minutes between resyncs while the repo isn't watched, 0 to disable
+  auto_resync_interval: number;
+  locking_mode: "Exclusive" | "Shared";
+  case_insensitive_paths: boolean;
+  case_insensitive_tags: boolean;
+  invalid_tag_chars: string;
+  tag_policy: "Allow" | "Reject" | "Replace";
+  sync_confirmation_threshold: number;
+  tombstone_retention_days: number;
+  rename_pairing_window_ms: number;
+  watcher_resume_delay_ms: number;
+  // "Auto" polls the repo folder if it's on a network drive
+  watcher_strategy: "Auto" | "Native" | "Polling";
+  // seconds between scans when polling
+  watcher_poll_interval: number;
+  // named sets of tags separated by spaces, see `applyTemplate`
+  tag_templates: Record<string, string>;
+}
+
+export async function getRepoConfig(): Promise<RepoConfig> {
+  return await invoke("get_repo_config");
+}
+
+export async function setRepoConfig(config: RepoConfig) {
+  await invoke("set_repo_config", { config: config });
+}
+
+export interface CreateRepoOptions {
+  // missing fields use the default config
+  config?: Partial<RepoConfig>;
+  // open the repo after creating it, which scans the folder
+  scan?: boolean;
+}
+
+export interface CreateRepoSummary {
+  path: string;
+  scanned: boolean;
+  item_count: number;
+}
+
+/**
+ * Turn a folder into a new repo. Fails if the folder is already a repo, use
+ * `openRepo` to open existing repos.
+ */
+export async function createRepo(
+  path: string,
+  options: CreateRepoOptions = {}
+): Promise<CreateRepoSummary> {
+  return await invoke("create_repo", { path, options });
+}
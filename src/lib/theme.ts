@@ -0,0 +1,38 @@
+import type { ThemeConfig } from "@/lib/ffi";
+
+declare global {
+  interface Window {
+    // set by the backend before the page loads
+    __THEME__?: ThemeConfig;
+  }
+}
+
+const systemDark = window.matchMedia("(prefers-color-scheme: dark)");
+let currentTheme: ThemeConfig | null = window.__THEME__ ?? null;
+
+/**
+ * Apply the dark mode and accent color of a theme to the page. The font size is only applied when
+ * the page loads, since some element sizes are computed from it once.
+ */
+export function applyTheme(theme: ThemeConfig) {
+  currentTheme = theme;
+  const root = document.documentElement;
+  const dark =
+    theme.mode === "dark" || (theme.mode === "system" && systemDark.matches);
+  root.classList.toggle("dark", dark);
+  if (theme.accent_color === null) {
+    root.style.removeProperty("--accent-color");
+  } else {
+    root.style.setProperty("--accent-color", theme.accent_color);
+  }
+}
+
+if (currentTheme !== null) {
+  document.documentElement.style.fontSize = `${currentTheme.font_size}px`;
+  applyTheme(currentTheme);
+}
+
+// follow the OS when the theme mode is "system"
+systemDark.addEventListener("change", () => {
+  if (currentTheme !== null) applyTheme(currentTheme);
+});
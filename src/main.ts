@@ -1,3 +1,5 @@
+// must run first, so sizes computed from the font size are correct
+import "./lib/theme";
 import { createApp } from "vue";
 import "./styles.css";
 import App from "./App.vue";
@@ -1,6 +1,8 @@
 /** @type {import('./tailwind.config.d.ts').Config} */
 export default {
   content: ["./index.html", "./src/**/*.{vue,js,ts,jsx,tsx}"],
+  // toggled by `applyTheme()` in src/lib/theme.ts
+  darkMode: "class",
   theme: {
     fontSize: {
       xs: "0.625rem", // 10px
@@ -18,6 +20,10 @@ export default {
       "9xl": "6rem", // 96px
     },
     extend: {
+      colors: {
+        // set by the theme in the app config
+        accent: "var(--accent-color, #3b82f6)",
+      },
       width: {
         "title-button": "2.85rem",
       },